@@ -0,0 +1,261 @@
+/// What a search bot or file server told us about one of our requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotEvent
+{
+    QueueUpdate
+    {
+        position: Option<u32>,
+    },
+    NoResults,
+    Rejected
+    {
+        reason: String,
+        /// The bot refused because we already use all of our slots with it.
+        slots_exhausted: bool,
+    },
+    Sending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind
+{
+    QueueUpdate,
+    NoResults,
+    Rejected,
+    SlotsExhausted,
+    Sending,
+}
+
+impl ReplyKind
+{
+    pub fn from_name(name: &str) -> Result<ReplyKind, &'static str>
+    {
+        match name.trim().to_lowercase().as_str()
+        {
+            "queue" => Ok(ReplyKind::QueueUpdate),
+            "none" => Ok(ReplyKind::NoResults),
+            "rejected" => Ok(ReplyKind::Rejected),
+            "slots" => Ok(ReplyKind::SlotsExhausted),
+            "sending" => Ok(ReplyKind::Sending),
+            _ => Err("Unknown reply kind, use queue, none, rejected, slots or sending"),
+        }
+    }
+}
+
+/// A rule is a case-insensitive pattern that may appear anywhere in a reply.
+///
+/// `*` matches any text and `#` matches a number, the first of which becomes the queue position.
+#[derive(Debug, Clone)]
+pub struct ReplyRule
+{
+    pub pattern: String,
+    pub kind: ReplyKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct BotFamily
+{
+    /// Nicknames starting with any of these belong to the family, empty matches every bot.
+    pub nick_prefixes: Vec<String>,
+    pub rules: Vec<ReplyRule>,
+}
+
+impl BotFamily
+{
+    pub fn new(nick_prefixes: &[&str]) -> Self
+    {
+        BotFamily {
+            nick_prefixes: nick_prefixes.iter().map(|x| x.to_lowercase()).collect(),
+            rules: Vec::new(),
+        }
+    }
+    pub fn with_rule(mut self, pattern: &str, kind: ReplyKind) -> Self
+    {
+        self.add_rule(pattern, kind);
+        self
+    }
+    pub fn add_rule(&mut self, pattern: &str, kind: ReplyKind)
+    {
+        self.rules.push(ReplyRule {
+            pattern: pattern.to_lowercase(),
+            kind,
+        });
+    }
+    pub fn matches_sender(&self, sender: &str) -> bool
+    {
+        let sender = sender.to_lowercase();
+        self.nick_prefixes.is_empty() || self.nick_prefixes.iter().any(|x| sender.starts_with(x))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BotReplyClassifier
+{
+    /// Checked in order, so more specific families go first.
+    pub families: Vec<BotFamily>,
+}
+
+impl BotReplyClassifier
+{
+    /// Rules for SearchBot-style search bots and the common file server scripts.
+    pub fn with_default_families() -> Self
+    {
+        let search_bots = BotFamily::new(&["search"])
+            .with_rule("returned 0 matches", ReplyKind::NoResults)
+            .with_rule("returned no matches", ReplyKind::NoResults)
+            .with_rule("no matches found", ReplyKind::NoResults)
+            .with_rule("you already have a search", ReplyKind::SlotsExhausted)
+            .with_rule("you are # in the search queue", ReplyKind::QueueUpdate)
+            .with_rule("search accepted", ReplyKind::QueueUpdate);
+        // File servers have no common nick, so these match a NOTICE from anyone. Callers only
+        // act on a reply from a nick that has one of our requests outstanding.
+        let file_servers = BotFamily::new(&[])
+            .with_rule("queue position #", ReplyKind::QueueUpdate)
+            .with_rule("queued at position #", ReplyKind::QueueUpdate)
+            .with_rule("queue, position #", ReplyKind::QueueUpdate)
+            .with_rule("position # in * queue", ReplyKind::QueueUpdate)
+            .with_rule("all slots * full", ReplyKind::QueueUpdate)
+            .with_rule("you already have * queued", ReplyKind::SlotsExhausted)
+            .with_rule("you already have * in queue", ReplyKind::SlotsExhausted)
+            .with_rule("you can only have # ", ReplyKind::SlotsExhausted)
+            .with_rule("maximum * queues", ReplyKind::SlotsExhausted)
+            .with_rule("invalid pack", ReplyKind::Rejected)
+            .with_rule("file not found", ReplyKind::Rejected)
+            .with_rule("no such file", ReplyKind::Rejected)
+            .with_rule("could not find", ReplyKind::Rejected)
+            .with_rule("denied", ReplyKind::Rejected)
+            .with_rule("banned", ReplyKind::Rejected)
+            .with_rule("sending you", ReplyKind::Sending)
+            .with_rule("now sending", ReplyKind::Sending)
+            .with_rule("transfer started", ReplyKind::Sending)
+            .with_rule("returned 0 matches", ReplyKind::NoResults)
+            .with_rule("no results", ReplyKind::NoResults);
+        BotReplyClassifier {
+            families: vec![search_bots, file_servers],
+        }
+    }
+    /// Puts `family` ahead of every family added before it.
+    pub fn add_family(&mut self, family: BotFamily)
+    {
+        self.families.insert(0, family);
+    }
+    pub fn classify(&self, sender: &str, text: &str) -> Option<BotEvent>
+    {
+        let text = strip_formatting(text).to_lowercase();
+        for family in self.families.iter().filter(|x| x.matches_sender(sender))
+        {
+            for rule in family.rules.iter()
+            {
+                if let Some(numbers) = find_pattern(&rule.pattern, &text)
+                {
+                    return Some(match rule.kind
+                    {
+                        ReplyKind::QueueUpdate => BotEvent::QueueUpdate {
+                            position: numbers.first().copied(),
+                        },
+                        ReplyKind::NoResults => BotEvent::NoResults,
+                        ReplyKind::Rejected => BotEvent::Rejected {
+                            reason: text.trim().to_string(),
+                            slots_exhausted: false,
+                        },
+                        ReplyKind::SlotsExhausted => BotEvent::Rejected {
+                            reason: text.trim().to_string(),
+                            slots_exhausted: true,
+                        },
+                        ReplyKind::Sending => BotEvent::Sending,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Removes mIRC bold, colour, reverse, italic, underline and reset codes.
+pub fn strip_formatting(text: &str) -> String
+{
+    let mut ret_val = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(x) = chars.next()
+    {
+        match x
+        {
+            '\u{2}' | '\u{f}' | '\u{16}' | '\u{1d}' | '\u{1e}' | '\u{1f}' =>
+            {}
+            '\u{3}' =>
+            {
+                // Foreground and optional background, each up to two digits
+                for _ in 0..2
+                {
+                    if chars.peek().is_some_and(|x| x.is_ascii_digit())
+                    {
+                        chars.next();
+                    }
+                }
+                let mut lookahead = chars.clone();
+                if lookahead.next() == Some(',')
+                    && lookahead.peek().is_some_and(|x| x.is_ascii_digit())
+                {
+                    chars.next();
+                    for _ in 0..2
+                    {
+                        if chars.peek().is_some_and(|x| x.is_ascii_digit())
+                        {
+                            chars.next();
+                        }
+                    }
+                }
+            }
+            _ => ret_val.push(x),
+        }
+    }
+    ret_val
+}
+
+/// Searches `text` for `pattern` at any position, returning the numbers matched by `#`.
+pub fn find_pattern(pattern: &str, text: &str) -> Option<Vec<u32>>
+{
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+    (0..=text.len()).find_map(|start| match_here(&pattern, &text[start..]))
+}
+
+fn match_here(pattern: &[char], text: &[char]) -> Option<Vec<u32>>
+{
+    let first = match pattern.first()
+    {
+        Some(t) => *t,
+        None => return Some(Vec::new()),
+    };
+    match first
+    {
+        '*' => (0..=text.len()).find_map(|skip| match_here(&pattern[1..], &text[skip..])),
+        '#' =>
+        {
+            let digits = text.iter().take_while(|x| x.is_ascii_digit()).count();
+            if digits == 0
+            {
+                return None;
+            }
+            let number = text[..digits]
+                .iter()
+                .collect::<String>()
+                .parse::<u32>()
+                .ok()?;
+            let mut numbers = match_here(&pattern[1..], &text[digits..])?;
+            numbers.insert(0, number);
+            Some(numbers)
+        }
+        _ =>
+        {
+            if text.first() == Some(&first)
+            {
+                match_here(&pattern[1..], &text[1..])
+            }
+            else
+            {
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::bot_reply::{
+    find_pattern, strip_formatting, BotEvent, BotFamily, BotReplyClassifier, ReplyKind,
+};
+use crate::irc_message::{IrcMessage, MessageCommand};
+
+#[test]
+fn strip_formatting_test()
+{
+    assert_eq!(
+        "Search accepted",
+        strip_formatting("\u{3}4,1Search\u{3} \u{2}accepted\u{f}")
+    );
+    assert_eq!("10 results", strip_formatting("\u{3}0310 results"));
+    assert_eq!("a,b", strip_formatting("\u{3}4a,b"));
+}
+#[test]
+fn find_pattern_test()
+{
+    assert_eq!(
+        Some(vec![3]),
+        find_pattern("queue, position #", "added to queue, position 3 of 10")
+    );
+    assert_eq!(
+        Some(vec![]),
+        find_pattern(
+            "you already have * queued",
+            "sorry, you already have 2 files queued"
+        )
+    );
+    assert_eq!(None, find_pattern("position #", "position unknown"));
+}
+#[test]
+fn classify_search_bot_replies_test()
+{
+    let classifier = BotReplyClassifier::with_default_families();
+    assert_eq!(
+        Some(BotEvent::NoResults),
+        classifier.classify(
+            "Search",
+            "Your search for \"zzqx\" returned 0 matches. Please try again."
+        )
+    );
+    assert_eq!(
+        Some(BotEvent::NoResults),
+        classifier.classify(
+            "SearchOok",
+            "\u{2}Sorry\u{2}, your search for \"zzqx\" returned no matches."
+        )
+    );
+    assert_eq!(
+        Some(BotEvent::QueueUpdate { position: None }),
+        classifier.classify(
+            "Search",
+            "Search accepted. Your results will be sent shortly."
+        )
+    );
+    assert_eq!(
+        Some(BotEvent::QueueUpdate { position: Some(4) }),
+        classifier.classify("Search", "You are 4 in the search queue.")
+    );
+}
+#[test]
+fn classify_file_server_replies_test()
+{
+    let classifier = BotReplyClassifier::with_default_families();
+    assert_eq!(
+        Some(BotEvent::QueueUpdate { position: Some(3) }),
+        classifier.classify(
+            "Bsk",
+            "Added to queue, position 3. Thank you for using OmenServe."
+        )
+    );
+    assert_eq!(
+        Some(BotEvent::QueueUpdate { position: Some(12) }),
+        classifier.classify(
+            "DV8",
+            "** All Slots Full, you have been queued at position 12 for The Hobbit.epub"
+        )
+    );
+    assert_eq!(
+        Some(BotEvent::QueueUpdate { position: None }),
+        classifier.classify("DV8", "** All slots are full, please wait")
+    );
+    assert_eq!(
+        Some(BotEvent::Sending),
+        classifier.classify("Bsk", "** Sending you The Hobbit.epub (1.2MB)")
+    );
+    assert!(matches!(
+        classifier.classify("Bsk", "You already have a request queued."),
+        Some(BotEvent::Rejected {
+            slots_exhausted: true,
+            ..
+        })
+    ));
+    assert!(matches!(
+        classifier.classify("Bsk", "You can only have 1 request at a time."),
+        Some(BotEvent::Rejected {
+            slots_exhausted: true,
+            ..
+        })
+    ));
+    assert!(matches!(
+        classifier.classify("Bsk", "Invalid pack number, try again"),
+        Some(BotEvent::Rejected {
+            slots_exhausted: false,
+            ..
+        })
+    ));
+    assert_eq!(None, classifier.classify("Bsk", "Welcome to #bookz!"));
+}
+#[test]
+fn classify_custom_family_test()
+{
+    let mut classifier = BotReplyClassifier::with_default_families();
+    classifier.add_family(
+        BotFamily::new(&["pondering"]).with_rule("you are #th in line", ReplyKind::QueueUpdate),
+    );
+    assert_eq!(
+        Some(BotEvent::QueueUpdate { position: Some(5) }),
+        classifier.classify("Pondering42", "You are 5th in line.")
+    );
+    assert_eq!(None, classifier.classify("Bsk", "You are 5th in line."));
+}
+#[test]
+fn parse_notice_test()
+{
+    let message = IrcMessage::parse_message(
+        &":Search!Search@bookz.search.bot NOTICE rapere :Your search for \"zzqx\" returned 0 matches."
+            .to_string(),
+    )
+    .unwrap();
+    match message.command
+    {
+        MessageCommand::NOTICE {
+            message_target,
+            text,
+        } =>
+        {
+            assert_eq!("rapere", message_target);
+            assert_eq!("Your search for \"zzqx\" returned 0 matches.", text);
+        }
+        _ => panic!("Expected a NOTICE"),
+    }
+}
@@ -0,0 +1,88 @@
+use crate::irc_connection::IrcConnection;
+use crate::tui::restore_terminal;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{process, thread, time};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+
+// signal(2) from the C library, declared by hand as the crate has no dependencies. This
+// matches `sighandler_t signal(int, sighandler_t)` on every unix target: `int` is 32 bits,
+// and the handler and the previous handler it returns are plain function pointers, so the
+// pointer-sized return value can be read as a `usize` and ignored.
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_signum: i32)
+{
+    // Only async-signal-safe work here, the watcher thread does the cleanup
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool
+{
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Partially downloaded files that must be removed if the session is cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct PartFiles
+{
+    paths: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl PartFiles
+{
+    pub fn register(&self, path: &Path)
+    {
+        self.paths.lock().unwrap().push(path.to_path_buf());
+    }
+    pub fn unregister(&self, path: &Path)
+    {
+        self.paths.lock().unwrap().retain(|x| x != path);
+    }
+    pub fn remove_all(&self)
+    {
+        for path in self.paths.lock().unwrap().drain(..)
+        {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Installs the Ctrl-C handler and starts a thread that, once Ctrl-C is pressed, restores the
+/// terminal, leaves IRC with a QUIT, deletes `.part` files and exits. Running this on its own
+/// thread means a cancellation is noticed even while the main thread is blocked reading stdin.
+///
+/// Only unix gets a handler. Elsewhere Ctrl-C keeps its default of ending the process at once,
+/// without the QUIT or the clean up.
+pub fn install_ctrlc_handler(mut connex: IrcConnection, part_files: PartFiles)
+{
+    #[cfg(unix)]
+    unsafe {
+        signal(SIGINT, on_sigint);
+    }
+    thread::spawn(move || {
+        loop
+        {
+            if is_cancelled()
+            {
+                // The full-screen interface may still have the terminal in raw mode
+                restore_terminal();
+                println!("Cancelled, cleaning up...");
+                let _ = connex.send_command_args("QUIT", ":Cancelled");
+                part_files.remove_all();
+                process::exit(130);
+            }
+            thread::sleep(time::Duration::from_millis(100));
+        }
+    });
+}
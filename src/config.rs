@@ -0,0 +1,487 @@
+use crate::bot_reply::{BotFamily, ReplyKind};
+use crate::export::OutputFormat;
+use crate::irc_message::DEFAULT_VERSION;
+use crate::library::LibraryTemplate;
+use crate::ranking::default_formats;
+use crate::result_cache::DEFAULT_CACHE_TTL;
+use crate::search_provider::SearchDialect;
+use crate::search_result::BookFormat;
+use crate::watchlist::{DEFAULT_SEARCH_SPACING, DEFAULT_WATCH_INTERVAL};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Search dialects per channel, kept in the download directory.
+pub const SEARCH_DIALECTS_FILE_NAME: &str = ".rs-book-downloader-dialects";
+/// Extra bot reply rules, kept in the download directory.
+pub const BOT_REPLIES_FILE_NAME: &str = ".rs-book-downloader-replies";
+
+#[derive(Debug, Clone)]
+pub struct Timeouts
+{
+    /// From connecting until the server welcomes us.
+    pub registration: Duration,
+    /// From sending the search until the result list is offered.
+    pub search_results: Duration,
+    /// From requesting a pack until its DCC offer arrives.
+    pub dcc_offer: Duration,
+    pub dcc_connect: Duration,
+    /// How long a DCC transfer may go without receiving any data.
+    pub chunk_idle: Duration,
+}
+
+impl Default for Timeouts
+{
+    fn default() -> Self
+    {
+        Timeouts {
+            registration: Duration::from_secs(60),
+            search_results: Duration::from_secs(120),
+            dcc_offer: Duration::from_secs(300),
+            dcc_connect: Duration::from_secs(30),
+            chunk_idle: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A file to fetch from an fserve bot over DCC CHAT instead of searching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FserveTarget
+{
+    pub bot: String,
+    /// Triggers starting with `!` or `@` are said in the channel, others are sent to the bot as CTCP.
+    pub trigger: String,
+    pub path: String,
+}
+
+/// A look at the download history, done without connecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryCommand
+{
+    /// Every search and download, oldest first.
+    Show,
+    /// Totals and how reliable each bot has been.
+    Stats,
+}
+
+/// A change to, or a look at, the watchlist, done without connecting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchCommand
+{
+    Add(String),
+    /// By number or by `author - title`.
+    Remove(String),
+    List,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config
+{
+    pub timeouts: Timeouts,
+    pub fserve: Option<FserveTarget>,
+    /// Sent in reply to CTCP VERSION.
+    pub version: String,
+    /// Use line-based prompts instead of the full-screen interface.
+    pub plain: bool,
+    /// Search for this instead of asking.
+    pub query: Option<String>,
+    /// Download the best ranked result without asking.
+    pub auto_pick: bool,
+    /// Print how every result was scored when picking automatically.
+    pub explain: bool,
+    /// Most preferred first, used when ranking results.
+    pub preferred_formats: Vec<BookFormat>,
+    /// The IRC server to connect to, as `host:port`.
+    pub server: String,
+    /// Joined, searched and requested from.
+    pub channel: String,
+    /// The only nick a search result list is accepted from.
+    pub search_bot: String,
+    /// Search dialect per channel, channels not listed use `@search`.
+    pub search_dialects: Vec<(String, SearchDialect)>,
+    /// Bot families with replies the built-in rules miss, checked before the built-in ones.
+    pub bot_families: Vec<BotFamily>,
+    /// Browse cached search results without connecting.
+    pub offline: bool,
+    /// How long a cached result list is used instead of searching again.
+    pub cache_ttl: Duration,
+    /// Print the search results in this format and quit instead of downloading.
+    pub output_format: Option<OutputFormat>,
+    /// Search for every book in this list and queue the ones found unambiguously.
+    pub reading_list: Option<PathBuf>,
+    pub watch_command: Option<WatchCommand>,
+    /// Keep searching for the watchlist's books and download them as they appear.
+    pub daemon: bool,
+    /// How long the daemon waits before searching again for a book it did not find.
+    pub watch_interval: Duration,
+    /// The least time the daemon leaves between two searches.
+    pub search_spacing: Duration,
+    /// Run when the daemon finds or downloads a book.
+    pub notify_command: Option<String>,
+    pub history_command: Option<HistoryCommand>,
+    /// Download books again even when the history shows we already have them.
+    pub redownload: bool,
+    /// Finished downloads are filed under this directory.
+    pub library: Option<PathBuf>,
+    pub library_template: LibraryTemplate,
+    /// File earlier downloads that are not in the library yet, then quit.
+    pub organize: bool,
+    /// Only show where books would be filed.
+    pub dry_run: bool,
+    /// Move downloads that fail verification into a quarantine directory.
+    pub quarantine: bool,
+}
+
+impl Default for Config
+{
+    fn default() -> Self
+    {
+        Config {
+            timeouts: Timeouts::default(),
+            fserve: None,
+            version: DEFAULT_VERSION.to_string(),
+            plain: false,
+            query: None,
+            auto_pick: false,
+            explain: false,
+            preferred_formats: default_formats(),
+            server: "66.207.167.12:6660".to_string(),
+            channel: "#bookz".to_string(),
+            search_bot: "Search".to_string(),
+            search_dialects: Vec::new(),
+            bot_families: Vec::new(),
+            offline: false,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            output_format: None,
+            reading_list: None,
+            watch_command: None,
+            daemon: false,
+            watch_interval: DEFAULT_WATCH_INTERVAL,
+            search_spacing: DEFAULT_SEARCH_SPACING,
+            notify_command: None,
+            history_command: None,
+            redownload: false,
+            library: None,
+            library_template: LibraryTemplate::default(),
+            organize: false,
+            dry_run: false,
+            quarantine: false,
+        }
+    }
+}
+
+pub const USAGE: &str = "Usage: rs-book-downloader-cli [options]
+       rs-book-downloader-cli history|stats
+       rs-book-downloader-cli organize --library <dir> [--dry-run]
+
+Commands:
+    history                         List every search and download so far
+    stats                           Show download totals and how reliable each bot has been
+    organize                        File earlier downloads into the --library
+
+Options:
+    --registration-timeout <secs>   Wait this long for the server to accept our nickname
+    --search-timeout <secs>         Wait this long for search results
+    --offer-timeout <secs>          Wait this long for a bot to offer a requested file
+    --connect-timeout <secs>        Wait this long when connecting to a DCC sender
+    --idle-timeout <secs>           Abort a transfer after this long without data
+    --fserve <bot> <trigger> <path> Fetch <path> from an fserve bot instead of searching
+    --version-string <text>         Reply to CTCP VERSION with <text>
+    --plain                         Use line-based prompts instead of the full-screen interface
+    --search <title>                Search for <title> instead of asking
+    --auto                          Download the best ranked result without asking
+    --explain                       With --auto, print how each result was scored
+    --formats <list>                Preferred formats, best first (default epub,azw3,mobi,pdf)
+    --server <host:port>            Connect to <host:port> (default 66.207.167.12:6660)
+    --channel <name>                Search and request in <name> (default #bookz)
+    --search-bot <nick>             Only accept search results from <nick> (default Search)
+    --search-dialect [<chan>=]<d>   Search <chan>, or the --channel, with @search, !search,
+                                    @find or @seek. Dialects can also be kept in
+                                    .rs-book-downloader-dialects, one <chan>=<d> per line
+    --offline                       Browse cached search results without connecting
+    --cache-ttl <secs>              Reuse cached results this long instead of searching again
+    --format <fmt>                  Print the results as json, csv, ndjson or table and quit
+    --batch <file>                  Download every `author - title` line of <file>, or the
+                                    to-read shelf of a Goodreads CSV export, and report how
+                                    each went in <file>.report.txt
+    --watch-add <book>              Add an `author - title` to the watchlist and quit
+    --watch-remove <book>           Remove a book, by number or name, from the watchlist and quit
+    --watch-list                    Show the watchlist and quit
+    --daemon                        Keep searching for the watchlist's books and download each
+                                    as soon as a copy in a preferred format appears
+    --watch-interval <secs>         Search again for a missing book after this long (default 6h)
+    --search-spacing <secs>         Leave at least this long between two searches (default 60)
+    --notify <command>              Run <command> <event> <book> <detail> when the daemon
+                                    queues, downloads or fails to download a book
+    --redownload                    Download books again even if an earlier copy is still here
+    --library <dir>                 File finished downloads under <dir>
+    --library-template <template>   Path of a book in the library, from {author},
+                                    {author_sort}, {title}, {series}, {series_index}, {year}
+                                    and {ext} (default {author_sort}/{title} ({year}).{ext})
+    --dry-run                       Show where books would be filed without moving them
+    --quarantine                    Move downloads that are damaged, in the wrong format or not
+                                    the requested book into a quarantine directory
+    -h, --help                      Show this message
+
+Bot replies the built-in rules miss can be added to .rs-book-downloader-replies, one
+<nick prefix> <queue|none|rejected|slots|sending>=<pattern> per line. A nick prefix of *
+matches every bot, and in the pattern * matches any text and # a number.";
+
+impl Config
+{
+    /// Parses the command line, `args` excludes the program name.
+    pub fn from_args(args: &[String]) -> Result<Config, &'static str>
+    {
+        let mut config = Config::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            match arg.as_str()
+            {
+                "--registration-timeout" =>
+                {
+                    config.timeouts.registration = parse_seconds(args.next())?
+                }
+                "--search-timeout" => config.timeouts.search_results = parse_seconds(args.next())?,
+                "--offer-timeout" => config.timeouts.dcc_offer = parse_seconds(args.next())?,
+                "--connect-timeout" => config.timeouts.dcc_connect = parse_seconds(args.next())?,
+                "--idle-timeout" => config.timeouts.chunk_idle = parse_seconds(args.next())?,
+                "--fserve" =>
+                {
+                    let mut values = args.by_ref().take(3).cloned().collect::<Vec<String>>();
+                    if values.len() != 3
+                    {
+                        return Err("--fserve needs a bot, a trigger and a path");
+                    }
+                    config.fserve = Some(FserveTarget {
+                        path: values.pop().unwrap(),
+                        trigger: values.pop().unwrap(),
+                        bot: values.pop().unwrap(),
+                    });
+                }
+                "--version-string" => match args.next()
+                {
+                    Some(t) => config.version = t.to_string(),
+                    None => return Err("Missing value for --version-string"),
+                },
+                "--plain" => config.plain = true,
+                "--search" => match args.next()
+                {
+                    Some(t) if !t.trim().is_empty() => config.query = Some(t.trim().to_string()),
+                    _ => return Err("Missing value for --search"),
+                },
+                "--auto" => config.auto_pick = true,
+                "--offline" => config.offline = true,
+                "--cache-ttl" => config.cache_ttl = parse_seconds(args.next())?,
+                "--batch" => match args.next()
+                {
+                    Some(t) => config.reading_list = Some(PathBuf::from(t)),
+                    None => return Err("Missing value for --batch"),
+                },
+                "--watch-add" => match args.next()
+                {
+                    Some(t) => config.watch_command = Some(WatchCommand::Add(t.to_string())),
+                    None => return Err("Missing value for --watch-add"),
+                },
+                "--watch-remove" => match args.next()
+                {
+                    Some(t) => config.watch_command = Some(WatchCommand::Remove(t.to_string())),
+                    None => return Err("Missing value for --watch-remove"),
+                },
+                "--watch-list" => config.watch_command = Some(WatchCommand::List),
+                "--daemon" => config.daemon = true,
+                "--watch-interval" => config.watch_interval = parse_seconds(args.next())?,
+                "--search-spacing" => config.search_spacing = parse_seconds(args.next())?,
+                "--notify" => match args.next()
+                {
+                    Some(t) if !t.trim().is_empty() => config.notify_command = Some(t.to_string()),
+                    _ => return Err("Missing value for --notify"),
+                },
+                "history" => config.history_command = Some(HistoryCommand::Show),
+                "stats" => config.history_command = Some(HistoryCommand::Stats),
+                "--redownload" => config.redownload = true,
+                "organize" => config.organize = true,
+                "--library" => match args.next()
+                {
+                    Some(t) => config.library = Some(PathBuf::from(t)),
+                    None => return Err("Missing value for --library"),
+                },
+                "--library-template" => match args.next()
+                {
+                    Some(t) => config.library_template = LibraryTemplate::parse(t)?,
+                    None => return Err("Missing value for --library-template"),
+                },
+                "--dry-run" => config.dry_run = true,
+                "--quarantine" => config.quarantine = true,
+                "--format" => match args.next()
+                {
+                    Some(t) => config.output_format = Some(OutputFormat::from_name(t)?),
+                    None => return Err("Missing value for --format"),
+                },
+                "--explain" => config.explain = true,
+                "--formats" =>
+                {
+                    let formats = match args.next()
+                    {
+                        Some(t) => t
+                            .split(',')
+                            .map(|x| x.trim())
+                            .filter(|x| !x.is_empty())
+                            .map(|x| BookFormat::from_extension(Some(x)))
+                            .collect::<Vec<BookFormat>>(),
+                        None => Vec::new(),
+                    };
+                    if formats.is_empty()
+                    {
+                        return Err("--formats needs a comma separated list such as epub,mobi");
+                    }
+                    config.preferred_formats = formats;
+                }
+                "--server" => match args.next()
+                {
+                    Some(t) if !t.trim().is_empty() => config.server = t.trim().to_string(),
+                    _ => return Err("Missing value for --server"),
+                },
+                "--channel" => match args.next()
+                {
+                    Some(t) if !t.trim().is_empty() => config.channel = channel_name(t),
+                    _ => return Err("Missing value for --channel"),
+                },
+                "--search-bot" => match args.next()
+                {
+                    Some(t) if !t.trim().is_empty() => config.search_bot = t.trim().to_string(),
+                    _ => return Err("Missing value for --search-bot"),
+                },
+                "--search-dialect" =>
+                {
+                    let value = match args.next()
+                    {
+                        Some(t) => t,
+                        None => return Err("Missing value for --search-dialect"),
+                    };
+                    // Without a channel the dialect is for whichever --channel ends up chosen
+                    let (channel, dialect) = match value.split_once('=')
+                    {
+                        Some((channel, dialect)) => (channel_name(channel), dialect),
+                        None => (String::new(), value.as_str()),
+                    };
+                    let dialect = SearchDialect::from_name(dialect)?;
+                    config.search_dialects.retain(|x| x.0 != channel);
+                    config.search_dialects.push((channel, dialect));
+                }
+                "-h" | "--help" => return Err("Help requested"),
+                _ => return Err("Unknown argument"),
+            }
+        }
+        if config.organize && config.library.is_none()
+        {
+            return Err("organize needs a --library to file books into");
+        }
+        Ok(config)
+    }
+    /// Adds the dialects listed in `path`, one `<channel>=<dialect>` per line, for channels the
+    /// command line did not already set. A missing file lists none.
+    pub fn load_search_dialects(&mut self, path: &Path) -> Result<(), &'static str>
+    {
+        let contents = match fs::read_to_string(path)
+        {
+            Ok(t) => t,
+            Err(_e) => return Ok(()),
+        };
+        for line in contents.lines().filter(|x| !x.trim().is_empty())
+        {
+            let (channel, dialect) = match line.split_once('=')
+            {
+                Some((channel, dialect)) if !channel.trim().is_empty() =>
+                {
+                    (channel_name(channel), dialect.trim())
+                }
+                _ => return Err("Malformed line in dialects file, use <channel>=<dialect>"),
+            };
+            let dialect = SearchDialect::from_name(dialect)?;
+            if !self.search_dialects.iter().any(|x| x.0.eq_ignore_ascii_case(&channel))
+            {
+                self.search_dialects.push((channel, dialect));
+            }
+        }
+        Ok(())
+    }
+    /// Adds the reply rules listed in `path`, one `<nick prefix> <kind>=<pattern>` per line.
+    /// Rules for the same prefix form one family, checked in the order of the file. A missing
+    /// file lists none.
+    pub fn load_bot_replies(&mut self, path: &Path) -> Result<(), &'static str>
+    {
+        let contents = match fs::read_to_string(path)
+        {
+            Ok(t) => t,
+            Err(_e) => return Ok(()),
+        };
+        for line in contents.lines().filter(|x| !x.trim().is_empty())
+        {
+            let malformed = "Malformed line in replies file, use <nick prefix> <kind>=<pattern>";
+            let (rule, pattern) = match line.split_once('=')
+            {
+                Some((rule, pattern)) if !pattern.trim().is_empty() => (rule, pattern.trim()),
+                _ => return Err(malformed),
+            };
+            let (prefix, kind) = match rule.split_whitespace().collect::<Vec<&str>>()[..]
+            {
+                [prefix, kind] => (prefix, ReplyKind::from_name(kind)?),
+                _ => return Err(malformed),
+            };
+            let prefixes = if prefix == "*" { Vec::new() } else { vec![prefix] };
+            let family = BotFamily::new(&prefixes);
+            let index = match self
+                .bot_families
+                .iter()
+                .position(|x| x.nick_prefixes == family.nick_prefixes)
+            {
+                Some(t) => t,
+                None =>
+                {
+                    self.bot_families.push(family);
+                    self.bot_families.len() - 1
+                }
+            };
+            self.bot_families[index].add_rule(pattern, kind);
+        }
+        Ok(())
+    }
+    /// How to search the configured channel.
+    pub fn search_dialect(&self) -> SearchDialect
+    {
+        self.search_dialects
+            .iter()
+            .find(|x| x.0.eq_ignore_ascii_case(&self.channel))
+            .or(self.search_dialects.iter().find(|x| x.0.is_empty()))
+            .map(|x| x.1)
+            .unwrap_or(SearchDialect::Search)
+    }
+}
+
+fn channel_name(value: &str) -> String
+{
+    let value = value.trim();
+    if value.starts_with('#') || value.starts_with('&')
+    {
+        value.to_string()
+    }
+    else
+    {
+        format!("#{}", value)
+    }
+}
+
+fn parse_seconds(value: Option<&String>) -> Result<Duration, &'static str>
+{
+    let value = match value
+    {
+        Some(t) => t,
+        None => return Err("Missing value for timeout"),
+    };
+    match value.parse::<u64>()
+    {
+        Ok(t) if t > 0 => Ok(Duration::from_secs(t)),
+        _ => Err("Timeouts must be a positive number of seconds"),
+    }
+}
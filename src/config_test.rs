@@ -0,0 +1,307 @@
+use crate::bot_reply::{BotEvent, BotReplyClassifier};
+use crate::config::{Config, FserveTarget, HistoryCommand, WatchCommand};
+use crate::export::OutputFormat;
+use crate::search_provider::SearchDialect;
+use crate::search_result::BookFormat;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, fs, process};
+
+fn args(values: &[&str]) -> Vec<String>
+{
+    values.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn config_defaults_test()
+{
+    let config = Config::from_args(&[]).unwrap();
+    assert_eq!(Duration::from_secs(60), config.timeouts.registration);
+    assert_eq!(Duration::from_secs(300), config.timeouts.dcc_offer);
+    assert!(!config.plain);
+    assert!(Config::from_args(&args(&["--plain"])).unwrap().plain);
+}
+#[test]
+fn config_timeouts_test()
+{
+    let config = Config::from_args(&args(&[
+        "--registration-timeout",
+        "5",
+        "--search-timeout",
+        "6",
+        "--offer-timeout",
+        "7",
+        "--connect-timeout",
+        "8",
+        "--idle-timeout",
+        "9",
+    ]))
+    .unwrap();
+    assert_eq!(Duration::from_secs(5), config.timeouts.registration);
+    assert_eq!(Duration::from_secs(6), config.timeouts.search_results);
+    assert_eq!(Duration::from_secs(7), config.timeouts.dcc_offer);
+    assert_eq!(Duration::from_secs(8), config.timeouts.dcc_connect);
+    assert_eq!(Duration::from_secs(9), config.timeouts.chunk_idle);
+}
+#[test]
+fn config_invalid_arguments_test()
+{
+    assert!(Config::from_args(&args(&["--idle-timeout"])).is_err());
+    assert!(Config::from_args(&args(&["--idle-timeout", "0"])).is_err());
+    assert!(Config::from_args(&args(&["--idle-timeout", "soon"])).is_err());
+    assert!(Config::from_args(&args(&["--frobnicate"])).is_err());
+}
+#[test]
+fn config_fserve_test()
+{
+    let args = ["--fserve", "Fserv", "!fserv", "books/The Hobbit.epub"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(
+        Some(FserveTarget {
+            bot: "Fserv".to_string(),
+            trigger: "!fserv".to_string(),
+            path: "books/The Hobbit.epub".to_string(),
+        }),
+        Config::from_args(&args).unwrap().fserve
+    );
+    assert!(Config::from_args(&args[..3]).is_err());
+}
+#[test]
+fn config_version_string_test()
+{
+    assert!(Config::from_args(&[])
+        .unwrap()
+        .version
+        .starts_with("rs-book-downloader-cli"));
+    assert_eq!(
+        "mIRC v7.66",
+        Config::from_args(&args(&["--version-string", "mIRC v7.66"]))
+            .unwrap()
+            .version
+    );
+}
+#[test]
+fn config_search_bot_test()
+{
+    assert_eq!("Search", Config::from_args(&[]).unwrap().search_bot);
+    assert_eq!(
+        "SearchOok",
+        Config::from_args(&args(&["--search-bot", "SearchOok"]))
+            .unwrap()
+            .search_bot
+    );
+    assert!(Config::from_args(&args(&["--search-bot"])).is_err());
+}
+#[test]
+fn config_auto_pick_test()
+{
+    let config = Config::from_args(&args(&[
+        "--search",
+        "dune",
+        "--auto",
+        "--explain",
+        "--formats",
+        "mobi, pdf",
+    ]))
+    .unwrap();
+    assert_eq!(Some("dune".to_string()), config.query);
+    assert!(config.auto_pick && config.explain);
+    assert_eq!(
+        vec![BookFormat::Mobi, BookFormat::Pdf],
+        config.preferred_formats
+    );
+    assert_eq!(
+        BookFormat::Epub,
+        Config::from_args(&[]).unwrap().preferred_formats[0]
+    );
+    assert!(Config::from_args(&args(&["--formats", ","])).is_err());
+    assert!(Config::from_args(&args(&["--search"])).is_err());
+}
+#[test]
+fn config_search_dialect_test()
+{
+    let config = Config::from_args(&[]).unwrap();
+    assert_eq!("#bookz", config.channel);
+    assert_eq!("66.207.167.12:6660", config.server);
+    assert_eq!(SearchDialect::Search, config.search_dialect());
+    let config = Config::from_args(&args(&["--server", "irc.example.net:6667"])).unwrap();
+    assert_eq!("irc.example.net:6667", config.server);
+    assert!(Config::from_args(&args(&["--server"])).is_err());
+
+    let config = Config::from_args(&args(&[
+        "--search-dialect",
+        "bookz=@find",
+        "--search-dialect",
+        "!search",
+        "--channel",
+        "bookz",
+    ]))
+    .unwrap();
+    assert_eq!("#bookz", config.channel);
+    assert_eq!(SearchDialect::Find, config.search_dialect());
+    // Without a channel the dialect applies to whichever channel is chosen
+    let config =
+        Config::from_args(&args(&["--channel", "#ebooks", "--search-dialect", "seek"])).unwrap();
+    assert_eq!(SearchDialect::Seek, config.search_dialect());
+    assert!(Config::from_args(&args(&["--search-dialect", "@locate"])).is_err());
+    assert!(Config::from_args(&args(&["--channel"])).is_err());
+}
+#[test]
+fn config_search_dialects_file_test()
+{
+    let path = env::temp_dir().join(format!("rsbd_dialects_{}", process::id()));
+    fs::write(&path, "#ebooks=@find\n\nbookz = !search\n").unwrap();
+    let mut config = Config::from_args(&args(&["--search-dialect", "ebooks=@seek"])).unwrap();
+    config.load_search_dialects(&path).unwrap();
+    // The command line wins over the file
+    assert_eq!(SearchDialect::BangSearch, config.search_dialect());
+    config.channel = "#ebooks".to_string();
+    assert_eq!(SearchDialect::Seek, config.search_dialect());
+
+    fs::write(&path, "#ebooks @find\n").unwrap();
+    let result = Config::default().load_search_dialects(&path);
+    fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+    assert!(Config::default().load_search_dialects(&path).is_ok());
+}
+#[test]
+fn config_bot_replies_file_test()
+{
+    let path = env::temp_dir().join(format!("rsbd_replies_{}", process::id()));
+    fs::write(
+        &path,
+        "Pondering queue=you are #th in line\n\n* rejected=not on my list\n\
+         pondering sending=here it comes\n",
+    )
+    .unwrap();
+    let mut config = Config::default();
+    config.load_bot_replies(&path).unwrap();
+    assert_eq!(2, config.bot_families.len());
+    assert_eq!(2, config.bot_families[0].rules.len());
+    let mut classifier = BotReplyClassifier::with_default_families();
+    for family in config.bot_families.iter().rev()
+    {
+        classifier.add_family(family.clone());
+    }
+    assert_eq!(
+        Some(BotEvent::QueueUpdate { position: Some(5) }),
+        classifier.classify("Pondering42", "You are 5th in line.")
+    );
+    assert_eq!(
+        Some(BotEvent::Sending),
+        classifier.classify("Pondering42", "Here it comes!")
+    );
+    assert!(matches!(
+        classifier.classify("Bsk", "That is not on my list"),
+        Some(BotEvent::Rejected { .. })
+    ));
+    assert_eq!(None, classifier.classify("Bsk", "You are 5th in line."));
+
+    fs::write(&path, "Pondering later=you are #th in line\n").unwrap();
+    let unknown_kind = Config::default().load_bot_replies(&path);
+    fs::write(&path, "you are #th in line\n").unwrap();
+    let malformed = Config::default().load_bot_replies(&path);
+    fs::remove_file(&path).unwrap();
+    assert!(unknown_kind.is_err());
+    assert!(malformed.is_err());
+    assert!(Config::default().load_bot_replies(&path).is_ok());
+}
+#[test]
+fn config_offline_test()
+{
+    let config = Config::from_args(&args(&["--offline", "--cache-ttl", "600"])).unwrap();
+    assert!(config.offline);
+    assert_eq!(Duration::from_secs(600), config.cache_ttl);
+    assert!(!Config::from_args(&[]).unwrap().offline);
+    assert!(Config::from_args(&args(&["--cache-ttl", "soon"])).is_err());
+}
+#[test]
+fn config_output_format_test()
+{
+    let config = Config::from_args(&args(&["--search", "dune", "--format", "json"])).unwrap();
+    assert_eq!(Some(OutputFormat::Json), config.output_format);
+    assert_eq!(None, Config::from_args(&[]).unwrap().output_format);
+    assert!(Config::from_args(&args(&["--format", "xml"])).is_err());
+    assert!(Config::from_args(&args(&["--format"])).is_err());
+}
+#[test]
+fn config_reading_list_test()
+{
+    let config = Config::from_args(&args(&["--batch", "to-read.csv"])).unwrap();
+    assert_eq!(Some(PathBuf::from("to-read.csv")), config.reading_list);
+    assert_eq!(None, Config::from_args(&[]).unwrap().reading_list);
+    assert!(Config::from_args(&args(&["--batch"])).is_err());
+}
+#[test]
+fn config_watch_test()
+{
+    let config = Config::from_args(&args(&["--watch-add", "Frank Herbert - Dune"])).unwrap();
+    assert_eq!(
+        Some(WatchCommand::Add("Frank Herbert - Dune".to_string())),
+        config.watch_command
+    );
+    let config = Config::from_args(&args(&["--watch-remove", "2"])).unwrap();
+    assert_eq!(
+        Some(WatchCommand::Remove("2".to_string())),
+        config.watch_command
+    );
+    let config = Config::from_args(&args(&[
+        "--daemon",
+        "--watch-interval",
+        "3600",
+        "--search-spacing",
+        "90",
+        "--notify",
+        "notify-send-book",
+    ]))
+    .unwrap();
+    assert!(config.daemon);
+    assert_eq!(None, config.watch_command);
+    assert_eq!(Duration::from_secs(3600), config.watch_interval);
+    assert_eq!(Duration::from_secs(90), config.search_spacing);
+    assert_eq!(Some("notify-send-book".to_string()), config.notify_command);
+    assert!(Config::from_args(&args(&["--notify", " "])).is_err());
+    assert!(Config::from_args(&args(&["--watch-interval", "0"])).is_err());
+}
+#[test]
+fn config_history_test()
+{
+    let config = Config::from_args(&args(&["history"])).unwrap();
+    assert_eq!(Some(HistoryCommand::Show), config.history_command);
+    let config = Config::from_args(&args(&["stats"])).unwrap();
+    assert_eq!(Some(HistoryCommand::Stats), config.history_command);
+    let config = Config::from_args(&args(&["--auto", "--redownload"])).unwrap();
+    assert!(config.redownload);
+    assert_eq!(None, config.history_command);
+    assert!(!Config::from_args(&[]).unwrap().redownload);
+}
+#[test]
+fn config_library_test()
+{
+    let config = Config::from_args(&args(&[
+        "organize",
+        "--library",
+        "books",
+        "--library-template",
+        "{author}/{title}.{ext}",
+        "--dry-run",
+    ]))
+    .unwrap();
+    assert!(config.organize);
+    assert!(config.dry_run);
+    assert_eq!(Some(PathBuf::from("books")), config.library);
+    assert!(Config::from_args(&args(&["organize"])).is_err());
+    assert!(Config::from_args(&args(&["--library-template", "{isbn}"])).is_err());
+}
+#[test]
+fn config_quarantine_test()
+{
+    assert!(!Config::from_args(&args(&[])).unwrap().quarantine);
+    assert!(
+        Config::from_args(&args(&["--quarantine"]))
+            .unwrap()
+            .quarantine
+    );
+}
@@ -0,0 +1,33 @@
+/// CRC-32 (IEEE 802.3), fed in pieces.
+pub struct Crc32
+{
+    value: u32,
+}
+
+impl Default for Crc32
+{
+    fn default() -> Self
+    {
+        Crc32 { value: 0xffff_ffff }
+    }
+}
+
+impl Crc32
+{
+    pub fn update(&mut self, data: &[u8])
+    {
+        for x in data.iter()
+        {
+            self.value ^= *x as u32;
+            for _ in 0..8
+            {
+                let mask = (self.value & 1).wrapping_neg();
+                self.value = (self.value >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+    pub fn finish(&self) -> u32
+    {
+        !self.value
+    }
+}
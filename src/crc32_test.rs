@@ -0,0 +1,11 @@
+use crate::crc32::Crc32;
+
+#[test]
+fn crc32_test()
+{
+    let mut crc = Crc32::default();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(0xcbf4_3926, crc.finish());
+    assert_eq!(0, Crc32::default().finish());
+}
@@ -0,0 +1,191 @@
+use crate::bot_reply::{find_pattern, strip_formatting};
+use crate::config::Timeouts;
+use crate::irc_message::{CtcpMessage, DCCQueryType};
+use crate::tui::report;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+/// One step of a scripted DCC CHAT dialogue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatStep
+{
+    Send(String),
+    /// Waits for a line matching any of the `|` separated patterns, see
+    /// [`find_pattern`](crate::bot_reply::find_pattern).
+    Expect(String),
+    /// Waits for the fserve prompt, which follows a listing or the reply to a command.
+    ExpectPrompt,
+}
+
+#[derive(Debug)]
+pub struct DccChat
+{
+    pub sock: TcpStream,
+    pub reader: BufReader<TcpStream>,
+    /// Every line sent and received, with `> ` marking our own.
+    pub transcript: Vec<String>,
+}
+
+impl DccChat
+{
+    pub fn connect_raw(address: SocketAddr, timeouts: &Timeouts) -> Result<DccChat, &'static str>
+    {
+        report(&format!("Opening DCC CHAT with: {}", address));
+        let sock = match TcpStream::connect_timeout(&address, timeouts.dcc_connect)
+        {
+            Ok(t) => t,
+            Err(_e) => return Err("Unable to connect to DCC CHAT peer"),
+        };
+        if sock.set_read_timeout(Some(timeouts.chunk_idle)).is_err()
+        {
+            return Err("Unable to set DCC CHAT read timeout");
+        }
+        let reader = match sock.try_clone()
+        {
+            Ok(t) => BufReader::new(t),
+            Err(_e) => return Err("Unable to clone DCC CHAT socket"),
+        };
+        Ok(DccChat {
+            sock,
+            reader,
+            transcript: Vec::new(),
+        })
+    }
+    pub fn connect(offer: &CtcpMessage, timeouts: &Timeouts) -> Result<DccChat, &'static str>
+    {
+        match offer
+        {
+            CtcpMessage::DCC {
+                query_type: DCCQueryType::CHAT,
+                ..
+            } => DccChat::connect_raw(offer.get_full_address()?, timeouts),
+            _ => Err("Not a DCC CHAT offer"),
+        }
+    }
+    pub fn send_line(&mut self, line: &str) -> Result<(), &'static str>
+    {
+        self.transcript.push(format!("> {}", line));
+        match self.sock.write_all(format!("{}\n", line).as_bytes())
+        {
+            Ok(_) => Ok(()),
+            Err(_e) => Err("Unable to send DCC CHAT line"),
+        }
+    }
+    /// Returns `None` once the peer closes the chat.
+    pub fn read_line(&mut self) -> Result<Option<String>, &'static str>
+    {
+        let mut buf = String::new();
+        match self.reader.read_line(&mut buf)
+        {
+            Ok(0) => Ok(None),
+            Ok(_) =>
+            {
+                let line = strip_formatting(buf.trim_end_matches(['\r', '\n']));
+                self.transcript.push(line.clone());
+                Ok(Some(line))
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                Err("DCC CHAT peer stopped responding")
+            }
+            Err(_e) => Err("Unable to read DCC CHAT line"),
+        }
+    }
+    /// Reads until a line matches one of the `|` separated patterns and returns that line.
+    pub fn expect(&mut self, patterns: &str, timeout: Duration) -> Result<String, &'static str>
+    {
+        let patterns = patterns
+            .split('|')
+            .map(|x| x.to_lowercase())
+            .collect::<Vec<String>>();
+        self.expect_line(timeout, |line| {
+            let lowercase_line = line.to_lowercase();
+            patterns
+                .iter()
+                .any(|x| find_pattern(x, &lowercase_line).is_some())
+        })
+    }
+    /// Reads until the fserve prompt and returns it.
+    pub fn expect_prompt(&mut self, timeout: Duration) -> Result<String, &'static str>
+    {
+        self.expect_line(timeout, is_fserve_prompt)
+    }
+    fn expect_line(
+        &mut self,
+        timeout: Duration,
+        matches: impl Fn(&str) -> bool,
+    ) -> Result<String, &'static str>
+    {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline
+        {
+            let line = match self.read_line()?
+            {
+                Some(t) => t,
+                None => return Err("DCC CHAT closed before the expected reply"),
+            };
+            if matches(&line)
+            {
+                return Ok(line);
+            }
+        }
+        Err("Timed out waiting for DCC CHAT reply")
+    }
+    pub fn run_script(&mut self, steps: &[ChatStep], timeout: Duration)
+        -> Result<(), &'static str>
+    {
+        for step in steps
+        {
+            match step
+            {
+                ChatStep::Send(line) => self.send_line(line)?,
+                ChatStep::Expect(patterns) =>
+                {
+                    self.expect(patterns, timeout)?;
+                }
+                ChatStep::ExpectPrompt =>
+                {
+                    self.expect_prompt(timeout)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The prompt is a line holding nothing but the current directory in brackets, as in
+/// `[\books\Tolkien]`. Banners and listings may contain brackets too, but not on their own.
+pub fn is_fserve_prompt(line: &str) -> bool
+{
+    let line = line.trim();
+    (line.starts_with("[\\") || line.starts_with("[/"))
+        && line.ends_with(']')
+        && !line[1..line.len() - 1].contains(['[', ']'])
+}
+
+/// Dialogue that asks an fserve for `path`, given relative to the fserve root.
+///
+/// `get` is answered with a sending or queued notice before the file itself arrives as a
+/// normal DCC SEND.
+pub fn fserve_get_script(path: &str) -> Vec<ChatStep>
+{
+    let path = path.replace('\\', "/");
+    let (directory, file_name) = match path.rsplit_once('/')
+    {
+        Some((directory, file_name)) => (directory.trim_matches('/'), file_name),
+        None => ("", path.as_str()),
+    };
+    let mut steps = vec![ChatStep::ExpectPrompt];
+    if !directory.is_empty()
+    {
+        steps.push(ChatStep::Send(format!("cd {}", directory)));
+        steps.push(ChatStep::ExpectPrompt);
+    }
+    steps.push(ChatStep::Send(format!("get {}", file_name)));
+    steps.push(ChatStep::Expect(
+        "sending|queue|will be sent|transfer".to_string(),
+    ));
+    steps.push(ChatStep::Send("quit".to_string()));
+    steps
+}
@@ -0,0 +1,118 @@
+use crate::config::Timeouts;
+use crate::dcc_chat::{fserve_get_script, is_fserve_prompt, ChatStep, DccChat};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+/// Plays a tiny fserve on a local socket and returns the lines it received.
+fn spawn_fserve(
+    replies: Vec<(&'static str, &'static str)>,
+) -> (std::net::SocketAddr, thread::JoinHandle<Vec<String>>)
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(sock.try_clone().unwrap());
+        sock.write_all(b"\x02Welcome\x02 to my fserve [v1.2]\r\n[\\]\r\n")
+            .unwrap();
+        let mut received = Vec::new();
+        loop
+        {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0
+            {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            for (command, reply) in replies.iter()
+            {
+                if line.starts_with(command)
+                {
+                    sock.write_all(reply.as_bytes()).unwrap();
+                }
+            }
+            let is_quit = line == "quit";
+            received.push(line);
+            if is_quit
+            {
+                break;
+            }
+        }
+        received
+    });
+    (address, handle)
+}
+
+fn test_timeouts() -> Timeouts
+{
+    Timeouts {
+        chunk_idle: Duration::from_secs(5),
+        ..Timeouts::default()
+    }
+}
+
+#[test]
+fn fserve_get_script_test()
+{
+    assert_eq!(
+        vec![
+            ChatStep::ExpectPrompt,
+            ChatStep::Send("cd books/Tolkien".to_string()),
+            ChatStep::ExpectPrompt,
+            ChatStep::Send("get The Hobbit.epub".to_string()),
+            ChatStep::Expect("sending|queue|will be sent|transfer".to_string()),
+            ChatStep::Send("quit".to_string()),
+        ],
+        fserve_get_script("\\books\\Tolkien\\The Hobbit.epub")
+    );
+    assert_eq!(
+        ChatStep::Send("get book.epub".to_string()),
+        fserve_get_script("book.epub")[1]
+    );
+}
+#[test]
+fn is_fserve_prompt_test()
+{
+    assert!(is_fserve_prompt("[\\]"));
+    assert!(is_fserve_prompt("[\\books\\Tolkien]"));
+    assert!(is_fserve_prompt(" [/books] "));
+    assert!(!is_fserve_prompt("Welcome to my fserve [v1.2]"));
+    assert!(!is_fserve_prompt("[1.2MB] The Hobbit.epub"));
+    assert!(!is_fserve_prompt("[\\books] has 3 files [end]"));
+}
+#[test]
+fn dcc_chat_runs_fserve_dialogue_test()
+{
+    let (address, handle) = spawn_fserve(vec![
+        ("cd ", "[\\books]\r\n"),
+        ("get ", "Sending The Hobbit.epub, 1.2MB\r\n"),
+    ]);
+    let mut chat = DccChat::connect_raw(address, &test_timeouts()).unwrap();
+    chat.run_script(
+        &fserve_get_script("books/The Hobbit.epub"),
+        Duration::from_secs(5),
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec!["cd books", "get The Hobbit.epub", "quit"],
+        handle.join().unwrap()
+    );
+    assert!(chat
+        .transcript
+        .contains(&"Welcome to my fserve [v1.2]".to_string()));
+    assert!(chat
+        .transcript
+        .contains(&"> get The Hobbit.epub".to_string()));
+}
+#[test]
+fn dcc_chat_fails_when_peer_closes_test()
+{
+    let (address, handle) = spawn_fserve(vec![]);
+    let mut chat = DccChat::connect_raw(address, &test_timeouts()).unwrap();
+    chat.send_line("quit").unwrap();
+    handle.join().unwrap();
+    assert!(chat.expect("never said", Duration::from_secs(5)).is_err());
+}
@@ -0,0 +1,262 @@
+use crate::irc_message::{CtcpMessage, DCCQueryType};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The largest search result list accepted, whatever size the bot advertises.
+pub const MAX_SEARCH_RESULT_SIZE: u64 = 20 * 1024 * 1024;
+/// The largest pack accepted, whatever size the bot advertises.
+pub const MAX_PACK_SIZE: u64 = 500 * 1024 * 1024;
+const SEARCH_RESULT_EXTENSIONS: [&str; 2] = ["zip", "txt"];
+const BOOK_EXTENSIONS: [&str; 16] = [
+    "epub", "mobi", "azw3", "azw", "pdf", "txt", "rtf", "doc", "docx", "lit", "fb2", "djvu", "cbr",
+    "cbz", "rar", "zip",
+];
+
+#[derive(Debug, Clone)]
+pub struct DccPolicy
+{
+    /// Nicknames allowed to send us files, empty means anyone.
+    pub trusted_senders: Vec<String>,
+    /// Offers whose filename does not match this are not accepted automatically.
+    pub expected_file: Option<String>,
+    pub block_private_addresses: bool,
+    pub max_file_size: Option<u64>,
+    /// Lowercase extensions without the dot, empty means any extension.
+    pub allowed_extensions: Vec<String>,
+}
+
+/// Accepted and prompted offers carry the address that passed the checks, which is the one to
+/// connect to. Resolving the offer's host again could give a different answer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DccDecision
+{
+    Accept
+    {
+        address: SocketAddr
+    },
+    Prompt
+    {
+        reason: &'static str,
+        address: SocketAddr,
+    },
+    Reject
+    {
+        reason: &'static str
+    },
+}
+
+impl DccPolicy
+{
+    pub fn new() -> Self
+    {
+        DccPolicy {
+            trusted_senders: Vec::new(),
+            expected_file: None,
+            block_private_addresses: true,
+            max_file_size: None,
+            allowed_extensions: Vec::new(),
+        }
+    }
+    /// Policy for the result list `search_bot` sends back for `query`.
+    pub fn for_search(search_bot: &str, query: &str) -> Self
+    {
+        DccPolicy {
+            trusted_senders: vec![search_bot.to_string()],
+            expected_file: Some(query.to_string()),
+            max_file_size: Some(MAX_SEARCH_RESULT_SIZE),
+            allowed_extensions: SEARCH_RESULT_EXTENSIONS
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            ..DccPolicy::new()
+        }
+    }
+    /// Policy for a file requested from `bot` with the `!bot <file>` trigger.
+    pub fn for_pack(bot: &str, requested_file: &str) -> Self
+    {
+        DccPolicy {
+            trusted_senders: vec![bot.to_string()],
+            expected_file: Some(requested_file.to_string()),
+            max_file_size: Some(MAX_PACK_SIZE),
+            allowed_extensions: BOOK_EXTENSIONS.iter().map(|x| x.to_string()).collect(),
+            ..DccPolicy::new()
+        }
+    }
+    /// Whether offers and replies from `sender` are about what this policy is waiting for.
+    pub fn trusts(&self, sender: &str) -> bool
+    {
+        self.trusted_senders.is_empty()
+            || self
+                .trusted_senders
+                .iter()
+                .any(|x| x.eq_ignore_ascii_case(sender))
+    }
+    pub fn evaluate(&self, sender: &str, offer: &CtcpMessage) -> DccDecision
+    {
+        let (argument, size) = match offer
+        {
+            CtcpMessage::DCC {
+                query_type: DCCQueryType::SEND,
+                argument,
+                size,
+                ..
+            } => (argument, size),
+            _ =>
+            {
+                return DccDecision::Reject {
+                    reason: "not a DCC SEND offer",
+                }
+            }
+        };
+
+        if !self.trusts(sender)
+        {
+            return DccDecision::Reject {
+                reason: "unsolicited offer from an untrusted sender",
+            };
+        }
+
+        let address = match offer.get_full_address()
+        {
+            Ok(t) => t,
+            Err(_e) =>
+            {
+                return DccDecision::Reject {
+                    reason: "unusable DCC address",
+                }
+            }
+        };
+        if self.block_private_addresses && !is_public_address(&address.ip())
+        {
+            return DccDecision::Reject {
+                reason: "offer points at a private, loopback or link-local address",
+            };
+        }
+
+        if let (Some(max_file_size), Some(size)) = (self.max_file_size, size)
+        {
+            if *size > max_file_size
+            {
+                return DccDecision::Reject {
+                    reason: "file is larger than the allowed maximum",
+                };
+            }
+        }
+
+        if !self.allowed_extensions.is_empty()
+        {
+            let extension = match argument.rsplit_once('.')
+            {
+                Some((_, extension)) => extension.to_lowercase(),
+                None => String::new(),
+            };
+            if !self.allowed_extensions.contains(&extension)
+            {
+                return DccDecision::Reject {
+                    reason: "file extension is not allowed",
+                };
+            }
+        }
+
+        match &self.expected_file
+        {
+            Some(expected_file) if !filenames_match(expected_file, argument) =>
+            {
+                DccDecision::Prompt {
+                    reason: "filename does not match the requested file",
+                    address,
+                }
+            }
+            _ => DccDecision::Accept { address },
+        }
+    }
+}
+
+/// Whether `offered` names the expected file, ignoring case, punctuation and whitespace since
+/// bots and the sanitizer both substitute `_` for spaces. The offered name must contain the
+/// whole expected name without its extension, and keep the extension if one was expected.
+pub fn filenames_match(expected: &str, offered: &str) -> bool
+{
+    let (expected_stem, expected_extension) = split_extension(expected);
+    let (offered_stem, offered_extension) = split_extension(offered);
+    let expected_stem = normalize_filename(expected_stem);
+    let offered_stem = normalize_filename(offered_stem);
+    let extension_matches = match expected_extension
+    {
+        Some(t) => offered_extension.is_some_and(|x| x.eq_ignore_ascii_case(t)),
+        None => true,
+    };
+    !expected_stem.is_empty() && offered_stem.contains(&expected_stem) && extension_matches
+}
+
+/// Whether a bot's message names `file_name`, with or without its extension.
+pub fn mentions_file(text: &str, file_name: &str) -> bool
+{
+    let stem = normalize_filename(split_extension(file_name).0);
+    !stem.is_empty() && normalize_filename(text).contains(&stem)
+}
+
+/// Splits off a short extension like `epub`, leaving names such as `J.R.R. Tolkien` whole.
+fn split_extension(file_name: &str) -> (&str, Option<&str>)
+{
+    match file_name.rsplit_once('.')
+    {
+        Some((stem, extension))
+            if !extension.is_empty()
+                && extension.len() <= 4
+                && extension.chars().all(|x| x.is_ascii_alphanumeric()) =>
+        {
+            (stem, Some(extension))
+        }
+        _ => (file_name, None),
+    }
+}
+
+fn normalize_filename(file_name: &str) -> String
+{
+    file_name
+        .chars()
+        .filter(|x| x.is_alphanumeric())
+        .flat_map(|x| x.to_lowercase())
+        .collect::<String>()
+}
+
+pub fn is_public_address(address: &IpAddr) -> bool
+{
+    match address
+    {
+        IpAddr::V4(v4) => is_public_ipv4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped()
+        {
+            Some(v4) => is_public_ipv4(&v4),
+            None => is_public_ipv6(v6),
+        },
+    }
+}
+
+fn is_public_ipv4(address: &Ipv4Addr) -> bool
+{
+    let octets = address.octets();
+    // 100.64.0.0/10 carrier-grade NAT
+    let is_shared = octets[0] == 100 && (octets[1] & 0b1100_0000) == 64;
+    !(address.is_private()
+        || address.is_loopback()
+        || address.is_link_local()
+        || address.is_unspecified()
+        || address.is_broadcast()
+        || address.is_multicast()
+        || address.is_documentation()
+        || is_shared
+        || octets[0] == 0)
+}
+
+fn is_public_ipv6(address: &Ipv6Addr) -> bool
+{
+    let first_segment = address.segments()[0];
+    let is_unique_local = (first_segment & 0xfe00) == 0xfc00;
+    let is_link_local = (first_segment & 0xffc0) == 0xfe80;
+    !(address.is_loopback()
+        || address.is_unspecified()
+        || address.is_multicast()
+        || is_unique_local
+        || is_link_local)
+}
@@ -0,0 +1,168 @@
+use crate::dcc_policy::{
+    filenames_match, is_public_address, mentions_file, DccDecision, DccPolicy,
+};
+use crate::irc_message::{CtcpMessage, DCCQueryType};
+use std::net::{IpAddr, SocketAddr};
+
+fn offer(argument: &str, address: &str, size: Option<u64>) -> CtcpMessage
+{
+    CtcpMessage::DCC {
+        query_type: DCCQueryType::SEND,
+        argument: argument.to_string(),
+        address: address.to_string(),
+        port: "5000".to_string(),
+        size,
+        token: None,
+    }
+}
+
+// 93.184.216.34
+const PUBLIC_ADDRESS: &str = "1572395042";
+
+#[test]
+fn pack_policy_accepts_requested_file_from_bot_test()
+{
+    let policy = DccPolicy::for_pack("Bsk", "Tolkien, J.R.R. - The Hobbit.epub");
+    // The address to connect to is the one that was checked
+    assert_eq!(
+        DccDecision::Accept {
+            address: "93.184.216.34:5000".parse::<SocketAddr>().unwrap()
+        },
+        policy.evaluate(
+            "bsk",
+            &offer(
+                "Tolkien_J.R.R._-_The_Hobbit.epub",
+                PUBLIC_ADDRESS,
+                Some(1000)
+            )
+        )
+    );
+}
+#[test]
+fn pack_policy_rejects_unsolicited_sender_test()
+{
+    let policy = DccPolicy::for_pack("Bsk", "The Hobbit.epub");
+    assert!(matches!(
+        policy.evaluate("Stranger", &offer("The Hobbit.epub", PUBLIC_ADDRESS, None)),
+        DccDecision::Reject { .. }
+    ));
+    assert!(policy.trusts("bsk"));
+    assert!(!policy.trusts("Stranger"));
+    assert!(DccPolicy::new().trusts("Stranger"));
+}
+#[test]
+fn pack_policy_prompts_on_filename_mismatch_test()
+{
+    let policy = DccPolicy::for_pack("Bsk", "The Hobbit.epub");
+    assert!(matches!(
+        policy.evaluate("Bsk", &offer("Something Else.epub", PUBLIC_ADDRESS, None)),
+        DccDecision::Prompt { .. }
+    ));
+}
+#[test]
+fn policy_rejects_private_addresses_test()
+{
+    let policy = DccPolicy::for_pack("Bsk", "The Hobbit.epub");
+    // 192.168.1.1, 127.0.0.1, 169.254.0.1
+    for address in [
+        "3232235777",
+        "2130706433",
+        "2851995649",
+        "::1",
+        "fe80::1",
+        "fd00::1",
+    ]
+    {
+        assert!(matches!(
+            policy.evaluate("Bsk", &offer("The Hobbit.epub", address, None)),
+            DccDecision::Reject { .. }
+        ));
+    }
+    let permissive = DccPolicy {
+        block_private_addresses: false,
+        ..policy
+    };
+    assert!(matches!(
+        permissive.evaluate("Bsk", &offer("The Hobbit.epub", "3232235777", None)),
+        DccDecision::Accept { .. }
+    ));
+}
+#[test]
+fn policy_enforces_size_and_extension_test()
+{
+    let policy = DccPolicy::for_pack("Bsk", "The Hobbit.epub");
+    assert!(matches!(
+        policy.evaluate(
+            "Bsk",
+            &offer("The Hobbit.epub", PUBLIC_ADDRESS, Some(u64::MAX))
+        ),
+        DccDecision::Reject { .. }
+    ));
+    assert!(matches!(
+        policy.evaluate("Bsk", &offer("The Hobbit.exe", PUBLIC_ADDRESS, None)),
+        DccDecision::Reject { .. }
+    ));
+}
+#[test]
+fn search_policy_accepts_result_list_test()
+{
+    let policy = DccPolicy::for_search("Search", "the hobbit");
+    let list = offer(
+        "SearchBot_results_for__the_hobbit.txt.zip",
+        PUBLIC_ADDRESS,
+        Some(2048),
+    );
+    assert!(matches!(
+        policy.evaluate("Search", &list),
+        DccDecision::Accept { .. }
+    ));
+    // Only the bot the search was sent to may answer it
+    assert!(matches!(
+        policy.evaluate("Stranger", &list),
+        DccDecision::Reject { .. }
+    ));
+}
+#[test]
+fn filenames_match_test()
+{
+    assert!(filenames_match(
+        "Some Book - Author.epub",
+        "Some_Book_-_Author.epub"
+    ));
+    assert!(filenames_match(
+        "the hobbit",
+        "SearchBot_results_for__the_hobbit.txt.zip"
+    ));
+    assert!(!filenames_match("Some Book.epub", "Other Book.epub"));
+    assert!(!filenames_match("", "Other Book.epub"));
+    // Part of the expected name, or the same name in another format, is not enough
+    assert!(!filenames_match("The Hobbit.epub", "epub"));
+    assert!(!filenames_match("The Hobbit.epub", "bit.epub"));
+    assert!(!filenames_match("The Hobbit.epub", "Hobbit.epub"));
+    assert!(!filenames_match("The Hobbit.epub", "The Hobbit.exe"));
+    assert!(!filenames_match("The Hobbit.epub", ".epub"));
+}
+#[test]
+fn mentions_file_test()
+{
+    assert!(mentions_file(
+        "Sending you Tolkien - The Hobbit.epub now",
+        "Tolkien - The Hobbit.epub"
+    ));
+    assert!(!mentions_file("Sending you bit.epub", "The Hobbit.epub"));
+}
+#[test]
+fn is_public_address_test()
+{
+    assert!(is_public_address(
+        &"93.184.216.34".parse::<IpAddr>().unwrap()
+    ));
+    assert!(is_public_address(
+        &"2606:4700::1".parse::<IpAddr>().unwrap()
+    ));
+    assert!(!is_public_address(&"10.1.2.3".parse::<IpAddr>().unwrap()));
+    assert!(!is_public_address(&"100.64.0.1".parse::<IpAddr>().unwrap()));
+    assert!(!is_public_address(
+        &"::ffff:192.168.0.1".parse::<IpAddr>().unwrap()
+    ));
+}
@@ -0,0 +1,407 @@
+use crate::dcc_policy::{mentions_file, DccDecision, DccPolicy};
+use crate::irc_message::CtcpMessage;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub const QUEUE_FILE_NAME: &str = ".rs-book-downloader-queue";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadState
+{
+    Queued,
+    Requested,
+    Transferring,
+    Completed,
+    Failed,
+}
+
+impl DownloadState
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            DownloadState::Queued => "queued",
+            DownloadState::Requested => "requested",
+            DownloadState::Transferring => "transferring",
+            DownloadState::Completed => "completed",
+            DownloadState::Failed => "failed",
+        }
+    }
+    fn from_str(value: &str) -> Result<DownloadState, &'static str>
+    {
+        match value
+        {
+            "queued" => Ok(DownloadState::Queued),
+            "requested" => Ok(DownloadState::Requested),
+            "transferring" => Ok(DownloadState::Transferring),
+            "completed" => Ok(DownloadState::Completed),
+            "failed" => Ok(DownloadState::Failed),
+            _ => Err("Unknown download state in queue file"),
+        }
+    }
+    pub fn is_in_flight(&self) -> bool
+    {
+        matches!(self, DownloadState::Requested | DownloadState::Transferring)
+    }
+}
+
+/// Another copy of the same book, tried when the current one fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alternate
+{
+    pub bot_source: String,
+    pub request_line: String,
+    pub requested_file: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadRequest
+{
+    pub bot_source: String,
+    pub request_line: String,
+    pub requested_file: String,
+    pub state: DownloadState,
+    /// When the request line was last sent, or the bot last confirmed it.
+    pub requested_at: Option<Instant>,
+    /// Untried copies, best first.
+    pub alternates: Vec<Alternate>,
+}
+
+#[derive(Debug)]
+pub struct DownloadQueue
+{
+    pub requests: Vec<DownloadRequest>,
+    pub default_slot_limit: usize,
+    slot_limits: HashMap<String, usize>,
+    queue_path: Option<PathBuf>,
+}
+
+impl DownloadQueue
+{
+    pub fn new(queue_path: Option<PathBuf>) -> Self
+    {
+        DownloadQueue {
+            requests: Vec::new(),
+            default_slot_limit: 1,
+            slot_limits: HashMap::new(),
+            queue_path,
+        }
+    }
+    /// Loads a persisted queue. Requests that were in flight when the previous session ended are
+    /// queued again, since the bot has long forgotten about them.
+    pub fn load(queue_path: PathBuf) -> Result<Self, &'static str>
+    {
+        let mut queue = DownloadQueue::new(Some(queue_path.clone()));
+        let contents = match fs::read_to_string(&queue_path)
+        {
+            Ok(t) => t,
+            Err(_e) => return Ok(queue),
+        };
+        for line in contents.lines().filter(|x| !x.trim().is_empty())
+        {
+            // state, bot, file and request line, then bot, file and request line per alternate
+            let fields = line.split('\t').map(unescape).collect::<Vec<String>>();
+            if fields.len() < 4 || (fields.len() - 4) % 3 != 0
+            {
+                return Err("Malformed line in queue file");
+            }
+            let mut state = DownloadState::from_str(&fields[0])?;
+            if state.is_in_flight()
+            {
+                state = DownloadState::Queued;
+            }
+            queue.requests.push(DownloadRequest {
+                state,
+                bot_source: fields[1].clone(),
+                requested_file: fields[2].clone(),
+                request_line: fields[3].clone(),
+                requested_at: None,
+                alternates: fields[4..]
+                    .chunks(3)
+                    .map(|x| Alternate {
+                        bot_source: x[0].clone(),
+                        requested_file: x[1].clone(),
+                        request_line: x[2].clone(),
+                    })
+                    .collect(),
+            });
+        }
+        Ok(queue)
+    }
+    pub fn save(&self) -> Result<(), &'static str>
+    {
+        let queue_path = match &self.queue_path
+        {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let contents = self
+            .requests
+            .iter()
+            .map(|x| {
+                let mut line = format!(
+                    "{}\t{}\t{}\t{}",
+                    x.state.as_str(),
+                    escape(&x.bot_source),
+                    escape(&x.requested_file),
+                    escape(&x.request_line)
+                );
+                for alternate in x.alternates.iter()
+                {
+                    line.push_str(&format!(
+                        "\t{}\t{}\t{}",
+                        escape(&alternate.bot_source),
+                        escape(&alternate.requested_file),
+                        escape(&alternate.request_line)
+                    ));
+                }
+                line.push('\n');
+                line
+            })
+            .collect::<String>();
+        match fs::write(queue_path, contents)
+        {
+            Ok(_) => Ok(()),
+            Err(_e) => Err("Unable to write queue file"),
+        }
+    }
+    pub fn push(&mut self, bot_source: &str, request_line: &str, requested_file: &str) -> usize
+    {
+        self.requests.push(DownloadRequest {
+            bot_source: bot_source.to_string(),
+            request_line: request_line.to_string(),
+            requested_file: requested_file.to_string(),
+            state: DownloadState::Queued,
+            requested_at: None,
+            alternates: Vec::new(),
+        });
+        self.requests.len() - 1
+    }
+    pub fn add_alternate(
+        &mut self,
+        id: usize,
+        bot_source: &str,
+        request_line: &str,
+        requested_file: &str,
+    )
+    {
+        if let Some(request) = self.requests.get_mut(id)
+        {
+            request.alternates.push(Alternate {
+                bot_source: bot_source.to_string(),
+                request_line: request_line.to_string(),
+                requested_file: requested_file.to_string(),
+            });
+        }
+    }
+    /// Overrides how many requests may be outstanding with `bot` at once.
+    pub fn set_slot_limit(&mut self, bot: &str, limit: usize)
+    {
+        self.slot_limits.insert(bot.to_lowercase(), limit);
+    }
+    pub fn slot_limit(&self, bot: &str) -> usize
+    {
+        *self
+            .slot_limits
+            .get(&bot.to_lowercase())
+            .unwrap_or(&self.default_slot_limit)
+    }
+    pub fn in_flight_count(&self, bot: &str) -> usize
+    {
+        self.requests
+            .iter()
+            .filter(|x| x.state.is_in_flight() && x.bot_source.eq_ignore_ascii_case(bot))
+            .count()
+    }
+    /// Marks as many queued requests as the bots' free slots allow as requested, and returns
+    /// their ids so the caller can send the request lines.
+    pub fn start_next(&mut self) -> Vec<usize>
+    {
+        let mut started = Vec::new();
+        for id in 0..self.requests.len()
+        {
+            if self.requests[id].state != DownloadState::Queued
+            {
+                continue;
+            }
+            let bot = self.requests[id].bot_source.clone();
+            if self.in_flight_count(&bot) < self.slot_limit(&bot)
+            {
+                self.requests[id].state = DownloadState::Requested;
+                self.requests[id].requested_at = Some(Instant::now());
+                started.push(id);
+            }
+        }
+        started
+    }
+    /// Finds the outstanding request a DCC offer answers. Returns the matching request together
+    /// with the policy decision, preferring requests that are accepted outright.
+    pub fn match_offer(&self, sender: &str, offer: &CtcpMessage) -> (Option<usize>, DccDecision)
+    {
+        let mut best: (Option<usize>, DccDecision) = (
+            None,
+            DccDecision::Reject {
+                reason: "no outstanding request from this sender",
+            },
+        );
+        for (id, request) in self.requests.iter().enumerate()
+        {
+            if request.state != DownloadState::Requested
+            {
+                continue;
+            }
+            let policy = DccPolicy::for_pack(&request.bot_source, &request.requested_file);
+            match policy.evaluate(sender, offer)
+            {
+                decision @ DccDecision::Accept { .. } => return (Some(id), decision),
+                DccDecision::Prompt { reason, address } if best.0.is_none() =>
+                {
+                    best = (Some(id), DccDecision::Prompt { reason, address });
+                }
+                DccDecision::Reject { reason } if best.0.is_none() =>
+                {
+                    best = (None, DccDecision::Reject { reason });
+                }
+                _ =>
+                {}
+            }
+        }
+        best
+    }
+    /// Finds which outstanding request from `bot` a NOTICE or PRIVMSG is about. A reply that
+    /// does not mention a filename only counts when `bot` has a single outstanding request, as
+    /// there is no telling which of several it means.
+    pub fn find_request_for_reply(&self, bot: &str, text: &str) -> Option<usize>
+    {
+        let outstanding = (0..self.requests.len())
+            .filter(|x| {
+                self.requests[*x].state == DownloadState::Requested
+                    && self.requests[*x].bot_source.eq_ignore_ascii_case(bot)
+            })
+            .collect::<Vec<usize>>();
+        let mentioned = outstanding
+            .iter()
+            .find(|x| mentions_file(text, &self.requests[**x].requested_file))
+            .copied();
+        match outstanding.as_slice()
+        {
+            [only] => mentioned.or(Some(*only)),
+            _ => mentioned,
+        }
+    }
+    pub fn touch(&mut self, id: usize)
+    {
+        if let Some(request) = self.requests.get_mut(id)
+        {
+            request.requested_at = Some(Instant::now());
+        }
+    }
+    /// Requests whose bot has not offered the file within `timeout`. The caller gives up on
+    /// each with [`DownloadQueue::fail`].
+    pub fn expired_requests(&self, timeout: Duration) -> Vec<usize>
+    {
+        (0..self.requests.len())
+            .filter(|x| {
+                self.requests[*x].state == DownloadState::Requested
+                    && self.requests[*x]
+                        .requested_at
+                        .is_some_and(|requested_at| requested_at.elapsed() >= timeout)
+            })
+            .collect()
+    }
+    pub fn set_state(&mut self, id: usize, state: DownloadState) -> Result<(), &'static str>
+    {
+        match self.requests.get_mut(id)
+        {
+            Some(request) => request.state = state,
+            None => return Err("Unknown download request"),
+        }
+        self.save()
+    }
+    /// Marks a request failed, or queues its next alternate copy in its place. Returns whether
+    /// an alternate was queued.
+    pub fn fail(&mut self, id: usize) -> Result<bool, &'static str>
+    {
+        if id >= self.requests.len()
+        {
+            return Err("Unknown download request");
+        }
+        let fell_back = self.fall_back(id);
+        self.save()?;
+        Ok(fell_back)
+    }
+    fn fall_back(&mut self, id: usize) -> bool
+    {
+        let request = &mut self.requests[id];
+        if request.alternates.is_empty()
+        {
+            request.state = DownloadState::Failed;
+            return false;
+        }
+        let alternate = request.alternates.remove(0);
+        request.bot_source = alternate.bot_source;
+        request.request_line = alternate.request_line;
+        request.requested_file = alternate.requested_file;
+        request.state = DownloadState::Queued;
+        request.requested_at = None;
+        true
+    }
+    pub fn has_pending(&self) -> bool
+    {
+        self.requests
+            .iter()
+            .any(|x| x.state == DownloadState::Queued || x.state.is_in_flight())
+    }
+    /// Drops finished requests so the persisted queue only holds outstanding work.
+    pub fn remove_finished(&mut self) -> Result<(), &'static str>
+    {
+        self.requests
+            .retain(|x| x.state != DownloadState::Completed && x.state != DownloadState::Failed);
+        self.save()
+    }
+}
+
+/// Tabs separate the fields of a queue line and line breaks separate requests, so both are
+/// written as backslash escapes, as is the backslash itself. The watchlist is kept the same way.
+pub fn escape(field: &str) -> String
+{
+    let mut escaped = String::new();
+    for x in field.chars()
+    {
+        match x
+        {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(x),
+        }
+    }
+    escaped
+}
+
+pub fn unescape(field: &str) -> String
+{
+    let mut unescaped = String::new();
+    let mut chars = field.chars();
+    while let Some(x) = chars.next()
+    {
+        if x != '\\'
+        {
+            unescaped.push(x);
+            continue;
+        }
+        match chars.next()
+        {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
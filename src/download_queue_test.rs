@@ -0,0 +1,217 @@
+use crate::dcc_policy::DccDecision;
+use crate::download_queue::{DownloadQueue, DownloadState};
+use crate::irc_message::{CtcpMessage, DCCQueryType};
+use std::fs;
+
+fn offer(argument: &str) -> CtcpMessage
+{
+    CtcpMessage::DCC {
+        query_type: DCCQueryType::SEND,
+        argument: argument.to_string(),
+        // 93.184.216.34
+        address: "1572395042".to_string(),
+        port: "5000".to_string(),
+        size: None,
+        token: None,
+    }
+}
+
+#[test]
+fn queue_respects_slot_limits_test()
+{
+    let mut queue = DownloadQueue::new(None);
+    queue.push("Bsk", "!Bsk A.epub", "A.epub");
+    queue.push("Bsk", "!Bsk B.epub", "B.epub");
+    queue.push("DV8", "!DV8 C.epub", "C.epub");
+
+    // One slot per bot, so the two bots transfer in parallel
+    assert_eq!(vec![0, 2], queue.start_next());
+    assert!(queue.start_next().is_empty());
+
+    queue.set_state(0, DownloadState::Completed).unwrap();
+    assert_eq!(vec![1], queue.start_next());
+}
+#[test]
+fn queue_custom_slot_limit_test()
+{
+    let mut queue = DownloadQueue::new(None);
+    queue.set_slot_limit("bsk", 2);
+    queue.push("Bsk", "!Bsk A.epub", "A.epub");
+    queue.push("Bsk", "!Bsk B.epub", "B.epub");
+    queue.push("Bsk", "!Bsk C.epub", "C.epub");
+    assert_eq!(vec![0, 1], queue.start_next());
+}
+#[test]
+fn queue_matches_offer_to_request_test()
+{
+    let mut queue = DownloadQueue::new(None);
+    queue.set_slot_limit("Bsk", 2);
+    queue.push("Bsk", "!Bsk A Book.epub", "A Book.epub");
+    queue.push("Bsk", "!Bsk Other Book.epub", "Other Book.epub");
+    queue.push("DV8", "!DV8 Third Book.epub", "Third Book.epub");
+    queue.start_next();
+
+    let (id, decision) = queue.match_offer("Bsk", &offer("Other_Book.epub"));
+    assert_eq!(Some(1), id);
+    assert!(matches!(decision, DccDecision::Accept { .. }));
+
+    let (id, decision) = queue.match_offer("Stranger", &offer("Other_Book.epub"));
+    assert_eq!(None, id);
+    assert!(matches!(decision, DccDecision::Reject { .. }));
+
+    let (id, decision) = queue.match_offer("DV8", &offer("Unexpected.epub"));
+    assert_eq!(Some(2), id);
+    assert!(matches!(decision, DccDecision::Prompt { .. }));
+}
+#[test]
+fn queue_matches_reply_to_request_test()
+{
+    let mut queue = DownloadQueue::new(None);
+    queue.set_slot_limit("Bsk", 2);
+    queue.push("Bsk", "!Bsk A Book.epub", "A Book.epub");
+    queue.push("Bsk", "!Bsk Other Book.epub", "Other Book.epub");
+    queue.start_next();
+
+    let reply = "Other Book.epub is queued at position 3";
+    assert_eq!(Some(1), queue.find_request_for_reply("Bsk", reply));
+    // Someone we asked nothing of, and a reply that could be about either request
+    assert_eq!(None, queue.find_request_for_reply("Stranger", reply));
+    assert_eq!(None, queue.find_request_for_reply("Bsk", "Access denied"));
+
+    queue.set_state(0, DownloadState::Completed).unwrap();
+    assert_eq!(
+        Some(1),
+        queue.find_request_for_reply("Bsk", "Access denied")
+    );
+}
+#[test]
+fn queue_persists_across_sessions_test()
+{
+    let queue_path = std::env::temp_dir().join(format!("rsbd_queue_{}", std::process::id()));
+    let mut queue = DownloadQueue::new(Some(queue_path.clone()));
+    queue.push("Bsk", "!Bsk A.epub ::INFO:: 1.2MB", "A.epub");
+    queue.push("DV8", "!DV8 B.epub", "B.epub");
+    queue.push("DV8", "!DV8 C.epub", "C.epub");
+    queue.start_next();
+    queue.set_state(2, DownloadState::Completed).unwrap();
+
+    let loaded = DownloadQueue::load(queue_path.clone()).unwrap();
+    assert_eq!(3, loaded.requests.len());
+    assert_eq!(
+        "!Bsk A.epub ::INFO:: 1.2MB",
+        loaded.requests[0].request_line
+    );
+    // Requests in flight when the session ended are queued again
+    assert_eq!(DownloadState::Queued, loaded.requests[0].state);
+    assert_eq!(DownloadState::Queued, loaded.requests[1].state);
+    assert_eq!(DownloadState::Completed, loaded.requests[2].state);
+
+    let mut loaded = loaded;
+    loaded.remove_finished().unwrap();
+    assert_eq!(
+        2,
+        DownloadQueue::load(queue_path.clone())
+            .unwrap()
+            .requests
+            .len()
+    );
+
+    fs::remove_file(&queue_path).unwrap();
+}
+#[test]
+fn queue_persists_tabs_and_line_breaks_test()
+{
+    let queue_path = std::env::temp_dir().join(format!("rsbd_queue_tabs_{}", std::process::id()));
+    let mut queue = DownloadQueue::new(Some(queue_path.clone()));
+    let id = queue.push("Bsk", "!Bsk A\tBook.epub", "A\tBook.epub");
+    queue.add_alternate(id, "DV8", "!DV8 C:\\Books\\B\nook.epub", "B\r\nook.epub");
+    queue.save().unwrap();
+
+    let loaded = DownloadQueue::load(queue_path.clone()).unwrap();
+    fs::remove_file(&queue_path).unwrap();
+    assert_eq!(1, loaded.requests.len());
+    assert_eq!("!Bsk A\tBook.epub", loaded.requests[0].request_line);
+    assert_eq!("A\tBook.epub", loaded.requests[0].requested_file);
+    assert_eq!(
+        "!DV8 C:\\Books\\B\nook.epub",
+        loaded.requests[0].alternates[0].request_line
+    );
+    assert_eq!(
+        "B\r\nook.epub",
+        loaded.requests[0].alternates[0].requested_file
+    );
+}
+#[test]
+fn queue_load_missing_file_test()
+{
+    let queue_path = std::env::temp_dir().join("rsbd_queue_does_not_exist");
+    let queue = DownloadQueue::load(queue_path).unwrap();
+    assert!(!queue.has_pending());
+}
+#[test]
+fn queue_expires_unanswered_requests_test()
+{
+    let mut queue = DownloadQueue::new(None);
+    queue.push("Bsk", "!Bsk A.epub", "A.epub");
+    queue.push("DV8", "!DV8 B.epub", "B.epub");
+    queue.start_next();
+    queue.set_state(1, DownloadState::Transferring).unwrap();
+
+    assert!(queue
+        .expired_requests(std::time::Duration::from_secs(3600))
+        .is_empty());
+    // Transfers in progress are governed by the idle timeout instead
+    assert_eq!(vec![0], queue.expired_requests(std::time::Duration::ZERO));
+    assert!(!queue.fail(0).unwrap());
+    assert_eq!(DownloadState::Failed, queue.requests[0].state);
+    assert_eq!(DownloadState::Transferring, queue.requests[1].state);
+}
+#[test]
+fn queue_falls_back_to_alternates_test()
+{
+    let mut queue = DownloadQueue::new(None);
+    let id = queue.push("Bsk", "!Bsk Dune.epub", "Dune.epub");
+    queue.add_alternate(id, "DV8", "!DV8 Dune.epub", "Dune.epub");
+    queue.start_next();
+
+    assert!(queue.fail(id).unwrap());
+    assert_eq!(DownloadState::Queued, queue.requests[id].state);
+    assert_eq!("DV8", queue.requests[id].bot_source);
+    assert_eq!("!DV8 Dune.epub", queue.requests[id].request_line);
+
+    // An expired request moves on the same way, and fails once no copies are left
+    queue.start_next();
+    queue.add_alternate(id, "Oatmeal", "!Oatmeal Dune.epub", "Dune.epub");
+    assert_eq!(vec![id], queue.expired_requests(std::time::Duration::ZERO));
+    assert!(queue.fail(id).unwrap());
+    assert_eq!("Oatmeal", queue.requests[id].bot_source);
+    assert!(!queue.fail(id).unwrap());
+    assert_eq!(DownloadState::Failed, queue.requests[id].state);
+    assert!(queue.fail(5).is_err());
+}
+#[test]
+fn queue_persists_alternates_test()
+{
+    let queue_path =
+        std::env::temp_dir().join(format!("rsbd_queue_alternates_{}", std::process::id()));
+    let mut queue = DownloadQueue::new(Some(queue_path.clone()));
+    let id = queue.push("Bsk", "!Bsk Dune.epub", "Dune.epub");
+    queue.add_alternate(id, "DV8", "!DV8 Herbert - Dune.epub", "Herbert - Dune.epub");
+    queue.push("Bsk", "!Bsk Emma.epub", "Emma.epub");
+    queue.save().unwrap();
+
+    let loaded = DownloadQueue::load(queue_path.clone()).unwrap();
+    assert_eq!(2, loaded.requests.len());
+    assert_eq!(1, loaded.requests[0].alternates.len());
+    assert_eq!("DV8", loaded.requests[0].alternates[0].bot_source);
+    assert_eq!(
+        "Herbert - Dune.epub",
+        loaded.requests[0].alternates[0].requested_file
+    );
+    assert!(loaded.requests[1].alternates.is_empty());
+
+    // A partial alternate means the file is damaged
+    fs::write(&queue_path, "queued\tBsk\tDune.epub\t!Bsk Dune.epub\tDV8\n").unwrap();
+    assert!(DownloadQueue::load(queue_path.clone()).is_err());
+    fs::remove_file(&queue_path).unwrap();
+}
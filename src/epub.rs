@@ -0,0 +1,639 @@
+use crate::pkzip::{PkZip, PkZipFile};
+use std::fs;
+use std::path::Path;
+
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// What an epub's package document says about the book.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EpubMetadata
+{
+    pub title: String,
+    /// Only the creators credited as authors, unless none are.
+    pub authors: Vec<String>,
+    pub language: Option<String>,
+    /// Every identifier as written, such as `urn:isbn:9780441172719` or a calibre uuid.
+    pub identifiers: Vec<String>,
+    /// The first valid ISBN among the identifiers, digits only.
+    pub isbn: Option<String>,
+    pub publisher: Option<String>,
+    pub series: Option<String>,
+    /// The volume number within the series, without a trailing `.0`.
+    pub series_index: Option<String>,
+    /// The publication date, as written, usually `1965` or `1965-08-01`.
+    pub date: Option<String>,
+    pub cover: Option<Vec<u8>>,
+    pub cover_media_type: Option<String>,
+}
+
+pub fn read_epub_file(path: &Path) -> Result<EpubMetadata, &'static str>
+{
+    match fs::read(path)
+    {
+        Ok(t) => read_epub(&t),
+        Err(_e) => Err("Unable to read epub file"),
+    }
+}
+
+pub fn read_epub(data: &[u8]) -> Result<EpubMetadata, &'static str>
+{
+    if !PkZip::data_is_pkzip(data) || !PkZip::is_complete(data)
+    {
+        return Err("Not a complete epub archive");
+    }
+    let files = PkZip::new(data).get_files();
+    let names = files
+        .iter()
+        .map(|x| x.file_name.as_str())
+        .collect::<Vec<&str>>();
+    read_package(
+        &names,
+        &|name| match files.iter().find(|x| x.file_name == name)
+        {
+            Some(t) => entry_data(t),
+            None => Err("The epub has no such entry"),
+        },
+    )
+}
+
+/// Reads an epub whose entries were already decompressed, given by name.
+pub fn read_epub_entries(entries: &[(String, Vec<u8>)]) -> Result<EpubMetadata, &'static str>
+{
+    let names = entries.iter().map(|x| x.0.as_str()).collect::<Vec<&str>>();
+    read_package(&names, &|name| match entries.iter().find(|x| x.0 == name)
+    {
+        Some(t) => Ok(t.1.clone()),
+        None => Err("The epub has no such entry"),
+    })
+}
+
+/// Finds the package document through the container and reads it, taking the contents of
+/// each entry named in `names` from `entry`.
+fn read_package(
+    names: &[&str],
+    entry: &dyn Fn(&str) -> Result<Vec<u8>, &'static str>,
+) -> Result<EpubMetadata, &'static str>
+{
+    let package_path = match find_entry(names, CONTAINER_PATH)
+    {
+        Some(t) => package_path(&entry_text(&entry(t)?)),
+        None => None,
+    };
+    // Some broken epubs lack the container but still have a package document
+    let package_path = match package_path.or_else(|| {
+        names
+            .iter()
+            .find(|x| x.to_lowercase().ends_with(".opf"))
+            .map(|x| x.to_string())
+    })
+    {
+        Some(t) => t,
+        None => return Err("No package document in the epub"),
+    };
+    let package = match find_entry(names, &package_path)
+    {
+        Some(t) => entry_text(&entry(t)?),
+        None => return Err("The epub's package document is missing"),
+    };
+    let mut metadata = parse_package(&package);
+    let package_directory = package_path.rsplit_once('/').map(|(x, _)| x).unwrap_or("");
+    if let Some((href, media_type)) = cover_item(&package)
+    {
+        if let Some(t) = find_entry(names, &resolve_href(package_directory, &href))
+        {
+            metadata.cover = entry(t).ok();
+            metadata.cover_media_type = media_type;
+        }
+    }
+    Ok(metadata)
+}
+
+/// The path of the package document, from `META-INF/container.xml`.
+fn package_path(container: &str) -> Option<String>
+{
+    let rootfiles = xml_elements(container, "rootfile");
+    rootfiles
+        .iter()
+        .find(|x| x.attribute("media-type") == Some("application/oebps-package+xml"))
+        .or(rootfiles.first())
+        .and_then(|x| x.attribute("full-path"))
+        .map(|x| x.to_string())
+}
+
+/// Everything but the cover, which lives in a file of its own.
+fn parse_package(package: &str) -> EpubMetadata
+{
+    let metas = xml_elements(package, "meta");
+    // EPUB 3 refines an element with `<meta refines="#id" property="...">`
+    let refinement = |id: Option<&str>, property: &str| {
+        let id = id?;
+        metas
+            .iter()
+            .find(|x| {
+                x.attribute("refines") == Some(&format!("#{}", id))
+                    && x.attribute("property") == Some(property)
+            })
+            .map(|x| x.text.clone())
+    };
+    let named = |name: &str| {
+        metas
+            .iter()
+            .find(|x| x.attribute("name") == Some(name))
+            .and_then(|x| x.attribute("content"))
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+    };
+    let first = |name: &str| {
+        xml_elements(package, name)
+            .into_iter()
+            .map(|x| x.text)
+            .find(|x| !x.is_empty())
+    };
+
+    let creators = xml_elements(package, "creator")
+        .into_iter()
+        .filter(|x| !x.text.is_empty())
+        .collect::<Vec<XmlElement>>();
+    let is_author = |x: &XmlElement| match x
+        .attribute("role")
+        .map(|x| x.to_string())
+        .or_else(|| refinement(x.attribute("id"), "role"))
+    {
+        Some(role) => role.eq_ignore_ascii_case("aut"),
+        None => true,
+    };
+    let mut authors = creators
+        .iter()
+        .filter(|x| is_author(x))
+        .map(|x| x.text.clone())
+        .collect::<Vec<String>>();
+    // An editor or translator is still better than nobody
+    if authors.is_empty()
+    {
+        authors = creators.iter().map(|x| x.text.clone()).collect();
+    }
+
+    let identifiers = xml_elements(package, "identifier")
+        .into_iter()
+        .filter(|x| !x.text.is_empty())
+        .collect::<Vec<XmlElement>>();
+    let isbn = identifiers.iter().find_map(|x| {
+        let declared = x
+            .attribute("scheme")
+            .is_some_and(|x| x.eq_ignore_ascii_case("isbn"));
+        parse_isbn(&x.text, declared)
+    });
+
+    let date = xml_elements(package, "date")
+        .into_iter()
+        .filter(|x| !x.text.is_empty())
+        .find(|x| x.attribute("event").is_none_or(|x| x == "publication"))
+        .map(|x| x.text);
+
+    let (series, series_index) = match named("calibre:series")
+    {
+        Some(t) => (Some(t), named("calibre:series_index")),
+        None =>
+        {
+            let collection = metas
+                .iter()
+                .find(|x| x.attribute("property") == Some("belongs-to-collection"));
+            match collection
+            {
+                Some(t) if !t.text.is_empty() => (
+                    Some(t.text.clone()),
+                    refinement(t.attribute("id"), "group-position"),
+                ),
+                _ => (None, None),
+            }
+        }
+    };
+
+    EpubMetadata {
+        title: first("title").unwrap_or_default(),
+        authors,
+        language: first("language"),
+        identifiers: identifiers.into_iter().map(|x| x.text).collect(),
+        isbn,
+        publisher: first("publisher"),
+        series,
+        series_index: series_index.map(|x| tidy_series_index(&x)),
+        date,
+        cover: None,
+        cover_media_type: None,
+    }
+}
+
+/// calibre writes `1.0` for the first volume.
+fn tidy_series_index(index: &str) -> String
+{
+    let index = index.trim();
+    match index.strip_suffix(".0")
+    {
+        Some(t) if !t.is_empty() => t.to_string(),
+        _ => index.to_string(),
+    }
+}
+
+/// The manifest entry of the cover image, as its href and media type.
+fn cover_item(package: &str) -> Option<(String, Option<String>)>
+{
+    let items = xml_elements(package, "item");
+    let as_cover = |x: &XmlElement| {
+        x.attribute("href").map(|href| {
+            (
+                href.to_string(),
+                x.attribute("media-type").map(|x| x.to_string()),
+            )
+        })
+    };
+    let is_image = |x: &XmlElement| {
+        x.attribute("media-type")
+            .is_some_and(|x| x.starts_with("image/"))
+    };
+    // EPUB 2 names the manifest id in `<meta name="cover">`, some tools put the href there
+    let epub2_cover = xml_elements(package, "meta")
+        .into_iter()
+        .find(|x| x.attribute("name") == Some("cover"))
+        .and_then(|x| x.attribute("content").map(|x| x.to_string()));
+    if let Some(cover) = epub2_cover
+    {
+        let item = items
+            .iter()
+            .find(|x| x.attribute("id") == Some(&cover))
+            .or_else(|| items.iter().find(|x| x.attribute("href") == Some(&cover)));
+        if let Some(t) = item.filter(|x| is_image(x))
+        {
+            return as_cover(t);
+        }
+    }
+    let epub3_cover = items.iter().find(|x| {
+        x.attribute("properties")
+            .is_some_and(|x| x.split_whitespace().any(|x| x == "cover-image"))
+    });
+    if let Some(t) = epub3_cover
+    {
+        return as_cover(t);
+    }
+    items
+        .iter()
+        .filter(|x| is_image(x))
+        .find(|x| {
+            [x.attribute("id"), x.attribute("href")]
+                .iter()
+                .flatten()
+                .any(|x| x.to_lowercase().contains("cover"))
+        })
+        .and_then(as_cover)
+}
+
+/// The ISBN in an identifier such as `urn:isbn:978-0-441-17271-9`, digits only. Numbers not
+/// declared to be an ISBN must have a valid check digit, so other ids are not mistaken for one.
+pub fn parse_isbn(identifier: &str, declared: bool) -> Option<String>
+{
+    let lower = identifier.trim().to_lowercase();
+    let (declared, rest) = match lower
+        .strip_prefix("urn:isbn:")
+        .or_else(|| lower.strip_prefix("isbn:"))
+    {
+        Some(t) => (true, t),
+        None => (declared, lower.as_str()),
+    };
+    let isbn = rest
+        .chars()
+        .filter(|x| !matches!(x, '-' | ' '))
+        .collect::<String>()
+        .to_uppercase();
+    // A book controls this value, and slicing by byte would panic inside a wide character
+    let well_formed = isbn.is_ascii()
+        && match isbn.len()
+        {
+            10 =>
+            {
+                isbn[..9].chars().all(|x| x.is_ascii_digit())
+                    && isbn[9..].chars().all(|x| x.is_ascii_digit() || x == 'X')
+            }
+            13 => isbn.chars().all(|x| x.is_ascii_digit()),
+            _ => false,
+        };
+    if well_formed && (declared || isbn_check_digit_valid(&isbn))
+    {
+        Some(isbn)
+    }
+    else
+    {
+        None
+    }
+}
+
+fn isbn_check_digit_valid(isbn: &str) -> bool
+{
+    // An X check digit stands for 10
+    let digits = isbn
+        .chars()
+        .map(|x| x.to_digit(10).unwrap_or(10))
+        .collect::<Vec<u32>>();
+    let sum = match digits.len()
+    {
+        10 =>
+        {
+            digits
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (10 - i as u32) * x)
+                .sum::<u32>()
+                % 11
+        }
+        _ =>
+        {
+            digits
+                .iter()
+                .enumerate()
+                .map(|(i, x)| {
+                    if i % 2 == 0
+                    {
+                        *x
+                    }
+                    else
+                    {
+                        3 * x
+                    }
+                })
+                .sum::<u32>()
+                % 10
+        }
+    };
+    sum == 0
+}
+
+/// Zip entry names are case sensitive, but not every tool that writes epubs agrees.
+/// The entry called `name`, or failing that one whose name differs only in case.
+fn find_entry<'a>(names: &[&'a str], name: &str) -> Option<&'a str>
+{
+    names
+        .iter()
+        .find(|x| **x == name)
+        .or_else(|| names.iter().find(|x| x.eq_ignore_ascii_case(name)))
+        .copied()
+}
+
+fn entry_data(file: &PkZipFile) -> Result<Vec<u8>, &'static str>
+{
+    // decompress gives a single zero byte for an empty file
+    if file.uncompressed_size == 0
+    {
+        return Ok(Vec::new());
+    }
+    file.decompress()
+}
+
+fn entry_text(data: &[u8]) -> String
+{
+    let text = String::from_utf8_lossy(data);
+    text.trim_start_matches('\u{feff}').to_string()
+}
+
+/// A manifest href relative to the package document's directory, as a zip entry name.
+fn resolve_href(directory: &str, href: &str) -> String
+{
+    let href = percent_decode(href.split('#').next().unwrap_or(""));
+    let mut components = directory
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<&str>>();
+    for component in href.split('/')
+    {
+        match component
+        {
+            "" | "." => (),
+            ".." =>
+            {
+                components.pop();
+            }
+            x => components.push(x),
+        }
+    }
+    components.join("/")
+}
+
+fn percent_decode(text: &str) -> String
+{
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len()
+    {
+        let escaped = match bytes.get(i + 1..i + 3)
+        {
+            Some(t) if bytes[i] == b'%' => std::str::from_utf8(t)
+                .ok()
+                .and_then(|x| u8::from_str_radix(x, 16).ok()),
+            _ => None,
+        };
+        match escaped
+        {
+            Some(t) =>
+            {
+                decoded.push(t);
+                i += 3;
+            }
+            None =>
+            {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// An element of an XML document. Just enough XML for container and package documents, which
+/// tools write with all kinds of namespace prefixes, so names are compared without them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct XmlElement
+{
+    attributes: Vec<(String, String)>,
+    /// The text inside, with nested markup removed and whitespace collapsed.
+    text: String,
+}
+
+impl XmlElement
+{
+    fn attribute(&self, name: &str) -> Option<&str>
+    {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Every element named `name`, in document order.
+fn xml_elements(xml: &str, name: &str) -> Vec<XmlElement>
+{
+    let mut ret_val = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<')
+    {
+        rest = &rest[start + 1..];
+        let end = match rest.find('>')
+        {
+            Some(t) => t,
+            None => break,
+        };
+        let tag = &rest[..end];
+        let name_end = tag
+            .find(|x: char| x.is_whitespace() || x == '/')
+            .unwrap_or(tag.len());
+        if tag.starts_with(['/', '!', '?']) || local_name(&tag[..name_end]) != name
+        {
+            continue;
+        }
+        rest = &rest[end + 1..];
+        let text = if tag.ends_with('/')
+        {
+            String::new()
+        }
+        else
+        {
+            strip_tags(&rest[..closing_tag(rest, name)])
+        };
+        ret_val.push(XmlElement {
+            attributes: parse_attributes(&tag[name_end..]),
+            text,
+        });
+    }
+    ret_val
+}
+
+fn local_name(name: &str) -> &str
+{
+    match name.rsplit_once(':')
+    {
+        Some((_, t)) => t,
+        None => name,
+    }
+}
+
+/// Where the element ends, or the end of the document if it never does.
+fn closing_tag(xml: &str, name: &str) -> usize
+{
+    let mut offset = 0;
+    while let Some(start) = xml[offset..].find("</")
+    {
+        let start = offset + start;
+        let end = match xml[start..].find('>')
+        {
+            Some(t) => start + t,
+            None => break,
+        };
+        if local_name(xml[start + 2..end].trim()) == name
+        {
+            return start;
+        }
+        offset = end;
+    }
+    xml.len()
+}
+
+fn parse_attributes(text: &str) -> Vec<(String, String)>
+{
+    let mut ret_val = Vec::new();
+    let mut rest = text;
+    while let Some(equals) = rest.find('=')
+    {
+        let key = rest[..equals].split_whitespace().last().unwrap_or("");
+        let value = rest[equals + 1..].trim_start();
+        let quote = match value.chars().next()
+        {
+            Some(t @ ('"' | '\'')) => t,
+            _ => break,
+        };
+        let value = &value[1..];
+        let end = match value.find(quote)
+        {
+            Some(t) => t,
+            None => break,
+        };
+        ret_val.push((local_name(key).to_string(), decode_entities(&value[..end])));
+        rest = &value[end + 1..];
+    }
+    ret_val
+}
+
+fn strip_tags(text: &str) -> String
+{
+    let mut ret_val = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<')
+    {
+        ret_val.push_str(&decode_entities(&rest[..start]));
+        rest = &rest[start..];
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[")
+        {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            ret_val.push_str(&cdata[..end]);
+            rest = cdata.get(end + 3..).unwrap_or("");
+            continue;
+        }
+        rest = match rest.find('>')
+        {
+            Some(t) => &rest[t + 1..],
+            None => "",
+        };
+    }
+    ret_val.push_str(&decode_entities(rest));
+    ret_val.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn decode_entities(text: &str) -> String
+{
+    let mut ret_val = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&')
+    {
+        ret_val.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest
+            .find(';')
+            .and_then(|end| decode_entity(&rest[1..end]).map(|x| (x, end)));
+        match decoded
+        {
+            Some((character, end)) =>
+            {
+                ret_val.push(character);
+                rest = &rest[end + 1..];
+            }
+            // A stray ampersand, as written by sloppy tools
+            None =>
+            {
+                ret_val.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    ret_val.push_str(rest);
+    ret_val
+}
+
+fn decode_entity(entity: &str) -> Option<char>
+{
+    match entity
+    {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ =>
+        {
+            let number = entity.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X'])
+            {
+                Some(t) => u32::from_str_radix(t, 16).ok()?,
+                None => number.parse::<u32>().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
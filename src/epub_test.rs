@@ -0,0 +1,204 @@
+use crate::epub::{parse_isbn, read_epub};
+use crate::library::embedded_metadata;
+use std::env;
+use std::fs;
+use std::process;
+
+const CONTAINER: &str = r##"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"##;
+
+const EPUB2_PACKAGE: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uuid_id" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Dune &amp; Sand</dc:title>
+    <dc:creator opf:role="aut" opf:file-as="Herbert, Frank">Frank Herbert</dc:creator>
+    <dc:creator opf:role="trl">Some Translator</dc:creator>
+    <dc:language>en</dc:language>
+    <dc:identifier id="uuid_id" opf:scheme="uuid">0b9d4e2a-1c3f-4a5b-9c8d-7e6f5a4b3c2d</dc:identifier>
+    <dc:identifier opf:scheme="ISBN">978-0-441-17271-9</dc:identifier>
+    <dc:publisher>Ace</dc:publisher>
+    <dc:date opf:event="modification">2020-01-01</dc:date>
+    <dc:date>1965-08-01T00:00:00+00:00</dc:date>
+    <meta name="calibre:series" content="Dune"/>
+    <meta name="calibre:series_index" content="1.0"/>
+    <meta name="cover" content="cover-id"/>
+  </metadata>
+  <manifest>
+    <item id="cover-id" href="images/cover%20art.jpg" media-type="image/jpeg"/>
+    <item id="text" href="text/chapter1.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+</package>"##;
+
+const EPUB3_PACKAGE: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<opf:package xmlns:opf="http://www.idpf.org/2007/opf" version="3.0">
+  <opf:metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title id="t1"><![CDATA[Children of Dune]]></dc:title>
+    <dc:creator id="c1">Frank Herbert</dc:creator>
+    <dc:creator id="c2">Jane Editor</dc:creator>
+    <meta refines="#c1" property="role" scheme="marc:relators">aut</meta>
+    <meta refines="#c2" property="role" scheme="marc:relators">edt</meta>
+    <dc:identifier>urn:isbn:0441104029</dc:identifier>
+    <meta property="belongs-to-collection" id="col">Dune Chronicles</meta>
+    <meta refines="#col" property="group-position">3</meta>
+  </opf:metadata>
+  <opf:manifest>
+    <opf:item id="img" href="../art/front.png" media-type="image/png" properties="cover-image"/>
+  </opf:manifest>
+</opf:package>"##;
+
+/// A zip archive with every entry stored uncompressed, as `PkZip` reads it.
+fn stored_zip(entries: &[(&str, &[u8])]) -> Vec<u8>
+{
+    let mut data: Vec<u8> = Vec::new();
+    let mut central_directory: Vec<u8> = Vec::new();
+    for (name, contents) in entries.iter()
+    {
+        let offset = data.len() as u32;
+        let size = (contents.len() as u32).to_le_bytes();
+        let name_length = (name.len() as u16).to_le_bytes();
+        // Version needed, flags, stored, time, date and a CRC nobody checks
+        let common = [20u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend([0x50, 0x4b, 0x03, 0x04]);
+        data.extend(common);
+        data.extend(size);
+        data.extend(size);
+        data.extend(name_length);
+        data.extend([0, 0]);
+        data.extend(name.as_bytes());
+        data.extend(*contents);
+
+        central_directory.extend([0x50, 0x4b, 0x01, 0x02, 20, 0]);
+        central_directory.extend(common);
+        central_directory.extend(size);
+        central_directory.extend(size);
+        central_directory.extend(name_length);
+        // Extra field, comment, disk, internal and external attributes
+        central_directory.extend([0; 12]);
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(name.as_bytes());
+    }
+    let central_directory_offset = (data.len() as u32).to_le_bytes();
+    let count = (entries.len() as u16).to_le_bytes();
+    let central_directory_size = (central_directory.len() as u32).to_le_bytes();
+    data.extend(central_directory);
+    data.extend([0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0]);
+    data.extend(count);
+    data.extend(count);
+    data.extend(central_directory_size);
+    data.extend(central_directory_offset);
+    data.extend([0, 0]);
+    data
+}
+
+fn epub2() -> Vec<u8>
+{
+    stored_zip(&[
+        ("mimetype", b"application/epub+zip"),
+        ("META-INF/container.xml", CONTAINER.as_bytes()),
+        ("OEBPS/content.opf", EPUB2_PACKAGE.as_bytes()),
+        ("OEBPS/images/cover art.jpg", &[0xff, 0xd8, 0xff, 0xe0]),
+        ("OEBPS/text/chapter1.xhtml", b"<html/>"),
+    ])
+}
+
+#[test]
+fn read_epub_test()
+{
+    let metadata = read_epub(&epub2()).unwrap();
+    assert_eq!("Dune & Sand", metadata.title);
+    assert_eq!(vec!["Frank Herbert".to_string()], metadata.authors);
+    assert_eq!(Some("en".to_string()), metadata.language);
+    assert_eq!(2, metadata.identifiers.len());
+    assert_eq!(Some("9780441172719".to_string()), metadata.isbn);
+    assert_eq!(Some("Ace".to_string()), metadata.publisher);
+    assert_eq!(Some("Dune".to_string()), metadata.series);
+    assert_eq!(Some("1".to_string()), metadata.series_index);
+    assert_eq!(Some("1965-08-01T00:00:00+00:00".to_string()), metadata.date);
+    assert_eq!(Some(vec![0xff, 0xd8, 0xff, 0xe0]), metadata.cover);
+    assert_eq!(Some("image/jpeg".to_string()), metadata.cover_media_type);
+}
+#[test]
+fn read_epub3_test()
+{
+    let data = stored_zip(&[
+        ("META-INF/container.xml", CONTAINER.as_bytes()),
+        ("OEBPS/content.opf", EPUB3_PACKAGE.as_bytes()),
+        ("art/front.png", &[0x89, 0x50, 0x4e, 0x47]),
+    ]);
+    let metadata = read_epub(&data).unwrap();
+    assert_eq!("Children of Dune", metadata.title);
+    assert_eq!(vec!["Frank Herbert".to_string()], metadata.authors);
+    assert_eq!(Some("0441104029".to_string()), metadata.isbn);
+    assert_eq!(Some("Dune Chronicles".to_string()), metadata.series);
+    assert_eq!(Some("3".to_string()), metadata.series_index);
+    assert_eq!(None, metadata.language);
+    assert_eq!(Some(vec![0x89, 0x50, 0x4e, 0x47]), metadata.cover);
+}
+#[test]
+fn read_epub_without_container_test()
+{
+    // Falls back to the first package document in the archive
+    let data = stored_zip(&[("content.opf", EPUB3_PACKAGE.as_bytes())]);
+    let metadata = read_epub(&data).unwrap();
+    assert_eq!("Children of Dune", metadata.title);
+    assert_eq!(None, metadata.cover);
+}
+#[test]
+fn read_malformed_epub_test()
+{
+    let data = epub2();
+    assert!(read_epub(&data[..data.len() - 30]).is_err());
+    assert!(read_epub(&data[..data.len() / 2]).is_err());
+    assert!(read_epub(b"%PDF-1.4").is_err());
+    let data = stored_zip(&[("mimetype", b"application/epub+zip")]);
+    assert_eq!(Err("No package document in the epub"), read_epub(&data));
+}
+#[test]
+fn parse_isbn_test()
+{
+    assert_eq!(
+        Some("9780441172719".to_string()),
+        parse_isbn("978-0-441-17271-9", false)
+    );
+    assert_eq!(
+        Some("080442957X".to_string()),
+        parse_isbn("0-8044-2957-x", false)
+    );
+    assert_eq!(
+        Some("9780441172719".to_string()),
+        parse_isbn("urn:isbn:9780441172719", false)
+    );
+    // A bad check digit is kept only when the identifier says it is an ISBN
+    assert_eq!(None, parse_isbn("9780441172718", false));
+    assert_eq!(
+        Some("9780441172718".to_string()),
+        parse_isbn("9780441172718", true)
+    );
+    assert_eq!(None, parse_isbn("0b9d4e2a-1c3f-4a5b", true));
+    assert_eq!(None, parse_isbn("B00B7NPRY8", false));
+}
+#[test]
+// The right number of bytes but not of characters must not panic
+fn parse_isbn_non_ascii_test()
+{
+    assert_eq!(None, parse_isbn("12345678É", true));
+    assert_eq!(None, parse_isbn("0-441-1727-É", false));
+}
+#[test]
+fn embedded_metadata_test()
+{
+    let path = env::temp_dir().join(format!("rs-book-downloader-epub-{}.epub", process::id()));
+    fs::write(&path, epub2()).unwrap();
+    let metadata = embedded_metadata(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!("Frank Herbert", metadata.author);
+    assert_eq!("Dune & Sand", metadata.title);
+    assert_eq!(Some("Dune".to_string()), metadata.series);
+    assert_eq!(Some("1".to_string()), metadata.series_index);
+    assert_eq!(Some("1965".to_string()), metadata.year);
+    assert_eq!(Some("epub".to_string()), metadata.extension);
+}
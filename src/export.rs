@@ -0,0 +1,206 @@
+use crate::search_result::{format_size, SearchResult};
+
+/// Bumped whenever a field is renamed, removed or changes meaning. New fields may be added
+/// without a bump.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Field names shared by every format, in CSV column order.
+pub const FIELDS: [&str; 10] = [
+    "bot",
+    "author",
+    "title",
+    "series",
+    "format",
+    "extension",
+    "size_bytes",
+    "requested_file",
+    "request_line",
+    "metadata",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat
+{
+    Table,
+    Json,
+    Csv,
+    Ndjson,
+}
+
+impl OutputFormat
+{
+    pub fn from_name(name: &str) -> Result<OutputFormat, &'static str>
+    {
+        match name.trim().to_lowercase().as_str()
+        {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            _ => Err("Output format must be json, csv, ndjson or table"),
+        }
+    }
+}
+
+/// Renders `results` for other programs, or as a plain table for people.
+pub fn export_results(format: OutputFormat, query: &str, results: &[SearchResult]) -> String
+{
+    match format
+    {
+        OutputFormat::Table => to_table(results),
+        OutputFormat::Json => to_json(query, results),
+        OutputFormat::Csv => to_csv(results),
+        OutputFormat::Ndjson => to_ndjson(results),
+    }
+}
+
+/// One document with the query and every result.
+pub fn to_json(query: &str, results: &[SearchResult]) -> String
+{
+    let mut ret_val = format!(
+        "{{\"schema_version\":{},\"query\":{},\"results\":[",
+        SCHEMA_VERSION,
+        json_string(query)
+    );
+    ret_val.push_str(
+        &results
+            .iter()
+            .map(result_object)
+            .collect::<Vec<String>>()
+            .join(","),
+    );
+    ret_val.push_str("]}\n");
+    ret_val
+}
+
+/// One object per line, each carrying the schema version since lines are read on their own.
+pub fn to_ndjson(results: &[SearchResult]) -> String
+{
+    results
+        .iter()
+        .map(|x| {
+            let object = result_object(x);
+            format!("{{\"schema_version\":{},{}\n", SCHEMA_VERSION, &object[1..])
+        })
+        .collect()
+}
+
+/// RFC 4180 CSV with a header row, metadata as `key=value` pairs joined by `; `.
+pub fn to_csv(results: &[SearchResult]) -> String
+{
+    let mut ret_val = format!("schema_version,{}\r\n", FIELDS.join(","));
+    for result in results.iter()
+    {
+        let values = field_values(result);
+        let metadata = result
+            .metadata
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<String>>()
+            .join("; ");
+        ret_val.push_str(&SCHEMA_VERSION.to_string());
+        for value in values.iter()
+        {
+            ret_val.push(',');
+            ret_val.push_str(&csv_field(value.as_deref().unwrap_or("")));
+        }
+        ret_val.push(',');
+        ret_val.push_str(&csv_field(&metadata));
+        ret_val.push_str("\r\n");
+    }
+    ret_val
+}
+
+pub fn to_table(results: &[SearchResult]) -> String
+{
+    let mut ret_val = format!(
+        "{:>4}  {:<40}  {:<24}  {:<6}  {:>9}  {}\n",
+        "#", "Title", "Author", "Format", "Size", "Bot"
+    );
+    for (i, result) in results.iter().enumerate()
+    {
+        ret_val.push_str(&format!(
+            "{:>4}  {:<40}  {:<24}  {:<6}  {:>9}  {}\n",
+            i,
+            result.title,
+            result.author,
+            result.format.as_str(),
+            result.size_bytes.map(format_size).unwrap_or_default(),
+            result.bot
+        ));
+    }
+    ret_val
+}
+
+/// Every field but the metadata, in `FIELDS` order. Missing values are `None`.
+fn field_values(result: &SearchResult) -> Vec<Option<String>>
+{
+    vec![
+        Some(result.bot.clone()),
+        Some(result.author.clone()),
+        Some(result.title.clone()),
+        result.series.clone(),
+        Some(result.format.as_str().to_string()),
+        result.extension.clone(),
+        result.size_bytes.map(|x| x.to_string()),
+        Some(result.requested_file()),
+        Some(result.request_line.clone()),
+    ]
+}
+
+fn result_object(result: &SearchResult) -> String
+{
+    let mut members = FIELDS
+        .iter()
+        .zip(field_values(result))
+        .map(|(name, value)| {
+            let value = match value
+            {
+                None => "null".to_string(),
+                Some(t) if *name == "size_bytes" => t,
+                Some(t) => json_string(&t),
+            };
+            format!("\"{}\":{}", name, value)
+        })
+        .collect::<Vec<String>>();
+    let metadata = result
+        .metadata
+        .iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+        .collect::<Vec<String>>()
+        .join(",");
+    members.push(format!("\"metadata\":{{{}}}", metadata));
+    format!("{{{}}}", members.join(","))
+}
+
+pub fn json_string(text: &str) -> String
+{
+    let mut ret_val = String::from("\"");
+    for x in text.chars()
+    {
+        match x
+        {
+            '"' => ret_val.push_str("\\\""),
+            '\\' => ret_val.push_str("\\\\"),
+            '\n' => ret_val.push_str("\\n"),
+            '\r' => ret_val.push_str("\\r"),
+            '\t' => ret_val.push_str("\\t"),
+            x if (x as u32) < 0x20 => ret_val.push_str(&format!("\\u{:04x}", x as u32)),
+            x => ret_val.push(x),
+        }
+    }
+    ret_val.push('"');
+    ret_val
+}
+
+fn csv_field(text: &str) -> String
+{
+    if text.contains([',', '"', '\r', '\n'])
+    {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    }
+    else
+    {
+        text.to_string()
+    }
+}
@@ -0,0 +1,55 @@
+use crate::export::{export_results, json_string, to_csv, to_json, to_ndjson, OutputFormat};
+use crate::search_result::SearchResult;
+
+fn results() -> Vec<SearchResult>
+{
+    [
+        "!Bsk Tolkien, J.R.R - The Hobbit.epub ::INFO:: 1.2MB ::HASH:: 0a1b2c3d",
+        "!DV8 Herbert, Frank - Dune, \"Deluxe\".mobi",
+    ]
+    .iter()
+    .map(|x| SearchResult::parse(x).unwrap())
+    .collect()
+}
+
+#[test]
+fn json_test()
+{
+    let json = to_json("the \"hobbit\"", &results());
+    assert!(json.starts_with("{\"schema_version\":1,\"query\":\"the \\\"hobbit\\\"\",\"results\":[{\"bot\":\"Bsk\",\"author\":\"Tolkien, J.R.R\",\"title\":\"The Hobbit\",\"series\":null,\"format\":\"epub\",\"extension\":\"epub\",\"size_bytes\":1258291,"));
+    assert!(json.contains("\"metadata\":{\"hash\":\"0a1b2c3d\"}"));
+    assert!(json.contains("\"size_bytes\":null"));
+    assert!(json.ends_with("]}\n"));
+    assert_eq!("\"a\\\\b\\n\\u0001\"", json_string("a\\b\n\u{1}"));
+}
+#[test]
+fn ndjson_test()
+{
+    let ndjson = to_ndjson(&results());
+    let lines = ndjson.lines().collect::<Vec<&str>>();
+    assert_eq!(2, lines.len());
+    assert!(lines[1].starts_with("{\"schema_version\":1,\"bot\":\"DV8\","));
+    assert!(lines[1].ends_with("\"metadata\":{}}"));
+}
+#[test]
+fn csv_test()
+{
+    let csv = to_csv(&results());
+    let rows = csv.split("\r\n").collect::<Vec<&str>>();
+    assert_eq!(
+        "schema_version,bot,author,title,series,format,extension,size_bytes,requested_file,request_line,metadata",
+        rows[0]
+    );
+    assert!(rows[1].starts_with("1,Bsk,\"Tolkien, J.R.R\",The Hobbit,,epub,epub,1258291,"));
+    assert!(rows[1].ends_with(",hash=0a1b2c3d"));
+    assert!(rows[2].contains(",\"Dune, \"\"Deluxe\"\"\","));
+    assert_eq!("", rows[3]);
+}
+#[test]
+fn output_format_test()
+{
+    assert_eq!(Ok(OutputFormat::Ndjson), OutputFormat::from_name("NDJSON"));
+    assert!(OutputFormat::from_name("xml").is_err());
+    let table = export_results(OutputFormat::Table, "dune", &results());
+    assert!(table.lines().nth(2).unwrap().starts_with("   1  Dune"));
+}
@@ -0,0 +1,437 @@
+//! The download history is a plain text file rather than SQLite or another embedded database.
+//! That is deliberate: the program depends on nothing but the standard library, and an append
+//! only log of a few hundred lines a year needs no queries that a linear scan cannot answer.
+//!
+//! Each line is one record, its fields separated by tabs, with tabs and line breaks in the
+//! values replaced by spaces. The first field names the kind of record:
+//!
+//! ```text
+//! search    at  dialect  results  query
+//! download  requested_at  finished_at  completed|failed  reason  bot  requested_file
+//!           request_line  path  size  crc32
+//! filed     at  from  to
+//! ```
+//!
+//! Times are seconds since the epoch, and empty fields mean unknown. The file is never
+//! rewritten, so there is no migration step. A field added later goes at the end of its
+//! record, readers ignore fields past the ones they know and skip kinds they do not know, so
+//! older and newer versions share one file.
+
+use crate::crc32::Crc32;
+use crate::ranking::BotStats;
+use crate::search_result::{format_size, SearchResult};
+use crate::timestamp::format_log_time;
+use crate::works::{work_key, Work};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const HISTORY_FILE_NAME: &str = ".rs-book-downloader-history";
+
+/// A search that was sent, or answered from the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchRecord
+{
+    pub at: u64,
+    pub dialect: String,
+    pub query: String,
+    pub results: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadOutcome
+{
+    Completed,
+    Failed(String),
+}
+
+/// One attempt at one copy of a book, whether or not it arrived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadRecord
+{
+    /// When the pack was requested from the bot, in seconds since the epoch.
+    pub requested_at: u64,
+    pub finished_at: u64,
+    pub bot: String,
+    /// The chosen pack, empty for files fetched from an fserve.
+    pub request_line: String,
+    pub requested_file: String,
+    pub outcome: DownloadOutcome,
+    /// Where the file was saved, for completed downloads.
+    pub path: Option<String>,
+    pub size: Option<u64>,
+    /// CRC-32 of the saved file as 8 hex digits.
+    pub checksum: Option<String>,
+}
+
+/// A downloaded book that was moved into the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FiledRecord
+{
+    pub at: u64,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryRecord
+{
+    Search(SearchRecord),
+    Download(DownloadRecord),
+    Filed(FiledRecord),
+}
+
+/// Every search and download, appended one tab separated line at a time so a crash never
+/// loses more than the line being written.
+#[derive(Debug, Clone)]
+pub struct History
+{
+    pub path: Option<PathBuf>,
+}
+
+impl History
+{
+    pub fn new(path: Option<PathBuf>) -> Self
+    {
+        History { path }
+    }
+    /// Every record, oldest first. Lines that cannot be read, such as one cut short by a
+    /// crash, are skipped.
+    pub fn records(&self) -> Vec<HistoryRecord>
+    {
+        let contents = match self.path.as_ref().map(fs::read_to_string)
+        {
+            Some(Ok(t)) => t,
+            _ => return Vec::new(),
+        };
+        contents.lines().filter_map(parse_record).collect()
+    }
+    pub fn record_search(&self, search: &SearchRecord) -> Result<(), &'static str>
+    {
+        self.append(&format!(
+            "search\t{}\t{}\t{}\t{}",
+            search.at,
+            search.dialect,
+            search.results,
+            clean(&search.query)
+        ))
+    }
+    pub fn record_download(&self, download: &DownloadRecord) -> Result<(), &'static str>
+    {
+        let (outcome, reason) = match &download.outcome
+        {
+            DownloadOutcome::Completed => ("completed", ""),
+            DownloadOutcome::Failed(reason) => ("failed", reason.as_str()),
+        };
+        self.append(&format!(
+            "download\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            download.requested_at,
+            download.finished_at,
+            outcome,
+            clean(reason),
+            clean(&download.bot),
+            clean(&download.requested_file),
+            clean(&download.request_line),
+            clean(download.path.as_deref().unwrap_or("")),
+            download.size.map(|x| x.to_string()).unwrap_or_default(),
+            download.checksum.as_deref().unwrap_or("")
+        ))
+    }
+    pub fn record_filed(&self, filed: &FiledRecord) -> Result<(), &'static str>
+    {
+        self.append(&format!(
+            "filed\t{}\t{}\t{}",
+            filed.at,
+            clean(&filed.from),
+            clean(&filed.to)
+        ))
+    }
+    fn append(&self, line: &str) -> Result<(), &'static str>
+    {
+        let path = match &self.path
+        {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let written = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut x| {
+                // Finish a line cut short by a crash, or this record would be lost with it
+                let mut last = [b'\n'];
+                if x.metadata()?.len() > 0
+                {
+                    x.seek(SeekFrom::End(-1))?;
+                    x.read_exact(&mut last)?;
+                }
+                let separator = if last[0] == b'\n' { "" } else { "\n" };
+                writeln!(x, "{}{}", separator, line)
+            });
+        match written
+        {
+            Ok(_) => Ok(()),
+            Err(_e) => Err("Unable to write to the history file"),
+        }
+    }
+}
+
+fn parse_record(line: &str) -> Option<HistoryRecord>
+{
+    let fields = line.split('\t').collect::<Vec<&str>>();
+    match fields[0]
+    {
+        "search" if fields.len() >= 5 => Some(HistoryRecord::Search(SearchRecord {
+            at: fields[1].parse().ok()?,
+            dialect: fields[2].to_string(),
+            results: fields[3].parse().ok()?,
+            query: fields[4].to_string(),
+        })),
+        "download" if fields.len() >= 11 => Some(HistoryRecord::Download(DownloadRecord {
+            requested_at: fields[1].parse().ok()?,
+            finished_at: fields[2].parse().ok()?,
+            outcome: match fields[3]
+            {
+                "completed" => DownloadOutcome::Completed,
+                "failed" => DownloadOutcome::Failed(fields[4].to_string()),
+                _ => return None,
+            },
+            bot: fields[5].to_string(),
+            requested_file: fields[6].to_string(),
+            request_line: fields[7].to_string(),
+            path: Some(fields[8].to_string()).filter(|x| !x.is_empty()),
+            size: fields[9].parse().ok(),
+            checksum: Some(fields[10].to_string()).filter(|x| !x.is_empty()),
+        })),
+        "filed" if fields.len() >= 4 => Some(HistoryRecord::Filed(FiledRecord {
+            at: fields[1].parse().ok()?,
+            from: fields[2].to_string(),
+            to: fields[3].to_string(),
+        })),
+        _ => None,
+    }
+}
+
+/// Tabs and line breaks would split the record.
+fn clean(text: &str) -> String
+{
+    text.replace(['\t', '\r', '\n'], " ")
+}
+
+/// How many requests each bot delivered or failed, for ranking.
+pub fn bot_stats(records: &[HistoryRecord]) -> HashMap<String, BotStats>
+{
+    let mut stats: HashMap<String, BotStats> = HashMap::new();
+    for record in records.iter()
+    {
+        if let HistoryRecord::Download(download) = record
+        {
+            // Nicknames are case insensitive, the first spelling seen is kept
+            let bot = stats
+                .keys()
+                .find(|x| x.eq_ignore_ascii_case(&download.bot))
+                .cloned()
+                .unwrap_or_else(|| download.bot.clone());
+            let entry = stats.entry(bot).or_default();
+            match download.outcome
+            {
+                DownloadOutcome::Completed => entry.successes += 1,
+                DownloadOutcome::Failed(_) => entry.failures += 1,
+            }
+        }
+    }
+    stats
+}
+
+/// Completed downloads whose file is still on disk, oldest first, with paths updated for any
+/// later moves into the library.
+pub fn downloads_on_disk(records: &[HistoryRecord]) -> Vec<DownloadRecord>
+{
+    records
+        .iter()
+        .enumerate()
+        .filter_map(|(i, x)| match x
+        {
+            HistoryRecord::Download(download) if download.outcome == DownloadOutcome::Completed =>
+            {
+                let mut path = download.path.clone()?;
+                for record in records[i + 1..].iter()
+                {
+                    if let HistoryRecord::Filed(filed) = record
+                    {
+                        if filed.from == path
+                        {
+                            path = filed.to.clone();
+                        }
+                    }
+                }
+                if !Path::new(&path).exists()
+                {
+                    return None;
+                }
+                Some(DownloadRecord {
+                    path: Some(path),
+                    ..download.clone()
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The latest download of any copy of `work` that is still on disk.
+pub fn already_downloaded(records: &[HistoryRecord], work: &Work) -> Option<DownloadRecord>
+{
+    let keys = work
+        .copies
+        .iter()
+        .map(work_key)
+        .collect::<Vec<(String, String)>>();
+    downloads_on_disk(records)
+        .into_iter()
+        .rev()
+        .find(|x| SearchResult::parse(&x.request_line).is_ok_and(|x| keys.contains(&work_key(&x))))
+}
+
+/// One line per record, oldest first.
+pub fn render_history(records: &[HistoryRecord]) -> String
+{
+    if records.is_empty()
+    {
+        return "Nothing has been searched for or downloaded yet.\n".to_string();
+    }
+    records
+        .iter()
+        .map(|x| match x
+        {
+            HistoryRecord::Search(search) => format!(
+                "{}  search    {} ({}, {} results)\n",
+                format_log_time(search.at),
+                search.query,
+                search.dialect,
+                search.results
+            ),
+            HistoryRecord::Filed(filed) => format!(
+                "{}  filed     {} as {}\n",
+                format_log_time(filed.at),
+                filed.from,
+                filed.to
+            ),
+            HistoryRecord::Download(download) =>
+            {
+                let detail = match &download.outcome
+                {
+                    DownloadOutcome::Completed =>
+                    {
+                        let mut detail = Vec::new();
+                        if let Some(size) = download.size
+                        {
+                            detail.push(format_size(size));
+                        }
+                        if let Some(checksum) = &download.checksum
+                        {
+                            detail.push(format!("crc32 {}", checksum));
+                        }
+                        detail.join(", ")
+                    }
+                    DownloadOutcome::Failed(reason) => reason.clone(),
+                };
+                format!(
+                    "{}  {:<8}  {} from {} ({})\n",
+                    format_log_time(download.finished_at),
+                    match download.outcome
+                    {
+                        DownloadOutcome::Completed => "download",
+                        DownloadOutcome::Failed(_) => "failed",
+                    },
+                    download.requested_file,
+                    download.bot,
+                    detail
+                )
+            }
+        })
+        .collect()
+}
+
+/// Totals, then how reliable each bot has been, most delivered first.
+pub fn render_stats(records: &[HistoryRecord]) -> String
+{
+    let searches = records
+        .iter()
+        .filter(|x| matches!(x, HistoryRecord::Search(_)))
+        .count();
+    let downloads = records
+        .iter()
+        .filter_map(|x| match x
+        {
+            HistoryRecord::Download(t) => Some(t),
+            _ => None,
+        })
+        .collect::<Vec<&DownloadRecord>>();
+    let completed = downloads
+        .iter()
+        .filter(|x| x.outcome == DownloadOutcome::Completed)
+        .collect::<Vec<_>>();
+    let received = completed.iter().filter_map(|x| x.size).sum::<u64>();
+    let mut ret_val = format!(
+        "Searches:   {}\nDownloads:  {} completed, {} failed\nReceived:   {}\n",
+        searches,
+        completed.len(),
+        downloads.len() - completed.len(),
+        format_size(received)
+    );
+    let mut bots = bot_stats(records)
+        .into_iter()
+        .collect::<Vec<(String, BotStats)>>();
+    if bots.is_empty()
+    {
+        return ret_val;
+    }
+    bots.sort_by(|a, b| {
+        b.1.successes
+            .cmp(&a.1.successes)
+            .then(a.1.failures.cmp(&b.1.failures))
+            .then(a.0.cmp(&b.0))
+    });
+    ret_val.push_str(&format!(
+        "\n{:<20}  {:>9}  {:>6}  {:>8}\n",
+        "Bot", "Delivered", "Failed", "Success"
+    ));
+    for (bot, stats) in bots.iter()
+    {
+        let attempts = stats.successes + stats.failures;
+        ret_val.push_str(&format!(
+            "{:<20}  {:>9}  {:>6}  {:>7}%\n",
+            bot,
+            stats.successes,
+            stats.failures,
+            stats.successes * 100 / attempts
+        ));
+    }
+    ret_val
+}
+
+/// The CRC-32 zip uses, as 8 lowercase hex digits.
+pub fn file_checksum(path: &Path) -> Result<String, &'static str>
+{
+    let mut file = match File::open(path)
+    {
+        Ok(t) => t,
+        Err(_e) => return Err("Unable to open the file to checksum it"),
+    };
+    let mut crc = Crc32::default();
+    let mut buf = [0u8; 64 * 1024];
+    loop
+    {
+        match file.read(&mut buf)
+        {
+            Ok(0) => break,
+            Ok(t) => crc.update(&buf[..t]),
+            Err(_e) => return Err("Unable to read the file to checksum it"),
+        }
+    }
+    Ok(format!("{:08x}", crc.finish()))
+}
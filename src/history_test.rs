@@ -0,0 +1,225 @@
+use crate::history::{
+    already_downloaded, bot_stats, downloads_on_disk, file_checksum, render_history, render_stats,
+    DownloadOutcome, DownloadRecord, FiledRecord, History, HistoryRecord, SearchRecord,
+};
+use crate::ranking::BotStats;
+use crate::search_result::SearchResult;
+use crate::works::group_works;
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process;
+
+fn download(bot: &str, request_line: &str, outcome: DownloadOutcome) -> DownloadRecord
+{
+    DownloadRecord {
+        requested_at: 1_700_000_000,
+        finished_at: 1_700_000_060,
+        bot: bot.to_string(),
+        request_line: request_line.to_string(),
+        requested_file: request_line.split_once(' ').unwrap().1.to_string(),
+        outcome,
+        path: None,
+        size: None,
+        checksum: None,
+    }
+}
+
+#[test]
+fn history_round_trip_test()
+{
+    let path = env::temp_dir().join(format!("history-test-{}", process::id()));
+    let history = History::new(Some(path.clone()));
+    let search = SearchRecord {
+        at: 1_700_000_000,
+        dialect: "@search".to_string(),
+        query: "frank herbert\tdune".to_string(),
+        results: 35,
+    };
+    let mut completed = download(
+        "Bot1",
+        "!Bot1 Frank Herbert - Dune.epub",
+        DownloadOutcome::Completed,
+    );
+    completed.path = Some("/books/Frank Herbert - Dune.epub".to_string());
+    completed.size = Some(1_258_291);
+    completed.checksum = Some("0a1b2c3d".to_string());
+    let failed = download(
+        "Bot2",
+        "!Bot2 Frank Herbert - Dune.mobi",
+        DownloadOutcome::Failed("not offered in time".to_string()),
+    );
+    history.record_search(&search).unwrap();
+    history.record_download(&completed).unwrap();
+    // A line cut short by a crash is skipped
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(b"download\t1700000000\t17000")
+        .unwrap();
+    history.record_download(&failed).unwrap();
+    let records = history.records();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(3, records.len());
+    assert_eq!(
+        HistoryRecord::Search(SearchRecord {
+            query: "frank herbert dune".to_string(),
+            ..search
+        }),
+        records[0]
+    );
+    assert_eq!(HistoryRecord::Download(completed), records[1]);
+    assert_eq!(HistoryRecord::Download(failed), records[2]);
+    assert!(History::new(None).records().is_empty());
+}
+#[test]
+// Lines written by a later version still read
+fn history_reads_newer_lines_test()
+{
+    let path = env::temp_dir().join(format!("history-newer-test-{}", process::id()));
+    fs::write(
+        &path,
+        "search\t1700000000\t@search\t35\tfrank herbert dune\tan added field\n\
+         shelved\t1700000060\tDune\n",
+    )
+    .unwrap();
+    let records = History::new(Some(path.clone())).records();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        vec![HistoryRecord::Search(SearchRecord {
+            at: 1_700_000_000,
+            dialect: "@search".to_string(),
+            query: "frank herbert dune".to_string(),
+            results: 35,
+        })],
+        records
+    );
+}
+#[test]
+fn bot_stats_test()
+{
+    let records = vec![
+        HistoryRecord::Download(download("Bot1", "!Bot1 a.epub", DownloadOutcome::Completed)),
+        HistoryRecord::Download(download(
+            "bot1",
+            "!bot1 b.epub",
+            DownloadOutcome::Failed("refused".to_string()),
+        )),
+        HistoryRecord::Download(download("Bot2", "!Bot2 c.epub", DownloadOutcome::Completed)),
+    ];
+    let stats = bot_stats(&records);
+    assert_eq!(2, stats.len());
+    assert_eq!(
+        BotStats {
+            successes: 1,
+            failures: 1
+        },
+        stats["Bot1"]
+    );
+    assert_eq!(1, stats["Bot2"].successes);
+    let rendered = render_stats(&records);
+    assert!(rendered.contains("Downloads:  2 completed, 1 failed\n"));
+    assert!(rendered.contains("Bot1"));
+    assert!(rendered.find("Bot2").unwrap() < rendered.find("Bot1").unwrap());
+}
+#[test]
+fn already_downloaded_test()
+{
+    let path = env::temp_dir().join(format!("history-owned-{}.epub", process::id()));
+    fs::write(&path, b"book").unwrap();
+    let mut owned = download(
+        "Bot1",
+        "!Bot1 Frank Herbert - Dune.epub",
+        DownloadOutcome::Completed,
+    );
+    owned.path = Some(path.display().to_string());
+    let mut deleted = download(
+        "Bot1",
+        "!Bot1 Dan Simmons - Hyperion.epub",
+        DownloadOutcome::Completed,
+    );
+    deleted.path = Some("/nonexistent/Dan Simmons - Hyperion.epub".to_string());
+    let records = vec![
+        HistoryRecord::Download(owned.clone()),
+        HistoryRecord::Download(deleted),
+    ];
+    let works = group_works(&[
+        SearchResult::parse("!Bot2 Herbert, Frank - Dune.mobi").unwrap(),
+        SearchResult::parse("!Bot2 Dan Simmons - Hyperion.mobi").unwrap(),
+    ]);
+    let found = already_downloaded(&records, &works[0]);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(Some(owned), found);
+    // Deleted since, so it may be fetched again
+    assert_eq!(None, already_downloaded(&records, &works[1]));
+}
+#[test]
+fn render_history_test()
+{
+    let mut completed = download("Bot1", "!Bot1 Dune.epub", DownloadOutcome::Completed);
+    completed.size = Some(2048);
+    completed.checksum = Some("0a1b2c3d".to_string());
+    let records = vec![
+        HistoryRecord::Search(SearchRecord {
+            at: 1_700_000_000,
+            dialect: "@find".to_string(),
+            query: "dune".to_string(),
+            results: 3,
+        }),
+        HistoryRecord::Download(completed),
+        HistoryRecord::Download(download(
+            "Bot2",
+            "!Bot2 Dune.mobi",
+            DownloadOutcome::Failed("refused".to_string()),
+        )),
+    ];
+    let rendered = render_history(&records);
+    let lines = rendered.lines().collect::<Vec<&str>>();
+    assert_eq!(3, lines.len());
+    assert_eq!(
+        "2023-11-14 22:13:20 UTC  search    dune (@find, 3 results)",
+        lines[0]
+    );
+    assert!(lines[1].starts_with("2023-11-14 22:14:20 UTC  download  Dune.epub from Bot1 ("));
+    assert!(lines[1].ends_with("crc32 0a1b2c3d)"));
+    assert!(lines[2].ends_with("failed    Dune.mobi from Bot2 (refused)"));
+    assert!(render_history(&[]).starts_with("Nothing"));
+}
+#[test]
+fn checksum_test()
+{
+    let path = env::temp_dir().join(format!("history-checksum-{}", process::id()));
+    fs::write(&path, b"123456789").unwrap();
+    let checksum = file_checksum(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(Ok("cbf43926".to_string()), checksum);
+}
+#[test]
+fn downloads_on_disk_follows_moves_test()
+{
+    let from = env::temp_dir().join(format!("history-moved-from-{}.epub", process::id()));
+    let to = env::temp_dir().join(format!("history-moved-to-{}.epub", process::id()));
+    fs::write(&to, b"book").unwrap();
+    let mut moved = download(
+        "Bot1",
+        "!Bot1 Frank Herbert - Dune.epub",
+        DownloadOutcome::Completed,
+    );
+    moved.path = Some(from.display().to_string());
+    let records = vec![
+        HistoryRecord::Download(moved.clone()),
+        HistoryRecord::Filed(FiledRecord {
+            at: 1_700_000_100,
+            from: from.display().to_string(),
+            to: to.display().to_string(),
+        }),
+    ];
+    let on_disk = downloads_on_disk(&records);
+    fs::remove_file(&to).unwrap();
+    assert_eq!(1, on_disk.len());
+    assert_eq!(Some(to.display().to_string()), on_disk[0].path);
+    assert!(render_history(&records).contains("  filed     "));
+    assert!(downloads_on_disk(&records).is_empty());
+}
@@ -74,6 +74,10 @@ impl IrcConnection
     {
         self.send_command_multiple_args("PRIVMSG", vec![channel, " :", message])
     }
+    pub fn send_notice(&mut self, target: &str, message: &str) -> Result<usize, &'static str>
+    {
+        self.send_command_multiple_args("NOTICE", vec![target, " :", message])
+    }
     pub fn send_string(&mut self, message: &str) -> Result<usize, &'static str>
     {
         println!("Sending: {}", message);
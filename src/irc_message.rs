@@ -1,6 +1,19 @@
 use crate::message_prefix::MessagePrefix;
+use crate::timestamp::format_ctcp_time;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
+pub const DEFAULT_VERSION: &str = concat!("rs-book-downloader-cli ", env!("CARGO_PKG_VERSION"));
+const SOURCE_URL: &str = "https://github.com/nate601/Rs-Book-Downloader-Cli";
+const SUPPORTED_CTCP: &str = "ACTION CLIENTINFO DCC PING SOURCE TIME USERINFO VERSION";
+/// The real name we register with. Anyone can already see it with WHOIS, so it is also the
+/// USERINFO reply.
+pub const REAL_NAME: &str = "nathan";
+/// Replies that may be sent in a burst, and how often another one becomes available.
+const CTCP_REPLY_BURST: u32 = 4;
+const CTCP_REPLY_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
 pub struct IrcMessage
 {
     pub prefix: Option<MessagePrefix>,
@@ -25,6 +38,10 @@ impl IrcMessage
 
             split_message.remove(0);
         }
+        if split_message.is_empty() || split_message[0].is_empty()
+        {
+            return Err("IRC message has no command");
+        }
         let command_string = split_message[0];
         split_message.remove(0);
 
@@ -46,17 +63,17 @@ impl IrcMessage
 
         let command = match command_string.to_lowercase().as_str()
         {
-            "ping" => MessageCommand::PING {
+            "ping" if !params.is_empty() => MessageCommand::PING {
                 token: params.get(0).unwrap().to_string(),
             },
-            "privmsg" if params.get(1).unwrap().to_string().starts_with("\u{1}") == false =>
+            "privmsg" if params.len() >= 2 && !params[1].starts_with('\u{1}') =>
             {
                 MessageCommand::PRIVMSG {
                     message_target: params.get(0).unwrap().to_string(),
                     text: params.get(1).unwrap().to_string(),
                 }
             }
-            "privmsg" if params.get(1).unwrap().to_string().starts_with("\u{1}") == true =>
+            "privmsg" if params.len() >= 2 && params[1].starts_with('\u{1}') =>
             {
                 let inner_text = params
                     .get(1)
@@ -64,13 +81,16 @@ impl IrcMessage
                     .trim_start_matches("\u{1}")
                     .trim_end_matches("\u{1}")
                     .to_string();
-                let inner_text_split: Vec<String> =
-                    inner_text.split(" ").map(|x| x.to_string()).collect();
+                let inner_text_split: Vec<String> = CtcpMessage::tokenize_arguments(&inner_text);
                 // println!("#### INNER_TEXT_SPLIT {:#?}",inner_text_split);
                 MessageCommand::PRIVMSGCTCP {
                     message_target: params.get(0).unwrap().to_string(),
                     text: params.get(1).unwrap().to_string(),
-                    inner_message: match inner_text_split[0].to_lowercase().as_str()
+                    inner_message: match inner_text_split
+                        .first()
+                        .map(|x| x.to_lowercase())
+                        .unwrap_or_default()
+                        .as_str()
                     {
                         //#### INNER_TEXT_SPLIT [
                         //    "DCC",
@@ -79,24 +99,32 @@ impl IrcMessage
                         //    "413319771",
                         //    "1023",
                         //]
-                        "dcc" => Some(CtcpMessage::DCC {
-                            query_type: match inner_text_split[1].to_lowercase().as_str()
-                            {
-                                "send" => DCCQueryType::SEND,
-                                "chat" => DCCQueryType::CHAT,
-                                _ => DCCQueryType::UNHANDLED,
-                            },
-                            argument: inner_text_split[2].to_string(),
-                            address: inner_text_split[3].to_string(),
-                            port: inner_text_split[4].to_string(),
+                        "dcc" => CtcpMessage::parse_dcc(&inner_text).ok(),
+                        "action" => Some(CtcpMessage::ACTION {
+                            text: CtcpMessage::get_ctcp_argument(&inner_text),
+                        }),
+                        "clientinfo" => Some(CtcpMessage::CLIENTINFO {
+                            token: CtcpMessage::get_ctcp_argument(&inner_text),
+                        }),
+                        "ping" => Some(CtcpMessage::PING {
+                            token: CtcpMessage::get_ctcp_argument(&inner_text),
                         }),
+                        "version" => Some(CtcpMessage::VERSION),
+                        "time" => Some(CtcpMessage::TIME),
+                        "source" => Some(CtcpMessage::SOURCE),
+                        "userinfo" => Some(CtcpMessage::USERINFO),
                         _ => Some(CtcpMessage::UNHANDLED),
                     },
                     inner_text,
                     inner_params: inner_text_split,
                 }
             }
-            "353" => MessageCommand::RPL_NAME_REPLY {
+            "notice" if params.len() >= 2 => MessageCommand::NOTICE {
+                message_target: params[0].to_string(),
+                text: params[1].to_string(),
+            },
+            "001" => MessageCommand::RPL_WELCOME,
+            "353" if params.len() >= 4 => MessageCommand::RPL_NAME_REPLY {
                 channel: params.get(2).unwrap().to_string(),
                 names: params
                     .get(3)
@@ -125,7 +153,7 @@ impl IrcMessage
         });
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MessageCommand
 {
     PING
@@ -145,6 +173,12 @@ pub enum MessageCommand
         inner_text: String,
         inner_params: Vec<String>,
     },
+    NOTICE
+    {
+        message_target: String,
+        text: String,
+    },
+    RPL_WELCOME,
     RPL_NAME_REPLY
     {
         channel: String,
@@ -153,7 +187,7 @@ pub enum MessageCommand
     NONHANDLED,
     EMPTY,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CtcpMessage
 {
     ACTION
@@ -170,11 +204,17 @@ pub enum CtcpMessage
         argument: String,
         address: String,
         port: String,
+        size: Option<u64>,
+        token: Option<String>,
     },
     PING
     {
         token: String,
     },
+    SOURCE,
+    TIME,
+    USERINFO,
+    VERSION,
     UNHANDLED,
 }
 
@@ -187,68 +227,235 @@ impl CtcpMessage
     {
         matches!(self, Self::DCC { .. })
     }
-    pub fn get_full_address(&self) -> Result<String, &'static str>
+    /// Everything after the CTCP command, e.g. the token of `PING 1234`.
+    fn get_ctcp_argument(inner_text: &str) -> String
     {
-        if let CtcpMessage::DCC {
-            query_type,
-            argument,
-            address,
-            port,
-        } = &self
+        match inner_text.split_once(' ')
         {
-            Ok(
-                CtcpMessage::get_full_address_from_strings(address.to_string(), port.to_string())
-                    .unwrap()
-                    .to_string(),
-            )
+            Some((_, argument)) => argument.to_string(),
+            None => String::new(),
+        }
+    }
+    /// The CTCP reply to send back in a NOTICE, without the `\u{1}` delimiters. Messages that
+    /// do not expect a reply, such as ACTION and DCC, return `None`.
+    pub fn get_reply(&self, version: &str, now_unix_seconds: u64) -> Option<String>
+    {
+        match self
+        {
+            CtcpMessage::VERSION => Some(format!("VERSION {}", version)),
+            CtcpMessage::PING { token } => Some(format!("PING {}", token).trim_end().to_string()),
+            CtcpMessage::TIME => Some(format!("TIME {}", format_ctcp_time(now_unix_seconds))),
+            CtcpMessage::SOURCE => Some(format!("SOURCE {}", SOURCE_URL)),
+            CtcpMessage::CLIENTINFO { .. } => Some(format!("CLIENTINFO {}", SUPPORTED_CTCP)),
+            CtcpMessage::USERINFO => Some(format!("USERINFO {}", REAL_NAME)),
+            CtcpMessage::ACTION { .. } | CtcpMessage::DCC { .. } | CtcpMessage::UNHANDLED =>
+            {
+                None
+            }
+        }
+    }
+    /// Splits a CTCP body on whitespace, keeping `"quoted arguments"` together.
+    pub fn tokenize_arguments(inner_text: &str) -> Vec<String>
+    {
+        let mut tokens: Vec<String> = Vec::new();
+        let mut current = String::new();
+        let mut in_token = false;
+        let mut in_quotes = false;
+        for x in inner_text.chars()
+        {
+            if in_quotes
+            {
+                if x == '"'
+                {
+                    in_quotes = false;
+                }
+                else
+                {
+                    current.push(x);
+                }
+            }
+            else if x.is_whitespace()
+            {
+                if in_token
+                {
+                    tokens.push(current.clone());
+                    current.clear();
+                    in_token = false;
+                }
+            }
+            else if x == '"' && !in_token
+            {
+                in_token = true;
+                in_quotes = true;
+            }
+            else
+            {
+                in_token = true;
+                current.push(x);
+            }
+        }
+        if in_token
+        {
+            tokens.push(current);
+        }
+        tokens
+    }
+    /// Parses `DCC <type> <argument> <address> <port> [size] [token]`.
+    ///
+    /// Unquoted filenames may contain spaces, so the address and port are located from the end
+    /// of the message rather than by position.
+    pub fn parse_dcc(inner_text: &str) -> Result<CtcpMessage, &'static str>
+    {
+        let tokens = CtcpMessage::tokenize_arguments(inner_text);
+        if tokens.len() < 5 || !tokens[0].eq_ignore_ascii_case("dcc")
+        {
+            return Err("DCC message has too few arguments");
+        }
+        let query_type = match tokens[1].to_lowercase().as_str()
+        {
+            "send" => DCCQueryType::SEND,
+            "chat" => DCCQueryType::CHAT,
+            _ => DCCQueryType::UNHANDLED,
+        };
+        let arguments = &tokens[2..];
+        // Try address/port/size/token, then address/port/size, then address/port
+        for trailing_count in [4, 3, 2]
+        {
+            if arguments.len() <= trailing_count
+            {
+                continue;
+            }
+            let address_index = arguments.len() - trailing_count;
+            let trailing = &arguments[address_index..];
+            let address = &trailing[0];
+            let port = &trailing[1];
+            if !CtcpMessage::looks_like_dcc_address(address) || port.parse::<u16>().is_err()
+            {
+                continue;
+            }
+            let size = match trailing.get(2)
+            {
+                Some(t) => match t.parse::<u64>()
+                {
+                    Ok(v) => Some(v),
+                    Err(_e) => continue,
+                },
+                None => None,
+            };
+            return Ok(CtcpMessage::DCC {
+                query_type,
+                argument: arguments[..address_index].join(" "),
+                address: address.to_string(),
+                port: port.to_string(),
+                size,
+                token: trailing.get(3).map(|x| x.to_string()),
+            });
+        }
+        Err("Unable to locate address and port in DCC message")
+    }
+    fn looks_like_dcc_address(address: &str) -> bool
+    {
+        match address.parse::<u64>()
+        {
+            // Integer addresses below 1.0.0.0 are almost always part of the filename
+            Ok(v) => (1 << 24..=u32::MAX as u64).contains(&v),
+            Err(_e) => address.contains('.') || address.contains(':'),
+        }
+    }
+    pub fn get_full_address(&self) -> Result<SocketAddr, &'static str>
+    {
+        if let CtcpMessage::DCC { address, port, .. } = &self
+        {
+            CtcpMessage::get_full_address_from_strings(address.to_string(), port.to_string())
         }
         else
         {
-            return Err("Unable to get full_address of non DCC CtcpMessage");
+            Err("Unable to get full_address of non DCC CtcpMessage")
         }
     }
     pub fn get_full_address_from_strings(
         address: String,
         port: String,
-    ) -> Result<String, &'static str>
+    ) -> Result<SocketAddr, &'static str>
     {
-        //TODO: remove these lines before connecting to non-test or non-local devices
-
-        let converted_ip = CtcpMessage::convert_ip(address.to_string()).unwrap();
-        let mut ret_val = String::new();
-        ret_val.push_str(converted_ip.as_str());
-        // ret_val.push_str("192.168.1.29");
-        ret_val.push_str(":");
-        ret_val.push_str(port.as_str());
-        return Ok(ret_val);
+        let port = match port.parse::<u16>()
+        {
+            Ok(t) => t,
+            Err(_e) => return Err("Unexpected port in DCC address"),
+        };
+        if let Ok(ip) = CtcpMessage::convert_ip(&address)
+        {
+            return Ok(SocketAddr::new(ip, port));
+        }
+        // Anything that is not an IP literal is treated as a hostname
+        let mut resolved = match (address.as_str(), port).to_socket_addrs()
+        {
+            Ok(t) => t,
+            Err(_e) => return Err("Unable to resolve DCC hostname"),
+        };
+        match resolved.next()
+        {
+            Some(t) => Ok(t),
+            None => Err("DCC hostname did not resolve to any address"),
+        }
     }
-    fn convert_ip(start: String) -> Result<String, &'static str>
+    /// Accepts the classic unsigned 32-bit integer form as well as IPv4 and IPv6 literals.
+    pub fn convert_ip(start: &str) -> Result<IpAddr, &'static str>
     {
-        let int_val = {
-            let this = start.parse::<i32>();
-            match this
-            {
-                Ok(t) => t,
-                Err(_e) => return Err("Unexpected value in convert_ip"),
-            }
-        };
-        let y = int_val.to_be_bytes();
-        let mut ret_val = String::new();
-        ret_val.push_str(y[0].to_string().as_str());
-        ret_val.push_str(".");
-        ret_val.push_str(y[1].to_string().as_str());
-        ret_val.push_str(".");
-        ret_val.push_str(y[2].to_string().as_str());
-        ret_val.push_str(".");
-        ret_val.push_str(y[3].to_string().as_str());
-        Ok(ret_val.to_string())
+        if let Ok(int_val) = start.parse::<u32>()
+        {
+            return Ok(IpAddr::V4(Ipv4Addr::from(int_val)));
+        }
+        let unbracketed = start.trim_start_matches('[').trim_end_matches(']');
+        match unbracketed.parse::<IpAddr>()
+        {
+            Ok(t) => Ok(t),
+            Err(_e) => Err("Unexpected value in convert_ip"),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum DCCQueryType
 {
     SEND,
     CHAT,
     UNHANDLED,
 }
+
+/// A token bucket shared by every CTCP reply, so a flood of requests, from one nick or many,
+/// cannot get us disconnected for flooding the server.
+#[derive(Debug)]
+pub struct CtcpRateLimit
+{
+    tokens: u32,
+    last_refill: Instant,
+}
+
+impl CtcpRateLimit
+{
+    pub fn new(now: Instant) -> Self
+    {
+        CtcpRateLimit {
+            tokens: CTCP_REPLY_BURST,
+            last_refill: now,
+        }
+    }
+    /// Whether a reply may be sent at `now`, using up a token if so.
+    pub fn allow(&mut self, now: Instant) -> bool
+    {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refilled = (elapsed.as_millis() / CTCP_REPLY_INTERVAL.as_millis()) as u32;
+        if refilled > 0
+        {
+            self.tokens = (self.tokens + refilled).min(CTCP_REPLY_BURST);
+            self.last_refill += CTCP_REPLY_INTERVAL * refilled;
+        }
+        if self.tokens == 0
+        {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
@@ -0,0 +1,363 @@
+use crate::irc_message::{CtcpMessage, CtcpRateLimit, DCCQueryType, IrcMessage, MessageCommand};
+use crate::timestamp::format_ctcp_time;
+use std::time::{Duration, Instant};
+
+fn parse_ctcp_line(line: &str) -> CtcpMessage
+{
+    let message = IrcMessage::parse_message(&line.to_string()).unwrap();
+    match message.command
+    {
+        MessageCommand::PRIVMSGCTCP { inner_message, .. } => inner_message.unwrap(),
+        _ => panic!("Expected a CTCP message"),
+    }
+}
+
+fn assert_dcc(
+    message: CtcpMessage,
+    expected_argument: &str,
+    expected_address: &str,
+    expected_port: &str,
+    expected_size: Option<u64>,
+    expected_token: Option<&str>,
+)
+{
+    match message
+    {
+        CtcpMessage::DCC {
+            argument,
+            address,
+            port,
+            size,
+            token,
+            ..
+        } =>
+        {
+            assert_eq!(expected_argument, argument);
+            assert_eq!(expected_address, address);
+            assert_eq!(expected_port, port);
+            assert_eq!(expected_size, size);
+            assert_eq!(expected_token, token.as_deref());
+        }
+        _ => panic!("Expected a DCC message"),
+    }
+}
+
+#[test]
+fn tokenize_quoted_arguments_test()
+{
+    assert_eq!(
+        vec![
+            "DCC",
+            "SEND",
+            "Some Book - Author.epub",
+            "3232235777",
+            "5000"
+        ],
+        CtcpMessage::tokenize_arguments("DCC SEND \"Some Book - Author.epub\" 3232235777 5000")
+    );
+    assert_eq!(
+        vec!["DCC", "SEND", "a", "b"],
+        CtcpMessage::tokenize_arguments("  DCC   SEND a  b  ")
+    );
+    assert_eq!(vec!["it\"s"], CtcpMessage::tokenize_arguments("it\"s"));
+}
+#[test]
+fn dcc_send_searchbot_results_test()
+{
+    let message = parse_ctcp_line(
+        ":Search!Search@bookz.search.bot PRIVMSG rapere :\u{1}DCC SEND SearchBot_results_for__the_hobbit.txt.zip 1123456789 4123 2048\u{1}",
+    );
+    assert_dcc(
+        message,
+        "SearchBot_results_for__the_hobbit.txt.zip",
+        "1123456789",
+        "4123",
+        Some(2048),
+        None,
+    );
+}
+#[test]
+fn dcc_send_quoted_filename_test()
+{
+    let message = parse_ctcp_line(
+        ":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}DCC SEND \"Some Book - Author.epub\" 3232235777 5000 123456\u{1}",
+    );
+    match &message
+    {
+        CtcpMessage::DCC { query_type, .. } => assert!(matches!(query_type, DCCQueryType::SEND)),
+        _ => panic!("Expected a DCC message"),
+    }
+    assert_dcc(
+        message,
+        "Some Book - Author.epub",
+        "3232235777",
+        "5000",
+        Some(123456),
+        None,
+    );
+}
+#[test]
+fn dcc_send_unquoted_filename_with_spaces_test()
+{
+    let message =
+        parse_ctcp_line(":Oatmeal!~oat@oat.host PRIVMSG rapere :\u{1}DCC SEND Some Book 1999.epub 3232235777 5000 123456\u{1}");
+    assert_dcc(
+        message,
+        "Some Book 1999.epub",
+        "3232235777",
+        "5000",
+        Some(123456),
+        None,
+    );
+}
+#[test]
+fn dcc_send_trailing_numbers_in_filename_test()
+{
+    let message = parse_ctcp_line(
+        ":Oatmeal!~oat@oat.host PRIVMSG rapere :\u{1}DCC SEND Book 2 3232235777 5000 123456\u{1}",
+    );
+    assert_dcc(message, "Book 2", "3232235777", "5000", Some(123456), None);
+}
+#[test]
+fn dcc_send_passive_with_token_test()
+{
+    let message = parse_ctcp_line(
+        ":DV8!dv8@dv8.host PRIVMSG rapere :\u{1}DCC SEND \"A Book.epub\" 3232235777 0 654321 77\u{1}",
+    );
+    assert_dcc(
+        message,
+        "A Book.epub",
+        "3232235777",
+        "0",
+        Some(654321),
+        Some("77"),
+    );
+}
+#[test]
+fn dcc_send_extra_whitespace_test()
+{
+    let message = parse_ctcp_line(
+        ":Pondering!p@p.host PRIVMSG rapere :\u{1}DCC SEND   book.epub   3232235777  5000   99 \u{1}",
+    );
+    assert_dcc(message, "book.epub", "3232235777", "5000", Some(99), None);
+}
+#[test]
+fn dcc_send_without_size_test()
+{
+    let message = parse_ctcp_line(
+        ":Old!o@o.host PRIVMSG rapere :\u{1}DCC SEND book.epub 3232235777 5000\u{1}",
+    );
+    assert_dcc(message, "book.epub", "3232235777", "5000", None, None);
+}
+#[test]
+fn dcc_chat_test()
+{
+    let message =
+        parse_ctcp_line(":FServ!f@f.host PRIVMSG rapere :\u{1}DCC CHAT chat 413319771 1023\u{1}");
+    assert_dcc(message, "chat", "413319771", "1023", None, None);
+}
+#[test]
+fn dcc_send_malformed_test()
+{
+    assert!(CtcpMessage::parse_dcc("DCC SEND book.epub").is_err());
+    assert!(CtcpMessage::parse_dcc("DCC SEND book.epub notanaddress 5000").is_err());
+    assert!(CtcpMessage::parse_dcc("DCC SEND book.epub 3232235777 99999").is_err());
+}
+#[test]
+fn parse_malformed_message_test()
+{
+    // Truncated lines from the server are errors or unhandled, never panics
+    for line in [
+        "",
+        ":irc.example.net",
+        "PING",
+        "PRIVMSG rapere",
+        ":a!b@c 353 rapere =",
+    ]
+    {
+        match IrcMessage::parse_message(&line.to_string())
+        {
+            Ok(t) => assert!(matches!(t.command, MessageCommand::NONHANDLED)),
+            Err(_e) => (),
+        }
+    }
+    assert!(matches!(
+        parse_ctcp_line(":a!b@c PRIVMSG rapere :\u{1}\u{1}"),
+        CtcpMessage::UNHANDLED
+    ));
+}
+#[test]
+fn convert_ip_unsigned_integer_test()
+{
+    // 3232235777 overflowed the old i32 parsing
+    assert_eq!(
+        "192.168.1.1",
+        CtcpMessage::convert_ip("3232235777").unwrap().to_string()
+    );
+    assert_eq!(
+        "127.0.0.1",
+        CtcpMessage::convert_ip("2130706433").unwrap().to_string()
+    );
+    assert_eq!(
+        "255.255.255.255",
+        CtcpMessage::convert_ip("4294967295").unwrap().to_string()
+    );
+    assert!(CtcpMessage::convert_ip("4294967296").is_err());
+}
+#[test]
+fn full_address_ipv4_test()
+{
+    let address =
+        CtcpMessage::get_full_address_from_strings("3232235777".to_string(), "5000".to_string())
+            .unwrap();
+    assert_eq!("192.168.1.1:5000", address.to_string());
+    let address =
+        CtcpMessage::get_full_address_from_strings("10.0.0.2".to_string(), "5000".to_string())
+            .unwrap();
+    assert_eq!("10.0.0.2:5000", address.to_string());
+}
+#[test]
+fn full_address_ipv6_test()
+{
+    let address =
+        CtcpMessage::get_full_address_from_strings("2001:db8::1".to_string(), "5000".to_string())
+            .unwrap();
+    assert_eq!("[2001:db8::1]:5000", address.to_string());
+    let address =
+        CtcpMessage::get_full_address_from_strings("[::1]".to_string(), "5000".to_string())
+            .unwrap();
+    assert_eq!("[::1]:5000", address.to_string());
+}
+#[test]
+fn full_address_hostname_test()
+{
+    let address =
+        CtcpMessage::get_full_address_from_strings("localhost".to_string(), "5000".to_string())
+            .unwrap();
+    assert!(address.ip().is_loopback());
+    assert_eq!(5000, address.port());
+}
+#[test]
+fn full_address_invalid_port_test()
+{
+    assert!(CtcpMessage::get_full_address_from_strings(
+        "3232235777".to_string(),
+        "70000".to_string()
+    )
+    .is_err());
+}
+#[test]
+fn dcc_send_ipv6_address_test()
+{
+    let message = parse_ctcp_line(
+        ":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}DCC SEND \"A Book.epub\" 2001:db8::1 5000 123\u{1}",
+    );
+    assert_eq!(
+        "[2001:db8::1]:5000",
+        message.get_full_address().unwrap().to_string()
+    );
+}
+
+#[test]
+fn ctcp_queries_are_parsed_test()
+{
+    assert!(matches!(
+        parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}VERSION\u{1}"),
+        CtcpMessage::VERSION
+    ));
+    assert!(matches!(
+        parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}TIME\u{1}"),
+        CtcpMessage::TIME
+    ));
+    assert!(matches!(
+        parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}SOURCE\u{1}"),
+        CtcpMessage::SOURCE
+    ));
+    assert!(matches!(
+        parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}USERINFO\u{1}"),
+        CtcpMessage::USERINFO
+    ));
+    match parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}PING 1792418589 123\u{1}")
+    {
+        CtcpMessage::PING { token } => assert_eq!("1792418589 123", token),
+        _ => panic!("Expected a PING"),
+    }
+    match parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG #bookz :\u{1}ACTION waves at everyone\u{1}")
+    {
+        CtcpMessage::ACTION { text } => assert_eq!("waves at everyone", text),
+        _ => panic!("Expected an ACTION"),
+    }
+    assert!(matches!(
+        parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}CLIENTINFO\u{1}"),
+        CtcpMessage::CLIENTINFO { .. }
+    ));
+    assert!(matches!(
+        parse_ctcp_line(":Bsk!~bsk@bsk.host PRIVMSG rapere :\u{1}FINGER\u{1}"),
+        CtcpMessage::UNHANDLED
+    ));
+}
+#[test]
+fn ctcp_replies_test()
+{
+    let now = 1792418589;
+    assert_eq!(
+        Some("VERSION my client 1.0".to_string()),
+        CtcpMessage::VERSION.get_reply("my client 1.0", now)
+    );
+    assert_eq!(
+        Some("PING 1234".to_string()),
+        CtcpMessage::PING {
+            token: "1234".to_string()
+        }
+        .get_reply("v", now)
+    );
+    assert_eq!(
+        Some("TIME Mon Oct 19 14:03:09 2026 UTC".to_string()),
+        CtcpMessage::TIME.get_reply("v", now)
+    );
+    assert!(CtcpMessage::CLIENTINFO {
+        token: String::new()
+    }
+    .get_reply("v", now)
+    .unwrap()
+    .contains("VERSION"));
+    assert!(CtcpMessage::SOURCE.get_reply("v", now).is_some());
+    assert_eq!(
+        None,
+        CtcpMessage::ACTION {
+            text: "waves".to_string()
+        }
+        .get_reply("v", now)
+    );
+    // USERINFO gives the real name, never the version
+    assert_eq!(
+        Some("USERINFO nathan".to_string()),
+        CtcpMessage::USERINFO.get_reply("v", now)
+    );
+    assert!(CtcpMessage::CLIENTINFO {
+        token: String::new()
+    }
+    .get_reply("v", now)
+    .unwrap()
+    .contains("USERINFO"));
+}
+#[test]
+fn ctcp_rate_limit_test()
+{
+    let start = Instant::now();
+    let mut limit = CtcpRateLimit::new(start);
+    let allowed = (0..10).filter(|_| limit.allow(start)).count();
+    assert_eq!(4, allowed);
+    assert!(!limit.allow(start + Duration::from_secs(1)));
+    assert!(limit.allow(start + Duration::from_secs(2)));
+    assert!(!limit.allow(start + Duration::from_secs(3)));
+    // A long quiet spell only refills the burst
+    let later = start + Duration::from_secs(600);
+    assert_eq!(4, (0..10).filter(|_| limit.allow(later)).count());
+}
+#[test]
+fn ctcp_time_format_test()
+{
+    assert_eq!("Thu Jan 01 00:00:00 1970 UTC", format_ctcp_time(0));
+    assert_eq!("Tue Feb 29 23:59:59 2000 UTC", format_ctcp_time(951868799));
+}
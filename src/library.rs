@@ -0,0 +1,473 @@
+use crate::epub::read_epub_file;
+use crate::mobi::read_mobi_file;
+use crate::sanitize::{numbered_filename, sanitize_filename};
+use crate::search_result::{is_volume_word, SearchResult};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_LIBRARY_TEMPLATE: &str = "{author_sort}/{title} ({year}).{ext}";
+const PLACEHOLDERS: [&str; 7] = [
+    "author",
+    "author_sort",
+    "title",
+    "series",
+    "series_index",
+    "year",
+    "ext",
+];
+const TARGET_TAKEN: &str = "Another file appeared where the book was to be filed";
+const UNKNOWN_PLACEHOLDER: &str = "Unknown library template placeholder, use {author}, \
+{author_sort}, {title}, {series}, {series_index}, {year} or {ext}";
+/// Stands in for a directory or file name whose placeholders were all empty.
+const UNKNOWN: &str = "Unknown";
+
+/// What is known about a downloaded book, from its search result and the file itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookMetadata
+{
+    pub author: String,
+    pub title: String,
+    pub series: Option<String>,
+    /// The volume number within the series, without leading zeros.
+    pub series_index: Option<String>,
+    pub year: Option<String>,
+    /// Lowercase, without the dot.
+    pub extension: Option<String>,
+    /// The file is encrypted and will only open on the device it was bought for.
+    pub drm_locked: bool,
+}
+
+impl BookMetadata
+{
+    pub fn from_result(result: &SearchResult) -> Self
+    {
+        let (series, series_index) = match &result.series
+        {
+            Some(t) => split_series(t),
+            None => (None, None),
+        };
+        // `(1965)` ends up as a tag, a bot may also send `::YEAR:: 1965`
+        let year = result
+            .get_metadata("year")
+            .into_iter()
+            .chain(
+                result
+                    .metadata
+                    .iter()
+                    .filter(|(key, _)| key == "tag")
+                    .map(|(_, value)| value.as_str()),
+            )
+            .find(|x| is_year(x))
+            .map(|x| x.to_string());
+        BookMetadata {
+            author: result.author.clone(),
+            title: result.title.clone(),
+            series,
+            series_index,
+            year,
+            extension: result.extension.clone(),
+            drm_locked: false,
+        }
+    }
+    /// Fills in and overrides fields with what the file says about itself, which is more
+    /// trustworthy than a bot's file name.
+    pub fn merge(&mut self, embedded: &BookMetadata)
+    {
+        if !embedded.author.trim().is_empty()
+        {
+            self.author = embedded.author.clone();
+        }
+        if !embedded.title.trim().is_empty()
+        {
+            self.title = embedded.title.clone();
+        }
+        if embedded.series.is_some()
+        {
+            self.series = embedded.series.clone();
+            self.series_index = embedded.series_index.clone();
+        }
+        if embedded.year.is_some()
+        {
+            self.year = embedded.year.clone();
+        }
+        if embedded.extension.is_some()
+        {
+            self.extension = embedded.extension.clone();
+        }
+        self.drm_locked |= embedded.drm_locked;
+    }
+    fn field(&self, name: &str) -> String
+    {
+        match name
+        {
+            "author" => self.author.clone(),
+            "author_sort" => author_sort(&self.author),
+            "title" => self.title.clone(),
+            "series" => self.series.clone().unwrap_or_default(),
+            "series_index" => self.series_index.clone().unwrap_or_default(),
+            "year" => self.year.clone().unwrap_or_default(),
+            "ext" => self.extension.clone().unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Metadata embedded in the file itself, for the formats it can be read from.
+pub fn embedded_metadata(path: &Path) -> Option<BookMetadata>
+{
+    let extension = path
+        .extension()
+        .map(|x| x.to_string_lossy().to_lowercase())?;
+    match extension.as_str()
+    {
+        "epub" =>
+        {
+            let epub = read_epub_file(path).ok()?;
+            Some(BookMetadata {
+                author: epub.authors.join(" & "),
+                title: epub.title,
+                series: epub.series,
+                series_index: epub.series_index,
+                year: date_year(epub.date),
+                extension: Some(extension),
+                drm_locked: false,
+            })
+        }
+        "mobi" | "azw" | "azw3" | "prc" =>
+        {
+            let mobi = read_mobi_file(path).ok()?;
+            Some(BookMetadata {
+                drm_locked: mobi.drm_locked(),
+                author: mobi.authors.join(" & "),
+                title: mobi.title,
+                series: None,
+                series_index: None,
+                year: date_year(mobi.date),
+                extension: Some(extension),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The year of a date such as `1965-08-01`.
+fn date_year(date: Option<String>) -> Option<String>
+{
+    date.and_then(|x| x.get(..4).filter(|x| is_year(x)).map(|x| x.to_string()))
+}
+
+/// `Frank Herbert` becomes `Herbert, Frank`. Only the first of several authors is used, and
+/// names already written last name first are kept.
+pub fn author_sort(author: &str) -> String
+{
+    let first_author = author
+        .split(" & ")
+        .next()
+        .unwrap_or("")
+        .split(" and ")
+        .next()
+        .unwrap_or("")
+        .trim();
+    if first_author.contains(',')
+    {
+        return first_author.to_string();
+    }
+    let mut words = first_author.split_whitespace().collect::<Vec<&str>>();
+    let suffix = match words.last()
+    {
+        Some(t) if words.len() > 2 && is_suffix(t) => words.pop(),
+        _ => None,
+    };
+    if words.len() < 2
+    {
+        return first_author.to_string();
+    }
+    let last_name = words.pop().unwrap();
+    let mut ret_val = format!("{}, {}", last_name, words.join(" "));
+    if let Some(suffix) = suffix
+    {
+        ret_val.push_str(&format!(", {}", suffix));
+    }
+    ret_val
+}
+
+fn is_suffix(word: &str) -> bool
+{
+    ["jr", "jr.", "sr", "sr.", "ii", "iii", "iv"].contains(&word.to_lowercase().as_str())
+}
+
+fn is_year(text: &str) -> bool
+{
+    text.len() == 4
+        && text.chars().all(|x| x.is_ascii_digit())
+        && (text.starts_with('1') || text.starts_with("20"))
+}
+
+/// `Dune 01` becomes `Dune` and `1`, `Discworld #3` becomes `Discworld` and `3`.
+fn split_series(series: &str) -> (Option<String>, Option<String>)
+{
+    match series.rsplit_once(' ')
+    {
+        Some((name, number)) =>
+        {
+            let number = number.trim_start_matches('#').trim_start_matches('0');
+            let number = if number.is_empty() || number.starts_with('.')
+            {
+                format!("0{}", number)
+            }
+            else
+            {
+                number.to_string()
+            };
+            // `Dune Book 1` is the Dune series
+            let name = match name.rsplit_once(' ')
+            {
+                Some((series_name, word)) if is_volume_word(word) => series_name,
+                _ => name,
+            };
+            (Some(name.trim().to_string()), Some(number))
+        }
+        None => (Some(series.to_string()), None),
+    }
+}
+
+/// Where in the library a book goes, such as `{author_sort}/{title} ({year}).{ext}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryTemplate
+{
+    components: Vec<String>,
+}
+
+impl Default for LibraryTemplate
+{
+    fn default() -> Self
+    {
+        LibraryTemplate::parse(DEFAULT_LIBRARY_TEMPLATE).unwrap()
+    }
+}
+
+impl LibraryTemplate
+{
+    pub fn parse(text: &str) -> Result<LibraryTemplate, &'static str>
+    {
+        if text.starts_with(['/', '\\'])
+        {
+            return Err("The library template must be relative to the library");
+        }
+        let components = text
+            .split(['/', '\\'])
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        for component in components.iter()
+        {
+            if component.trim().is_empty()
+            {
+                return Err("The library template has an empty directory name");
+            }
+            let mut rest = component.as_str();
+            while let Some(start) = rest.find('{')
+            {
+                let end = match rest[start..].find('}')
+                {
+                    Some(t) => start + t,
+                    None => return Err("The library template has an unclosed {"),
+                };
+                if !PLACEHOLDERS.contains(&&rest[start + 1..end])
+                {
+                    return Err(UNKNOWN_PLACEHOLDER);
+                }
+                rest = &rest[end + 1..];
+            }
+        }
+        Ok(LibraryTemplate { components })
+    }
+    /// The path of a book relative to the library. Brackets left empty by a missing field
+    /// are dropped, so `{title} ({year})` is just the title when the year is unknown.
+    pub fn render(&self, metadata: &BookMetadata) -> PathBuf
+    {
+        let mut ret_val = PathBuf::new();
+        for component in self.components.iter()
+        {
+            let mut rendered = component.clone();
+            for name in PLACEHOLDERS.iter()
+            {
+                // Separators in a value would create directories of their own
+                let value = metadata.field(name).replace(['/', '\\'], " ");
+                rendered = rendered.replace(&format!("{{{}}}", name), value.trim());
+            }
+            ret_val.push(tidy_component(&rendered));
+        }
+        ret_val
+    }
+}
+
+fn tidy_component(component: &str) -> String
+{
+    let mut tidied = component.to_string();
+    for empty in ["()", "[]", "{}"]
+    {
+        tidied = tidied.replace(empty, "");
+    }
+    tidied = tidied.split_whitespace().collect::<Vec<&str>>().join(" ");
+    tidied = tidied.replace(" .", ".");
+    tidied = tidied
+        .trim_matches(|x: char| x == '-' || x == ',' || x.is_whitespace())
+        .to_string();
+    match sanitize_filename(&tidied)
+    {
+        // A lone extension, as in `.epub`, means the name itself was empty
+        Ok(t) if !tidied.starts_with('.') => t,
+        _ => match tidied.rsplit_once('.')
+        {
+            Some((_, extension)) if !extension.is_empty() =>
+            {
+                format!("{}.{}", UNKNOWN, extension)
+            }
+            _ => UNKNOWN.to_string(),
+        },
+    }
+}
+
+/// What filing a book into the library amounts to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filing
+{
+    Move(PathBuf),
+    /// The library already has an identical copy here.
+    Duplicate(PathBuf),
+    /// The book is already where it belongs.
+    InPlace,
+}
+
+/// Decides where `source` goes under `root`. Taken names get a number, unless the file
+/// there is identical.
+pub fn plan_filing(root: &Path, relative: &Path, source: &Path) -> Filing
+{
+    let target = root.join(relative);
+    if same_file(&target, source)
+    {
+        return Filing::InPlace;
+    }
+    let file_name = target
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut candidate = target.clone();
+    let mut counter = 1;
+    while candidate.exists()
+    {
+        if same_contents(&candidate, source)
+        {
+            return Filing::Duplicate(candidate);
+        }
+        candidate = target.with_file_name(numbered_filename(&file_name, counter));
+        counter += 1;
+    }
+    Filing::Move(candidate)
+}
+
+/// Carries out a filing, returning where the book is now. A file that took the target name
+/// since the filing was planned is never overwritten, the filing fails instead.
+pub fn file_book(source: &Path, filing: &Filing) -> Result<PathBuf, &'static str>
+{
+    match filing
+    {
+        Filing::InPlace => Ok(source.to_path_buf()),
+        Filing::Duplicate(existing) => match fs::remove_file(source)
+        {
+            Ok(_) => Ok(existing.clone()),
+            Err(_e) => Err("Unable to remove the duplicate download"),
+        },
+        Filing::Move(target) =>
+        {
+            if let Some(parent) = target.parent()
+            {
+                if fs::create_dir_all(parent).is_err()
+                {
+                    return Err("Unable to create the library directory");
+                }
+            }
+            // Unlike a rename, a hard link never replaces a file that appeared at the target
+            // after the filing was planned
+            match fs::hard_link(source, target)
+            {
+                Ok(_) => return remove_filed_source(source, target),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(TARGET_TAKEN),
+                Err(_e) => (),
+            }
+            // Linking fails across file systems and on some that lack links, so copy instead
+            let mut copy = match OpenOptions::new().write(true).create_new(true).open(target)
+            {
+                Ok(t) => t,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(TARGET_TAKEN),
+                Err(_e) => return Err("Unable to move the book into the library"),
+            };
+            match File::open(source).and_then(|mut x| io::copy(&mut x, &mut copy))
+            {
+                Ok(_) => remove_filed_source(source, target),
+                Err(_e) =>
+                {
+                    let _ = fs::remove_file(target);
+                    Err("Unable to move the book into the library")
+                }
+            }
+        }
+    }
+}
+
+/// Removes the download once it also exists at `target`.
+fn remove_filed_source(source: &Path, target: &Path) -> Result<PathBuf, &'static str>
+{
+    match fs::remove_file(source)
+    {
+        Ok(_) => Ok(target.to_path_buf()),
+        Err(_e) => Err("Unable to move the book into the library"),
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool
+{
+    match (a.canonicalize(), b.canonicalize())
+    {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Compares the files byte for byte once their sizes match. A duplicate gets deleted, and a
+/// matching checksum is not proof enough for that.
+fn same_contents(a: &Path, b: &Path) -> bool
+{
+    let size = |x: &Path| fs::metadata(x).map(|x| x.len()).ok();
+    if size(a).is_none() || size(a) != size(b)
+    {
+        return false;
+    }
+    let (mut a, mut b) = match (File::open(a), File::open(b))
+    {
+        (Ok(a), Ok(b)) => (BufReader::new(a), BufReader::new(b)),
+        _ => return false,
+    };
+    loop
+    {
+        let (a_data, b_data) = match (a.fill_buf(), b.fill_buf())
+        {
+            (Ok(a_data), Ok(b_data)) => (a_data, b_data),
+            _ => return false,
+        };
+        let length = a_data.len().min(b_data.len());
+        if length == 0
+        {
+            return a_data.is_empty() && b_data.is_empty();
+        }
+        if a_data[..length] != b_data[..length]
+        {
+            return false;
+        }
+        a.consume(length);
+        b.consume(length);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::library::{author_sort, file_book, plan_filing, BookMetadata, Filing, LibraryTemplate};
+use crate::search_result::SearchResult;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn metadata(line: &str) -> BookMetadata
+{
+    BookMetadata::from_result(&SearchResult::parse(line).unwrap())
+}
+
+fn scratch_dir(name: &str) -> PathBuf
+{
+    let dir = env::temp_dir().join(format!("library-test-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn metadata_from_result_test()
+{
+    let dune = metadata("!Bot1 Frank Herbert - Dune 01 - Dune (1965) (retail).epub ::INFO:: 1MB");
+    assert_eq!("Frank Herbert", dune.author);
+    assert_eq!("Dune", dune.title);
+    assert_eq!(Some("Dune".to_string()), dune.series);
+    assert_eq!(Some("1".to_string()), dune.series_index);
+    assert_eq!(Some("1965".to_string()), dune.year);
+    assert_eq!(Some("epub".to_string()), dune.extension);
+    let foundation = metadata("!Bot1 Isaac Asimov - Foundation Book 1 - Foundation.epub");
+    assert_eq!(Some("Foundation".to_string()), foundation.series);
+    assert_eq!(Some("1".to_string()), foundation.series_index);
+    let mut hobbit = metadata("!Bot1 Tolkien - The Hobbit.mobi ::YEAR:: 1937");
+    assert_eq!(Some("1937".to_string()), hobbit.year);
+    hobbit.merge(&BookMetadata {
+        author: "J. R. R. Tolkien".to_string(),
+        title: String::new(),
+        ..BookMetadata::default()
+    });
+    assert_eq!("J. R. R. Tolkien", hobbit.author);
+    assert_eq!("The Hobbit", hobbit.title);
+    assert_eq!(Some("1937".to_string()), hobbit.year);
+}
+#[test]
+fn author_sort_test()
+{
+    assert_eq!("Herbert, Frank", author_sort("Frank Herbert"));
+    assert_eq!("Tolkien, J. R. R.", author_sort("J. R. R. Tolkien"));
+    assert_eq!("Herbert, Frank", author_sort("Herbert, Frank"));
+    assert_eq!(
+        "Pratchett, Terry",
+        author_sort("Terry Pratchett & Neil Gaiman")
+    );
+    assert_eq!(
+        "King, Martin Luther, Jr.",
+        author_sort("Martin Luther King Jr.")
+    );
+    assert_eq!("Homer", author_sort("Homer"));
+    assert_eq!("", author_sort(""));
+}
+#[test]
+fn library_template_test()
+{
+    let template = LibraryTemplate::default();
+    let dune = metadata("!Bot1 Frank Herbert - Dune (1965).epub");
+    assert_eq!(
+        Path::new("Herbert, Frank").join("Dune (1965).epub"),
+        template.render(&dune)
+    );
+    // Brackets around a missing year are dropped, a missing author is Unknown
+    let anonymous = metadata("!Bot1 Beowulf.epub");
+    assert_eq!(
+        Path::new("Unknown").join("Beowulf.epub"),
+        template.render(&anonymous)
+    );
+    let template =
+        LibraryTemplate::parse("{series}/[{series_index}] {title}: {author}.{ext}").unwrap();
+    let messiah = metadata("!Bot1 Frank Herbert - Dune 02 - Dune Messiah.epub");
+    assert_eq!(
+        Path::new("Dune").join("[2] Dune Messiah Frank Herbert.epub"),
+        template.render(&messiah)
+    );
+    // Values cannot add directories
+    let mut slashed = dune.clone();
+    slashed.title = "AC/DC".to_string();
+    assert_eq!(
+        Path::new("Herbert, Frank").join("AC DC (1965).epub"),
+        LibraryTemplate::default().render(&slashed)
+    );
+    assert!(LibraryTemplate::parse("{author}/{isbn}.{ext}").is_err());
+    assert!(LibraryTemplate::parse("/books/{title}").is_err());
+    assert!(LibraryTemplate::parse("{author}//{title}").is_err());
+    assert!(LibraryTemplate::parse("{author/{title}").is_err());
+}
+#[test]
+fn plan_filing_test()
+{
+    let dir = scratch_dir("plan");
+    let library = dir.join("library");
+    let relative = Path::new("Herbert, Frank").join("Dune.epub");
+    let download = dir.join("Frank Herbert - Dune.epub");
+    fs::write(&download, b"first edition").unwrap();
+    let target = library.join(&relative);
+    assert_eq!(
+        Filing::Move(target.clone()),
+        plan_filing(&library, &relative, &download)
+    );
+    assert_eq!(
+        Ok(target.clone()),
+        file_book(&download, &Filing::Move(target.clone()))
+    );
+    assert!(!download.exists());
+    assert_eq!(Filing::InPlace, plan_filing(&library, &relative, &target));
+
+    // A different book under the same name gets a number
+    fs::write(&download, b"second edition").unwrap();
+    let numbered = library.join("Herbert, Frank").join("Dune (1).epub");
+    assert_eq!(
+        Filing::Move(numbered.clone()),
+        plan_filing(&library, &relative, &download)
+    );
+    file_book(&download, &Filing::Move(numbered.clone())).unwrap();
+
+    // The same book again is a duplicate of whichever copy matches
+    fs::write(&download, b"second edition").unwrap();
+    let filing = plan_filing(&library, &relative, &download);
+    assert_eq!(Filing::Duplicate(numbered.clone()), filing);
+    assert_eq!(Ok(numbered.clone()), file_book(&download, &filing));
+    assert!(!download.exists());
+
+    // Same size and CRC32 but different bytes is a different book
+    let collision = library.join("Herbert, Frank").join("Dune (2).epub");
+    fs::write(&collision, b"edition 09685295").unwrap();
+    fs::write(&download, b"edition 12060020").unwrap();
+    assert_eq!(
+        Filing::Move(library.join("Herbert, Frank").join("Dune (3).epub")),
+        plan_filing(&library, &relative, &download)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn file_book_never_overwrites_test()
+{
+    let dir = scratch_dir("collision");
+    let download = dir.join("Frank Herbert - Dune.epub");
+    let target = dir.join("Dune.epub");
+    let filing = Filing::Move(target.clone());
+    // Another file takes the name between planning and filing
+    fs::write(&download, b"download").unwrap();
+    fs::write(&target, b"already filed").unwrap();
+    let result = file_book(&download, &filing);
+    let contents = (fs::read(&download).unwrap(), fs::read(&target).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(result.is_err());
+    assert_eq!((b"download".to_vec(), b"already filed".to_vec()), contents);
+}
@@ -1,253 +1,2303 @@
+use bot_reply::*;
+use cancel::*;
+use config::*;
+use dcc_chat::*;
+use dcc_policy::*;
+use download_queue::*;
+use export::*;
+use history::*;
 use irc_connection::*;
 use irc_message::*;
+use library::*;
 use message_prefix::*;
-use pkzip::*;
+use ranking::*;
+use reading_list::*;
+use result_browser::*;
+use result_cache::*;
+use sanitize::*;
+use search_provider::*;
+use search_result::*;
 use std::collections::HashMap;
-use std::fs::File;
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
 use std::io::prelude::*;
-use std::io::{stdin, BufReader};
-use std::net::TcpStream;
+use std::io::{stdin, BufReader, ErrorKind};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
 use std::ops::DerefMut;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
-use std::{thread, time};
+use std::{process, thread, time};
+use timestamp::{format_log_time, unix_seconds};
+use tui::*;
+use verify::*;
+use watchlist::*;
+use works::*;
 
+mod bot_reply;
+#[cfg(test)]
+mod bot_reply_test;
+mod cancel;
+mod config;
+#[cfg(test)]
+mod config_test;
+mod crc32;
+#[cfg(test)]
+mod crc32_test;
+mod dcc_chat;
+#[cfg(test)]
+mod dcc_chat_test;
+mod dcc_policy;
+#[cfg(test)]
+mod dcc_policy_test;
+mod download_queue;
+#[cfg(test)]
+mod download_queue_test;
+mod epub;
+#[cfg(test)]
+mod epub_test;
+mod export;
+#[cfg(test)]
+mod export_test;
+mod history;
+#[cfg(test)]
+mod history_test;
 mod irc_connection;
 mod irc_message;
+#[cfg(test)]
+mod irc_message_test;
+mod library;
+#[cfg(test)]
+mod library_test;
 mod message_prefix;
+mod mobi;
+#[cfg(test)]
+mod mobi_test;
 mod pkzip;
+#[cfg(test)]
 mod pkzip_test;
+mod ranking;
+#[cfg(test)]
+mod ranking_test;
+mod reading_list;
+#[cfg(test)]
+mod reading_list_test;
+mod result_browser;
+#[cfg(test)]
+mod result_browser_test;
+mod result_cache;
+#[cfg(test)]
+mod result_cache_test;
+mod sanitize;
+#[cfg(test)]
+mod sanitize_test;
+mod search_provider;
+#[cfg(test)]
+mod search_provider_test;
+mod search_result;
+#[cfg(test)]
+mod search_result_test;
+mod timestamp;
+mod tui;
+#[cfg(test)]
+mod tui_test;
+mod verify;
+#[cfg(test)]
+mod verify_test;
+mod watchlist;
+#[cfg(test)]
+mod watchlist_test;
+mod works;
+#[cfg(test)]
+mod works_test;
 
 fn main()
 {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let mut config = match Config::from_args(&args)
+    {
+        Ok(t) => t,
+        Err(e) =>
+        {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if config.output_format.is_some()
+    {
+        // stdout is for the exported results alone
+        report_to_stderr(true);
+    }
+    let download_dir = env::current_dir().expect("Unable to read current directory");
+    if let Err(e) = config.load_search_dialects(&download_dir.join(SEARCH_DIALECTS_FILE_NAME))
+    {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+    if let Err(e) = config.load_bot_replies(&download_dir.join(BOT_REPLIES_FILE_NAME))
+    {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+    let cache = ResultCache::new(
+        download_dir.join(CACHE_DIR_NAME),
+        config.cache_ttl,
+        &config.server,
+        &config.channel,
+    );
+    cache.prune(CACHE_PRUNE_AGE);
+    let history = History::new(Some(download_dir.join(HISTORY_FILE_NAME)));
+    if let Some(command) = config.history_command
+    {
+        let records = history.records();
+        match command
+        {
+            HistoryCommand::Show => print!("{}", render_history(&records)),
+            HistoryCommand::Stats => print!("{}", render_stats(&records)),
+        }
+        return;
+    }
+    if config.organize
+    {
+        organize_downloads(&config, &history);
+        return;
+    }
+    if config.offline
+    {
+        browse_offline(&config, &cache, &history, &download_dir);
+        return;
+    }
+    if let Some(command) = &config.watch_command
+    {
+        if let Err(e) = edit_watchlist(command, &download_dir)
+        {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let queue_path = download_dir.join(QUEUE_FILE_NAME);
+    let mut queue = match DownloadQueue::load(queue_path.clone())
+    {
+        Ok(t) => t,
+        Err(e) =>
+        {
+            eprintln!("{}: {}, fix or remove it to continue.", e, queue_path.display());
+            process::exit(1);
+        }
+    };
     //Connect to server
-    let mut connex = IrcConnection::connect("66.207.167.12:6660").unwrap();
+    let mut connex = IrcConnection::connect(&config.server).unwrap();
 
     let mut users: HashMap<String, Vec<String>> = HashMap::new();
     let user_arc = Arc::new(Mutex::new(users));
 
     let user_arc_clone = Arc::clone(&user_arc);
     let (tx, rx) = mpsc::channel();
+    // Only the full-screen interface keeps a sender of its own, dropped everywhere else so that
+    // losing the connection closes the channel
+    let event_tx = tx.clone();
     //start read loop thread
     // read_loop(&mut connex);
     let read_connex = connex.try_clone().unwrap();
+    let ctcp_version = config.version.clone();
+    let mut classifier = BotReplyClassifier::with_default_families();
+    // Each family added goes first, so adding them backwards keeps the order of the file
+    for family in config.bot_families.iter().rev()
+    {
+        classifier.add_family(family.clone());
+    }
     let _read_loop_handle = thread::spawn(move || {
-        read_loop(read_connex, tx, user_arc_clone);
+        read_loop(
+            read_connex,
+            tx,
+            classifier,
+            ctcp_version,
+            user_arc_clone,
+        );
     });
     //login to #bookz channel
     connex.send_command_args("NICK", "rapere").unwrap();
+    let real_name = format!(" :{}", REAL_NAME);
     connex
-        .send_command_multiple_args("USER", vec!["rapere", " ", "8", " ", "*", " :nathan"])
+        .send_command_multiple_args("USER", vec!["rapere", " ", "8", " ", "*", &real_name])
         .unwrap();
-    println!("Connecting... Please wait...");
-    thread::sleep(time::Duration::from_secs(10));
-    connex.send_command_args("JOIN", "#ebooks").unwrap();
+    report("Connecting... Please wait...");
+    let part_files = PartFiles::default();
+    install_ctrlc_handler(connex.try_clone().unwrap(), part_files.clone());
+    if !wait_until_registered(&rx, config.timeouts.registration)
+    {
+        eprintln!("The server did not accept our registration in time.");
+        let _ = connex.send_command_args("QUIT", ":Registration timed out");
+        process::exit(1);
+    }
+    connex.send_command_args("JOIN", &config.channel).unwrap();
 
     assert!(matches!(connex.status, ConnectionStatus::Connected));
-    //Ask user for desired book
+    if let Some(format) = config.output_format
+    {
+        drop(event_tx);
+        let query = match &config.query
+        {
+            Some(t) => t.clone(),
+            None => ask_for_title(),
+        };
+        match search_for_packs(&mut connex, &rx, &user_arc, &query, &config, &cache, &history)
+        {
+            Ok(packlist) => print!("{}", export_results(format, &query, &packlist)),
+            Err(e) => eprintln!("{}", e),
+        }
+        let _ = connex.send_command_args("QUIT", ":Thank you, come again!");
+        return;
+    }
+    let mut resume = false;
+    if queue.has_pending()
+    {
+        println!(
+            "There are {} unfinished downloads from a previous session. (y) to resume",
+            queue.requests.len()
+        );
+        let mut buf = String::new();
+        stdin().read_line(&mut buf).unwrap();
+        resume = buf.starts_with('y');
+    }
+    let terminal = if config.fserve.is_none()
+        && config.reading_list.is_none()
+        && !config.daemon
+        && !config.plain
+        && !config.auto_pick
+    {
+        match TerminalBackend::enter()
+        {
+            Ok(t) => Some(t),
+            Err(e) =>
+            {
+                println!("{} Using line-based prompts instead.", e);
+                None
+            }
+        }
+    }
+    else
+    {
+        None
+    };
+    if let Some(terminal) = terminal
+    {
+        if !resume
+        {
+            queue.requests.clear();
+        }
+        run_tui(
+            terminal,
+            &mut connex,
+            &rx,
+            event_tx,
+            &user_arc,
+            &mut queue,
+            &download_dir,
+            &config,
+            &cache,
+            &history,
+            &part_files,
+        );
+        // Anything still transferring was abandoned when the user quit
+        part_files.remove_all();
+    }
+    else
+    {
+        drop(event_tx);
+        if let Some(fserve) = &config.fserve
+        {
+            if !resume
+            {
+                queue.requests.clear();
+            }
+            if let Err(e) = request_from_fserve(&mut connex, &rx, fserve, &config)
+            {
+                println!("Unable to fetch {} from {}: {}", fserve.path, fserve.bot, e);
+                return;
+            }
+            let file_name = fserve.path.rsplit(['/', '\\']).next().unwrap_or("");
+            let id = queue.push(&fserve.bot, "", file_name);
+            // The fserve already has our request, so only its DCC SEND is outstanding
+            report_queue_error(queue.set_state(id, DownloadState::Requested));
+            queue.touch(id);
+        }
+        else if let Some(reading_list) = &config.reading_list
+        {
+            if !resume
+            {
+                queue.requests.clear();
+            }
+            let batch = run_batch(
+                &mut connex,
+                &rx,
+                &user_arc,
+                reading_list,
+                &mut queue,
+                &config,
+                &cache,
+                &history,
+            );
+            if let Err(e) = batch
+            {
+                println!("Unable to run the reading list: {}", e);
+                let _ = connex.send_command_args("QUIT", ":Thank you, come again!");
+                return;
+            }
+        }
+        else if config.daemon
+        {
+            if !resume
+            {
+                queue.requests.clear();
+            }
+            let watched = run_watch_daemon(
+                &mut connex,
+                &rx,
+                &user_arc,
+                &mut queue,
+                &download_dir,
+                &config,
+                &cache,
+                &history,
+                &part_files,
+            );
+            if let Err(e) = watched
+            {
+                println!("The watch daemon stopped: {}", e);
+            }
+        }
+        else if !resume
+        {
+            queue.requests.clear();
+            let query = match &config.query
+            {
+                Some(t) => t.clone(),
+                None => ask_for_title(),
+            };
+            let searched =
+                search_for_packs(&mut connex, &rx, &user_arc, &query, &config, &cache, &history);
+            let packlist = match searched
+            {
+                Ok(t) => t,
+                Err(e) =>
+                {
+                    println!("{}", e);
+                    let _ = connex.send_command_args("QUIT", ":Thank you, come again!");
+                    return;
+                }
+            };
+            if packlist.is_empty()
+            {
+                println!("No results were found.");
+                return;
+            }
+            let selected = if config.auto_pick
+            {
+                auto_pick_pack(&packlist, &query, &config, &user_arc, &history)
+            }
+            else
+            {
+                let ranked = ranker(&config, &user_arc, &history).rank(&query, &packlist);
+                select_packs(&ranked.into_iter().map(|x| x.result).collect::<Vec<_>>())
+            };
+            for work in selected.iter()
+            {
+                queue_work(&mut queue, work, &history, &config);
+            }
+            report_queue_error(queue.save());
+        }
+
+        let downloaded = run_download_queue(
+            &mut connex,
+            &rx,
+            &mut queue,
+            &download_dir,
+            &config,
+            &history,
+            &part_files,
+        );
+        if let Err(e) = downloaded
+        {
+            println!("{}. Unfinished downloads can be resumed next time.", e);
+            part_files.remove_all();
+            let _ = connex.send_command_args("QUIT", ":Thank you, come again!");
+            return;
+        }
+    }
+    report_queue_error(queue.remove_finished());
+    let _ = connex.send_command_args("QUIT", ":Thank you, come again!");
+    println!("Thank you, come again!");
+}
+
+/// Triggers an fserve, waits for it to open a DCC CHAT and asks it for the file. The file
+/// itself then arrives as an ordinary DCC SEND.
+fn request_from_fserve(
+    connex: &mut IrcConnection,
+    read_loop_receiver: &mpsc::Receiver<SessionEvent>,
+    fserve: &FserveTarget,
+    config: &Config,
+) -> Result<(), &'static str>
+{
+    let timeouts = &config.timeouts;
+    let sent = if fserve.trigger.starts_with('!') || fserve.trigger.starts_with('@')
+    {
+        connex.send_message(&config.channel, &fserve.trigger)
+    }
+    else
+    {
+        connex.send_message(&fserve.bot, &format!("\u{1}{}\u{1}", fserve.trigger))
+    };
+    sent?;
+
+    let deadline = time::Instant::now() + timeouts.dcc_offer;
+    let offer = loop
+    {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        match read_loop_receiver.recv_timeout(remaining)
+        {
+            Ok(SessionEvent::ChatOffer { message }) =>
+            {
+                let sender = get_sender(&message);
+                if !sender.eq_ignore_ascii_case(&fserve.bot)
+                {
+                    println!("Ignoring DCC CHAT from {}.", sender);
+                    continue;
+                }
+                match message.command
+                {
+                    MessageCommand::PRIVMSGCTCP {
+                        inner_message: Some(offer),
+                        ..
+                    } => break offer,
+                    _ => continue,
+                }
+            }
+            Ok(_) => continue,
+            Err(_e) => return Err("Timed out waiting for the fserve to open a DCC CHAT"),
+        }
+    };
+    let address = offer.get_full_address()?;
+    if !is_public_address(&address.ip())
+    {
+        return Err("fserve offered a private, loopback or link-local address");
+    }
+
+    let mut chat = DccChat::connect(&offer, timeouts)?;
+    let result = chat.run_script(&fserve_get_script(&fserve.path), timeouts.chunk_idle);
+    for line in chat.transcript.iter()
+    {
+        println!("{}", line);
+    }
+    result
+}
+
+fn wait_until_registered(
+    read_loop_receiver: &mpsc::Receiver<SessionEvent>,
+    timeout: time::Duration,
+) -> bool
+{
+    let deadline = time::Instant::now() + timeout;
+    loop
+    {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        match read_loop_receiver.recv_timeout(remaining)
+        {
+            Ok(SessionEvent::Registered) => return true,
+            Ok(_) => continue,
+            Err(_e) => return false,
+        }
+    }
+}
+
+//Ask user for desired book
+fn ask_for_title() -> String
+{
     let mut name = String::new();
-    println!("Book Title?");
+    report("Book Title?");
     stdin().read_line(&mut name).expect("Unable to read line");
-    name = name.lines().take(1).collect::<String>();
-    println!("Searching for {}.  Please wait...", name);
-    println!("{:#?}", user_arc.lock().unwrap());
+    name.lines().take(1).collect::<String>()
+}
+
+/// Searches the channel, or the cache, and records the search in the history.
+fn search_for_packs(
+    connex: &mut IrcConnection,
+    rx: &mpsc::Receiver<SessionEvent>,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    query: &str,
+    config: &Config,
+    cache: &ResultCache,
+    history: &History,
+) -> Result<Vec<SearchResult>, &'static str>
+{
+    let results = search_channel(connex, rx, user_arc, query, config, cache)?;
+    record_search(history, config, query, results.len());
+    Ok(results)
+}
 
-    name = format!("{}{}", "@search ", &name);
+fn search_channel(
+    connex: &mut IrcConnection,
+    rx: &mpsc::Receiver<SessionEvent>,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    query: &str,
+    config: &Config,
+    cache: &ResultCache,
+) -> Result<Vec<SearchResult>, &'static str>
+{
+    let timeouts = &config.timeouts;
+    let dialect = config.search_dialect();
+    let provider = dialect.provider();
+    if let Some(data) = cache.fetch(dialect, query)
+    {
+        match provider.parse_list(&data)
+        {
+            Ok(t) =>
+            {
+                report("Using the results of the same search from earlier.");
+                return Ok(online_results(t, &config.channel, user_arc));
+            }
+            Err(e) => report(&format!("Ignoring unreadable cached results: {}", e)),
+        }
+    }
+    report(&format!(
+        "Searching {} for {} with {}.  Please wait...",
+        config.channel,
+        query,
+        dialect.as_str()
+    ));
+    report(&format!("{:#?}", user_arc.lock().unwrap()));
+
+    let search_policy = DccPolicy::for_search(&config.search_bot, query);
     //Request search results from SearchBox
     connex
-        .send_message("#bookz", &name)
+        .send_message(&config.channel, &provider.search_command(query))
         .expect("Unable to send message");
+    if provider.delivery() == ResultDelivery::Notices
+    {
+        let results = collect_notice_results(rx, provider.as_ref(), timeouts.search_results)?;
+        cache_notice_results(cache, dialect, query, &results);
+        return Ok(online_results(results, &config.channel, user_arc));
+    }
     //wait to receive DCC Send request for packlist
-    let (dcc_send_request, _) = wait_until_new_dcc(&rx);
-    let mut dcc_connex = DccConnection::connect(dcc_send_request).unwrap();
+    let (dcc_send_request, address) =
+        match wait_until_new_dcc(rx, &search_policy, timeouts.search_results)?
+        {
+            Some(t) => t,
+            None => return Ok(Vec::new()),
+        };
+    match fetch_search_results(dcc_send_request, address, timeouts, dialect, cache, query)
+    {
+        Ok(t) => Ok(online_results(t, &config.channel, user_arc)),
+        Err(e) =>
+        {
+            report(&format!("Unable to download search results: {}", e));
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Gathers results sent one NOTICE at a time, until the bots go quiet or `timeout` passes.
+/// Fails if the connection is lost meanwhile.
+fn collect_notice_results(
+    rx: &mpsc::Receiver<SessionEvent>,
+    provider: &dyn SearchProvider,
+    timeout: time::Duration,
+) -> Result<Vec<SearchResult>, &'static str>
+{
+    let deadline = time::Instant::now() + timeout;
+    let mut results: Vec<SearchResult> = Vec::new();
+    loop
+    {
+        let mut remaining = deadline.saturating_duration_since(time::Instant::now());
+        if !results.is_empty()
+        {
+            remaining = remaining.min(NOTICE_RESULTS_QUIET);
+        }
+        match rx.recv_timeout(remaining)
+        {
+            Ok(SessionEvent::BotText { sender, text }) =>
+            {
+                if let Some(result) = provider.parse_notice(&sender, &text)
+                {
+                    results.push(result);
+                }
+            }
+            Ok(SessionEvent::BotReply { sender, text, .. }) =>
+            {
+                report(&format!("{}: {}", sender, strip_formatting(&text)));
+            }
+            Ok(_) => continue,
+            Err(mpsc::RecvTimeoutError::Timeout) => return Ok(results),
+            Err(mpsc::RecvTimeoutError::Disconnected) =>
+            {
+                return Err("Lost connection to the IRC server")
+            }
+        }
+    }
+}
+
+/// Downloads the result list a search bot offered, caches it and parses its lines.
+fn fetch_search_results(
+    dcc_send_request: IrcMessage,
+    address: SocketAddr,
+    timeouts: &Timeouts,
+    dialect: SearchDialect,
+    cache: &ResultCache,
+    query: &str,
+) -> Result<Vec<SearchResult>, &'static str>
+{
     //Respond to DCC request and read all
-    let zipped_results_file_bytes = dcc_connex.get_all_bytes();
+    let results_file_bytes =
+        DccConnection::connect(dcc_send_request, address, timeouts).and_then(|mut x| {
+            x.max_size = Some(MAX_SEARCH_RESULT_SIZE);
+            x.get_all_bytes()
+        })?;
+    let results = dialect.provider().parse_list(&results_file_bytes)?;
+    if let Err(e) = cache.store(dialect, query, &results_file_bytes)
+    {
+        report(&format!("Unable to cache the search results: {}", e));
+    }
+    Ok(results)
+}
+
+/// Results that came as NOTICEs have no list file, so their request lines are cached as one.
+fn cache_notice_results(
+    cache: &ResultCache,
+    dialect: SearchDialect,
+    query: &str,
+    results: &[SearchResult],
+)
+{
+    let list = results
+        .iter()
+        .map(|x| format!("{}\r\n", x.request_line))
+        .collect::<String>();
+    if let Err(e) = cache.store(dialect, query, list.as_bytes())
+    {
+        report(&format!("Unable to cache the search results: {}", e));
+    }
+}
+
+/// Browses a cached search without connecting. Picks are queued for the next session.
+fn browse_offline(config: &Config, cache: &ResultCache, history: &History, download_dir: &Path)
+{
+    let entries = cache.entries();
+    if entries.is_empty()
+    {
+        report("There are no cached searches to browse.");
+        return;
+    }
+    let query = match &config.query
+    {
+        Some(t) => t.clone(),
+        None =>
+        {
+            report("Cached searches:");
+            for entry in entries.iter()
+            {
+                report(&format!(
+                    "    {:<8} {}  ({})",
+                    entry.dialect,
+                    entry.query,
+                    format_age(age(entry.saved_at))
+                ));
+            }
+            ask_for_title()
+        }
+    };
+    // The configured dialect first, then whichever other dialect searched for it
+    let dialects = [
+        config.search_dialect(),
+        SearchDialect::Search,
+        SearchDialect::BangSearch,
+        SearchDialect::Find,
+        SearchDialect::Seek,
+    ];
+    let (data, saved_at) = match dialects.iter().find_map(|x| cache.fetch_any(*x, &query))
+    {
+        Some(t) => t,
+        None =>
+        {
+            report(&format!("There are no cached results for {}.", query));
+            return;
+        }
+    };
+    let results = match parse_result_list(&data)
+    {
+        Ok(t) if !t.is_empty() => t,
+        Ok(_) =>
+        {
+            report("No results were found.");
+            return;
+        }
+        Err(e) =>
+        {
+            report(&format!("Unable to read the cached results: {}", e));
+            return;
+        }
+    };
+    report(&format!(
+        "Results for {} from {}, bots may have gone offline since.",
+        query,
+        format_age(age(saved_at))
+    ));
+    if let Some(format) = config.output_format
+    {
+        print!("{}", export_results(format, &query, &results));
+        return;
+    }
+    let ranker = Ranker {
+        preferred_formats: config.preferred_formats.clone(),
+        bot_stats: bot_stats(&history.records()),
+        ..Ranker::default()
+    };
+    let ranked = ranker.rank(&query, &results);
+    let selected = select_packs(&ranked.into_iter().map(|x| x.result).collect::<Vec<_>>());
+    if selected.is_empty()
+    {
+        return;
+    }
+    let mut queue = match DownloadQueue::load(download_dir.join(QUEUE_FILE_NAME))
+    {
+        Ok(t) => t,
+        Err(e) =>
+        {
+            report(&format!("Unable to queue the picks: {}", e));
+            return;
+        }
+    };
+    for work in selected.iter()
+    {
+        queue_work(&mut queue, work, history, config);
+    }
+    if let Err(e) = queue.save()
+    {
+        report(&format!("Unable to queue the picks: {}", e));
+        return;
+    }
+    println!(
+        "Queued {} downloads, resume them the next time you connect.",
+        selected.len()
+    );
+}
+
+/// Drops results from bots that are not in the channel, they would never answer a request.
+fn filter_online_bots(
+    results: Vec<SearchResult>,
+    channel: &str,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+) -> Result<Vec<SearchResult>, &'static str>
+{
+    let users = user_arc.lock().unwrap();
+    let names = match users.get(channel)
+    {
+        Some(t) => t,
+        None => return Err("No one is known to be in the channel, so no bot can be asked"),
+    };
+    Ok(results
+        .into_iter()
+        .filter(|x| nick_in_names(names, &x.bot))
+        .collect::<Vec<SearchResult>>())
+}
+
+/// Keeps the results whose bot is in the channel, reporting why there are none if that fails.
+fn online_results(
+    results: Vec<SearchResult>,
+    channel: &str,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+) -> Vec<SearchResult>
+{
+    filter_online_bots(results, channel, user_arc).unwrap_or_else(|e| {
+        report(e);
+        Vec::new()
+    })
+}
+
+fn ranker(
+    config: &Config,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    history: &History,
+) -> Ranker
+{
+    Ranker {
+        preferred_formats: config.preferred_formats.clone(),
+        bot_stats: bot_stats(&history.records()),
+        online_bots: user_arc.lock().unwrap().get(&config.channel).cloned(),
+    }
+}
+
+/// Picks the best ranked result for unattended runs, printing the scoring with `--explain`.
+/// The other usable copies of the same book are kept as fallbacks.
+fn auto_pick_pack(
+    packlist: &[SearchResult],
+    query: &str,
+    config: &Config,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    history: &History,
+) -> Vec<Work>
+{
+    let ranked = ranker(config, user_arc, history).rank(query, packlist);
+    if config.explain
+    {
+        for result in ranked.iter()
+        {
+            print!("{}", result.explain());
+        }
+    }
+    let usable = ranked
+        .iter()
+        .filter(|x| x.rejected.is_none())
+        .map(|x| x.result.clone())
+        .collect::<Vec<SearchResult>>();
+    match group_works(&usable).into_iter().next()
+    {
+        Some(t) =>
+        {
+            println!(
+                "Picked {} by {} from {} ({:.1} points).",
+                t.best().title,
+                t.best().author,
+                t.best().bot,
+                ranked
+                    .iter()
+                    .find(|x| &x.result == t.best())
+                    .map_or(0.0, |x| x.score)
+            );
+            if !t.alternates().is_empty()
+            {
+                println!(
+                    "{} other copies will be tried if it fails.",
+                    t.alternates().len()
+                );
+            }
+            vec![t]
+        }
+        None =>
+        {
+            println!("None of the results looked like a usable copy.");
+            Vec::new()
+        }
+    }
+}
+
+//:Present the choices to the user a page at a time, returns nothing if they quit
+fn select_packs(packlist: &[SearchResult]) -> Vec<Work>
+{
+    let mut browser = ResultBrowser::new(packlist.to_vec(), 10);
+    println!("{}", browser.render_page());
+    println!("Enter result numbers to download them, or `h` for more commands.");
+    loop
+    {
+        let mut user_response = String::new();
+        if stdin().read_line(&mut user_response).unwrap() == 0
+        {
+            break Vec::new();
+        }
+        match browser.handle(&user_response)
+        {
+            BrowserOutcome::Redraw => println!("{}", browser.render_page()),
+            BrowserOutcome::Show(text) => println!("{}", text),
+            BrowserOutcome::Selected(selected) if !selected.is_empty() => break selected,
+            BrowserOutcome::Selected(_) => (),
+            BrowserOutcome::Quit => break Vec::new(),
+            BrowserOutcome::Invalid(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Searches for every entry of a reading list in turn within this session, queues the books
+/// found unambiguously and writes a report of how each entry went next to the list.
+#[allow(clippy::too_many_arguments)]
+fn run_batch(
+    connex: &mut IrcConnection,
+    rx: &mpsc::Receiver<SessionEvent>,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    reading_list: &Path,
+    queue: &mut DownloadQueue,
+    config: &Config,
+    cache: &ResultCache,
+    history: &History,
+) -> Result<(), &'static str>
+{
+    let text = match fs::read_to_string(reading_list)
+    {
+        Ok(t) => t,
+        Err(_e) => return Err("Unable to read the reading list"),
+    };
+    let entries = parse_reading_list(&text)?;
+    let mut outcomes: Vec<(ReadingListEntry, BatchOutcome)> = Vec::new();
+    for (i, entry) in entries.iter().enumerate()
+    {
+        println!("[{}/{}] {}", i + 1, entries.len(), entry.describe());
+        let query = entry.query();
+        let packlist = search_for_packs(connex, rx, user_arc, &query, config, cache, history)?;
+        let ranked = ranker(config, user_arc, history).rank(&query, &packlist);
+        if config.explain
+        {
+            for result in ranked.iter()
+            {
+                print!("{}", result.explain());
+            }
+        }
+        let outcome = classify(entry, &ranked);
+        match &outcome
+        {
+            BatchOutcome::Hit(work) =>
+            {
+                if queue_work(queue, work, history, config)
+                {
+                    println!("Queued {} from {}.", work.best().requested_file(), work.best().bot);
+                }
+            }
+            BatchOutcome::Miss(reason) => println!("Skipped, {}.", reason),
+            BatchOutcome::Ambiguous(works) =>
+            {
+                println!("Skipped, {} different books matched.", works.len())
+            }
+        }
+        outcomes.push((entry.clone(), outcome));
+    }
+    queue.save()?;
+
+    let report = render_report(&outcomes);
+    let mut report_path = reading_list.as_os_str().to_os_string();
+    report_path.push(".report.txt");
+    let report_path = PathBuf::from(report_path);
+    if fs::write(&report_path, &report).is_err()
+    {
+        return Err("Unable to write the batch report");
+    }
+    print!("{}", report);
+    println!("Report written to {}.", report_path.display());
+    Ok(())
+}
+
+/// Adds to, removes from or prints the watchlist kept in the download directory.
+fn edit_watchlist(command: &WatchCommand, download_dir: &Path) -> Result<(), &'static str>
+{
+    let mut watchlist = Watchlist::load(download_dir.join(WATCHLIST_FILE_NAME))?;
+    match command
+    {
+        WatchCommand::Add(book) =>
+        {
+            let index = watchlist.add(book)?;
+            watchlist.save()?;
+            println!("Watching for {}.", watchlist.entries[index].describe());
+        }
+        WatchCommand::Remove(book) =>
+        {
+            let entry = watchlist.remove(book)?;
+            watchlist.save()?;
+            println!("No longer watching for {}.", entry.describe());
+        }
+        WatchCommand::List => print!("{}", watchlist.render()),
+    }
+    Ok(())
+}
+
+/// Searches for the watchlist's missing books whenever they are due, never faster than the
+/// configured spacing, and downloads each as soon as a copy meeting the quality policy shows
+/// up. Runs until the connection is lost or the user presses Ctrl-C.
+#[allow(clippy::too_many_arguments)]
+fn run_watch_daemon(
+    connex: &mut IrcConnection,
+    rx: &mpsc::Receiver<SessionEvent>,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    queue: &mut DownloadQueue,
+    download_dir: &Path,
+    config: &Config,
+    cache: &ResultCache,
+    history: &History,
+    part_files: &PartFiles,
+) -> Result<(), &'static str>
+{
+    let watchlist_path = download_dir.join(WATCHLIST_FILE_NAME);
+    let log_path = download_dir.join(WATCH_LOG_FILE_NAME);
+    // Books left over from a previous session first
+    run_download_queue(connex, rx, queue, download_dir, config, history, part_files)?;
+    queue.remove_finished()?;
+    let mut last_search: Option<time::Instant> = None;
+    loop
+    {
+        // Reloaded every round to pick up books added with --watch-add meanwhile
+        let watchlist = Watchlist::load(watchlist_path.clone())?;
+        let due = watchlist.due(config.watch_interval, time::SystemTime::now());
+        if due.is_empty()
+        {
+            let wait = watchlist
+                .next_due(config.watch_interval, time::SystemTime::now())
+                .unwrap_or(config.watch_interval)
+                .clamp(time::Duration::from_secs(1), WATCHLIST_POLL);
+            idle(rx, wait)?;
+            continue;
+        }
+        report(&format!("{} watched books are due for a search.", due.len()));
+        for index in due
+        {
+            if let Some(t) = last_search
+            {
+                idle(rx, config.search_spacing.saturating_sub(t.elapsed()))?;
+            }
+            last_search = Some(time::Instant::now());
+            let mut watched = watchlist.entries[index].clone();
+            watched.last_checked = Some(time::SystemTime::now());
+            let entry = watched.reading_list_entry(index + 1);
+            let book = entry.describe();
+            let query = entry.query();
+            let packlist =
+                search_for_packs(connex, rx, user_arc, &query, config, cache, history)?;
+            let ranked = ranker(config, user_arc, history).rank(&query, &packlist);
+            let mut work = match classify(&entry, &ranked)
+            {
+                BatchOutcome::Hit(t) => t,
+                BatchOutcome::Miss(reason) =>
+                {
+                    watch_event(config, &log_path, "missing", &book, &reason, false);
+                    record_watched(&watchlist_path, &watched)?;
+                    continue;
+                }
+                BatchOutcome::Ambiguous(works) =>
+                {
+                    let detail = format!("{} different books matched", works.len());
+                    watch_event(config, &log_path, "ambiguous", &book, &detail, false);
+                    record_watched(&watchlist_path, &watched)?;
+                    continue;
+                }
+            };
+            work.copies.retain(|x| config.preferred_formats.contains(&x.format));
+            if work.copies.is_empty()
+            {
+                let detail = "no copy in a preferred format";
+                watch_event(config, &log_path, "missing", &book, detail, false);
+                record_watched(&watchlist_path, &watched)?;
+                continue;
+            }
+            if let Some(owned) = owned_copy(history, config, &work)
+            {
+                watched.found = Some(owned.requested_file);
+                let detail = format!("already downloaded to {}", owned.path.unwrap_or_default());
+                watch_event(config, &log_path, "downloaded", &book, &detail, false);
+                record_watched(&watchlist_path, &watched)?;
+                continue;
+            }
+            let id = queue.requests.len();
+            queue_work(queue, &work, history, config);
+            queue.save()?;
+            let best = work.best();
+            let detail = format!("{} from {}", best.requested_file(), best.bot);
+            watch_event(config, &log_path, "queued", &book, &detail, true);
+
+            run_download_queue(connex, rx, queue, download_dir, config, history, part_files)?;
+            let request = &queue.requests[id];
+            let detail = format!("{} from {}", request.requested_file, request.bot_source);
+            if request.state == DownloadState::Completed
+            {
+                watched.found = Some(request.requested_file.clone());
+                watch_event(config, &log_path, "downloaded", &book, &detail, true);
+            }
+            else
+            {
+                watch_event(config, &log_path, "failed", &book, &detail, true);
+            }
+            queue.remove_finished()?;
+            record_watched(&watchlist_path, &watched)?;
+        }
+    }
+}
 
-    // println!("{:#?} ", zipped_results_file_bytes);
+/// Saves one book's progress into the watchlist as it is on disk now.
+fn record_watched(watchlist_path: &Path, watched: &WatchEntry) -> Result<(), &'static str>
+{
+    let mut watchlist = Watchlist::load(watchlist_path.to_path_buf())?;
+    watchlist.record(watched);
+    watchlist.save()
+}
 
-    //:verify file we received was pkzip file
-    if PkZip::data_is_pkzip(&zipped_results_file_bytes)
+/// Reports, logs and, when `notify` is set and a hook is configured, announces a daemon event.
+fn watch_event(
+    config: &Config,
+    log_path: &Path,
+    event: &str,
+    book: &str,
+    detail: &str,
+    notify: bool,
+)
+{
+    report(&format!("{}: {} ({})", book, event, detail));
+    if let Err(e) = append_watch_log(log_path, &format!("{}  {}  {}", event, book, detail))
+    {
+        report(e);
+    }
+    if let (true, Some(command)) = (notify, &config.notify_command)
+    {
+        if let Err(e) = run_notify_hook(command, event, book, detail)
+        {
+            report(e);
+        }
+    }
+}
+
+/// Waits out `duration` while discarding whatever the read loop sends meanwhile.
+fn idle(rx: &mpsc::Receiver<SessionEvent>, duration: time::Duration) -> Result<(), &'static str>
+{
+    let deadline = time::Instant::now() + duration;
+    loop
+    {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        if remaining.is_zero()
+        {
+            return Ok(());
+        }
+        if let Err(mpsc::RecvTimeoutError::Disconnected) = rx.recv_timeout(remaining)
+        {
+            return Err("Lost connection to the IRC server");
+        }
+    }
+}
+
+/// Queues the best copy of a work, with the others to fall back to, unless the history shows
+/// a copy is already here. Returns whether it was queued.
+fn queue_work(queue: &mut DownloadQueue, work: &Work, history: &History, config: &Config) -> bool
+{
+    if let Some(owned) = owned_copy(history, config, work)
+    {
+        report(&format!(
+            "Skipping {}, it was downloaded to {} on {}. Use --redownload to fetch it again.",
+            work.title,
+            owned.path.unwrap_or_default(),
+            format_log_time(owned.finished_at)
+        ));
+        return false;
+    }
+    let best = work.best();
+    let id = queue.push(&best.bot, &best.request_line, &best.requested_file());
+    for copy in work.alternates()
+    {
+        queue.add_alternate(id, &copy.bot, &copy.request_line, &copy.requested_file());
+    }
+    true
+}
+
+/// The earlier download of `work` that is still on disk, unless `--redownload` was given.
+fn owned_copy(history: &History, config: &Config, work: &Work) -> Option<DownloadRecord>
+{
+    if config.redownload
+    {
+        return None;
+    }
+    already_downloaded(&history.records(), work)
+}
+
+fn record_search(history: &History, config: &Config, query: &str, results: usize)
+{
+    let search = SearchRecord {
+        at: unix_seconds(time::SystemTime::now()),
+        dialect: config.search_dialect().as_str().to_string(),
+        query: query.to_string(),
+        results,
+    };
+    if let Err(e) = history.record_search(&search)
+    {
+        report(e);
+    }
+}
+
+/// Records how a request ended, with the size and checksum of the file when one was saved.
+fn record_download(
+    history: &History,
+    request: &DownloadRequest,
+    outcome: DownloadOutcome,
+    path: Option<&Path>,
+)
+{
+    let now = unix_seconds(time::SystemTime::now());
+    let download = DownloadRecord {
+        requested_at: request
+            .requested_at
+            .map_or(now, |x| now.saturating_sub(x.elapsed().as_secs())),
+        finished_at: now,
+        bot: request.bot_source.clone(),
+        request_line: request.request_line.clone(),
+        requested_file: request.requested_file.clone(),
+        outcome,
+        path: path.map(|x| x.display().to_string()),
+        size: path.and_then(|x| fs::metadata(x).ok()).map(|x| x.len()),
+        checksum: path.and_then(|x| file_checksum(x).ok()),
+    };
+    if let Err(e) = history.record_download(&download)
+    {
+        report(e);
+    }
+}
+
+/// Gives up on the current copy of a request, reporting which copy is tried next if any.
+fn fail_request(
+    queue: &mut DownloadQueue,
+    id: usize,
+    history: &History,
+    reason: &str,
+    path: Option<&Path>,
+)
+{
+    let outcome = DownloadOutcome::Failed(reason.to_string());
+    record_download(history, &queue.requests[id], outcome, path);
+    report_queue_error(queue.fail(id).map(|_| ()));
+    if queue.requests[id].state == DownloadState::Queued
+    {
+        let request = &queue.requests[id];
+        report(&format!(
+            "Trying {} from {} instead.",
+            request.requested_file, request.bot_source
+        ));
+    }
+}
+
+/// Reports a queue file that could not be written. The queue in memory is unaffected, only
+/// resuming it in a later session is, so the session carries on.
+fn report_queue_error(saved: Result<(), &'static str>)
+{
+    if let Err(e) = saved
+    {
+        report(e);
+    }
+}
+
+//:Once the user has selected packs, request them and save each file as its DCC transfer finishes
+//:Fails if the connection is lost before the queue is done
+fn run_download_queue(
+    connex: &mut IrcConnection,
+    read_loop_receiver: &mpsc::Receiver<SessionEvent>,
+    queue: &mut DownloadQueue,
+    download_dir: &Path,
+    config: &Config,
+    history: &History,
+    part_files: &PartFiles,
+) -> Result<(), &'static str>
+{
+    let (done_tx, done_rx) = mpsc::channel::<(usize, Result<PathBuf, &'static str>)>();
+    while queue.has_pending()
+    {
+        pump_download_queue(connex, queue, &done_rx, config, history);
+
+        let event = match read_loop_receiver.recv_timeout(time::Duration::from_millis(250))
+        {
+            Ok(t) => t,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) =>
+            {
+                return Err("Lost connection to the IRC server")
+            }
+        };
+        let (dcc_send_request, title) = match event
+        {
+            SessionEvent::DccOffer { message, argument } => (*message, argument),
+            SessionEvent::BotReply { sender, text, event } =>
+            {
+                handle_bot_reply(queue, &sender, &text, event, history);
+                continue;
+            }
+            // The channel closing right after this ends the wait
+            SessionEvent::Registered
+            | SessionEvent::Disconnected
+            | SessionEvent::ChatOffer { .. }
+            | SessionEvent::BotText { .. }
+            | SessionEvent::Key(_)
+            | SessionEvent::SearchResults(_) => continue,
+        };
+        let sender = get_sender(&dcc_send_request);
+        let (id, decision) = match &dcc_send_request.command
+        {
+            MessageCommand::PRIVMSGCTCP {
+                inner_message: Some(offer),
+                ..
+            } => queue.match_offer(&sender, offer),
+            _ => continue,
+        };
+        let address = match id
+        {
+            Some(_) => offer_accepted(decision, &title, &sender),
+            None => None,
+        };
+        match (id, address)
+        {
+            (Some(id), Some(address)) =>
+            {
+                start_transfer(
+                    queue,
+                    id,
+                    dcc_send_request,
+                    address,
+                    title,
+                    download_dir,
+                    config,
+                    part_files,
+                    &done_tx,
+                );
+            }
+            // The user turned the offer down, so the bot will not be sending anything else
+            (Some(id), None) => fail_request(queue, id, history, "the offer was declined", None),
+            (None, _) => continue,
+        }
+    }
+    Ok(())
+}
+
+/// Requests queued packs that have a free slot, records finished transfers and gives up on
+/// requests whose bot never offered the file.
+fn pump_download_queue(
+    connex: &mut IrcConnection,
+    queue: &mut DownloadQueue,
+    done_rx: &mpsc::Receiver<(usize, Result<PathBuf, &'static str>)>,
+    config: &Config,
+    history: &History,
+)
+{
+    for id in queue.start_next()
+    {
+        if queue.requests[id].request_line.is_empty()
+        {
+            report(&format!(
+                "{} came from an fserve session and cannot be requested again",
+                queue.requests[id].requested_file
+            ));
+            fail_request(queue, id, history, "cannot be requested again", None);
+            continue;
+        }
+        connex
+            .send_message(&config.channel, &queue.requests[id].request_line)
+            .unwrap();
+    }
+    report_queue_error(queue.save());
+
+    while let Ok((id, result)) = done_rx.try_recv()
+    {
+        let requested_file = &queue.requests[id].requested_file;
+        match result
+        {
+            Ok(path) =>
+            {
+                report(&format!(
+                    "Finished {}, saved to {}",
+                    requested_file,
+                    path.display()
+                ));
+                let problems = verify_download(&queue.requests[id], &path);
+                if !problems.is_empty()
+                {
+                    reject_download(queue, id, history, config, path, &problems);
+                    continue;
+                }
+                report_queue_error(queue.set_state(id, DownloadState::Completed));
+                let path = file_download(config, &queue.requests[id], path);
+                let outcome = DownloadOutcome::Completed;
+                record_download(history, &queue.requests[id], outcome, Some(&path));
+            }
+            Err(e) =>
+            {
+                report(&format!("Transfer of {} failed: {}", requested_file, e));
+                fail_request(queue, id, history, e, None);
+            }
+        }
+    }
+    for id in queue.expired_requests(config.timeouts.dcc_offer)
+    {
+        report(&format!(
+            "{} did not offer {} in time",
+            queue.requests[id].bot_source, queue.requests[id].requested_file
+        ));
+        fail_request(queue, id, history, "not offered in time", None);
+    }
+}
+
+/// What the request line says about a book.
+fn requested_metadata(bot: &str, request_line: &str, requested_file: &str) -> BookMetadata
+{
+    // Files fetched from an fserve have no request line, only their name
+    let result = SearchResult::parse(request_line)
+        .or_else(|_| SearchResult::parse(&format!("!{} {}", bot, requested_file)));
+    result
+        .map(|x| BookMetadata::from_result(&x))
+        .unwrap_or_default()
+}
+
+/// What the request line and the file itself say about a finished download.
+fn download_metadata(
+    bot: &str,
+    request_line: &str,
+    requested_file: &str,
+    path: &Path,
+) -> BookMetadata
+{
+    let mut metadata = requested_metadata(bot, request_line, requested_file);
+    if let Some(embedded) = embedded_metadata(path)
+    {
+        metadata.merge(&embedded);
+    }
+    if metadata.extension.is_none()
+    {
+        metadata.extension = path
+            .extension()
+            .map(|x| x.to_string_lossy().to_lowercase());
+    }
+    metadata
+}
+
+/// What is wrong with a finished download, if anything.
+fn verify_download(request: &DownloadRequest, path: &Path) -> Vec<Problem>
+{
+    let requested = requested_metadata(
+        &request.bot_source,
+        &request.request_line,
+        &request.requested_file,
+    );
+    match verify_file(path, &requested)
+    {
+        Ok(t) => t,
+        Err(e) =>
+        {
+            report(&format!("Unable to verify {}: {}", path.display(), e));
+            Vec::new()
+        }
+    }
+}
+
+/// Warns about a download that failed verification and keeps it out of the library, moving it
+/// into quarantine with `--quarantine`, then tries another copy if there is one.
+fn reject_download(
+    queue: &mut DownloadQueue,
+    id: usize,
+    history: &History,
+    config: &Config,
+    path: PathBuf,
+    problems: &[Problem],
+)
+{
+    for problem in problems.iter()
+    {
+        report(&format!("Warning: {} {}", path.display(), problem.describe()));
+    }
+    let path = if config.quarantine
+    {
+        match quarantine(&path)
+        {
+            Ok(t) =>
+            {
+                report(&format!("Moved {} to {}", path.display(), t.display()));
+                t
+            }
+            Err(e) =>
+            {
+                report(&format!("Unable to quarantine {}: {}", path.display(), e));
+                path
+            }
+        }
+    }
+    else
+    {
+        path
+    };
+    let reason = problems
+        .iter()
+        .map(|x| x.describe())
+        .collect::<Vec<String>>()
+        .join(", ");
+    fail_request(queue, id, history, &reason, Some(&path));
+}
+
+/// Files a finished download into the library when one is configured, returning where the
+/// book is now.
+fn file_download(config: &Config, request: &DownloadRequest, path: PathBuf) -> PathBuf
+{
+    let library = match &config.library
+    {
+        Some(t) => t,
+        None => return path,
+    };
+    let metadata = download_metadata(
+        &request.bot_source,
+        &request.request_line,
+        &request.requested_file,
+        &path,
+    );
+    match file_into_library(config, library, &metadata, &path)
+    {
+        Ok(t) => t,
+        Err(e) =>
+        {
+            report(&format!("Unable to file {}: {}", path.display(), e));
+            path
+        }
+    }
+}
+
+/// Moves a book to where the library template puts it, or only says where with `--dry-run`.
+fn file_into_library(
+    config: &Config,
+    library: &Path,
+    metadata: &BookMetadata,
+    path: &Path,
+) -> Result<PathBuf, &'static str>
+{
+    if metadata.drm_locked
+    {
+        report(&format!(
+            "Warning: {} is locked with DRM and will only open on the device it was bought for",
+            path.display()
+        ));
+    }
+    let relative = config.library_template.render(metadata);
+    let filing = plan_filing(library, &relative, path);
+    match &filing
+    {
+        Filing::InPlace => return Ok(path.to_path_buf()),
+        Filing::Move(target) => report(&format!(
+            "{} {} as {}",
+            if config.dry_run { "Would file" } else { "Filing" },
+            path.display(),
+            target.display()
+        )),
+        Filing::Duplicate(existing) => report(&format!(
+            "{} is identical to {}{}",
+            path.display(),
+            existing.display(),
+            if config.dry_run { "" } else { ", removing it" }
+        )),
+    }
+    if config.dry_run
+    {
+        return Ok(path.to_path_buf());
+    }
+    file_book(path, &filing)
+}
+
+/// Files every earlier download that is still on disk, recording the moves in the history so
+/// the books are still known to be here.
+fn organize_downloads(config: &Config, history: &History)
+{
+    let library = match &config.library
+    {
+        Some(t) => t,
+        None => return,
+    };
+    let mut filed = 0;
+    for download in downloads_on_disk(&history.records())
+    {
+        let from = PathBuf::from(download.path.unwrap_or_default());
+        let metadata = download_metadata(
+            &download.bot,
+            &download.request_line,
+            &download.requested_file,
+            &from,
+        );
+        let to = match file_into_library(config, library, &metadata, &from)
+        {
+            Ok(t) => t,
+            Err(e) =>
+            {
+                report(&format!("Unable to file {}: {}", from.display(), e));
+                continue;
+            }
+        };
+        if to == from
+        {
+            continue;
+        }
+        filed += 1;
+        let record = FiledRecord {
+            at: unix_seconds(time::SystemTime::now()),
+            from: from.display().to_string(),
+            to: to.display().to_string(),
+        };
+        if let Err(e) = history.record_filed(&record)
+        {
+            report(e);
+        }
+    }
+    if config.dry_run
     {
-        println!("File is PKZIP!");
+        println!("Nothing was moved, run again without --dry-run to file the books.");
     }
     else
     {
-        panic!(
-            "Non PKZIP file sent when expected PKZIP {:#?}",
-            zipped_results_file_bytes
-        )
-    }
-
-    //:unzip file received thru DCC request
-    let pkzip = PkZip::new(&zipped_results_file_bytes);
-    // eprintln!("pkzip = {:?}", pkzip);
-    let pkzip_files = pkzip.get_files();
-    // println!("pkzip files {:?}", pkzip_files);
-    if pkzip_files.len() != 1
-    {
-        panic!("more than 1 file detected in zip file, should only have 1!");
-    }
-    let list_file = pkzip_files.first().unwrap();
-    // println!("{:#?}", pkzip);
-    // println!("{:#?}", pkzip_files);
-    let decompressed_data = list_file.decompress().unwrap();
-    // println!("{:?}", decompressed_data);
-    let decompressed_string = String::from_utf8(decompressed_data).unwrap();
-    // println!("{:?}", decompressed_string);
-
-    //: parse the txt file from Searchbot
-
-    // eprintln!("split_string = {:#?}", split_string);
-
-    let packlist = decompressed_string
-        .split("\r\n")
-        .filter(|x| x.starts_with('!'))
-        .map(Pack::new)
-        .filter(|x| {
-            user_arc
-                .lock()
-                .unwrap()
-                .get(&"bookz".to_string())
-                .expect("No active bots for this book!")
-                .contains(&x.bot_source)
-        })
-        .collect::<Vec<Pack>>();
+        println!("Filed {} books into {}.", filed, library.display());
+    }
+}
 
-    //:Present in a table the choices to the user
+/// Receives an accepted offer on its own thread, returning the live count of bytes received.
+#[allow(clippy::too_many_arguments)]
+fn start_transfer(
+    queue: &mut DownloadQueue,
+    id: usize,
+    dcc_send_request: IrcMessage,
+    address: SocketAddr,
+    title: String,
+    download_dir: &Path,
+    config: &Config,
+    part_files: &PartFiles,
+    done_tx: &mpsc::Sender<(usize, Result<PathBuf, &'static str>)>,
+) -> Arc<AtomicU64>
+{
+    report_queue_error(queue.set_state(id, DownloadState::Transferring));
+    let progress = Arc::new(AtomicU64::new(0));
 
-    //:Once user has selected choice, verify bot is online send a new message in the IRC channel
-    let pack = loop
-    {
-        // print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-        println!("There are {} results.", { packlist.len() });
+    let done_tx = done_tx.clone();
+    let download_dir = download_dir.to_path_buf();
+    let timeouts = config.timeouts.clone();
+    let part_files = part_files.clone();
+    let transfer_progress = Arc::clone(&progress);
+    thread::spawn(move || {
+        let result = DccConnection::connect(dcc_send_request, address, &timeouts).and_then(|mut x| {
+            x.progress = Some(transfer_progress);
+            x.max_size = Some(MAX_PACK_SIZE);
+            save_download(&mut x, &download_dir, &title, &part_files)
+        });
+        done_tx.send((id, result)).unwrap();
+    });
+    progress
+}
+
+/// An offer waiting for the user to accept it, `request` is `None` for a search result list.
+struct PendingOffer
+{
+    request: Option<usize>,
+    message: IrcMessage,
+    address: SocketAddr,
+    title: String,
+}
 
-        for (i, e) in packlist.iter().take(5).enumerate()
+//:The full-screen session: search, pick results and watch transfers until the user quits
+#[allow(clippy::too_many_arguments)]
+fn run_tui(
+    mut terminal: TerminalBackend,
+    connex: &mut IrcConnection,
+    read_loop_receiver: &mpsc::Receiver<SessionEvent>,
+    event_tx: mpsc::Sender<SessionEvent>,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    queue: &mut DownloadQueue,
+    download_dir: &Path,
+    config: &Config,
+    cache: &ResultCache,
+    history: &History,
+    part_files: &PartFiles,
+)
+{
+    capture_output(true);
+    let key_tx = event_tx.clone();
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        loop
         {
-            println!(
-                "{}:\tTitle: {}\n\tBot: {}\n\tAuthor: {}\n",
-                i, e.book_title, e.bot_source, e.author
-            );
+            let read = match stdin().read(&mut buf)
+            {
+                Ok(0) | Err(_) => return,
+                Ok(t) => t,
+            };
+            for key in decode_keys(&buf[..read])
+            {
+                if key_tx.send(SessionEvent::Key(key)).is_err()
+                {
+                    return;
+                }
+            }
         }
-        let mut user_response = String::new();
-        stdin().read_line(&mut user_response).unwrap();
-        let number_resp_opt = user_response
-            .lines()
-            .take(1)
-            .collect::<String>()
-            .parse::<usize>();
-        println!("{} {:#?}", user_response, number_resp_opt);
-        if number_resp_opt.is_err()
+    });
+
+    let mut app = App::new();
+    let (done_tx, done_rx) = mpsc::channel::<(usize, Result<PathBuf, &'static str>)>();
+    let mut progress: HashMap<usize, (Arc<AtomicU64>, Option<u64>)> = HashMap::new();
+    let provider = config.search_dialect().provider();
+    let mut search_policy: Option<DccPolicy> = None;
+    let mut last_query = String::new();
+    // Results arriving as NOTICEs, handed over once the bots go quiet
+    let mut notice_results: Vec<SearchResult> = Vec::new();
+    let mut last_notice = time::Instant::now();
+    let mut pending_offers: Vec<PendingOffer> = Vec::new();
+    loop
+    {
+        pump_download_queue(connex, queue, &done_rx, config, history);
+        if !notice_results.is_empty() && last_notice.elapsed() >= NOTICE_RESULTS_QUIET
         {
-            continue;
+            let results = std::mem::take(&mut notice_results);
+            cache_notice_results(cache, config.search_dialect(), &last_query, &results);
+            let results = filter_online_bots(results, &config.channel, user_arc);
+            let _ = event_tx.send(SessionEvent::SearchResults(results));
         }
-        let number_resp = number_resp_opt.unwrap();
-        let pack_opt = packlist.get(number_resp);
-        if (pack_opt.is_none())
+        for line in take_output()
         {
-            continue;
+            app.push_log(line);
+        }
+        if app.prompt.is_none()
+        {
+            if let Some(offer) = pending_offers.first()
+            {
+                app.prompt = Some(format!(
+                    "Accept {} from {}?",
+                    offer.title,
+                    get_sender(&offer.message)
+                ));
+            }
+        }
+        app.transfers = transfer_views(queue, &progress);
+        terminal.refresh_size();
+        if app.draw(&mut terminal).is_err()
+        {
+            break;
         }
-        let pack = pack_opt.unwrap();
-        connex.send_message("#bookz", &pack.value).unwrap();
-        break pack;
-    };
 
-    //:wait for DCC request then save file
-    let (dcc_send_request, title) = wait_until_new_dcc(&rx);
-    let mut dcc_connex = DccConnection::connect(dcc_send_request).unwrap();
-    let file_data = dcc_connex.get_all_bytes();
-    // println!("{:#?}", file_data);
-    let mut file = File::create(title).unwrap();
-    file.write_all(&file_data).unwrap();
-    println!("Thank you, come again!");
-}
+        let event = match read_loop_receiver.recv_timeout(time::Duration::from_millis(250))
+        {
+            Ok(t) => t,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let (message, title) = match event
+        {
+            SessionEvent::Key(key) =>
+            {
+                match app.handle_key(key)
+                {
+                    AppAction::None => (),
+                    AppAction::Search(query) =>
+                    {
+                        let cached = cache
+                            .fetch(config.search_dialect(), &query)
+                            .and_then(|x| provider.parse_list(&x).ok());
+                        if let Some(results) = cached
+                        {
+                            report("Using the results of the same search from earlier.");
+                            let results = filter_online_bots(results, &config.channel, user_arc);
+                            let _ = event_tx.send(SessionEvent::SearchResults(results));
+                            last_query = query;
+                            continue;
+                        }
+                        if connex
+                            .send_message(&config.channel, &provider.search_command(&query))
+                            .is_err()
+                        {
+                            report("Unable to send the search");
+                        }
+                        notice_results.clear();
+                        search_policy = Some(DccPolicy::for_search(&config.search_bot, &query));
+                        last_query = query;
+                    }
+                    AppAction::Download(works) =>
+                    {
+                        for work in works.iter()
+                        {
+                            queue_work(queue, work, history, config);
+                        }
+                        report_queue_error(queue.save());
+                    }
+                    AppAction::Answer(accepted) if !pending_offers.is_empty() =>
+                    {
+                        let offer = pending_offers.remove(0);
+                        if !accepted
+                        {
+                            report(&format!("Ignoring {}.", offer.title));
+                            if let Some(id) = offer.request
+                            {
+                                let reason = "the offer was declined";
+                                fail_request(queue, id, history, reason, None);
+                            }
+                            continue;
+                        }
+                        match offer.request
+                        {
+                            Some(id) =>
+                            {
+                                let size = offer_size(&offer.message);
+                                let received = start_transfer(
+                                    queue,
+                                    id,
+                                    offer.message,
+                                    offer.address,
+                                    offer.title,
+                                    download_dir,
+                                    config,
+                                    part_files,
+                                    &done_tx,
+                                );
+                                progress.insert(id, (received, size));
+                            }
+                            None => spawn_search_fetch(
+                                offer.message,
+                                offer.address,
+                                config,
+                                cache,
+                                &last_query,
+                                user_arc,
+                                &event_tx,
+                            ),
+                        }
+                    }
+                    AppAction::Answer(_) => (),
+                    AppAction::Quit => break,
+                }
+                continue;
+            }
+            SessionEvent::SearchResults(result) =>
+            {
+                if let Ok(t) = &result
+                {
+                    record_search(history, config, &last_query, t.len());
+                }
+                match result
+                {
+                    Ok(t) if t.is_empty() => report("No results were found."),
+                    Ok(t) =>
+                    {
+                        // Listed best first, so each book's first copy is its best one
+                        let ranked = ranker(config, user_arc, history).rank(&last_query, &t);
+                        app.set_results(ranked.into_iter().map(|x| x.result).collect());
+                    }
+                    Err(e) => report(&format!("Unable to download search results: {}", e)),
+                }
+                continue;
+            }
+            SessionEvent::BotReply { sender, text, event } =>
+            {
+                if queue.find_request_for_reply(&sender, &text).is_some()
+                {
+                    handle_bot_reply(queue, &sender, &text, event, history);
+                }
+                else if search_policy.as_ref().is_some_and(|x| x.trusts(&sender))
+                {
+                    report(&format!("{}: {}", sender, strip_formatting(&text)));
+                    if matches!(event, BotEvent::NoResults | BotEvent::Rejected { .. })
+                    {
+                        search_policy = None;
+                    }
+                }
+                continue;
+            }
+            SessionEvent::BotText { sender, text } =>
+            {
+                if search_policy.is_some()
+                {
+                    if let Some(result) = provider.parse_notice(&sender, &text)
+                    {
+                        notice_results.push(result);
+                        last_notice = time::Instant::now();
+                    }
+                }
+                continue;
+            }
+            SessionEvent::DccOffer { message, argument } => (*message, argument),
+            SessionEvent::Disconnected =>
+            {
+                report("Lost connection to the IRC server.");
+                break;
+            }
+            SessionEvent::Registered | SessionEvent::ChatOffer { .. } => continue,
+        };
 
-#[derive(Debug, Clone)]
-struct Pack
-{
-    value: String,
-    bot_source: String,
-    author: String,
-    book_title: String,
-}
-
-impl Pack
-{
-    pub fn new(value: &str) -> Self
-    {
-        let mut edited_value = value.to_string();
-        let bot_source = value.split(' ').collect::<Vec<&str>>()[0]
-            .chars()
-            .skip(1)
-            .collect::<String>();
-        edited_value = edited_value
-            .chars()
-            .skip(1 + bot_source.chars().count() + 1)
-            .collect::<String>();
-        let author = edited_value.split('-').take(1).collect::<String>();
-        edited_value = edited_value
-            .chars()
-            .skip(author.chars().count() + 2)
-            .collect::<String>();
-        // println!("{}", edited_value);
-        let mut was_last_character_info_marker = false;
-        let junk_chars = [',', '_', '.', '-', ':'];
-        let book_title = edited_value
-            .chars()
-            .take_while(|x| {
-                if (*x == ':')
+        let sender = get_sender(&message);
+        let offer = match &message.command
+        {
+            MessageCommand::PRIVMSGCTCP {
+                inner_message: Some(offer),
+                ..
+            } => offer,
+            _ => continue,
+        };
+        let (request, decision) = match queue.match_offer(&sender, offer)
+        {
+            (Some(id), decision) => (Some(id), decision),
+            (None, _) => match &search_policy
+            {
+                Some(policy) => (None, policy.evaluate(&sender, offer)),
+                None => continue,
+            },
+        };
+        match decision
+        {
+            DccDecision::Accept { address } =>
+            {
+                report(&format!("Accepting {} from {}.", title, sender));
+                match request
                 {
-                    if (was_last_character_info_marker)
+                    Some(id) =>
                     {
-                        false
+                        let size = offer_size(&message);
+                        let received = start_transfer(
+                            queue,
+                            id,
+                            message,
+                            address,
+                            title,
+                            download_dir,
+                            config,
+                            part_files,
+                            &done_tx,
+                        );
+                        progress.insert(id, (received, size));
                     }
-                    else
+                    None =>
                     {
-                        was_last_character_info_marker = true;
-                        true
+                        search_policy = None;
+                        spawn_search_fetch(
+                            message,
+                            address,
+                            config,
+                            cache,
+                            &last_query,
+                            user_arc,
+                            &event_tx,
+                        );
                     }
                 }
-                else
+            }
+            DccDecision::Prompt { reason, address } =>
+            {
+                report(&format!("{} offered {} ({}).", sender, title, reason));
+                if request.is_none()
                 {
-                    was_last_character_info_marker = false;
-                    true
+                    search_policy = None;
                 }
-            })
-            .filter(|x| !junk_chars.contains(x))
-            .collect::<String>();
+                pending_offers.push(PendingOffer {
+                    request,
+                    message,
+                    address,
+                    title,
+                });
+            }
+            DccDecision::Reject { reason } =>
+            {
+                report(&format!(
+                    "Ignoring DCC SEND of {} from {}: {}.",
+                    title, sender, reason
+                ));
+            }
+        }
+    }
+    capture_output(false);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_search_fetch(
+    message: IrcMessage,
+    address: SocketAddr,
+    config: &Config,
+    cache: &ResultCache,
+    query: &str,
+    user_arc: &Arc<Mutex<HashMap<String, Vec<String>>>>,
+    event_tx: &mpsc::Sender<SessionEvent>,
+)
+{
+    let timeouts = config.timeouts.clone();
+    let dialect = config.search_dialect();
+    let channel = config.channel.clone();
+    let cache = cache.clone();
+    let query = query.to_string();
+    let user_arc = Arc::clone(user_arc);
+    let event_tx = event_tx.clone();
+    thread::spawn(move || {
+        let result = fetch_search_results(message, address, &timeouts, dialect, &cache, &query)
+            .and_then(|x| filter_online_bots(x, &channel, &user_arc));
+        let _ = event_tx.send(SessionEvent::SearchResults(result));
+    });
+}
+
+fn offer_size(message: &IrcMessage) -> Option<u64>
+{
+    match &message.command
+    {
+        MessageCommand::PRIVMSGCTCP {
+            inner_message: Some(CtcpMessage::DCC { size, .. }),
+            ..
+        } => *size,
+        _ => None,
+    }
+}
+
+/// Rows for the transfers pane, active requests first.
+fn transfer_views(
+    queue: &DownloadQueue,
+    progress: &HashMap<usize, (Arc<AtomicU64>, Option<u64>)>,
+) -> Vec<TransferView>
+{
+    let mut views = queue
+        .requests
+        .iter()
+        .enumerate()
+        .map(|(id, x)| {
+            let (received, total) = match progress.get(&id)
+            {
+                Some((received, total)) => (received.load(Ordering::Relaxed), *total),
+                None => (0, None),
+            };
+            TransferView {
+                file: x.requested_file.clone(),
+                bot: x.bot_source.clone(),
+                state: x.state,
+                received,
+                total,
+            }
+        })
+        .collect::<Vec<TransferView>>();
+    views.sort_by_key(|x| match x.state
+    {
+        DownloadState::Transferring => 0,
+        DownloadState::Requested => 1,
+        DownloadState::Queued => 2,
+        DownloadState::Failed => 3,
+        DownloadState::Completed => 4,
+    });
+    views
+}
 
-        Self {
-            value: value.to_string(),
-            bot_source,
-            author,
-            book_title,
+fn handle_bot_reply(
+    queue: &mut DownloadQueue,
+    sender: &str,
+    text: &str,
+    event: BotEvent,
+    history: &History,
+)
+{
+    let id = match queue.find_request_for_reply(sender, text)
+    {
+        Some(t) => t,
+        None => return,
+    };
+    let requested_file = queue.requests[id].requested_file.clone();
+    match event
+    {
+        BotEvent::QueueUpdate { position } =>
+        {
+            match position
+            {
+                Some(position) =>
+                {
+                    report(&format!(
+                        "{} is queued at {}, position {}",
+                        requested_file, sender, position
+                    ))
+                }
+                None => report(&format!("{} is queued at {}", requested_file, sender)),
+            }
+            // The bot knows about the request, so give it a fresh offer deadline
+            queue.touch(id);
+        }
+        BotEvent::Sending => report(&format!("{} is about to send {}", sender, requested_file)),
+        BotEvent::Rejected {
+            reason,
+            slots_exhausted: true,
+        } =>
+        {
+            // Wait for one of our other transfers with this bot to finish before asking again
+            let other_requests = queue.in_flight_count(sender) - 1;
+            if other_requests == 0
+            {
+                report(&format!("{} refused {}: {}", sender, requested_file, reason));
+                fail_request(queue, id, history, &reason, None);
+            }
+            else
+            {
+                queue.set_slot_limit(sender, other_requests);
+                report_queue_error(queue.set_state(id, DownloadState::Queued));
+            }
+        }
+        BotEvent::Rejected { reason, .. } =>
+        {
+            report(&format!("{} refused {}: {}", sender, requested_file, reason));
+            fail_request(queue, id, history, &reason, None);
+        }
+        BotEvent::NoResults =>
+        {
+            report(&format!("{} does not have {}", sender, requested_file));
+            fail_request(queue, id, history, "the bot does not have it", None);
         }
     }
 }
 
+/// Streams a transfer into `<file>.part` and renames it once complete, so an interrupted
+/// download never looks like a finished one. The final name is reserved up front, so the
+/// rename only ever replaces our own empty placeholder.
+fn save_download(
+    dcc_connex: &mut DccConnection,
+    download_dir: &Path,
+    title: &str,
+    part_files: &PartFiles,
+) -> Result<PathBuf, &'static str>
+{
+    let file_path = reserve_download_path(download_dir, title)?;
+    let mut part_name = file_path.file_name().unwrap().to_os_string();
+    part_name.push(".part");
+    let part_path = file_path.with_file_name(part_name);
+
+    part_files.register(&file_path);
+    part_files.register(&part_path);
+    let result = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&part_path)
+    {
+        Ok(mut file) => dcc_connex.transfer(&mut file),
+        Err(_e) => Err("Unable to create download file"),
+    };
+    // Once the placeholder is replaced it is the finished book, not something to clean up
+    part_files.unregister(&file_path);
+    let result = result.and_then(|_| match fs::rename(&part_path, &file_path)
+    {
+        Ok(_) => Ok(file_path.clone()),
+        Err(_e) => Err("Unable to move finished download into place"),
+    });
+    if result.is_err()
+    {
+        let _ = fs::remove_file(&part_path);
+        let _ = fs::remove_file(&file_path);
+    }
+    part_files.unregister(&part_path);
+    result
+}
+
+enum SessionEvent
+{
+    Registered,
+    /// The server closed the connection or it failed.
+    Disconnected,
+    ChatOffer
+    {
+        message: Box<IrcMessage>,
+    },
+    DccOffer
+    {
+        message: Box<IrcMessage>,
+        argument: String,
+    },
+    BotReply
+    {
+        sender: String,
+        text: String,
+        event: BotEvent,
+    },
+    /// A NOTICE or PRIVMSG from a bot that is not about one of our requests, such as a search
+    /// result sent line by line.
+    BotText
+    {
+        sender: String,
+        text: String,
+    },
+    Key(Key),
+    SearchResults(Result<Vec<SearchResult>, &'static str>),
+}
+
+/// Waits for a DCC offer the policy accepts and returns it with the address to connect to, or
+/// returns `None` once the bot reports that nothing will be sent. Fails if the connection is
+/// lost meanwhile.
 fn wait_until_new_dcc(
-    read_loop_receiver: &mpsc::Receiver<(IrcMessage, String)>,
-) -> (IrcMessage, String)
+    read_loop_receiver: &mpsc::Receiver<SessionEvent>,
+    policy: &DccPolicy,
+    timeout: time::Duration,
+) -> Result<Option<(IrcMessage, SocketAddr)>, &'static str>
 {
-    let (dcc_send_request, title) = loop
+    let deadline = time::Instant::now() + timeout;
+    loop
     {
-        let (dcc_send_request, title) = read_loop_receiver.recv().unwrap();
-        let sender = match dcc_send_request.prefix.as_ref().unwrap()
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        let event = match read_loop_receiver.recv_timeout(remaining)
         {
-            MessagePrefix::User {
-                nickname,
-                username: _,
-                host: _,
-            } => nickname,
-            MessagePrefix::Server { servername } => servername,
+            Ok(t) => t,
+            Err(mpsc::RecvTimeoutError::Timeout) =>
+            {
+                report("Timed out waiting for a DCC offer.");
+                return Ok(None);
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) =>
+            {
+                return Err("Lost connection to the IRC server")
+            }
         };
-        println!("DCC SEND Request from {}. (y) to accept", sender);
-        let mut buf = String::new();
-        stdin().read_line(&mut buf).unwrap();
-        if buf.starts_with('y')
+        let (dcc_send_request, title) = match event
+        {
+            SessionEvent::DccOffer { message, argument } => (*message, argument),
+            // The channel closing right after this ends the wait
+            SessionEvent::Registered
+            | SessionEvent::Disconnected
+            | SessionEvent::ChatOffer { .. }
+            | SessionEvent::BotText { .. }
+            | SessionEvent::Key(_)
+            | SessionEvent::SearchResults(_) => continue,
+            // Anyone can send a NOTICE, only the bot we asked may call the request off
+            SessionEvent::BotReply { sender, .. } if !policy.trusts(&sender) => continue,
+            SessionEvent::BotReply { sender, text, event } => match event
+            {
+                BotEvent::NoResults | BotEvent::Rejected { .. } =>
+                {
+                    report(&format!("{}: {}", sender, strip_formatting(&text)));
+                    return Ok(None);
+                }
+                BotEvent::QueueUpdate { .. } | BotEvent::Sending =>
+                {
+                    report(&format!("{}: {}", sender, strip_formatting(&text)));
+                    continue;
+                }
+            },
+        };
+        let sender = get_sender(&dcc_send_request);
+        let decision = match &dcc_send_request.command
+        {
+            MessageCommand::PRIVMSGCTCP {
+                inner_message: Some(offer),
+                ..
+            } => policy.evaluate(&sender, offer),
+            _ => DccDecision::Reject {
+                reason: "not a CTCP message",
+            },
+        };
+        if let Some(address) = offer_accepted(decision, &title, &sender)
         {
-            break (dcc_send_request, title);
+            return Ok(Some((dcc_send_request, address)));
         }
-    };
-    (dcc_send_request, title.to_string())
+    }
+}
+
+fn get_sender(message: &IrcMessage) -> String
+{
+    match message.prefix.as_ref()
+    {
+        Some(MessagePrefix::User {
+            nickname,
+            username: _,
+            host: _,
+        }) => nickname.to_string(),
+        Some(MessagePrefix::Server { servername }) => servername.to_string(),
+        None => String::new(),
+    }
+}
+
+/// Applies a policy decision, asking the user when the policy cannot decide on its own.
+/// Returns the checked address to connect to when the offer is accepted.
+fn offer_accepted(decision: DccDecision, title: &str, sender: &str) -> Option<SocketAddr>
+{
+    match decision
+    {
+        DccDecision::Accept { address } =>
+        {
+            report(&format!("Accepting {} from {}.", title, sender));
+            Some(address)
+        }
+        DccDecision::Reject { reason } =>
+        {
+            report(&format!(
+                "Ignoring DCC SEND of {} from {}: {}.",
+                title, sender, reason
+            ));
+            None
+        }
+        DccDecision::Prompt { reason, address } =>
+        {
+            report(&format!(
+                "DCC SEND Request for {} from {} ({}). (y) to accept",
+                title, sender, reason
+            ));
+            let mut buf = String::new();
+            stdin().read_line(&mut buf).unwrap();
+            buf.starts_with('y').then_some(address)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -255,27 +2305,57 @@ pub struct DccConnection
 {
     pub sock: TcpStream,
     pub reader: BufReader<TcpStream>,
+    pub expected_size: Option<u64>,
+    /// The transfer is aborted once more than this has arrived, as the advertised size may be
+    /// missing or false.
+    pub max_size: Option<u64>,
+    /// Bytes received so far, for showing progress while the transfer runs on another thread.
+    pub progress: Option<Arc<AtomicU64>>,
 }
 
 impl DccConnection
 {
-    pub fn connect_raw(ip_address: &str) -> Result<DccConnection, &'static str>
+    pub fn connect_raw(
+        ip_address: SocketAddr,
+        timeouts: &Timeouts,
+    ) -> Result<DccConnection, &'static str>
     {
-        println!("Attempting to connect to: {}", ip_address);
-        let sock = TcpStream::connect(ip_address).unwrap();
+        report(&format!("Attempting to connect to: {}", ip_address));
+        let sock = match TcpStream::connect_timeout(&ip_address, timeouts.dcc_connect)
+        {
+            Ok(t) => t,
+            Err(_e) => return Err("Unable to connect to DCC sender"),
+        };
+        if sock.set_read_timeout(Some(timeouts.chunk_idle)).is_err()
+        {
+            return Err("Unable to set DCC read timeout");
+        }
         let reader = BufReader::new(sock.try_clone().unwrap());
 
-        Ok(DccConnection { sock, reader })
+        Ok(DccConnection {
+            sock,
+            reader,
+            expected_size: None,
+            max_size: None,
+            progress: None,
+        })
     }
-    pub fn connect(msg: IrcMessage) -> Result<DccConnection, &'static str>
+    /// Connects to `address`, the one the DCC policy checked, rather than resolving the
+    /// offer's host again.
+    pub fn connect(
+        msg: IrcMessage,
+        address: SocketAddr,
+        timeouts: &Timeouts,
+    ) -> Result<DccConnection, &'static str>
     {
         match msg.command{
             MessageCommand::PRIVMSGCTCP { message_target: _, text: _, inner_message, inner_text: _, inner_params: _ } => match inner_message {
                 Some(x) => match x{
-                    CtcpMessage::DCC { query_type, argument: _, address, port } => match query_type{
+                    CtcpMessage::DCC { query_type, size, .. } => match query_type{
                         DCCQueryType::SEND => {
-                            let full_address = CtcpMessage::get_full_address_from_strings(address,port).unwrap();
-                            return DccConnection::connect_raw(full_address.as_str());
+                            let mut dcc_connex = DccConnection::connect_raw(address, timeouts)?;
+                            dcc_connex.expected_size = size;
+                            Ok(dcc_connex)
                         },
                         _ => Err("CTCP message was found, and it was a DCC request, but it was not a DCC Send")
                     },
@@ -286,98 +2366,190 @@ impl DccConnection
             _ => Err("Non CTCP message")
         }
     }
-    pub fn get_all_bytes(&mut self) -> Vec<u8>
+    pub fn get_all_bytes(&mut self) -> Result<Vec<u8>, &'static str>
     {
         let mut buf: Vec<u8> = Vec::new();
-        self.reader.read_to_end(&mut buf).unwrap();
-        buf
+        self.transfer(&mut buf)?;
+        Ok(buf)
+    }
+    /// Copies the file into `out` until the sender closes the connection or the advertised
+    /// size has arrived, acknowledging every chunk as the DCC protocol expects.
+    pub fn transfer<W: Write>(&mut self, out: &mut W) -> Result<u64, &'static str>
+    {
+        let mut chunk = [0u8; 16 * 1024];
+        let mut received: u64 = 0;
+        loop
+        {
+            if is_cancelled()
+            {
+                return Err("Transfer cancelled");
+            }
+            if self.expected_size.is_some_and(|x| received >= x)
+            {
+                break;
+            }
+            let read = match self.reader.read(&mut chunk)
+            {
+                Ok(t) => t,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    return Err("DCC transfer stalled")
+                }
+                Err(_e) => return Err("DCC transfer failed"),
+            };
+            if read == 0
+            {
+                break;
+            }
+            if self.max_size.is_some_and(|x| received + read as u64 > x)
+            {
+                return Err("DCC transfer is larger than the allowed maximum");
+            }
+            if out.write_all(&chunk[..read]).is_err()
+            {
+                return Err("Unable to write received data");
+            }
+            received += read as u64;
+            if let Some(progress) = &self.progress
+            {
+                progress.store(received, Ordering::Relaxed);
+            }
+            // Acks are the low 32 bits of the total, senders that do not need them ignore them
+            let _ = self.sock.write_all(&(received as u32).to_be_bytes());
+        }
+        if self.expected_size.is_some_and(|x| received < x)
+        {
+            return Err("DCC transfer ended before the whole file arrived");
+        }
+        Ok(received)
     }
 }
 
+/// Turns server lines into session events until the connection is lost, then sends
+/// `Disconnected` and returns. Dropping its sender lets a receiver see the channel close.
 fn read_loop(
     mut connex: IrcConnection,
-    tx: std::sync::mpsc::Sender<(IrcMessage, String)>,
+    tx: std::sync::mpsc::Sender<SessionEvent>,
+    classifier: BotReplyClassifier,
+    ctcp_version: String,
     users: Arc<Mutex<HashMap<String, Vec<String>>>>,
-) -> !
+)
 {
     let mut buf = String::new();
     let mut reader = connex.get_reader();
+    let mut ctcp_limit = CtcpRateLimit::new(time::Instant::now());
     loop
     {
+        buf.clear();
+        match reader.read_line(&mut buf)
         {
-            let this = reader.read_line(&mut buf);
-            match this {
-                Ok(t) => t,
-                Err(_) => {continue;}
-            }
-        };
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_e) => break,
+        }
         buf = buf.trim_end_matches("\r\n").to_string();
-        // println!("{:?}", buf);
-        let message = IrcMessage::parse_message(&buf).unwrap();
-        // println!("{:?}", buf);
-        // println!("{:#?}", message);
-        match message.command
+        log_irc_line(&buf);
+        let message = match IrcMessage::parse_message(&buf)
+        {
+            Ok(t) => t,
+            Err(_e) => continue,
+        };
+        let sender = get_sender(&message);
+        let from_user = matches!(message.prefix, Some(MessagePrefix::User { .. }));
+        let sent = match &message.command
         {
             MessageCommand::PING { token } =>
             {
-                connex.send_command_args("PONG", token.as_str()).unwrap();
+                if connex.send_command_args("PONG", token.as_str()).is_err()
+                {
+                    break;
+                }
+                Ok(())
             }
+            MessageCommand::RPL_WELCOME => tx.send(SessionEvent::Registered),
             MessageCommand::PRIVMSGCTCP {
-                message_target: _,
-                text: _,
-                inner_message,
-                inner_text: _,
-                inner_params: _,
-            } =>
-            {
-                if let CtcpMessage::DCC {
-                    query_type,
-                    argument,
-                    address,
-                    port,
-                } = inner_message.unwrap()
+                inner_message:
+                    Some(CtcpMessage::DCC {
+                        query_type,
+                        argument,
+                        address,
+                        port,
+                        ..
+                    }),
+                ..
+            } => match query_type
+            {
+                DCCQueryType::SEND =>
                 {
-                    match query_type
-                    {
-                        DCCQueryType::SEND =>
-                        {
-                            println!("New file: {} on {}:{}", argument, address, port);
-                            tx.send((
-                                IrcMessage::parse_message(&buf).unwrap(),
-                                argument.to_string(),
-                            ))
-                            .unwrap();
-                        }
-                        DCCQueryType::CHAT => println!("Attempted chat {}:{}", address, port),
-                        _ =>
-                        {}
-                    }
+                    report(&format!("New file: {} on {}:{}", argument, address, port));
+                    tx.send(SessionEvent::DccOffer {
+                        message: Box::new(message.clone()),
+                        argument: argument.to_string(),
+                    })
                 }
-            }
-            MessageCommand::RPL_NAME_REPLY { channel, names } =>
+                DCCQueryType::CHAT =>
+                {
+                    report(&format!("New chat from {} on {}:{}", sender, address, port));
+                    tx.send(SessionEvent::ChatOffer {
+                        message: Box::new(message.clone()),
+                    })
+                }
+                _ => Ok(()),
+            },
+            MessageCommand::PRIVMSGCTCP {
+                inner_message: Some(ctcp),
+                ..
+            } if from_user =>
             {
-                let mut users = users.lock().unwrap();
-                let cur_vec = users.get(&channel);
-                if cur_vec.is_none()
+                let now = time::SystemTime::now()
+                    .duration_since(time::UNIX_EPOCH)
+                    .map(|x| x.as_secs())
+                    .unwrap_or(0);
+                let reply = ctcp.get_reply(&ctcp_version, now);
+                if let Some(reply) = reply.filter(|_| ctcp_limit.allow(time::Instant::now()))
                 {
-                    users.insert(channel, names);
+                    let _ = connex.send_notice(&sender, &format!("\u{1}{}\u{1}", reply));
                 }
-                else
+                Ok(())
+            }
+            MessageCommand::NOTICE {
+                message_target,
+                text,
+            }
+            | MessageCommand::PRIVMSG {
+                message_target,
+                text,
+            } if from_user && !message_target.starts_with('#') =>
+            {
+                let text = text.to_string();
+                match classifier.classify(&sender, &text)
                 {
-                    let mut new_vec = cur_vec
-                        .unwrap()
-                        .iter()
-                        .map(|x| x.to_string())
-                        .collect::<Vec<String>>();
-                    new_vec.extend(names);
-                    users.insert(channel, new_vec);
+                    Some(event) => tx.send(SessionEvent::BotReply {
+                        sender,
+                        text,
+                        event,
+                    }),
+                    None => tx.send(SessionEvent::BotText { sender, text }),
                 }
             }
-            MessageCommand::NONHANDLED =>
-            {}
-            _ =>
-            {}
+            MessageCommand::RPL_NAME_REPLY { channel, names } =>
+            {
+                let mut users = users.lock().unwrap();
+                users
+                    .entry(channel.to_string())
+                    .or_default()
+                    .extend(names.iter().cloned());
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        // Nobody is listening any more
+        if sent.is_err()
+        {
+            return;
         }
-        buf.clear();
     }
+    let _ = tx.send(SessionEvent::Disconnected);
 }
@@ -0,0 +1,260 @@
+use crate::epub::parse_isbn;
+use std::fs;
+use std::path::Path;
+
+/// The PalmDB type and creator of MOBI and AZW3 books alike.
+const MOBI_TYPE_AND_CREATOR: &[u8] = b"BOOKMOBI";
+const PALM_DATABASE_HEADER_LENGTH: usize = 78;
+/// Set in the MOBI header when EXTH records follow it.
+const EXTH_FLAG: u32 = 0x40;
+const UTF8_ENCODING: u32 = 65001;
+
+// EXTH record types
+const EXTH_AUTHOR: u32 = 100;
+const EXTH_PUBLISHER: u32 = 101;
+const EXTH_ISBN: u32 = 104;
+const EXTH_PUBLISHING_DATE: u32 = 106;
+const EXTH_ASIN: u32 = 113;
+const EXTH_UPDATED_TITLE: u32 = 503;
+const EXTH_LANGUAGE: u32 = 524;
+
+/// What the PalmDB, MOBI and EXTH headers of a `.mobi`, `.azw` or `.azw3` file say about it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MobiMetadata
+{
+    pub title: String,
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    /// Digits only.
+    pub isbn: Option<String>,
+    /// Amazon's id for the book.
+    pub asin: Option<String>,
+    pub date: Option<String>,
+    /// The PalmDOC encryption type, 0 for none, 1 and 2 for the two kinds of Mobipocket DRM.
+    pub encryption: u16,
+    /// A KF8 book, the format of `.azw3` files.
+    pub kf8: bool,
+}
+
+impl MobiMetadata
+{
+    /// Whether the text is encrypted, so the book only opens on the device it was bought for.
+    pub fn drm_locked(&self) -> bool
+    {
+        self.encryption != 0
+    }
+}
+
+pub fn read_mobi_file(path: &Path) -> Result<MobiMetadata, &'static str>
+{
+    match fs::read(path)
+    {
+        Ok(t) => read_mobi(&t),
+        Err(_e) => Err("Unable to read MOBI file"),
+    }
+}
+
+pub fn read_mobi(data: &[u8]) -> Result<MobiMetadata, &'static str>
+{
+    if data.get(60..68) != Some(MOBI_TYPE_AND_CREATOR)
+    {
+        return Err("Not a MOBI file");
+    }
+    // Each entry of the record list is an offset and 4 bytes of attributes and id
+    let record_count = be_u16(data, 76)? as usize;
+    if record_count == 0
+    {
+        return Err("The MOBI file has no records");
+    }
+    let record0_start = be_u32(data, PALM_DATABASE_HEADER_LENGTH)? as usize;
+    let record0_end = match record_count
+    {
+        1 => data.len(),
+        _ => be_u32(data, PALM_DATABASE_HEADER_LENGTH + 8)? as usize,
+    };
+    let record0 = match data.get(record0_start..record0_end)
+    {
+        Some(t) => t,
+        None => return Err("Truncated MOBI header"),
+    };
+
+    // The PalmDOC header comes first, the MOBI header right after it
+    let encryption = be_u16(record0, 12)?;
+    if record0.get(16..20) != Some(b"MOBI")
+    {
+        return Err("No MOBI header");
+    }
+    let mobi_header_length = be_u32(record0, 20)? as usize;
+    let text_encoding = be_u32(record0, 28)?;
+    let version = be_u32(record0, 36)?;
+    let full_name_offset = be_u32(record0, 84)? as usize;
+    let full_name_length = be_u32(record0, 88)? as usize;
+    let locale = be_u32(record0, 92)?;
+    let exth_flags = be_u32(record0, 128).unwrap_or(0);
+
+    let decode = |x: &[u8]| decode_text(x, text_encoding);
+    let mut metadata = MobiMetadata {
+        title: record0
+            .get(full_name_offset..full_name_offset + full_name_length)
+            .map(decode)
+            .unwrap_or_default(),
+        language: locale_language(locale).map(|x| x.to_string()),
+        encryption,
+        kf8: version >= 8,
+        ..MobiMetadata::default()
+    };
+    if exth_flags & EXTH_FLAG == 0
+    {
+        return Ok(metadata);
+    }
+    for (record_type, value) in exth_records(record0, 16 + mobi_header_length)
+    {
+        let value = decode(value);
+        if value.is_empty()
+        {
+            continue;
+        }
+        match record_type
+        {
+            EXTH_AUTHOR => metadata.authors.push(value),
+            EXTH_PUBLISHER => metadata.publisher = Some(value),
+            EXTH_ISBN => metadata.isbn = metadata.isbn.or(parse_isbn(&value, true)),
+            EXTH_PUBLISHING_DATE => metadata.date = Some(value),
+            EXTH_ASIN => metadata.asin = Some(value),
+            EXTH_UPDATED_TITLE => metadata.title = value,
+            EXTH_LANGUAGE => metadata.language = Some(value),
+            _ => (),
+        }
+    }
+    Ok(metadata)
+}
+
+/// Checks that the record list points inside the file and in order, and that there are as many
+/// records as the PalmDOC header says the text takes. A file cut off in transfer fails this.
+pub fn check_records(data: &[u8]) -> Result<(), &'static str>
+{
+    let record_count = be_u16(data, 76)? as usize;
+    let mut previous = 0;
+    for i in 0..record_count
+    {
+        let offset = be_u32(data, PALM_DATABASE_HEADER_LENGTH + 8 * i)? as usize;
+        if offset < previous || offset > data.len()
+        {
+            return Err("The MOBI record list points past the end of the file");
+        }
+        previous = offset;
+    }
+    let record0_start = be_u32(data, PALM_DATABASE_HEADER_LENGTH)? as usize;
+    let text_record_count = be_u16(data, record0_start + 8)? as usize;
+    if text_record_count >= record_count
+    {
+        return Err("The MOBI file has fewer records than its text needs");
+    }
+    Ok(())
+}
+
+/// The type and value of each EXTH record, stopping at the first one that is cut off.
+fn exth_records(record0: &[u8], start: usize) -> Vec<(u32, &[u8])>
+{
+    let mut ret_val = Vec::new();
+    if record0.get(start..start + 4) != Some(b"EXTH")
+    {
+        return ret_val;
+    }
+    let count = be_u32(record0, start + 8).unwrap_or(0);
+    let mut position = start + 12;
+    for _i in 0..count
+    {
+        // The length includes the type and the length itself
+        let (record_type, length) = match (be_u32(record0, position), be_u32(record0, position + 4))
+        {
+            (Ok(record_type), Ok(length)) if length >= 8 => (record_type, length as usize),
+            _ => break,
+        };
+        match record0.get(position + 8..position + length)
+        {
+            Some(t) => ret_val.push((record_type, t)),
+            None => break,
+        }
+        position += length;
+    }
+    ret_val
+}
+
+fn be_u16(data: &[u8], position: usize) -> Result<u16, &'static str>
+{
+    match data.get(position..position + 2)
+    {
+        Some(t) => Ok(u16::from_be_bytes([t[0], t[1]])),
+        None => Err("Truncated MOBI header"),
+    }
+}
+
+fn be_u32(data: &[u8], position: usize) -> Result<u32, &'static str>
+{
+    match data.get(position..position + 4)
+    {
+        Some(t) => Ok(u32::from_be_bytes([t[0], t[1], t[2], t[3]])),
+        None => Err("Truncated MOBI header"),
+    }
+}
+
+/// MOBI text is UTF-8 or Windows-1252, padded with zero bytes.
+fn decode_text(data: &[u8], encoding: u32) -> String
+{
+    let text = if encoding == UTF8_ENCODING
+    {
+        String::from_utf8_lossy(data).to_string()
+    }
+    else
+    {
+        data.iter().map(|x| cp1252_char(*x)).collect()
+    };
+    text.trim_matches(|x: char| x == '\0' || x.is_whitespace())
+        .to_string()
+}
+
+fn cp1252_char(byte: u8) -> char
+{
+    // Windows-1252 differs from Latin-1 only in 0x80 to 0x9f
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+    match byte
+    {
+        0x80..=0x9f => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// The language of a Windows locale id, for books without an EXTH language record.
+fn locale_language(locale: u32) -> Option<&'static str>
+{
+    match locale & 0xff
+    {
+        0x04 => Some("zh"),
+        0x05 => Some("cs"),
+        0x06 => Some("da"),
+        0x07 => Some("de"),
+        0x08 => Some("el"),
+        0x09 => Some("en"),
+        0x0a => Some("es"),
+        0x0b => Some("fi"),
+        0x0c => Some("fr"),
+        0x0e => Some("hu"),
+        0x10 => Some("it"),
+        0x11 => Some("ja"),
+        0x12 => Some("ko"),
+        0x13 => Some("nl"),
+        0x14 => Some("no"),
+        0x15 => Some("pl"),
+        0x16 => Some("pt"),
+        0x19 => Some("ru"),
+        0x1d => Some("sv"),
+        0x1f => Some("tr"),
+        _ => None,
+    }
+}
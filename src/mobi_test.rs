@@ -0,0 +1,191 @@
+use crate::library::embedded_metadata;
+use crate::mobi::read_mobi;
+use std::env;
+use std::fs;
+use std::process;
+
+/// A PalmDB file with a record 0 holding the PalmDOC, MOBI and EXTH headers, and one text
+/// record after it.
+fn mobi_file(
+    full_name: &[u8],
+    encoding: u32,
+    encryption: u16,
+    locale: u32,
+    exth: &[(u32, &[u8])],
+) -> Vec<u8>
+{
+    let mobi_header_length = 232u32;
+    let mut record0: Vec<u8> = Vec::new();
+    // PalmDOC header: compression, unused, text length, record count, record size,
+    // encryption and unused
+    record0.extend([0, 1, 0, 0, 0, 0, 0, 4, 0, 1, 0x10, 0]);
+    record0.extend(encryption.to_be_bytes());
+    record0.extend([0, 0]);
+    let mut mobi_header = vec![0u8; mobi_header_length as usize];
+    mobi_header[0..4].copy_from_slice(b"MOBI");
+    mobi_header[4..8].copy_from_slice(&mobi_header_length.to_be_bytes());
+    mobi_header[12..16].copy_from_slice(&encoding.to_be_bytes());
+    mobi_header[20..24].copy_from_slice(&6u32.to_be_bytes());
+    mobi_header[76..80].copy_from_slice(&locale.to_be_bytes());
+    if !exth.is_empty()
+    {
+        mobi_header[112..116].copy_from_slice(&0x40u32.to_be_bytes());
+    }
+    let mut exth_header: Vec<u8> = Vec::new();
+    if !exth.is_empty()
+    {
+        let mut exth_records: Vec<u8> = Vec::new();
+        for (record_type, value) in exth.iter()
+        {
+            exth_records.extend(record_type.to_be_bytes());
+            exth_records.extend((value.len() as u32 + 8).to_be_bytes());
+            exth_records.extend(*value);
+        }
+        exth_header.extend(b"EXTH");
+        exth_header.extend((exth_records.len() as u32 + 12).to_be_bytes());
+        exth_header.extend((exth.len() as u32).to_be_bytes());
+        exth_header.extend(exth_records);
+    }
+    let full_name_offset = (16 + mobi_header.len() + exth_header.len()) as u32;
+    mobi_header[68..72].copy_from_slice(&full_name_offset.to_be_bytes());
+    mobi_header[72..76].copy_from_slice(&(full_name.len() as u32).to_be_bytes());
+    record0.extend(mobi_header);
+    record0.extend(exth_header);
+    record0.extend(full_name);
+    record0.extend([0, 0]);
+
+    let mut data = vec![0u8; 78];
+    data[..7].copy_from_slice(b"Example");
+    data[60..68].copy_from_slice(b"BOOKMOBI");
+    data[76..78].copy_from_slice(&2u16.to_be_bytes());
+    // Two record list entries and two bytes of padding
+    let record0_offset = 78 + 16 + 2;
+    let record1_offset = record0_offset + record0.len() as u32;
+    data.extend(record0_offset.to_be_bytes());
+    data.extend([0, 0, 0, 0]);
+    data.extend(record1_offset.to_be_bytes());
+    data.extend([0, 0, 0, 2]);
+    data.extend([0, 0]);
+    data.extend(record0);
+    data.extend(b"Some text");
+    data
+}
+
+#[test]
+fn read_mobi_test()
+{
+    let data = mobi_file(
+        b"Dune",
+        65001,
+        0,
+        0x0409,
+        &[
+            (100, b"Frank Herbert"),
+            (101, b"Ace"),
+            (104, b"978-0-441-17271-9"),
+            (106, b"1965-08-01"),
+            (113, b"B00B7NPRY8"),
+            (503, "Dune: Édition".as_bytes()),
+        ],
+    );
+    let metadata = read_mobi(&data).unwrap();
+    assert_eq!("Dune: Édition", metadata.title);
+    assert_eq!(vec!["Frank Herbert".to_string()], metadata.authors);
+    assert_eq!(Some("Ace".to_string()), metadata.publisher);
+    assert_eq!(Some("9780441172719".to_string()), metadata.isbn);
+    assert_eq!(Some("B00B7NPRY8".to_string()), metadata.asin);
+    assert_eq!(Some("1965-08-01".to_string()), metadata.date);
+    assert_eq!(Some("en".to_string()), metadata.language);
+    assert!(!metadata.drm_locked());
+    assert!(!metadata.kf8);
+}
+#[test]
+fn read_mobi_without_exth_test()
+{
+    // Windows-1252 text, and the language from the locale alone
+    let data = mobi_file(b"Caf\xe9 \x93Noir\x94", 1252, 0, 0x040c, &[]);
+    let metadata = read_mobi(&data).unwrap();
+    assert_eq!("Café “Noir”", metadata.title);
+    assert!(metadata.authors.is_empty());
+    assert_eq!(Some("fr".to_string()), metadata.language);
+    let data = mobi_file(
+        b"Dune",
+        65001,
+        0,
+        0,
+        &[(100, b"Frank Herbert"), (524, b"en-GB")],
+    );
+    assert_eq!(
+        Some("en-GB".to_string()),
+        read_mobi(&data).unwrap().language
+    );
+}
+#[test]
+// An EXTH ISBN with the right number of bytes but not of characters is skipped, not a panic
+fn read_mobi_non_ascii_isbn_test()
+{
+    let data = mobi_file(
+        b"Dune",
+        1252,
+        0,
+        0x0409,
+        &[(104, b"12345678\xc9"), (104, b"978-0-441-17271-9")],
+    );
+    assert_eq!(
+        Some("9780441172719".to_string()),
+        read_mobi(&data).unwrap().isbn
+    );
+    let data = mobi_file(b"Dune", 65001, 0, 0x0409, &[(104, "12345678É".as_bytes())]);
+    assert_eq!(None, read_mobi(&data).unwrap().isbn);
+}
+#[test]
+fn read_mobi_drm_test()
+{
+    let data = mobi_file(b"Dune", 65001, 2, 0x0409, &[(100, b"Frank Herbert")]);
+    let metadata = read_mobi(&data).unwrap();
+    assert_eq!(2, metadata.encryption);
+    assert!(metadata.drm_locked());
+}
+#[test]
+fn read_malformed_mobi_test()
+{
+    let data = mobi_file(b"Dune", 65001, 0, 0x0409, &[(100, b"Frank Herbert")]);
+    assert_eq!(Err("Truncated MOBI header"), read_mobi(&data[..120]));
+    assert_eq!(Err("Not a MOBI file"), read_mobi(b"%PDF-1.4"));
+    let mut palmdoc = data.clone();
+    palmdoc[60..68].copy_from_slice(b"TEXtREAd");
+    assert_eq!(Err("Not a MOBI file"), read_mobi(&palmdoc));
+    // An EXTH record running past the end of record 0 is dropped, not read out of bounds
+    let mut data = mobi_file(
+        b"Dune",
+        65001,
+        0,
+        0,
+        &[(101, b"Ace"), (100, b"Frank Herbert")],
+    );
+    let second_length = 16 + 232 + 12 + 11 + 4;
+    data[96 + second_length..96 + second_length + 4].copy_from_slice(&9999u32.to_be_bytes());
+    let metadata = read_mobi(&data).unwrap();
+    assert_eq!(Some("Ace".to_string()), metadata.publisher);
+    assert!(metadata.authors.is_empty());
+}
+#[test]
+fn embedded_mobi_metadata_test()
+{
+    let path = env::temp_dir().join(format!("rs-book-downloader-mobi-{}.azw3", process::id()));
+    let data = mobi_file(
+        b"Dune",
+        65001,
+        1,
+        0x0409,
+        &[(100, b"Frank Herbert"), (106, b"1965")],
+    );
+    fs::write(&path, data).unwrap();
+    let metadata = embedded_metadata(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!("Frank Herbert", metadata.author);
+    assert_eq!("Dune", metadata.title);
+    assert_eq!(Some("1965".to_string()), metadata.year);
+    assert_eq!(Some("azw3".to_string()), metadata.extension);
+    assert!(metadata.drm_locked);
+}
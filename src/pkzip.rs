@@ -6,6 +6,11 @@ use std::io::Seek;
 use std::io::SeekFrom;
 use std::ops::BitAnd;
 
+use crate::dcc_policy::MAX_PACK_SIZE;
+
+const MALFORMED_DEFLATE_STREAM: &str = "Malformed deflate stream";
+const DECOMPRESSED_TOO_LARGE: &str = "Decompressed data is larger than the allowed maximum";
+
 #[derive(Debug)]
 pub struct PkZip
 {
@@ -22,6 +27,63 @@ impl PkZip
         let pkzip_magic_numbers: &[u8] = &[0x50, 0x4b, 0x03, 0x04];
         file.starts_with(pkzip_magic_numbers)
     }
+    /// Whether every header `new` and `get_files` read lies within `file`, as they panic on
+    /// archives that are cut off or otherwise malformed.
+    pub fn is_complete(file: &[u8]) -> bool
+    {
+        let pkzip_end_of_central_directory_signature: &[u8] = &[0x50, 0x4b, 0x05, 0x06];
+        // Only called once the bounds are checked
+        let u16_at = |x: usize| u16::from_le_bytes([file[x], file[x + 1]]) as usize;
+        let u32_at = |x: usize| {
+            u32::from_le_bytes([file[x], file[x + 1], file[x + 2], file[x + 3]]) as usize
+        };
+        let position_of_ecdr = match file
+            .windows(pkzip_end_of_central_directory_signature.len())
+            .position(|x| x == pkzip_end_of_central_directory_signature)
+        {
+            Some(t) => t,
+            None => return false,
+        };
+        if position_of_ecdr + 22 > file.len()
+            || position_of_ecdr + 22 + u16_at(position_of_ecdr + 20) > file.len()
+        {
+            return false;
+        }
+        let mut position = u32_at(position_of_ecdr + 16);
+        for _i in 0..u16_at(position_of_ecdr + 10)
+        {
+            if position + 46 > file.len()
+                || file[position..position + 4] != [0x50, 0x4b, 0x01, 0x02]
+            {
+                return false;
+            }
+            let file_name_length = u16_at(position + 28);
+            let end_position =
+                position + 46 + file_name_length + u16_at(position + 30) + u16_at(position + 32);
+            if end_position > file.len()
+                || std::str::from_utf8(&file[position + 46..position + 46 + file_name_length])
+                    .is_err()
+                || get_compression_method(u16_at(position + 10) as u16).is_err()
+            {
+                return false;
+            }
+            let compressed_size = u32_at(position + 20);
+            let local_header = u32_at(position + 42);
+            if local_header + 30 > file.len()
+                || file[local_header..local_header + 4] != [0x50, 0x4b, 0x03, 0x04]
+            {
+                return false;
+            }
+            let data_start =
+                local_header + 30 + u16_at(local_header + 26) + u16_at(local_header + 28);
+            if data_start + compressed_size > file.len()
+            {
+                return false;
+            }
+            position = end_position;
+        }
+        true
+    }
     pub fn get_files(&self) -> Vec<PkZipFile>
     {
         let ret_val: &mut Vec<PkZipFile> = &mut Vec::new();
@@ -102,7 +164,7 @@ impl PkZip
             .read_exact(offset_of_start_of_central_directory_with_respect_to_starting_disk_number)
             .unwrap();
         cursor.read_exact(zip_file_comment_length).unwrap();
-        let zip_file_comment_length_value = u16::from_be_bytes(*zip_file_comment_length) as usize;
+        let zip_file_comment_length_value = u16::from_le_bytes(*zip_file_comment_length) as usize;
         let zip_file_comment = &mut Vec::with_capacity(zip_file_comment_length_value);
         zip_file_comment.resize(zip_file_comment_length_value, 0);
         cursor.read_exact(zip_file_comment).unwrap();
@@ -124,7 +186,7 @@ impl PkZip
             zip_file_comment: zip_file_comment.to_vec(),
         };
         let central_directory_header: &mut Vec<CentralDirectoryHeader> = &mut Vec::with_capacity(
-            u16::from_be_bytes(*total_number_of_entries_in_central_directory) as usize,
+            u16::from_le_bytes(*total_number_of_entries_in_central_directory) as usize,
         );
 
         //Find and seek central directory records
@@ -539,10 +601,8 @@ impl ByteStream
     }
     pub fn read_byte(&mut self) -> Result<u8, &'static str>
     {
-        // Ok(self.get_number_from_arbitrary_bits(8).unwrap() as u8)
-        let byte_bits = self.read_bits(8).unwrap();
-        let ba1 = BitArray::new_from_bits(&byte_bits);
-        Ok(ba1.get_byte())
+        // Bytes in stored blocks are read least significant bit first like any other number
+        Ok(self.read_number_from_arbitrary_bits(8)? as u8)
     }
     pub fn skip_until_byte_aligned(&mut self) -> Result<(), &'static str>
     {
@@ -577,7 +637,7 @@ impl ByteStream
             data: re_arranged_bytes,
         }
     }
-    pub fn read_next_symbol(&mut self, tree: &HuffmanTree) -> u16
+    pub fn read_next_symbol(&mut self, tree: &HuffmanTree) -> Result<u16, &'static str>
     {
         let mut cur_node = &tree.root_node;
         //TODO: remove debug value here
@@ -586,36 +646,50 @@ impl ByteStream
         {
             if cur_node._right.is_some() || cur_node._left.is_some()
             {
-                let b = self.read_bit().unwrap();
-                if b
+                let b = self.read_bit()?;
+                let next_node = if b
                 {
                     //right
                     code_so_far.push('1');
-                    cur_node = cur_node._right.as_ref().unwrap_or_else(|| panic!("Node has no child node {:#?}.  the current direction is {} (1=right). Code so far: {}  ",
-                        cur_node, b, code_so_far));
+                    cur_node._right.as_ref()
                 }
                 else
                 {
                     code_so_far.push('0');
-                    cur_node = cur_node._left.as_ref().unwrap();
-                }
+                    cur_node._left.as_ref()
+                };
+                // A code the tree has no symbol for, the stream is corrupt
+                cur_node = match next_node
+                {
+                    Some(t) => t,
+                    None => return Err(MALFORMED_DEFLATE_STREAM),
+                };
             }
             else
             {
                 break;
             }
         }
-        let val = cur_node._value.unwrap();
-        // println!("Symbol decoded: 0b{} = {}", code_so_far, val);
-
-        val
+        // println!("Symbol decoded: 0b{} = {:?}", code_so_far, cur_node._value);
+        cur_node._value.ok_or(MALFORMED_DEFLATE_STREAM)
     }
 }
 
 impl PkZipFile
 {
+    /// Decompresses the entry, refusing to produce more than the largest pack a bot may send.
     pub fn decompress(&self) -> Result<Vec<u8>, &'static str>
     {
+        self.decompress_with_limit(MAX_PACK_SIZE)
+    }
+    /// Decompresses the entry, failing once the output passes `limit` bytes so an untrusted
+    /// archive cannot exhaust memory however small it is.
+    pub fn decompress_with_limit(&self, limit: u64) -> Result<Vec<u8>, &'static str>
+    {
+        if self.uncompressed_size as u64 > limit
+        {
+            return Err(DECOMPRESSED_TOO_LARGE);
+        }
         if self.uncompressed_size == 0
         {
             return Ok(vec![0]);
@@ -632,24 +706,24 @@ impl PkZipFile
                 }
                 let mut byte_stream = ByteStream::new(compressed_byte_arrays);
 
-                let decompressed_byte_array = decompress_deflate(byte_stream);
-                Ok(decompressed_byte_array)
+                decompress_deflate(byte_stream, limit)
             }
             _ => Err("Unimplemented"),
         }
     }
 }
 
-fn decompress_deflate(mut byte_stream: ByteStream) -> Vec<u8>
+fn decompress_deflate(mut byte_stream: ByteStream, limit: u64) -> Result<Vec<u8>, &'static str>
 {
+    // Shared by all blocks, a block may refer back into the ones before it
+    let ret_val: Vec<u8> = Vec::new();
+    let mut ret_cursor = Cursor::new(ret_val);
     loop
     {
         // println!("New block!!######################");
-        let is_last_block = byte_stream.read_bit().unwrap();
-        let compression_type_indicator = byte_stream.read_number_from_arbitrary_bits(2).unwrap();
+        let is_last_block = byte_stream.read_bit()?;
+        let compression_type_indicator = byte_stream.read_number_from_arbitrary_bits(2)?;
         let compression_type = get_deflate_compression_type(compression_type_indicator);
-        let ret_val: Vec<u8> = Vec::new();
-        let mut ret_cursor = Cursor::new(ret_val);
         // println!(
         //     "Compression type: {} {:#?} ",
         //     compression_type_indicator, compression_type
@@ -658,39 +732,43 @@ fn decompress_deflate(mut byte_stream: ByteStream) -> Vec<u8>
         {
             DeflateCompressionType::Stored =>
             {
-                extract_stored(&mut byte_stream, &mut ret_cursor);
+                extract_stored(&mut byte_stream, &mut ret_cursor, limit)?;
             }
             DeflateCompressionType::FixedHuffman =>
             {
-                extract_fixed_huffman(&mut byte_stream, &mut ret_cursor);
+                extract_fixed_huffman(&mut byte_stream, &mut ret_cursor, limit)?;
             }
             DeflateCompressionType::DynamicHuffman =>
             {
-                extract_dynamic_huffman(&mut byte_stream, &mut ret_cursor);
+                extract_dynamic_huffman(&mut byte_stream, &mut ret_cursor, limit)?;
             }
             DeflateCompressionType::Reserved =>
             {
-                panic!("Malformed deflate stream");
+                return Err(MALFORMED_DEFLATE_STREAM);
             }
         };
 
         if is_last_block
         {
-            return ret_cursor.into_inner();
+            return Ok(ret_cursor.into_inner());
         }
     }
 }
 
-fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor<Vec<u8>>)
+fn extract_dynamic_huffman(
+    byte_stream: &mut ByteStream,
+    ret_cursor: &mut Cursor<Vec<u8>>,
+    limit: u64,
+) -> Result<(), &'static str>
 {
-    let hlit = byte_stream.read_number_from_arbitrary_bits(5).unwrap() + 257u16; // # of literal/length codes
-    let hdist = byte_stream.read_number_from_arbitrary_bits(5).unwrap() + 1u16; // # of Distance Codes
-    let hclen = byte_stream.read_number_from_arbitrary_bits(4).unwrap() + 4u16; // # of Code Length codes
+    let hlit = byte_stream.read_number_from_arbitrary_bits(5)? + 257u16; // # of literal/length codes
+    let hdist = byte_stream.read_number_from_arbitrary_bits(5)? + 1u16; // # of Distance Codes
+    let hclen = byte_stream.read_number_from_arbitrary_bits(4)? + 4u16; // # of Code Length codes
 
     let mut unsorted_lengths = Vec::new();
     for i in 0..hclen
     {
-        let length = byte_stream.read_number_from_arbitrary_bits(3).unwrap();
+        let length = byte_stream.read_number_from_arbitrary_bits(3)?;
         unsorted_lengths.push(length);
     }
     let sort_order: &[u16; 19] = &[
@@ -715,7 +793,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         {
             break;
         }
-        let bit_length_value = byte_stream.read_next_symbol(&ht_of_code_lengths);
+        let bit_length_value = byte_stream.read_next_symbol(&ht_of_code_lengths)?;
         if bit_length_value <= 15
         {
             //bit length literal
@@ -725,8 +803,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         }
         if bit_length_value == 16
         {
-            let number_of_times_to_repeat =
-                byte_stream.read_number_from_arbitrary_bits(2).unwrap() + 3u16;
+            let number_of_times_to_repeat = byte_stream.read_number_from_arbitrary_bits(2)? + 3u16;
             for i in 0..number_of_times_to_repeat
             {
                 literal_length_bitlengths.push(last_bit_length);
@@ -735,8 +812,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         }
         if bit_length_value == 17
         {
-            let number_of_times_to_repeat =
-                byte_stream.read_number_from_arbitrary_bits(3).unwrap() + 3u16;
+            let number_of_times_to_repeat = byte_stream.read_number_from_arbitrary_bits(3)? + 3u16;
             for i in 0..number_of_times_to_repeat
             {
                 literal_length_bitlengths.push(0);
@@ -746,8 +822,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         }
         if bit_length_value == 18
         {
-            let number_of_times_to_repeat =
-                byte_stream.read_number_from_arbitrary_bits(7).unwrap() + 11u16;
+            let number_of_times_to_repeat = byte_stream.read_number_from_arbitrary_bits(7)? + 11u16;
             for i in 0..number_of_times_to_repeat
             {
                 literal_length_bitlengths.push(0);
@@ -766,7 +841,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         {
             break;
         }
-        let bit_length_value = byte_stream.read_next_symbol(&ht_of_code_lengths);
+        let bit_length_value = byte_stream.read_next_symbol(&ht_of_code_lengths)?;
         if bit_length_value <= 15
         {
             distance_ht_bit_lengths.push(bit_length_value);
@@ -775,8 +850,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         }
         if bit_length_value == 16
         {
-            let number_of_times_to_repeat =
-                byte_stream.read_number_from_arbitrary_bits(2).unwrap() + 3u16;
+            let number_of_times_to_repeat = byte_stream.read_number_from_arbitrary_bits(2)? + 3u16;
             for i in 0..number_of_times_to_repeat
             {
                 distance_ht_bit_lengths.push(last_bit_length);
@@ -785,8 +859,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         }
         if bit_length_value == 17
         {
-            let number_of_times_to_repeat =
-                byte_stream.read_number_from_arbitrary_bits(3).unwrap() + 3u16;
+            let number_of_times_to_repeat = byte_stream.read_number_from_arbitrary_bits(3)? + 3u16;
             for i in 0..number_of_times_to_repeat
             {
                 distance_ht_bit_lengths.push(0);
@@ -796,8 +869,7 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         }
         if bit_length_value == 18
         {
-            let number_of_times_to_repeat =
-                byte_stream.read_number_from_arbitrary_bits(7).unwrap() + 11u16;
+            let number_of_times_to_repeat = byte_stream.read_number_from_arbitrary_bits(7)? + 11u16;
             for i in 0..number_of_times_to_repeat
             {
                 distance_ht_bit_lengths.push(0);
@@ -823,26 +895,37 @@ fn extract_dynamic_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor
         distance_ht,
         distance_valuess,
         ret_cursor,
-    );
+        limit,
+    )
 }
 
-fn extract_stored(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor<Vec<u8>>)
+fn extract_stored(
+    byte_stream: &mut ByteStream,
+    ret_cursor: &mut Cursor<Vec<u8>>,
+    limit: u64,
+) -> Result<(), &'static str>
 {
     // println!("extract_stored");
-    byte_stream.skip_until_byte_aligned().unwrap();
+    byte_stream.skip_until_byte_aligned()?;
     let mut len_buf: [u8; 2] = [0u8; 2];
-    len_buf[0] = byte_stream.read_byte().unwrap();
-    len_buf[1] = byte_stream.read_byte().unwrap();
-    let len = u16::from_be_bytes(len_buf);
+    len_buf[0] = byte_stream.read_byte()?;
+    len_buf[1] = byte_stream.read_byte()?;
+    let len = u16::from_le_bytes(len_buf);
+    // NLEN, the one's complement of the length
+    byte_stream.read_byte()?;
+    byte_stream.read_byte()?;
     for _i in 0..len
     {
-        ret_cursor
-            .write_all(&[byte_stream.read_byte().unwrap()])
-            .unwrap();
+        write_output(ret_cursor, byte_stream.read_byte()?, limit)?;
     }
+    Ok(())
 }
 
-fn extract_fixed_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor<Vec<u8>>)
+fn extract_fixed_huffman(
+    byte_stream: &mut ByteStream,
+    ret_cursor: &mut Cursor<Vec<u8>>,
+    limit: u64,
+) -> Result<(), &'static str>
 {
     let (literal_length_ht, distance_ht, length_values, distance_values) =
         get_fixed_huffman_trees();
@@ -853,9 +936,11 @@ fn extract_fixed_huffman(byte_stream: &mut ByteStream, ret_cursor: &mut Cursor<V
         distance_ht,
         distance_values,
         ret_cursor,
-    );
+        limit,
+    )?;
     let text = String::from_utf8(ret_cursor.get_ref().to_vec());
     // eprintln!("text = {:?}", text);
+    Ok(())
 }
 
 fn extract_using_given_huffman_trees(
@@ -865,7 +950,8 @@ fn extract_using_given_huffman_trees(
     distance_ht: HuffmanTree,
     distance_values: Vec<u16>,
     ret_cursor: &mut Cursor<Vec<u8>>,
-)
+    limit: u64,
+) -> Result<(), &'static str>
 {
     let mut debug_iterations = 0u16;
     let mut match_iterations = 0u16;
@@ -873,14 +959,12 @@ fn extract_using_given_huffman_trees(
     {
         debug_iterations += 1;
         //decode literal character from input stream
-        let next_literal_or_length = byte_stream.read_next_symbol(&literal_length_ht);
+        let next_literal_or_length = byte_stream.read_next_symbol(&literal_length_ht)?;
         if next_literal_or_length <= 255
         {
             //copy character to output stream
             // eprintln!("literal = {:?}", next_literal_or_length);
-            ret_cursor
-                .write_all(&[next_literal_or_length as u8])
-                .unwrap();
+            write_output(ret_cursor, next_literal_or_length as u8, limit)?;
         }
         else
         {
@@ -920,11 +1004,16 @@ fn extract_using_given_huffman_trees(
                     // println!("no length_extra_bits detected for code of: {}", length_code);
                     // eprintln!("length_values = {:?}", length_values);
                 }
-                let length_modifier = byte_stream
-                    .read_number_from_arbitrary_bits(length_number_of_extra_bits.unwrap())
-                    .unwrap() as u16;
-                let length = length_base.unwrap() + length_modifier;
-                let distance_code = byte_stream.read_next_symbol(&distance_ht);
+                let (length_base, length_number_of_extra_bits) =
+                    match (length_base, length_number_of_extra_bits)
+                    {
+                        (Some(base), Some(extra_bits)) => (base, extra_bits),
+                        _ => return Err(MALFORMED_DEFLATE_STREAM),
+                    };
+                let length_modifier =
+                    byte_stream.read_number_from_arbitrary_bits(length_number_of_extra_bits)?;
+                let length = length_base + length_modifier;
+                let distance_code = byte_stream.read_next_symbol(&distance_ht)?;
                 let mut distance_base = None;
                 let mut distance_number_of_extra_bits = None;
                 for (i, x) in distance_values.to_owned().into_iter().enumerate()
@@ -945,21 +1034,31 @@ fn extract_using_given_huffman_trees(
                     // println!("no distance number of bits for code of: {}", distance_code);
                     // eprintln!("distance_values = {:?}", distance_values);
                 }
-                let distance_modifier = byte_stream
-                    .read_number_from_arbitrary_bits(distance_number_of_extra_bits.unwrap())
-                    .unwrap() as u16;
-                let distance = distance_base.unwrap() + distance_modifier;
+                let (distance_base, distance_number_of_extra_bits) =
+                    match (distance_base, distance_number_of_extra_bits)
+                    {
+                        (Some(base), Some(extra_bits)) => (base, extra_bits),
+                        _ => return Err(MALFORMED_DEFLATE_STREAM),
+                    };
+                let distance_modifier =
+                    byte_stream.read_number_from_arbitrary_bits(distance_number_of_extra_bits)?;
+                let distance = distance_base + distance_modifier;
+                // Reaching back before the start of the output
+                if distance as u64 > ret_cursor.position()
+                {
+                    return Err(MALFORMED_DEFLATE_STREAM);
+                }
                 // println!(
                 //     "Iteration: {}/{}\nCurPos: {} \n\tSeek {} ({} + {})={} and copy {} ({} + {})={} bits\n\t{}",
                 //     match_iterations,
                 //     debug_iterations,
                 //     ret_cursor.position(),
                 //     distance_code,
-                //     distance_base.unwrap(),
+                //     distance_base,
                 //     distance_modifier,
                 //     distance,
                 //     next_literal_or_length,
-                //     length_base.unwrap(),
+                //     length_base,
                 //     length_modifier,
                 //     length,
                 //     String::from_utf8(ret_cursor.clone().into_inner()).unwrap_or("Unable to render!".to_string())
@@ -971,11 +1070,15 @@ fn extract_using_given_huffman_trees(
                 {
                     ret_cursor
                         .seek(SeekFrom::Current(-(distance as i64)))
-                        .unwrap();
+                        .map_err(|_| MALFORMED_DEFLATE_STREAM)?;
                     let buf: &mut [u8; 1] = &mut [0; 1];
-                    ret_cursor.read_exact(buf).unwrap();
-                    ret_cursor.seek(SeekFrom::End(0)).unwrap();
-                    ret_cursor.write_all(buf).unwrap();
+                    ret_cursor
+                        .read_exact(buf)
+                        .map_err(|_| MALFORMED_DEFLATE_STREAM)?;
+                    ret_cursor
+                        .seek(SeekFrom::End(0))
+                        .map_err(|_| MALFORMED_DEFLATE_STREAM)?;
+                    write_output(ret_cursor, buf[0], limit)?;
                 }
                 // ret_cursor.read_exact(&mut copy_value).unwrap();
                 // ret_cursor.seek(SeekFrom::End(0)).unwrap();
@@ -983,6 +1086,20 @@ fn extract_using_given_huffman_trees(
             }
         }
     }
+    Ok(())
+}
+
+/// Appends a decoded byte, stopping the stream once the output would pass `limit`.
+fn write_output(ret_cursor: &mut Cursor<Vec<u8>>, byte: u8, limit: u64)
+    -> Result<(), &'static str>
+{
+    if ret_cursor.position() >= limit
+    {
+        return Err(DECOMPRESSED_TOO_LARGE);
+    }
+    ret_cursor
+        .write_all(&[byte])
+        .map_err(|_| MALFORMED_DEFLATE_STREAM)
 }
 
 pub fn get_fixed_huffman_trees() -> (HuffmanTree, HuffmanTree, Vec<u16>, Vec<u16>)
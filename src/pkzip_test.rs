@@ -1,4 +1,30 @@
-use crate::pkzip::{get_fixed_huffman_trees, BitArray, ByteStream, HuffmanTree};
+use crate::pkzip::{
+    get_fixed_huffman_trees, BitArray, ByteStream, CompressionMethod, HuffmanTree, PkZip, PkZipFile,
+};
+
+/// Two stored entries, `a.txt` and `b.txt`, and the archive comment `hello`, as Python's
+/// zipfile writes them.
+const COMMENTED_ZIP: [u8; 205] = [
+    0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1d, 0x3b, 0x53, 0x5d, 0xc2, 0x41,
+    0x24, 0x35, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x61, 0x2e,
+    0x74, 0x78, 0x74, 0x61, 0x62, 0x63, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x1d, 0x3b, 0x53, 0x5d, 0x61, 0xe1, 0xc4, 0x0c, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+    0x05, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x64, 0x65, 0x66, 0x50, 0x4b, 0x01, 0x02,
+    0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1d, 0x3b, 0x53, 0x5d, 0xc2, 0x41, 0x24, 0x35,
+    0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00, 0x00, 0x00, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x50,
+    0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1d, 0x3b, 0x53, 0x5d, 0x61,
+    0xe1, 0xc4, 0x0c, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x26, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74,
+    0x78, 0x74, 0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x66, 0x00,
+    0x00, 0x00, 0x4c, 0x00, 0x00, 0x00, 0x05, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+];
+/// `abcdefgh` three times in a fixed Huffman block and an empty stored block, then a final
+/// block that repeats all 24 bytes with one back reference, as zlib writes them.
+const BACK_REFERENCE_DEFLATE: [u8; 21] = [
+    0x4a, 0x4c, 0x4a, 0x4e, 0x49, 0x4d, 0x4b, 0xcf, 0x48, 0x44, 0xa3, 0x01, 0x00, 0x00, 0x00, 0xff,
+    0xff, 0xc3, 0x45, 0x03, 0x00,
+];
 
 #[test]
 // Test that bytes can be bidirectionally cast with bits
@@ -53,6 +79,116 @@ fn byte_stream_first_byte_test()
     assert_eq!(15, bs.read_byte().unwrap());
 }
 #[test]
+// Output carries over between blocks, and stored blocks start with LEN and NLEN
+fn decompress_stored_blocks_test()
+{
+    let compressed_data = vec![
+        0x00, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x01, 0x03, 0x00, 0xfc, 0xff, b'd', b'e',
+        b'f',
+    ];
+    let file = PkZipFile {
+        file_name: "abc.txt".to_string(),
+        compressed_size: compressed_data.len() as u32,
+        compression_method: CompressionMethod::Deflated,
+        uncompressed_size: 6,
+        crc_32: 0,
+        compressed_data,
+    };
+    assert_eq!(b"abcdef".to_vec(), file.decompress().unwrap());
+}
+#[test]
+// The archive comment length is little-endian like the rest of the archive
+fn archive_comment_test()
+{
+    let pkzip = PkZip::new(&COMMENTED_ZIP);
+    assert_eq!(
+        b"hello".to_vec(),
+        pkzip.end_of_central_directory_record.zip_file_comment
+    );
+    let files = pkzip.get_files();
+    assert_eq!(
+        vec!["a.txt", "b.txt"],
+        files
+            .iter()
+            .map(|x| x.file_name.as_str())
+            .collect::<Vec<&str>>()
+    );
+    assert_eq!(b"def".to_vec(), files[1].decompress().unwrap());
+}
+#[test]
+// LEN is little-endian, and NLEN after it is not part of the data
+fn decompress_stored_block_test()
+{
+    let contents = (0..300).map(|x| x as u8).collect::<Vec<u8>>();
+    let mut compressed_data = vec![0x01, 0x2c, 0x01, 0xd3, 0xfe];
+    compressed_data.extend(&contents);
+    let file = PkZipFile {
+        file_name: "numbers.bin".to_string(),
+        compressed_size: compressed_data.len() as u32,
+        compression_method: CompressionMethod::Deflated,
+        uncompressed_size: 300,
+        crc_32: 0,
+        compressed_data,
+    };
+    assert_eq!(contents, file.decompress().unwrap());
+}
+#[test]
+// A back reference may reach into the output of an earlier block
+fn decompress_back_reference_across_blocks_test()
+{
+    let file = PkZipFile {
+        file_name: "abc.txt".to_string(),
+        compressed_size: BACK_REFERENCE_DEFLATE.len() as u32,
+        compression_method: CompressionMethod::Deflated,
+        uncompressed_size: 48,
+        crc_32: 0,
+        compressed_data: BACK_REFERENCE_DEFLATE.to_vec(),
+    };
+    assert_eq!(b"abcdefgh".repeat(6), file.decompress().unwrap());
+}
+#[test]
+// Corrupt streams are errors rather than panics
+fn decompress_corrupt_stream_test()
+{
+    let corrupt = |compressed_data: Vec<u8>| PkZipFile {
+        file_name: "corrupt.txt".to_string(),
+        compressed_size: compressed_data.len() as u32,
+        compression_method: CompressionMethod::Deflated,
+        uncompressed_size: 24,
+        crc_32: 0,
+        compressed_data,
+    };
+    // The reserved block type
+    assert!(corrupt(vec![0x07]).decompress().is_err());
+    // A back reference with nothing before it
+    assert!(corrupt(BACK_REFERENCE_DEFLATE[17..].to_vec())
+        .decompress()
+        .is_err());
+    // Cut off in the middle of a block
+    assert!(corrupt(BACK_REFERENCE_DEFLATE[..5].to_vec())
+        .decompress()
+        .is_err());
+}
+#[test]
+// Decompression stops at the limit even when the header understates the size
+fn decompress_with_limit_test()
+{
+    let file = |uncompressed_size: u32| PkZipFile {
+        file_name: "abc.txt".to_string(),
+        compressed_size: BACK_REFERENCE_DEFLATE.len() as u32,
+        compression_method: CompressionMethod::Deflated,
+        uncompressed_size,
+        crc_32: 0,
+        compressed_data: BACK_REFERENCE_DEFLATE.to_vec(),
+    };
+    assert_eq!(
+        b"abcdefgh".repeat(6),
+        file(48).decompress_with_limit(48).unwrap()
+    );
+    assert!(file(24).decompress_with_limit(30).is_err());
+    assert!(file(48).decompress_with_limit(30).is_err());
+}
+#[test]
 fn huffman_tree_creation_test()
 {
     let mut huffmanTree = HuffmanTree::new();
@@ -109,7 +245,7 @@ fn huffman_tree_bit_decode_test()
     let mut bs = ByteStream::new(vec![b1, b2]);
     loop
     {
-        let s = bs.read_next_symbol(&huffman_tree);
+        let s = bs.read_next_symbol(&huffman_tree).unwrap();
         println!("{}", s);
     }
 }
@@ -0,0 +1,262 @@
+use crate::search_result::{format_size, BookFormat, SearchResult};
+use crate::works::normalize_words;
+use std::collections::HashMap;
+
+/// Smallest file that can plausibly be a whole book, smaller ones are placeholders or junk.
+pub const MIN_BOOK_SIZE: u64 = 20 * 1024;
+/// Larger files are usually image-heavy scans or the wrong thing entirely.
+pub const MAX_BOOK_SIZE: u64 = 100 * 1024 * 1024;
+
+const FORMAT_POINTS: f64 = 30.0;
+const MATCH_POINTS: f64 = 40.0;
+const EXACT_TITLE_POINTS: f64 = 10.0;
+const SIZE_POINTS: f64 = 5.0;
+const RELIABILITY_POINTS: f64 = 20.0;
+const ONLINE_POINTS: f64 = 10.0;
+
+/// How often a bot delivered what we asked for in earlier sessions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BotStats
+{
+    pub successes: u32,
+    pub failures: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ranker
+{
+    /// Most preferred first, formats not listed score nothing.
+    pub preferred_formats: Vec<BookFormat>,
+    pub bot_stats: HashMap<String, BotStats>,
+    /// Nicknames in the channel, `None` when the names list has not arrived.
+    pub online_bots: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreComponent
+{
+    pub name: &'static str,
+    pub points: f64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankedResult
+{
+    pub result: SearchResult,
+    pub score: f64,
+    pub components: Vec<ScoreComponent>,
+    /// Set when the result must never be picked automatically, whatever its score.
+    pub rejected: Option<String>,
+}
+
+impl RankedResult
+{
+    /// One line per scoring input, for `--explain`.
+    pub fn explain(&self) -> String
+    {
+        let mut ret_val = format!("{:.1} points  {}\n", self.score, self.result.request_line);
+        if let Some(reason) = &self.rejected
+        {
+            ret_val.push_str(&format!("    rejected: {}\n", reason));
+        }
+        for component in self.components.iter()
+        {
+            ret_val.push_str(&format!(
+                "    {:+6.1}  {:<12} {}\n",
+                component.points, component.name, component.reason
+            ));
+        }
+        ret_val
+    }
+}
+
+impl Default for Ranker
+{
+    fn default() -> Self
+    {
+        Ranker {
+            preferred_formats: default_formats(),
+            bot_stats: HashMap::new(),
+            online_bots: None,
+        }
+    }
+}
+
+pub fn default_formats() -> Vec<BookFormat>
+{
+    vec![
+        BookFormat::Epub,
+        BookFormat::Azw3,
+        BookFormat::Mobi,
+        BookFormat::Pdf,
+    ]
+}
+
+impl Ranker
+{
+    /// Scores every result against `query`, best first with rejected results last.
+    pub fn rank(&self, query: &str, results: &[SearchResult]) -> Vec<RankedResult>
+    {
+        let mut ranked = results
+            .iter()
+            .map(|x| self.score(query, x))
+            .collect::<Vec<RankedResult>>();
+        // Stable, so equal scores keep the order the bot listed them in
+        ranked.sort_by(|a, b| {
+            a.rejected
+                .is_some()
+                .cmp(&b.rejected.is_some())
+                .then(b.score.total_cmp(&a.score))
+        });
+        ranked
+    }
+    pub fn score(&self, query: &str, result: &SearchResult) -> RankedResult
+    {
+        let mut components = Vec::new();
+        let mut rejected = None;
+
+        let format_points = match self
+            .preferred_formats
+            .iter()
+            .position(|x| *x == result.format)
+        {
+            Some(i) =>
+            {
+                let count = self.preferred_formats.len() as f64;
+                ScoreComponent {
+                    name: "format",
+                    points: FORMAT_POINTS * (count - i as f64) / count,
+                    reason: format!("{} is preference {}", result.format.as_str(), i + 1),
+                }
+            }
+            None => ScoreComponent {
+                name: "format",
+                points: 0.0,
+                reason: format!("{} is not a preferred format", result.format.as_str()),
+            },
+        };
+        components.push(format_points);
+
+        let query_words = normalize_words(query);
+        let title_words = normalize_words(&result.title);
+        let mut result_words = title_words.clone();
+        result_words.extend(normalize_words(&result.author));
+        let found = query_words
+            .iter()
+            .filter(|x| result_words.contains(x))
+            .count();
+        let fraction = if query_words.is_empty()
+        {
+            0.0
+        }
+        else
+        {
+            found as f64 / query_words.len() as f64
+        };
+        components.push(ScoreComponent {
+            name: "match",
+            points: MATCH_POINTS * fraction,
+            reason: format!(
+                "{} of {} query words in the title or author",
+                found,
+                query_words.len()
+            ),
+        });
+        if !title_words.is_empty() && title_words.iter().all(|x| query_words.contains(x))
+        {
+            components.push(ScoreComponent {
+                name: "exact title",
+                points: EXACT_TITLE_POINTS,
+                reason: "every title word is in the query".to_string(),
+            });
+        }
+
+        let size = match result.size_bytes
+        {
+            Some(t) if t < MIN_BOOK_SIZE =>
+            {
+                rejected = Some(format!("{} is too small to be a book", format_size(t)));
+                ScoreComponent {
+                    name: "size",
+                    points: -SIZE_POINTS,
+                    reason: format!("{} is below {}", format_size(t), format_size(MIN_BOOK_SIZE)),
+                }
+            }
+            Some(t) if t > MAX_BOOK_SIZE => ScoreComponent {
+                name: "size",
+                points: -2.0 * SIZE_POINTS,
+                reason: format!("{} is unusually large", format_size(t)),
+            },
+            Some(t) => ScoreComponent {
+                name: "size",
+                points: SIZE_POINTS,
+                reason: format!("{} is a plausible size", format_size(t)),
+            },
+            None => ScoreComponent {
+                name: "size",
+                points: -SIZE_POINTS,
+                reason: "size is unknown".to_string(),
+            },
+        };
+        components.push(size);
+
+        let stats = self
+            .bot_stats
+            .iter()
+            .find(|(bot, _)| bot.eq_ignore_ascii_case(&result.bot))
+            .map(|(_, stats)| *stats)
+            .unwrap_or_default();
+        let attempts = stats.successes + stats.failures;
+        // Smoothed so one early failure does not sink a bot for good
+        let rate = (stats.successes as f64 + 1.0) / (attempts as f64 + 2.0);
+        components.push(ScoreComponent {
+            name: "reliability",
+            points: RELIABILITY_POINTS * (rate - 0.5) * 2.0,
+            reason: if attempts == 0
+            {
+                format!("no history with {}", result.bot)
+            }
+            else
+            {
+                format!(
+                    "{} delivered {} of {} requests",
+                    result.bot, stats.successes, attempts
+                )
+            },
+        });
+
+        if let Some(online_bots) = &self.online_bots
+        {
+            if nick_in_names(online_bots, &result.bot)
+            {
+                components.push(ScoreComponent {
+                    name: "online",
+                    points: ONLINE_POINTS,
+                    reason: format!("{} is in the channel", result.bot),
+                });
+            }
+            else
+            {
+                rejected = Some(format!("{} is not in the channel", result.bot));
+            }
+        }
+
+        RankedResult {
+            result: result.clone(),
+            score: components.iter().map(|x| x.points).sum(),
+            components,
+            rejected,
+        }
+    }
+}
+
+/// Whether `nick` is in a channel's NAMES list, whose entries may carry a `@`, `+` or `%`
+/// mode prefix.
+pub fn nick_in_names(names: &[String], nick: &str) -> bool
+{
+    names.iter().any(|x| {
+        x.trim_start_matches(['@', '+', '%'])
+            .eq_ignore_ascii_case(nick)
+    })
+}
@@ -0,0 +1,110 @@
+use crate::ranking::{nick_in_names, BotStats, Ranker};
+use crate::search_result::{BookFormat, SearchResult};
+
+fn parse(lines: &[&str]) -> Vec<SearchResult>
+{
+    lines
+        .iter()
+        .map(|x| SearchResult::parse(x).unwrap())
+        .collect()
+}
+
+#[test]
+fn rank_prefers_formats_in_order_test()
+{
+    let results = parse(&[
+        "!Bsk Frank Herbert - Dune.pdf ::INFO:: 2MB",
+        "!Bsk Frank Herbert - Dune.mobi ::INFO:: 1MB",
+        "!Bsk Frank Herbert - Dune.epub ::INFO:: 1MB",
+    ]);
+    let ranked = Ranker::default().rank("dune", &results);
+    assert_eq!(BookFormat::Epub, ranked[0].result.format);
+    assert_eq!(BookFormat::Mobi, ranked[1].result.format);
+    assert_eq!(BookFormat::Pdf, ranked[2].result.format);
+
+    let ranker = Ranker {
+        preferred_formats: vec![BookFormat::Pdf],
+        ..Ranker::default()
+    };
+    assert_eq!(
+        BookFormat::Pdf,
+        ranker.rank("dune", &results)[0].result.format
+    );
+}
+#[test]
+fn rank_prefers_closer_matches_test()
+{
+    let results = parse(&[
+        "!Bsk Frank Herbert - Children of Dune.epub ::INFO:: 1MB",
+        "!Bsk Brian Herbert - Dune Messiah Notes.epub ::INFO:: 1MB",
+        "!Bsk Frank Herbert - Dune.epub ::INFO:: 1MB",
+    ]);
+    let ranked = Ranker::default().rank("Frank Herbert Dune", &results);
+    assert_eq!("Dune", ranked[0].result.title);
+    assert_eq!("Children of Dune", ranked[1].result.title);
+    assert!(ranked[0].components.iter().any(|x| x.name == "exact title"));
+}
+#[test]
+fn rank_rejects_tiny_files_and_offline_bots_test()
+{
+    let results = parse(&[
+        "!Tiny Frank Herbert - Dune.epub ::INFO:: 2KB",
+        "!Gone Frank Herbert - Dune.epub ::INFO:: 1MB",
+        "!Here Frank Herbert - Dune.mobi ::INFO:: 1MB",
+    ]);
+    let ranker = Ranker {
+        online_bots: Some(vec!["@Tiny".to_string(), "+here".to_string()]),
+        ..Ranker::default()
+    };
+    let ranked = ranker.rank("dune", &results);
+    assert_eq!("Here", ranked[0].result.bot);
+    assert!(ranked[0].rejected.is_none());
+    assert!(ranked[1..].iter().all(|x| x.rejected.is_some()));
+    assert!(ranked
+        .iter()
+        .find(|x| x.result.bot == "Tiny")
+        .unwrap()
+        .explain()
+        .contains("rejected: 2.0KB is too small to be a book"));
+}
+#[test]
+fn rank_uses_bot_reliability_test()
+{
+    let results = parse(&[
+        "!Flaky Frank Herbert - Dune.epub ::INFO:: 1MB",
+        "!Solid Frank Herbert - Dune.epub ::INFO:: 1MB",
+    ]);
+    let mut ranker = Ranker::default();
+    ranker.bot_stats.insert(
+        "flaky".to_string(),
+        BotStats {
+            successes: 1,
+            failures: 5,
+        },
+    );
+    ranker.bot_stats.insert(
+        "Solid".to_string(),
+        BotStats {
+            successes: 8,
+            failures: 0,
+        },
+    );
+    let ranked = ranker.rank("dune", &results);
+    assert_eq!("Solid", ranked[0].result.bot);
+    let explanation = ranked[1].explain();
+    assert!(explanation.contains("reliability  Flaky delivered 1 of 6 requests"));
+    assert!(explanation.starts_with(&format!("{:.1} points  !Flaky", ranked[1].score)));
+}
+#[test]
+fn nick_in_names_test()
+{
+    let names = vec![
+        "@Tiny".to_string(),
+        "+here".to_string(),
+        "plain".to_string(),
+    ];
+    assert!(nick_in_names(&names, "tiny"));
+    assert!(nick_in_names(&names, "Here"));
+    assert!(nick_in_names(&names, "plain"));
+    assert!(!nick_in_names(&names, "Gone"));
+}
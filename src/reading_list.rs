@@ -0,0 +1,284 @@
+use crate::ranking::RankedResult;
+use crate::search_result::SearchResult;
+use crate::works::{group_works, normalize_author, normalize_title, Work};
+
+/// One book to look for, from an `author - title` line or a CSV row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadingListEntry
+{
+    /// Empty when the line only named the title.
+    pub author: String,
+    pub title: String,
+    /// Line of the list, or row of the CSV, the entry came from.
+    pub line: usize,
+}
+
+impl ReadingListEntry
+{
+    pub fn query(&self) -> String
+    {
+        format!("{} {}", self.author, self.title).trim().to_string()
+    }
+    pub fn describe(&self) -> String
+    {
+        if self.author.is_empty()
+        {
+            self.title.clone()
+        }
+        else
+        {
+            format!("{} - {}", self.author, self.title)
+        }
+    }
+}
+
+/// What searching for one entry turned up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOutcome
+{
+    /// Exactly one book matched, queued with its other copies as fallbacks.
+    Hit(Work),
+    Miss(String),
+    /// Several different books matched, so none was queued.
+    Ambiguous(Vec<Work>),
+}
+
+/// Reads a list of `author - title` lines, or a Goodreads-style CSV export with `Title` and
+/// `Author` columns. Blank lines and lines starting with `#` are skipped. When the CSV has an
+/// `Exclusive Shelf` column, only books on the `to-read` shelf are taken.
+pub fn parse_reading_list(text: &str) -> Result<Vec<ReadingListEntry>, &'static str>
+{
+    let text = text.trim_start_matches('\u{feff}');
+    let first_line = text.lines().next().unwrap_or("").to_lowercase();
+    if first_line.contains("title") && first_line.contains("author") && first_line.contains(',')
+    {
+        return parse_csv_list(text);
+    }
+    Ok(text
+        .lines()
+        .enumerate()
+        .filter(|(_, x)| !x.trim().is_empty() && !x.trim_start().starts_with('#'))
+        .map(|(i, x)| {
+            let (author, title) = match x.split_once(" - ")
+            {
+                Some((author, title)) if !title.trim().is_empty() => (author.trim(), title.trim()),
+                _ => ("", x.trim().trim_end_matches('-').trim_end()),
+            };
+            ReadingListEntry {
+                author: author.to_string(),
+                title: title.to_string(),
+                line: i + 1,
+            }
+        })
+        .collect())
+}
+
+fn parse_csv_list(text: &str) -> Result<Vec<ReadingListEntry>, &'static str>
+{
+    let rows = parse_csv(text);
+    let header = rows
+        .first()
+        .map(|x| {
+            x.iter()
+                .map(|y| y.trim().to_lowercase())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+    let column = |name: &str| header.iter().position(|x| x == name);
+    let title_column = match column("title")
+    {
+        Some(t) => t,
+        None => return Err("The reading list has no Title column"),
+    };
+    let author_column = match column("author")
+    {
+        Some(t) => t,
+        None => return Err("The reading list has no Author column"),
+    };
+    let shelf_column = column("exclusive shelf");
+    Ok(rows
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, row)| {
+            shelf_column.is_none_or(|x| row.get(x).is_some_and(|shelf| shelf.trim() == "to-read"))
+        })
+        .filter_map(|(i, row)| {
+            let title = strip_series(row.get(title_column)?);
+            if title.is_empty()
+            {
+                return None;
+            }
+            Some(ReadingListEntry {
+                author: row.get(author_column).map_or("", |x| x.trim()).to_string(),
+                title,
+                line: i + 1,
+            })
+        })
+        .collect())
+}
+
+/// Splits CSV text into rows of fields, honouring quoted fields with commas, doubled quotes
+/// and line breaks.
+pub fn parse_csv(text: &str) -> Vec<Vec<String>>
+{
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(x) = chars.next()
+    {
+        match x
+        {
+            '"' if quoted && chars.peek() == Some(&'"') =>
+            {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => (),
+            '\n' if !quoted =>
+            {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            x => field.push(x),
+        }
+    }
+    if !field.is_empty() || !row.is_empty()
+    {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|x| x.iter().any(|y| !y.trim().is_empty()));
+    rows
+}
+
+/// Goodreads appends the series as `Title (Series, #2)`, which no bot includes.
+fn strip_series(title: &str) -> String
+{
+    let title = title.trim();
+    match title.rfind(" (")
+    {
+        Some(t) if title.ends_with(')') && title[t..].contains('#') =>
+        {
+            title[..t].trim().to_string()
+        }
+        _ => title.to_string(),
+    }
+}
+
+/// Decides which book, if any, the ranked results hold for an entry. Only results the ranker
+/// did not reject, whose title has every word of the entry's title and whose author shares a
+/// name with the entry's author are considered.
+pub fn classify(entry: &ReadingListEntry, ranked: &[RankedResult]) -> BatchOutcome
+{
+    if ranked.is_empty()
+    {
+        return BatchOutcome::Miss("no results".to_string());
+    }
+    let title = normalize_title(&entry.title);
+    let title_words = title.split(' ').collect::<Vec<&str>>();
+    let author_names = normalize_author(&entry.author)
+        .split(' ')
+        .filter(|x| x.len() > 2)
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>();
+    let candidates = ranked
+        .iter()
+        .filter(|x| x.rejected.is_none())
+        .map(|x| &x.result)
+        .filter(|x| {
+            let result_title = normalize_title(&x.title);
+            let result_author = normalize_author(&x.author);
+            title_words
+                .iter()
+                .all(|word| result_title.split(' ').any(|y| y == *word))
+                && (author_names.is_empty()
+                    || result_author.is_empty()
+                    || author_names
+                        .iter()
+                        .any(|name| result_author.split(' ').any(|y| y == name)))
+        })
+        .cloned()
+        .collect::<Vec<SearchResult>>();
+    let mut works = group_works(&candidates);
+    if works.is_empty()
+    {
+        let reason = if ranked.iter().all(|x| x.rejected.is_some())
+        {
+            "every result was rejected"
+        }
+        else
+        {
+            "no result matched the title and author"
+        };
+        return BatchOutcome::Miss(reason.to_string());
+    }
+    // `Dune` should not be ambiguous just because `Dune Messiah` matched too
+    let exact = works
+        .iter()
+        .filter(|x| normalize_title(&x.title) == title)
+        .count();
+    if exact == 1
+    {
+        works.retain(|x| normalize_title(&x.title) == title);
+    }
+    if works.len() == 1
+    {
+        BatchOutcome::Hit(works.remove(0))
+    }
+    else
+    {
+        BatchOutcome::Ambiguous(works)
+    }
+}
+
+/// Hits, misses and ambiguous entries, each under its own heading.
+pub fn render_report(outcomes: &[(ReadingListEntry, BatchOutcome)]) -> String
+{
+    let mut hits = Vec::new();
+    let mut misses = Vec::new();
+    let mut ambiguous = Vec::new();
+    for (entry, outcome) in outcomes.iter()
+    {
+        let name = format!("line {}: {}", entry.line, entry.describe());
+        match outcome
+        {
+            BatchOutcome::Hit(work) =>
+            {
+                let best = work.best();
+                hits.push(format!(
+                    "{}\n    {} from {}, {} fallback copies",
+                    name,
+                    best.requested_file(),
+                    best.bot,
+                    work.alternates().len()
+                ));
+            }
+            BatchOutcome::Miss(reason) => misses.push(format!("{}\n    {}", name, reason)),
+            BatchOutcome::Ambiguous(works) =>
+            {
+                let choices = works
+                    .iter()
+                    .map(|x| format!("    {} by {}", x.title, x.author))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                ambiguous.push(format!("{}\n{}", name, choices));
+            }
+        }
+    }
+    let mut ret_val = String::new();
+    for (heading, lines) in [("Hits", hits), ("Misses", misses), ("Ambiguous", ambiguous)]
+    {
+        ret_val.push_str(&format!("{} ({})\n", heading, lines.len()));
+        for line in lines.iter()
+        {
+            ret_val.push_str(&format!("  {}\n", line.replace('\n', "\n  ")));
+        }
+        ret_val.push('\n');
+    }
+    ret_val
+}
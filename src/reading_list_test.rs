@@ -0,0 +1,127 @@
+use crate::ranking::{RankedResult, Ranker};
+use crate::reading_list::{classify, parse_csv, parse_reading_list, render_report, BatchOutcome};
+use crate::search_result::SearchResult;
+
+fn rank(query: &str, lines: &[&str]) -> Vec<RankedResult>
+{
+    let results = lines
+        .iter()
+        .map(|x| SearchResult::parse(x).unwrap())
+        .collect::<Vec<SearchResult>>();
+    Ranker::default().rank(query, &results)
+}
+
+#[test]
+fn parse_reading_list_plain_test()
+{
+    let entries = parse_reading_list(
+        "# books for the summer\nFrank Herbert - Dune\n\n  The Hobbit  \nUrsula K. Le Guin - \n",
+    )
+    .unwrap();
+    assert_eq!(3, entries.len());
+    assert_eq!("Frank Herbert", entries[0].author);
+    assert_eq!("Dune", entries[0].title);
+    assert_eq!(2, entries[0].line);
+    assert_eq!("", entries[1].author);
+    assert_eq!("The Hobbit", entries[1].title);
+    assert_eq!("The Hobbit", entries[1].describe());
+    assert_eq!("Ursula K. Le Guin", entries[2].title);
+    assert_eq!("Frank Herbert Dune", entries[0].query());
+}
+#[test]
+fn parse_reading_list_goodreads_test()
+{
+    let csv = "\u{feff}Book Id,Title,Author,Exclusive Shelf\r\n\
+               1,\"Dune (Dune, #1)\",Frank Herbert,to-read\r\n\
+               2,The Hobbit,J.R.R. Tolkien,read\r\n\
+               3,\"Gödel, Escher, Bach\",Douglas Hofstadter,to-read\r\n";
+    let entries = parse_reading_list(csv).unwrap();
+    assert_eq!(2, entries.len());
+    assert_eq!("Dune", entries[0].title);
+    assert_eq!("Frank Herbert", entries[0].author);
+    assert_eq!(2, entries[0].line);
+    assert_eq!("Gödel, Escher, Bach", entries[1].title);
+    assert!(parse_reading_list("Title,Authors,Year\nDune,Frank Herbert,1965\n").is_err());
+}
+#[test]
+fn parse_csv_test()
+{
+    let rows = parse_csv("a,\"b, \"\"c\"\"\",\"d\ne\"\n\n1,2,3");
+    assert_eq!(
+        vec![
+            vec!["a".to_string(), "b, \"c\"".to_string(), "d\ne".to_string()],
+            vec!["1".to_string(), "2".to_string(), "3".to_string()],
+        ],
+        rows
+    );
+}
+#[test]
+fn classify_test()
+{
+    let entry = &parse_reading_list("Frank Herbert - Dune").unwrap()[0];
+    let ranked = rank(
+        "Frank Herbert Dune",
+        &[
+            "!Bot1 Frank Herbert - Dune.epub ::INFO:: 1.2MB",
+            "!Bot2 Herbert, Frank - Dune.mobi ::INFO:: 1.1MB",
+            "!Bot1 Frank Herbert - Dune Messiah.epub ::INFO:: 900KB",
+            "!Bot1 Brian Herbert - Dune Messiah.epub ::INFO:: 900KB",
+        ],
+    );
+    match classify(entry, &ranked)
+    {
+        BatchOutcome::Hit(work) => assert_eq!(2, work.copies.len()),
+        x => panic!("expected a hit, got {:?}", x),
+    }
+
+    // Without an author, the same title by two authors cannot be told apart
+    let entry = &parse_reading_list("Dune Messiah").unwrap()[0];
+    let ranked = rank(
+        "Dune Messiah",
+        &[
+            "!Bot1 Frank Herbert - Dune Messiah.epub ::INFO:: 900KB",
+            "!Bot1 Kevin Anderson - Dune Messiah.epub ::INFO:: 900KB",
+        ],
+    );
+    assert!(matches!(
+        classify(entry, &ranked),
+        BatchOutcome::Ambiguous(x) if x.len() == 2
+    ));
+
+    let ranked = rank("Frank Herbert Dune", &["!Bot1 Dan Simmons - Hyperion.epub"]);
+    assert_eq!(
+        BatchOutcome::Miss("no result matched the title and author".to_string()),
+        classify(entry, &ranked)
+    );
+    assert_eq!(
+        BatchOutcome::Miss("no results".to_string()),
+        classify(entry, &[])
+    );
+    let ranker = Ranker {
+        online_bots: Some(Vec::new()),
+        ..Ranker::default()
+    };
+    let ranked = ranker.rank(
+        "Frank Herbert Dune Messiah",
+        &[SearchResult::parse("!Bot1 Frank Herbert - Dune Messiah.epub").unwrap()],
+    );
+    assert_eq!(
+        BatchOutcome::Miss("every result was rejected".to_string()),
+        classify(entry, &ranked)
+    );
+}
+#[test]
+fn render_report_test()
+{
+    let entries = parse_reading_list("Frank Herbert - Dune\nDan Simmons - Hyperion").unwrap();
+    let ranked = rank("Frank Herbert Dune", &["!Bot1 Frank Herbert - Dune.epub"]);
+    let outcomes = vec![
+        (entries[0].clone(), classify(&entries[0], &ranked)),
+        (entries[1].clone(), classify(&entries[1], &[])),
+    ];
+    let report = render_report(&outcomes);
+    assert!(report.contains("Hits (1)\n  line 1: Frank Herbert - Dune\n"));
+    assert!(report.contains("Frank Herbert - Dune.epub from Bot1, 0 fallback copies"));
+    assert!(report.contains("Misses (1)\n  line 2: Dan Simmons - Hyperion\n      no results\n"));
+    assert!(report.contains("Ambiguous (0)\n"));
+}
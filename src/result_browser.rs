@@ -0,0 +1,454 @@
+use crate::search_result::{format_size, BookFormat, SearchResult};
+use crate::tui::clip;
+use crate::works::{group_works, Work};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey
+{
+    /// The order the search bot listed the results in.
+    Listed,
+    Title,
+    Author,
+    Size,
+    Bot,
+}
+
+/// What the caller should do after the browser handled a line of input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowserOutcome
+{
+    /// The page changed and should be shown again.
+    Redraw,
+    /// Text to show on its own, such as the detail view or help.
+    Show(String),
+    Selected(Vec<Work>),
+    Quit,
+    Invalid(&'static str),
+}
+
+pub const BROWSER_HELP: &str = "Commands:
+    <numbers>           Download the results with these numbers, e.g. 3 or 1,4 7
+    n, p                Next or previous page
+    sort <key>          Sort by title, author, size, bot or listed, again to reverse
+    format <ext>        Only show one format (epub, mobi, azw3, pdf...), `format all` to reset
+    filter <text>       Only show results containing <text>, `filter` alone to reset
+    d <number>          Show the full request line and details of a result
+    g                   Toggle listing copies of the same book from several bots once
+    q                   Quit without downloading";
+
+#[derive(Debug, Clone)]
+pub struct ResultBrowser
+{
+    pub results: Vec<SearchResult>,
+    pub page_size: usize,
+    pub page: usize,
+    pub sort_key: SortKey,
+    pub descending: bool,
+    pub format_filter: Option<BookFormat>,
+    /// Lowercase, matched against the whole request line.
+    pub text_filter: String,
+    /// Lists copies of the same book once, under the first of them.
+    pub grouped: bool,
+    /// Which work each result belongs to.
+    work_of: Vec<usize>,
+    /// Indices into `results` in display order, after filtering and sorting.
+    visible: Vec<usize>,
+    /// For each visible row, every copy it stands for, the row's own result first.
+    visible_copies: Vec<Vec<usize>>,
+}
+
+impl ResultBrowser
+{
+    pub fn new(results: Vec<SearchResult>, page_size: usize) -> Self
+    {
+        let works = group_works(&results);
+        // The request line identifies a copy, so it finds the work the copy was grouped into
+        let mut work_by_request: HashMap<&str, usize> = HashMap::new();
+        for (i, work) in works.iter().enumerate()
+        {
+            for copy in work.copies.iter()
+            {
+                work_by_request.entry(&copy.request_line).or_insert(i);
+            }
+        }
+        let work_of = results
+            .iter()
+            .map(|x| {
+                work_by_request
+                    .get(x.request_line.as_str())
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let mut browser = ResultBrowser {
+            results,
+            page_size: page_size.max(1),
+            page: 0,
+            sort_key: SortKey::Listed,
+            descending: false,
+            format_filter: None,
+            text_filter: String::new(),
+            grouped: true,
+            work_of,
+            visible: Vec::new(),
+            visible_copies: Vec::new(),
+        };
+        browser.refresh();
+        browser
+    }
+    /// Results after filtering and sorting, numbered from 0 in this order.
+    pub fn visible(&self) -> Vec<&SearchResult>
+    {
+        self.visible.iter().map(|x| &self.results[*x]).collect()
+    }
+    pub fn page_count(&self) -> usize
+    {
+        self.visible.len().div_ceil(self.page_size).max(1)
+    }
+    pub fn next_page(&mut self) -> bool
+    {
+        if self.page + 1 < self.page_count()
+        {
+            self.page += 1;
+            true
+        }
+        else
+        {
+            false
+        }
+    }
+    pub fn previous_page(&mut self) -> bool
+    {
+        if self.page > 0
+        {
+            self.page -= 1;
+            true
+        }
+        else
+        {
+            false
+        }
+    }
+    /// Sorting by the current key again reverses the order.
+    pub fn sort_by(&mut self, key: SortKey)
+    {
+        self.descending = self.sort_key == key && !self.descending;
+        self.sort_key = key;
+        self.refresh();
+    }
+    pub fn filter_format(&mut self, format: Option<BookFormat>)
+    {
+        self.format_filter = format;
+        self.refresh();
+    }
+    pub fn filter_text(&mut self, text: &str)
+    {
+        self.text_filter = text.trim().to_lowercase();
+        self.refresh();
+    }
+    pub fn set_grouped(&mut self, grouped: bool)
+    {
+        self.grouped = grouped;
+        self.refresh();
+    }
+    pub fn get(&self, number: usize) -> Option<&SearchResult>
+    {
+        self.visible.get(number).map(|x| &self.results[*x])
+    }
+    /// How many copies the row stands for, 1 unless results are grouped.
+    pub fn copy_count(&self, number: usize) -> usize
+    {
+        self.visible_copies.get(number).map_or(0, |x| x.len())
+    }
+    /// The row as a work, its own result first and the other visible copies as alternates.
+    pub fn work(&self, number: usize) -> Option<Work>
+    {
+        let copies = self.visible_copies.get(number)?;
+        let first = &self.results[copies[0]];
+        Some(Work {
+            author: first.author.clone(),
+            title: first.title.clone(),
+            copies: copies.iter().map(|x| self.results[*x].clone()).collect(),
+        })
+    }
+    fn refresh(&mut self)
+    {
+        let mut visible = (0..self.results.len())
+            .filter(|x| {
+                let result = &self.results[*x];
+                self.format_filter
+                    .as_ref()
+                    .is_none_or(|format| result.format == *format)
+                    && (self.text_filter.is_empty()
+                        || result
+                            .request_line
+                            .to_lowercase()
+                            .contains(&self.text_filter))
+            })
+            .collect::<Vec<usize>>();
+        // Stable, so ties keep the order the bot listed them in
+        visible.sort_by(|a, b| {
+            let ordering = match self.sort_key
+            {
+                SortKey::Listed => a.cmp(b),
+                key => compare(&self.results[*a], &self.results[*b], key),
+            };
+            // Unknown sizes stay last whichever way the known ones run
+            let unknown_size = self.sort_key == SortKey::Size
+                && (self.results[*a].size_bytes.is_none() || self.results[*b].size_bytes.is_none());
+            if self.descending && !unknown_size
+            {
+                ordering.reverse()
+            }
+            else
+            {
+                ordering
+            }
+        });
+        let mut visible_copies: Vec<Vec<usize>> = Vec::new();
+        for i in visible
+        {
+            let row = visible_copies
+                .iter_mut()
+                .find(|x| self.grouped && self.work_of[x[0]] == self.work_of[i]);
+            match row
+            {
+                Some(t) => t.push(i),
+                None => visible_copies.push(vec![i]),
+            }
+        }
+        self.visible = visible_copies.iter().map(|x| x[0]).collect();
+        self.visible_copies = visible_copies;
+        self.page = self.page.min(self.page_count() - 1);
+    }
+
+    pub fn handle(&mut self, input: &str) -> BrowserOutcome
+    {
+        let input = input.trim();
+        let (command, argument) = match input.split_once(char::is_whitespace)
+        {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (input, ""),
+        };
+        match command.to_lowercase().as_str()
+        {
+            "" => BrowserOutcome::Redraw,
+            "n" | "next" =>
+            {
+                if self.next_page()
+                {
+                    BrowserOutcome::Redraw
+                }
+                else
+                {
+                    BrowserOutcome::Invalid("Already on the last page")
+                }
+            }
+            "p" | "prev" | "previous" =>
+            {
+                if self.previous_page()
+                {
+                    BrowserOutcome::Redraw
+                }
+                else
+                {
+                    BrowserOutcome::Invalid("Already on the first page")
+                }
+            }
+            "sort" =>
+            {
+                let key = match argument.to_lowercase().as_str()
+                {
+                    "title" => SortKey::Title,
+                    "author" => SortKey::Author,
+                    "size" => SortKey::Size,
+                    "bot" => SortKey::Bot,
+                    "listed" | "" => SortKey::Listed,
+                    _ => return BrowserOutcome::Invalid("Sort by title, author, size or bot"),
+                };
+                self.sort_by(key);
+                BrowserOutcome::Redraw
+            }
+            "format" =>
+            {
+                let format = match argument.to_lowercase().as_str()
+                {
+                    "" | "all" => None,
+                    t => Some(BookFormat::from_extension(Some(t))),
+                };
+                self.filter_format(format);
+                BrowserOutcome::Redraw
+            }
+            "filter" =>
+            {
+                self.filter_text(argument);
+                BrowserOutcome::Redraw
+            }
+            "d" | "detail" => match argument.parse::<usize>()
+            {
+                Ok(t) => match self.render_detail(t)
+                {
+                    Some(detail) => BrowserOutcome::Show(detail),
+                    None => BrowserOutcome::Invalid("No result with that number"),
+                },
+                Err(_e) => BrowserOutcome::Invalid("Usage: d <number>"),
+            },
+            "g" | "group" =>
+            {
+                self.set_grouped(!self.grouped);
+                BrowserOutcome::Redraw
+            }
+            "h" | "help" | "?" => BrowserOutcome::Show(BROWSER_HELP.to_string()),
+            "q" | "quit" => BrowserOutcome::Quit,
+            _ => self.select(input),
+        }
+    }
+    fn select(&self, input: &str) -> BrowserOutcome
+    {
+        let numbers = input
+            .split(|x: char| x.is_whitespace() || x == ',')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>();
+        let numbers = match numbers
+        {
+            Ok(t) => t,
+            Err(_e) => return BrowserOutcome::Invalid("Unknown command, `h` lists them"),
+        };
+        let mut selected: Vec<Work> = Vec::new();
+        for number in numbers
+        {
+            match self.work(number)
+            {
+                Some(t) if !selected.contains(&t) => selected.push(t),
+                Some(_) => (),
+                None => return BrowserOutcome::Invalid("No result with that number"),
+            }
+        }
+        BrowserOutcome::Selected(selected)
+    }
+
+    /// The current page as a table, with a header describing the filters and paging.
+    pub fn render_page(&self) -> String
+    {
+        let mut filters = Vec::new();
+        if let Some(format) = &self.format_filter
+        {
+            filters.push(format!("format {}", format.as_str()));
+        }
+        if !self.text_filter.is_empty()
+        {
+            filters.push(format!("containing \"{}\"", self.text_filter));
+        }
+        let mut ret_val = format!(
+            "There are {} results{}. Page {} of {}, sorted by {}{}.\n",
+            self.visible.len(),
+            if filters.is_empty()
+            {
+                String::new()
+            }
+            else
+            {
+                format!(
+                    " ({} of {} shown: {})",
+                    self.visible.len(),
+                    self.results.len(),
+                    filters.join(", ")
+                )
+            },
+            self.page + 1,
+            self.page_count(),
+            match self.sort_key
+            {
+                SortKey::Listed => "listed order",
+                SortKey::Title => "title",
+                SortKey::Author => "author",
+                SortKey::Size => "size",
+                SortKey::Bot => "bot",
+            },
+            if self.descending { ", reversed" } else { "" },
+        );
+        if self.grouped && self.visible.len() < self.visible_copies.iter().flatten().count()
+        {
+            ret_val.push_str("Copies of the same book are listed once, `g` lists every copy.\n");
+        }
+        ret_val.push_str(&format!(
+            "{:>4}  {:<40}  {:<24}  {:<6}  {:>9}  {}\n",
+            "#", "Title", "Author", "Format", "Size", "Bot"
+        ));
+        let start = self.page * self.page_size;
+        for (i, result) in self
+            .visible()
+            .into_iter()
+            .enumerate()
+            .skip(start)
+            .take(self.page_size)
+        {
+            ret_val.push_str(&format!(
+                "{:>4}  {}  {}  {}  {:>9}  {}\n",
+                i,
+                clip(&result.title, 40),
+                clip(&result.author, 24),
+                clip(result.format.as_str(), 6),
+                result.size_bytes.map(format_size).unwrap_or_default(),
+                result.bot
+            ));
+            let others = self.visible_copies[i][1..]
+                .iter()
+                .map(|x| self.results[*x].bot.as_str())
+                .collect::<Vec<&str>>();
+            if !others.is_empty()
+            {
+                ret_val.push_str(&format!("        also from {}\n", others.join(", ")));
+            }
+        }
+        ret_val
+    }
+    pub fn render_detail(&self, number: usize) -> Option<String>
+    {
+        let result = self.get(number)?;
+        let mut ret_val = format!(
+            "Request: {}\nTitle:   {}\nAuthor:  {}\nSeries:  {}\nFormat:  {}\nSize:    {}\nBot:     {}\n",
+            result.request_line,
+            result.title,
+            result.author,
+            result.series.as_deref().unwrap_or(""),
+            result.format.as_str(),
+            result
+                .size_bytes
+                .map(|x| format!("{} ({} bytes)", format_size(x), x))
+                .unwrap_or_default(),
+            result.bot
+        );
+        for (key, value) in result.metadata.iter()
+        {
+            ret_val.push_str(&format!("{}: {}\n", key, value));
+        }
+        for copy in self.visible_copies[number][1..].iter()
+        {
+            ret_val.push_str(&format!("Also:    {}\n", self.results[*copy].request_line));
+        }
+        Some(ret_val)
+    }
+}
+
+fn compare(a: &SearchResult, b: &SearchResult, key: SortKey) -> Ordering
+{
+    match key
+    {
+        SortKey::Listed => Ordering::Equal,
+        SortKey::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+        SortKey::Author => a.author.to_lowercase().cmp(&b.author.to_lowercase()),
+        // Results without a size sort after every known size
+        SortKey::Size => match (a.size_bytes, b.size_bytes)
+        {
+            (Some(x), Some(y)) => x.cmp(&y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+        SortKey::Bot => a.bot.to_lowercase().cmp(&b.bot.to_lowercase()),
+    }
+}
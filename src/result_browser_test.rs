@@ -0,0 +1,196 @@
+use crate::result_browser::{BrowserOutcome, ResultBrowser, SortKey};
+use crate::search_result::{BookFormat, SearchResult};
+
+fn browser(page_size: usize) -> ResultBrowser
+{
+    let results = [
+        "!Bsk Tolkien, J.R.R - The Hobbit.epub ::INFO:: 1.2MB",
+        "!DV8 Herbert, Frank - Dune.mobi ::INFO:: 800KB",
+        "!Oatmeal Asimov, Isaac - Foundation.pdf ::INFO:: 3MB",
+        "!Bsk Herbert, Frank - Children of Dune.epub",
+        "!Pondering42 Le Guin, Ursula K - The Dispossessed.azw3 ::INFO:: 1MB",
+    ]
+    .iter()
+    .map(|x| SearchResult::parse(x).unwrap())
+    .collect();
+    ResultBrowser::new(results, page_size)
+}
+
+fn titles(browser: &ResultBrowser) -> Vec<String>
+{
+    browser.visible().iter().map(|x| x.title.clone()).collect()
+}
+
+#[test]
+fn paging_reaches_every_result_test()
+{
+    let mut browser = browser(2);
+    assert_eq!(3, browser.page_count());
+    assert!(browser.render_page().contains("0  The Hobbit"));
+    assert!(!browser.render_page().contains("Foundation"));
+
+    assert_eq!(BrowserOutcome::Redraw, browser.handle("n"));
+    assert_eq!(BrowserOutcome::Redraw, browser.handle("next"));
+    assert!(browser.render_page().contains("4  The Dispossessed"));
+    assert!(matches!(browser.handle("n"), BrowserOutcome::Invalid(_)));
+
+    assert_eq!(BrowserOutcome::Redraw, browser.handle("p"));
+    assert!(browser.render_page().contains("Page 2 of 3"));
+}
+#[test]
+fn sorting_test()
+{
+    let mut browser = browser(10);
+    browser.handle("sort title");
+    assert_eq!(
+        vec![
+            "Children of Dune",
+            "Dune",
+            "Foundation",
+            "The Dispossessed",
+            "The Hobbit"
+        ],
+        titles(&browser)
+    );
+    // Sorting by the same key again reverses it
+    browser.handle("sort title");
+    assert_eq!("The Hobbit", titles(&browser)[0]);
+    assert!(browser.render_page().contains("reversed"));
+
+    browser.sort_by(SortKey::Size);
+    assert_eq!(
+        vec![
+            "Dune",
+            "The Dispossessed",
+            "The Hobbit",
+            "Foundation",
+            "Children of Dune"
+        ],
+        titles(&browser)
+    );
+    // Reversed, the result without a size still comes last
+    browser.sort_by(SortKey::Size);
+    assert_eq!(
+        vec![
+            "Foundation",
+            "The Hobbit",
+            "The Dispossessed",
+            "Dune",
+            "Children of Dune"
+        ],
+        titles(&browser)
+    );
+    browser.handle("sort bot");
+    assert_eq!(
+        vec!["The Hobbit", "Children of Dune"],
+        titles(&browser)[..2].to_vec()
+    );
+    browser.handle("sort author");
+    assert_eq!("Foundation", titles(&browser)[0]);
+    assert!(matches!(
+        browser.handle("sort colour"),
+        BrowserOutcome::Invalid(_)
+    ));
+}
+#[test]
+fn filtering_test()
+{
+    let mut browser = browser(10);
+    browser.handle("format epub");
+    assert_eq!(Some(BookFormat::Epub), browser.format_filter);
+    assert_eq!(vec!["The Hobbit", "Children of Dune"], titles(&browser));
+
+    browser.handle("filter DUNE");
+    assert_eq!(vec!["Children of Dune"], titles(&browser));
+    assert!(browser.render_page().contains("1 of 5 shown"));
+
+    browser.handle("format all");
+    assert_eq!(vec!["Dune", "Children of Dune"], titles(&browser));
+    browser.handle("filter");
+    assert_eq!(5, titles(&browser).len());
+}
+#[test]
+fn filtering_keeps_page_in_range_test()
+{
+    let mut browser = browser(2);
+    browser.handle("n");
+    browser.handle("n");
+    browser.handle("format mobi");
+    assert_eq!(0, browser.page);
+    assert!(browser.render_page().contains("0  Dune"));
+}
+#[test]
+fn selection_uses_displayed_numbers_test()
+{
+    let mut browser = browser(10);
+    browser.handle("sort title");
+    match browser.handle("0, 4 0")
+    {
+        BrowserOutcome::Selected(selected) =>
+        {
+            assert_eq!(2, selected.len());
+            assert_eq!("Children of Dune", selected[0].title);
+            assert_eq!("The Hobbit", selected[1].title);
+        }
+        t => panic!("Unexpected outcome {:?}", t),
+    }
+    assert!(matches!(browser.handle("9"), BrowserOutcome::Invalid(_)));
+    assert!(matches!(browser.handle("nope"), BrowserOutcome::Invalid(_)));
+    assert_eq!(BrowserOutcome::Quit, browser.handle("q"));
+}
+#[test]
+fn detail_shows_request_line_test()
+{
+    let mut browser = browser(10);
+    match browser.handle("d 2")
+    {
+        BrowserOutcome::Show(detail) =>
+        {
+            assert!(
+                detail.contains("Request: !Oatmeal Asimov, Isaac - Foundation.pdf ::INFO:: 3MB")
+            );
+            assert!(detail.contains("3.0MB (3145728 bytes)"));
+        }
+        t => panic!("Unexpected outcome {:?}", t),
+    }
+    assert!(matches!(browser.handle("d 12"), BrowserOutcome::Invalid(_)));
+}
+#[test]
+fn grouping_copies_test()
+{
+    let results = [
+        "!Bsk Herbert, Frank - Dune.epub ::INFO:: 1MB",
+        "!DV8 Frank Herbert - Dune.epub ::INFO:: 1MB",
+        "!Oatmeal Herbert, Frank - Dune.mobi ::INFO:: 900KB",
+        "!Bsk Asimov, Isaac - Foundation.pdf",
+    ]
+    .iter()
+    .map(|x| SearchResult::parse(x).unwrap())
+    .collect();
+    let mut browser = ResultBrowser::new(results, 10);
+    assert_eq!(vec!["Dune", "Foundation"], titles(&browser));
+    assert_eq!(3, browser.copy_count(0));
+    assert!(browser.render_page().contains("also from DV8, Oatmeal"));
+    assert!(browser
+        .render_detail(0)
+        .unwrap()
+        .contains("Also:    !DV8 Frank Herbert - Dune.epub"));
+
+    // Alternates follow the filters, so an epub-only pick never falls back to a mobi
+    browser.handle("format epub");
+    match browser.handle("0")
+    {
+        BrowserOutcome::Selected(selected) =>
+        {
+            assert_eq!("Bsk", selected[0].best().bot);
+            assert_eq!(1, selected[0].alternates().len());
+            assert_eq!("DV8", selected[0].alternates()[0].bot);
+        }
+        t => panic!("Unexpected outcome {:?}", t),
+    }
+
+    browser.handle("format all");
+    assert_eq!(BrowserOutcome::Redraw, browser.handle("g"));
+    assert_eq!(4, browser.visible().len());
+    assert_eq!(1, browser.copy_count(0));
+}
@@ -0,0 +1,211 @@
+use crate::search_provider::SearchDialect;
+use crate::works::normalize_words;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+pub const CACHE_DIR_NAME: &str = ".rs-book-downloader-cache";
+/// Searches repeated within this long reuse the cached list instead of asking the bot again.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Lists older than this are deleted, even though `--offline` could still browse them.
+pub const CACHE_PRUNE_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// A past search that can be browsed again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry
+{
+    pub server: String,
+    pub channel: String,
+    pub dialect: String,
+    pub query: String,
+    pub saved_at: SystemTime,
+}
+
+/// Result lists as the bots sent them, one file per server, channel, dialect and normalized
+/// query. Each network has its own bots, so only the lists of `server` and `channel` are used.
+#[derive(Debug, Clone)]
+pub struct ResultCache
+{
+    pub dir: PathBuf,
+    pub ttl: Duration,
+    pub server: String,
+    pub channel: String,
+}
+
+impl ResultCache
+{
+    pub fn new(dir: PathBuf, ttl: Duration, server: &str, channel: &str) -> Self
+    {
+        ResultCache {
+            dir,
+            ttl,
+            server: server.to_string(),
+            channel: channel.to_string(),
+        }
+    }
+    pub fn store(
+        &self,
+        dialect: SearchDialect,
+        query: &str,
+        data: &[u8],
+    ) -> Result<(), &'static str>
+    {
+        if fs::create_dir_all(&self.dir).is_err()
+        {
+            return Err("Unable to create the cache directory");
+        }
+        let key = cache_key(&self.server, &self.channel, dialect, query);
+        let written = fs::write(self.dir.join(format!("{}.list", key)), data).and_then(|_| {
+            fs::write(
+                self.dir.join(format!("{}.query", key)),
+                format!(
+                    "{}\t{}\t{}\t{}",
+                    self.server,
+                    self.channel,
+                    dialect.as_str(),
+                    query.trim()
+                ),
+            )
+        });
+        match written
+        {
+            Ok(_) => Ok(()),
+            Err(_e) => Err("Unable to write to the cache directory"),
+        }
+    }
+    /// The cached list for a search, if it was saved within the TTL.
+    pub fn fetch(&self, dialect: SearchDialect, query: &str) -> Option<Vec<u8>>
+    {
+        let (data, saved_at) = self.fetch_any(dialect, query)?;
+        if age(saved_at) < self.ttl
+        {
+            Some(data)
+        }
+        else
+        {
+            None
+        }
+    }
+    /// The cached list for a search however old it is, with when it was saved.
+    pub fn fetch_any(&self, dialect: SearchDialect, query: &str) -> Option<(Vec<u8>, SystemTime)>
+    {
+        let key = cache_key(&self.server, &self.channel, dialect, query);
+        let path = self.dir.join(format!("{}.list", key));
+        let saved_at = fs::metadata(&path).and_then(|x| x.modified()).ok()?;
+        let data = fs::read(&path).ok()?;
+        Some((data, saved_at))
+    }
+    /// Every cached search on this server and channel, newest first.
+    pub fn entries(&self) -> Vec<CacheEntry>
+    {
+        let mut entries = match fs::read_dir(&self.dir)
+        {
+            Ok(t) => t
+                .filter_map(|x| x.ok())
+                .map(|x| x.path())
+                .filter(|x| x.extension().is_some_and(|extension| extension == "query"))
+                .filter_map(|x| {
+                    let contents = fs::read_to_string(&x).ok()?;
+                    let (server, rest) = contents.split_once('\t')?;
+                    let (channel, rest) = rest.split_once('\t')?;
+                    let (dialect, query) = rest.split_once('\t')?;
+                    let saved_at = fs::metadata(x.with_extension("list"))
+                        .and_then(|metadata| metadata.modified())
+                        .ok()?;
+                    Some(CacheEntry {
+                        server: server.to_string(),
+                        channel: channel.to_string(),
+                        dialect: dialect.to_string(),
+                        query: query.to_string(),
+                        saved_at,
+                    })
+                })
+                .filter(|x| {
+                    x.server.eq_ignore_ascii_case(&self.server)
+                        && x.channel.eq_ignore_ascii_case(&self.channel)
+                })
+                .collect::<Vec<CacheEntry>>(),
+            Err(_e) => Vec::new(),
+        };
+        entries.sort_by_key(|x| std::cmp::Reverse(x.saved_at));
+        entries
+    }
+    /// Deletes lists older than `max_age`.
+    pub fn prune(&self, max_age: Duration)
+    {
+        let paths = match fs::read_dir(&self.dir)
+        {
+            Ok(t) => t
+                .filter_map(|x| x.ok())
+                .map(|x| x.path())
+                .collect::<Vec<PathBuf>>(),
+            Err(_e) => return,
+        };
+        for path in paths
+            .iter()
+            .filter(|x| x.extension().is_some_and(|extension| extension == "list"))
+        {
+            let stale = fs::metadata(path)
+                .and_then(|x| x.modified())
+                .is_ok_and(|x| age(x) >= max_age);
+            if stale
+            {
+                let _ = fs::remove_file(path);
+                let _ = fs::remove_file(path.with_extension("query"));
+            }
+        }
+    }
+}
+
+/// File name for a search, equal for queries that differ only in case, punctuation or articles.
+pub fn cache_key(server: &str, channel: &str, dialect: SearchDialect, query: &str) -> String
+{
+    let words = normalize_words(query).join("-");
+    let mut readable = words.chars().take(60).collect::<String>();
+    if readable.is_empty()
+    {
+        readable.push('_');
+    }
+    // The readable part may be cut short and leaves out the network, so the hash keeps long
+    // queries and other networks apart
+    let network = format!("{}\t{}\t", server, channel).to_lowercase();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for x in network.bytes().chain(words.bytes())
+    {
+        hash ^= x as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    let prefix = match dialect
+    {
+        SearchDialect::Search => "search",
+        SearchDialect::BangSearch => "bangsearch",
+        SearchDialect::Find => "find",
+        SearchDialect::Seek => "seek",
+    };
+    format!("{}-{}-{:016x}", prefix, readable, hash)
+}
+
+/// How long ago `time` was, zero for times in the future.
+pub fn age(time: SystemTime) -> Duration
+{
+    SystemTime::now().duration_since(time).unwrap_or_default()
+}
+
+/// Rough age such as `5 minutes ago` or `2 days ago`.
+pub fn format_age(age: Duration) -> String
+{
+    let seconds = age.as_secs();
+    let (count, unit) = match seconds
+    {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
@@ -0,0 +1,92 @@
+use crate::result_cache::{cache_key, format_age, ResultCache, CACHE_PRUNE_AGE};
+use crate::search_provider::SearchDialect;
+use std::fs;
+use std::time::Duration;
+
+const NET: &str = "irc.example.net:6660";
+
+fn cache(name: &str, ttl: Duration) -> ResultCache
+{
+    let dir = std::env::temp_dir().join(format!("rsbd_cache_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    ResultCache::new(dir, ttl, "irc.example.net:6660", "#bookz")
+}
+
+#[test]
+fn cache_key_test()
+{
+    assert_eq!(
+        cache_key(NET, "#bookz", SearchDialect::Search, "The Hobbit"),
+        cache_key(NET, "#bookz", SearchDialect::Search, "  hobbit!  ")
+    );
+    assert_ne!(
+        cache_key(NET, "#bookz", SearchDialect::Search, "hobbit"),
+        cache_key(NET, "#bookz", SearchDialect::BangSearch, "hobbit")
+    );
+    assert_ne!(
+        cache_key(NET, "#bookz", SearchDialect::Search, "dune"),
+        cache_key(NET, "#bookz", SearchDialect::Search, "dune messiah")
+    );
+    assert!(
+        cache_key(NET, "#bookz", SearchDialect::Find, "Gödel, Escher/Bach")
+            .starts_with("find-godel-escher-bach-")
+    );
+    // Another network has other bots and other lists
+    assert_ne!(
+        cache_key(NET, "#bookz", SearchDialect::Search, "dune"),
+        cache_key(
+            "irc.other.net:6667",
+            "#bookz",
+            SearchDialect::Search,
+            "dune"
+        )
+    );
+    assert_ne!(
+        cache_key(NET, "#bookz", SearchDialect::Search, "dune"),
+        cache_key(NET, "#ebooks", SearchDialect::Search, "dune")
+    );
+}
+#[test]
+fn cache_store_and_fetch_test()
+{
+    let cache = cache("store", Duration::from_secs(3600));
+    assert!(cache.fetch(SearchDialect::Search, "dune").is_none());
+    cache
+        .store(SearchDialect::Search, "Dune", b"!DV8 Dune.epub\r\n")
+        .unwrap();
+    assert_eq!(
+        Some(b"!DV8 Dune.epub\r\n".to_vec()),
+        cache.fetch(SearchDialect::Search, "dune")
+    );
+    assert!(cache.fetch(SearchDialect::Find, "dune").is_none());
+
+    let entries = cache.entries();
+    assert_eq!(1, entries.len());
+    assert_eq!("@search", entries[0].dialect);
+    assert_eq!("Dune", entries[0].query);
+    assert_eq!("#bookz", entries[0].channel);
+
+    // Nothing cached on one network is used or listed on another
+    let other = ResultCache::new(cache.dir.clone(), cache.ttl, NET, "#ebooks");
+    assert!(other.fetch(SearchDialect::Search, "dune").is_none());
+    assert!(other.entries().is_empty());
+
+    // Stale lists are skipped by a normal search but still there to browse offline
+    let stale = ResultCache::new(cache.dir.clone(), Duration::ZERO, NET, "#bookz");
+    assert!(stale.fetch(SearchDialect::Search, "dune").is_none());
+    assert!(stale.fetch_any(SearchDialect::Search, "dune").is_some());
+
+    cache.prune(CACHE_PRUNE_AGE);
+    assert_eq!(1, cache.entries().len());
+    cache.prune(Duration::ZERO);
+    assert!(cache.entries().is_empty());
+    fs::remove_dir_all(&cache.dir).unwrap();
+}
+#[test]
+fn format_age_test()
+{
+    assert_eq!("just now", format_age(Duration::from_secs(5)));
+    assert_eq!("1 minute ago", format_age(Duration::from_secs(90)));
+    assert_eq!("3 hours ago", format_age(Duration::from_secs(3 * 3600 + 5)));
+    assert_eq!("2 days ago", format_age(Duration::from_secs(2 * 86400)));
+}
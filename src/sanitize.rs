@@ -0,0 +1,127 @@
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+const FORBIDDEN_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Turns a filename sent by a remote peer into a single safe path component.
+///
+/// Leading dots are stripped along with trailing ones, so `.bashrc` is saved as `bashrc`. This
+/// is deliberate: a book has no reason to be a hidden file, and a peer should not be able to
+/// drop one the user will not see in the download directory.
+pub fn sanitize_filename(raw: &str) -> Result<String, &'static str>
+{
+    let unquoted = raw.trim().trim_matches('"').trim_matches('\'');
+    // Only the last component is kept, whichever separator the sender used
+    let last_component = unquoted.rsplit(['/', '\\']).next().unwrap_or("");
+    let mut cleaned = last_component
+        .chars()
+        .filter(|x| !x.is_control() && !FORBIDDEN_CHARS.contains(x))
+        .collect::<String>();
+    cleaned = cleaned
+        .trim_matches(|x: char| x == '.' || x.is_whitespace())
+        .to_string();
+    if cleaned.is_empty()
+    {
+        return Err("Filename is empty after sanitization");
+    }
+
+    let stem = cleaned.split('.').next().unwrap_or("").to_uppercase();
+    if RESERVED_NAMES.contains(&stem.trim_end())
+    {
+        cleaned.insert(0, '_');
+    }
+
+    Ok(truncate_filename(&cleaned, MAX_FILENAME_BYTES))
+}
+
+/// Resolves a remote filename to a path inside `download_dir` and reserves it by creating an
+/// empty file there. The file is created exclusively, so a name taken by another download in
+/// the meantime, or a symlink planted there, moves on to the next numbered name instead of
+/// being written through.
+pub fn reserve_download_path(download_dir: &Path, raw: &str) -> Result<PathBuf, &'static str>
+{
+    let file_name = sanitize_filename(raw)?;
+    let root = match download_dir.canonicalize()
+    {
+        Ok(t) => t,
+        Err(_e) => return Err("Download directory does not exist"),
+    };
+
+    let mut candidate = root.join(&file_name);
+    let mut counter = 1;
+    loop
+    {
+        if !is_direct_child(&root, &candidate)
+        {
+            return Err("Resolved path escapes the download directory");
+        }
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists =>
+            {
+                candidate = root.join(numbered_filename(&file_name, counter));
+                counter += 1;
+            }
+            Err(_e) => return Err("Unable to create download file"),
+        }
+    }
+}
+
+fn is_direct_child(root: &Path, candidate: &Path) -> bool
+{
+    match candidate.strip_prefix(root)
+    {
+        Ok(relative) =>
+        {
+            let components = relative.components().collect::<Vec<Component>>();
+            components.len() == 1 && matches!(components[0], Component::Normal(_))
+        }
+        Err(_e) => false,
+    }
+}
+
+/// `book.epub` becomes `book (1).epub`, `book` becomes `book (1)`.
+pub fn numbered_filename(file_name: &str, counter: usize) -> String
+{
+    match file_name.rsplit_once('.')
+    {
+        Some((stem, extension)) if !stem.is_empty() =>
+        {
+            format!("{} ({}).{}", stem, counter, extension)
+        }
+        _ => format!("{} ({})", file_name, counter),
+    }
+}
+
+fn truncate_filename(file_name: &str, max_bytes: usize) -> String
+{
+    if file_name.len() <= max_bytes
+    {
+        return file_name.to_string();
+    }
+    let (stem, extension) = match file_name.rsplit_once('.')
+    {
+        Some((stem, extension)) if extension.len() < 16 => (stem, format!(".{}", extension)),
+        _ => (file_name, String::new()),
+    };
+    let mut truncated_stem = String::new();
+    for x in stem.chars()
+    {
+        if truncated_stem.len() + x.len_utf8() + extension.len() > max_bytes
+        {
+            break;
+        }
+        truncated_stem.push(x);
+    }
+    format!("{}{}", truncated_stem, extension)
+}
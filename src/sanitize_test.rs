@@ -0,0 +1,98 @@
+use crate::sanitize::{numbered_filename, reserve_download_path, sanitize_filename};
+use std::fs;
+
+#[test]
+fn sanitize_strips_directory_components_test()
+{
+    assert_eq!("passwd", sanitize_filename("/etc/passwd").unwrap());
+    assert_eq!("bashrc", sanitize_filename("../../.bashrc").unwrap());
+    assert_eq!(
+        "evil.exe",
+        sanitize_filename("..\\..\\Windows\\evil.exe").unwrap()
+    );
+}
+#[test]
+fn sanitize_removes_quotes_and_control_characters_test()
+{
+    assert_eq!(
+        "Some Book - Author.epub",
+        sanitize_filename("\"Some Book - Author.epub\"").unwrap()
+    );
+    assert_eq!(
+        "book.epub",
+        sanitize_filename("bo\u{7}ok\r\n.epub").unwrap()
+    );
+}
+#[test]
+fn sanitize_rejects_empty_and_dot_names_test()
+{
+    assert!(sanitize_filename("").is_err());
+    assert!(sanitize_filename("..").is_err());
+    assert!(sanitize_filename("/").is_err());
+    assert!(sanitize_filename("\"\"").is_err());
+}
+#[test]
+fn sanitize_reserved_names_test()
+{
+    assert_eq!("_CON", sanitize_filename("CON").unwrap());
+    assert_eq!("_nul.txt", sanitize_filename("nul.txt").unwrap());
+    assert_eq!("CONSOLE.txt", sanitize_filename("CONSOLE.txt").unwrap());
+}
+#[test]
+fn sanitize_truncates_long_names_test()
+{
+    let long_name = format!("{}.epub", "a".repeat(400));
+    let sanitized = sanitize_filename(&long_name).unwrap();
+    assert_eq!(255, sanitized.len());
+    assert!(sanitized.ends_with(".epub"));
+}
+#[test]
+fn numbered_filename_test()
+{
+    assert_eq!("book (1).epub", numbered_filename("book.epub", 1));
+    assert_eq!("book (2)", numbered_filename("book", 2));
+    assert_eq!(".hidden (1)", numbered_filename(".hidden", 1));
+}
+#[test]
+fn reserve_download_path_deduplicates_test()
+{
+    let dir = std::env::temp_dir().join(format!("rsbd_sanitize_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let root = dir.canonicalize().unwrap();
+
+    let first = reserve_download_path(&dir, "../book.epub").unwrap();
+    assert_eq!(root.join("book.epub"), first);
+    assert!(first.exists());
+
+    let second = reserve_download_path(&dir, "book.epub").unwrap();
+    assert_eq!(root.join("book (1).epub"), second);
+
+    let third = reserve_download_path(&dir, "book.epub").unwrap();
+    assert_eq!(root.join("book (2).epub"), third);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+#[cfg(unix)]
+#[test]
+fn reserve_download_path_skips_symlinks_test()
+{
+    let dir = std::env::temp_dir().join(format!("rsbd_symlink_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let root = dir.canonicalize().unwrap();
+    let target = root.join("target");
+    std::os::unix::fs::symlink(&target, root.join("book.epub")).unwrap();
+
+    let reserved = reserve_download_path(&dir, "book.epub").unwrap();
+    let target_exists = target.exists();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(root.join("book (1).epub"), reserved);
+    assert!(!target_exists);
+}
+#[test]
+fn sanitize_keeps_inner_apostrophes_test()
+{
+    assert_eq!(
+        "Ender's Game.epub",
+        sanitize_filename("'Ender's Game.epub'").unwrap()
+    );
+}
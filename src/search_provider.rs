@@ -0,0 +1,258 @@
+use crate::bot_reply::strip_formatting;
+use crate::pkzip::PkZip;
+use crate::search_result::SearchResult;
+use std::time::Duration;
+
+/// With results sent as NOTICEs there is no end marker, so the search is over once the bots
+/// have been quiet this long.
+pub const NOTICE_RESULTS_QUIET: Duration = Duration::from_secs(5);
+
+/// How a channel's search bots send back what they found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultDelivery
+{
+    /// A zipped text list offered over DCC SEND.
+    ZippedList,
+    /// A plain text list offered over DCC SEND.
+    TextList,
+    /// One NOTICE per result, from each bot that has a match.
+    Notices,
+}
+
+/// The search trigger a channel understands, selected per channel in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchDialect
+{
+    /// `@search`, answered by a SearchBot with a zipped list.
+    Search,
+    /// `!search`, the same list from bots that use `!` triggers.
+    BangSearch,
+    /// `@find`, answered by every file server with NOTICEs.
+    Find,
+    /// `@seek`, answered with a plain text list.
+    Seek,
+}
+
+impl SearchDialect
+{
+    pub fn from_name(name: &str) -> Result<SearchDialect, &'static str>
+    {
+        match name.trim().to_lowercase().as_str()
+        {
+            "@search" | "search" => Ok(SearchDialect::Search),
+            "!search" | "bang" => Ok(SearchDialect::BangSearch),
+            "@find" | "find" => Ok(SearchDialect::Find),
+            "@seek" | "seek" => Ok(SearchDialect::Seek),
+            _ => Err("Unknown search dialect, use @search, !search, @find or @seek"),
+        }
+    }
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            SearchDialect::Search => "@search",
+            SearchDialect::BangSearch => "!search",
+            SearchDialect::Find => "@find",
+            SearchDialect::Seek => "@seek",
+        }
+    }
+    pub fn provider(&self) -> Box<dyn SearchProvider>
+    {
+        match self
+        {
+            SearchDialect::Search => Box::new(SearchBotProvider { trigger: "@search" }),
+            SearchDialect::BangSearch => Box::new(SearchBotProvider { trigger: "!search" }),
+            SearchDialect::Find => Box::new(FindProvider),
+            SearchDialect::Seek => Box::new(SeekProvider),
+        }
+    }
+}
+
+/// Knows how to ask one kind of search bot for a book and how to read its answer.
+pub trait SearchProvider
+{
+    /// The line to say in the channel to search for `query`.
+    fn search_command(&self, query: &str) -> String;
+    fn delivery(&self) -> ResultDelivery;
+    /// Reads the results out of a result list received over DCC. Bots do not always send what
+    /// their dialect promises, so the list is recognized by its content.
+    fn parse_list(&self, data: &[u8]) -> Result<Vec<SearchResult>, &'static str>
+    {
+        parse_result_list(data)
+    }
+    /// The result a NOTICE or PRIVMSG from `sender` carries, if it is one.
+    fn parse_notice(&self, _sender: &str, _text: &str) -> Option<SearchResult>
+    {
+        None
+    }
+}
+
+/// SearchBot and its clones, which answer with a zipped list of `!bot file` lines.
+pub struct SearchBotProvider
+{
+    pub trigger: &'static str,
+}
+
+impl SearchProvider for SearchBotProvider
+{
+    fn search_command(&self, query: &str) -> String
+    {
+        format!("{} {}", self.trigger, query.trim())
+    }
+    fn delivery(&self) -> ResultDelivery
+    {
+        ResultDelivery::ZippedList
+    }
+}
+
+/// `@find` is answered by each file server on its own, one NOTICE per matching file.
+pub struct FindProvider;
+
+impl SearchProvider for FindProvider
+{
+    fn search_command(&self, query: &str) -> String
+    {
+        format!("@find {}", query.trim())
+    }
+    fn delivery(&self) -> ResultDelivery
+    {
+        ResultDelivery::Notices
+    }
+    fn parse_notice(&self, sender: &str, text: &str) -> Option<SearchResult>
+    {
+        let text = strip_formatting(text);
+        let result = SearchResult::parse(text.trim()).ok()?;
+        // Only trust a server to speak for itself
+        if result.bot.eq_ignore_ascii_case(sender)
+        {
+            Some(result)
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+/// `@seek` bots send their matches as an uncompressed text list.
+pub struct SeekProvider;
+
+impl SearchProvider for SeekProvider
+{
+    fn search_command(&self, query: &str) -> String
+    {
+        format!("@seek {}", query.trim())
+    }
+    fn delivery(&self) -> ResultDelivery
+    {
+        ResultDelivery::TextList
+    }
+}
+
+/// Parses every `!bot file` line of a result list, skipping headers and blank lines.
+pub fn parse_result_text(text: &str) -> Vec<SearchResult>
+{
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .filter_map(|x| SearchResult::parse(x.trim()).ok())
+        .collect()
+}
+
+/// Parses a result list that is either plain text or a zip archive holding one.
+pub fn parse_result_list(data: &[u8]) -> Result<Vec<SearchResult>, &'static str>
+{
+    if PkZip::data_is_pkzip(data)
+    {
+        return parse_zipped_list(data);
+    }
+    if data.starts_with(b"Rar!") || data.starts_with(&[0x1f, 0x8b]) || data.starts_with(b"7z")
+    {
+        return Err("Search results are compressed in a format other than zip");
+    }
+    if !looks_like_text(data)
+    {
+        return Err("Search results are neither a zip archive nor text");
+    }
+    Ok(parse_result_text(&String::from_utf8_lossy(data)))
+}
+
+/// Text has no NUL bytes and few other control characters besides line breaks, tabs and IRC
+/// formatting codes.
+pub fn looks_like_text(data: &[u8]) -> bool
+{
+    let sample = &data[..data.len().min(8192)];
+    let control = sample
+        .iter()
+        .filter(|x| **x < 0x20 && !b"\r\n\t\x02\x03\x0f\x16\x1d\x1f".contains(x))
+        .count();
+    !sample.contains(&0) && control * 100 <= sample.len()
+}
+
+/// Picks which entry of a result archive holds the results, from the entries' names and
+/// uncompressed sizes. Readmes and other notes are never picked, and when several entries look
+/// equally likely the largest wins.
+pub fn pick_results_file(entries: &[(&str, u32)]) -> Option<usize>
+{
+    let scores = entries
+        .iter()
+        .map(|(name, _)| {
+            let name = name.to_lowercase();
+            let base_name = name.rsplit(['/', '\\']).next().unwrap_or("");
+            if base_name.is_empty()
+            {
+                // A directory
+                return None;
+            }
+            if ["readme", "nfo", "info", "license", "about", "rules"]
+                .iter()
+                .any(|x| base_name.contains(x))
+            {
+                return None;
+            }
+            let mut score = 0;
+            if ["result", "search", "find", "seek"]
+                .iter()
+                .any(|x| base_name.contains(x))
+            {
+                score += 2;
+            }
+            if base_name.ends_with(".txt") || base_name.ends_with(".lst")
+            {
+                score += 1;
+            }
+            Some(score)
+        })
+        .collect::<Vec<Option<u32>>>();
+    (0..entries.len())
+        .filter(|x| scores[*x].is_some())
+        .max_by(|a, b| {
+            scores[*a]
+                .cmp(&scores[*b])
+                .then(entries[*a].1.cmp(&entries[*b].1))
+                // Earlier entries win ties
+                .then(b.cmp(a))
+        })
+}
+
+fn parse_zipped_list(data: &[u8]) -> Result<Vec<SearchResult>, &'static str>
+{
+    // PkZip panics on archives without a central directory, such as a cut off transfer
+    if !PkZip::is_complete(data)
+    {
+        return Err("Search result archive is incomplete");
+    }
+    let pkzip_files = PkZip::new(data).get_files();
+    let entries = pkzip_files
+        .iter()
+        .map(|x| (x.file_name.as_str(), x.uncompressed_size))
+        .collect::<Vec<(&str, u32)>>();
+    let list_file = match pick_results_file(&entries)
+    {
+        Some(t) => &pkzip_files[t],
+        None => return Err("No results file found in the search result archive"),
+    };
+    let decompressed_data = list_file.decompress()?;
+    Ok(parse_result_text(&String::from_utf8_lossy(
+        &decompressed_data,
+    )))
+}
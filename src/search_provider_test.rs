@@ -0,0 +1,172 @@
+use crate::search_provider::{
+    looks_like_text, parse_result_list, parse_result_text, pick_results_file, ResultDelivery,
+    SearchDialect,
+};
+
+/// A zip archive with every entry stored uncompressed.
+fn stored_zip(entries: &[(&str, &str)]) -> Vec<u8>
+{
+    let mut data: Vec<u8> = Vec::new();
+    let mut central_directory: Vec<u8> = Vec::new();
+    for (name, contents) in entries.iter()
+    {
+        let offset = data.len() as u32;
+        let size = (contents.len() as u32).to_le_bytes();
+        let name_length = (name.len() as u16).to_le_bytes();
+        // Version needed, flags, stored, time, date and a CRC nobody checks
+        let common = [20u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend([0x50, 0x4b, 0x03, 0x04]);
+        data.extend(common);
+        data.extend(size);
+        data.extend(size);
+        data.extend(name_length);
+        data.extend([0, 0]);
+        data.extend(name.as_bytes());
+        data.extend(contents.as_bytes());
+
+        central_directory.extend([0x50, 0x4b, 0x01, 0x02, 20, 0]);
+        central_directory.extend(common);
+        central_directory.extend(size);
+        central_directory.extend(size);
+        central_directory.extend(name_length);
+        // Extra field, comment, disk, internal and external attributes
+        central_directory.extend([0; 12]);
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(name.as_bytes());
+    }
+    let central_directory_offset = (data.len() as u32).to_le_bytes();
+    let count = (entries.len() as u16).to_le_bytes();
+    let central_directory_size = (central_directory.len() as u32).to_le_bytes();
+    data.extend(central_directory);
+    data.extend([0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0]);
+    data.extend(count);
+    data.extend(count);
+    data.extend(central_directory_size);
+    data.extend(central_directory_offset);
+    data.extend([0, 0]);
+    data
+}
+
+#[test]
+fn search_command_test()
+{
+    assert_eq!(
+        "@search the hobbit",
+        SearchDialect::Search
+            .provider()
+            .search_command(" the hobbit ")
+    );
+    assert_eq!(
+        "!search dune",
+        SearchDialect::BangSearch.provider().search_command("dune")
+    );
+    assert_eq!(
+        "@find dune",
+        SearchDialect::Find.provider().search_command("dune")
+    );
+    assert_eq!(
+        "@seek dune",
+        SearchDialect::Seek.provider().search_command("dune")
+    );
+    for dialect in [
+        SearchDialect::Search,
+        SearchDialect::BangSearch,
+        SearchDialect::Find,
+        SearchDialect::Seek,
+    ]
+    {
+        assert_eq!(dialect, SearchDialect::from_name(dialect.as_str()).unwrap());
+    }
+    assert!(SearchDialect::from_name("@locate").is_err());
+}
+#[test]
+fn text_list_test()
+{
+    let list = "Search results for \"dune\"\r\n\r\n!DV8 Herbert, Frank - Dune.mobi ::INFO:: 800KB\r\n!Bsk Herbert, Frank - Dune.epub\n";
+    let results = parse_result_text(list);
+    assert_eq!(2, results.len());
+    assert_eq!("DV8", results[0].bot);
+    assert_eq!("Bsk", results[1].bot);
+
+    let provider = SearchDialect::Seek.provider();
+    assert_eq!(ResultDelivery::TextList, provider.delivery());
+    assert_eq!(2, provider.parse_list(list.as_bytes()).unwrap().len());
+    // Recognized by content, whatever the dialect promised
+    assert_eq!(
+        2,
+        SearchDialect::Search
+            .provider()
+            .parse_list(list.as_bytes())
+            .unwrap()
+            .len()
+    );
+}
+#[test]
+fn notice_results_test()
+{
+    let provider = SearchDialect::Find.provider();
+    assert_eq!(ResultDelivery::Notices, provider.delivery());
+    let result = provider
+        .parse_notice(
+            "DV8",
+            "\u{2}!DV8\u{2} Herbert, Frank - Dune.mobi ::INFO:: 800KB",
+        )
+        .unwrap();
+    assert_eq!("Dune", result.title);
+    assert_eq!(Some(800 * 1024), result.size_bytes);
+    // Chatter, and lines claiming to be from another bot, are not results
+    assert!(provider
+        .parse_notice("DV8", "Found 3 files matching dune")
+        .is_none());
+    assert!(provider
+        .parse_notice("DV8", "!Bsk Herbert, Frank - Dune.epub")
+        .is_none());
+    assert!(SearchDialect::Search
+        .provider()
+        .parse_notice("DV8", "!DV8 Herbert, Frank - Dune.mobi")
+        .is_none());
+}
+#[test]
+fn result_archive_test()
+{
+    let list =
+        "!DV8 Herbert, Frank - Dune.mobi ::INFO:: 800KB\r\n!Bsk Herbert, Frank - Dune.epub\r\n";
+    let single = stored_zip(&[("SearchBot_results_for__dune.txt", list)]);
+    assert_eq!(2, parse_result_list(&single).unwrap().len());
+
+    let with_readme = stored_zip(&[
+        ("README.txt", "!Ignore - This is not a result.epub"),
+        ("SearchBot_results_for__dune.txt", list),
+    ]);
+    let results = parse_result_list(&with_readme).unwrap();
+    assert_eq!(2, results.len());
+    assert_eq!("DV8", results[0].bot);
+
+    // Cut off before the central directory
+    assert!(parse_result_list(&single[..40]).is_err());
+    assert!(parse_result_list(&[0x1f, 0x8b, 8, 0, 0, 0]).is_err());
+    assert!(parse_result_list(&[0, 1, 2, 3, 0xff]).is_err());
+    assert!(looks_like_text("\u{2}!DV8\u{2} Dune.mobi\r\n".as_bytes()));
+}
+#[test]
+fn pick_results_file_test()
+{
+    assert_eq!(Some(0), pick_results_file(&[("list.dat", 10)]));
+    assert_eq!(
+        Some(1),
+        pick_results_file(&[("readme.txt", 900), ("dune_results.txt", 100)])
+    );
+    assert_eq!(
+        Some(2),
+        pick_results_file(&[
+            ("lists/", 0),
+            ("a.txt", 100),
+            ("b.txt", 300),
+            ("c.nfo", 500)
+        ])
+    );
+    assert_eq!(
+        None,
+        pick_results_file(&[("README", 10), ("about.txt", 10)])
+    );
+}
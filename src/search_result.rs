@@ -0,0 +1,363 @@
+/// Words that put a volume number after them, as in `Dune Book 1` or `(Vol. 2)`.
+pub const VOLUME_WORDS: [&str; 7] = ["book", "vol", "volume", "part", "no", "nr", "tome"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookFormat
+{
+    Epub,
+    Mobi,
+    Azw3,
+    Azw,
+    Pdf,
+    Txt,
+    Rtf,
+    Doc,
+    Lit,
+    Fb2,
+    Djvu,
+    Cbr,
+    Cbz,
+    Rar,
+    Zip,
+    Other(String),
+    Unknown,
+}
+
+impl BookFormat
+{
+    pub fn from_extension(extension: Option<&str>) -> BookFormat
+    {
+        let extension = match extension
+        {
+            Some(t) => t.to_lowercase(),
+            None => return BookFormat::Unknown,
+        };
+        match extension.as_str()
+        {
+            "epub" => BookFormat::Epub,
+            "mobi" | "prc" => BookFormat::Mobi,
+            "azw3" | "kf8" => BookFormat::Azw3,
+            "azw" => BookFormat::Azw,
+            "pdf" => BookFormat::Pdf,
+            "txt" => BookFormat::Txt,
+            "rtf" => BookFormat::Rtf,
+            "doc" | "docx" => BookFormat::Doc,
+            "lit" => BookFormat::Lit,
+            "fb2" => BookFormat::Fb2,
+            "djvu" => BookFormat::Djvu,
+            "cbr" => BookFormat::Cbr,
+            "cbz" => BookFormat::Cbz,
+            "rar" => BookFormat::Rar,
+            "zip" => BookFormat::Zip,
+            _ => BookFormat::Other(extension),
+        }
+    }
+    pub fn as_str(&self) -> &str
+    {
+        match self
+        {
+            BookFormat::Epub => "epub",
+            BookFormat::Mobi => "mobi",
+            BookFormat::Azw3 => "azw3",
+            BookFormat::Azw => "azw",
+            BookFormat::Pdf => "pdf",
+            BookFormat::Txt => "txt",
+            BookFormat::Rtf => "rtf",
+            BookFormat::Doc => "doc",
+            BookFormat::Lit => "lit",
+            BookFormat::Fb2 => "fb2",
+            BookFormat::Djvu => "djvu",
+            BookFormat::Cbr => "cbr",
+            BookFormat::Cbz => "cbz",
+            BookFormat::Rar => "rar",
+            BookFormat::Zip => "zip",
+            BookFormat::Other(t) => t.as_str(),
+            BookFormat::Unknown => "unknown",
+        }
+    }
+}
+
+/// One `!bot Author - Title.ext ::INFO:: 1.2MB` line from a search bot's result list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult
+{
+    pub bot: String,
+    pub author: String,
+    pub title: String,
+    pub series: Option<String>,
+    pub extension: Option<String>,
+    pub format: BookFormat,
+    pub size_bytes: Option<u64>,
+    /// The whole line, which is also what gets sent to the channel to request the file.
+    pub request_line: String,
+    /// `::KEY:: value` markers other than INFO and tags like `(retail)`, lowercase keys.
+    pub metadata: Vec<(String, String)>,
+}
+
+impl SearchResult
+{
+    pub fn parse(line: &str) -> Result<SearchResult, &'static str>
+    {
+        let request_line = line.trim();
+        if !request_line.starts_with('!')
+        {
+            return Err("Search result lines start with !");
+        }
+        let (bot, rest) = match request_line[1..].split_once(char::is_whitespace)
+        {
+            Some((bot, rest)) => (bot.to_string(), rest.trim()),
+            None => return Err("Search result line has no file name"),
+        };
+        if bot.is_empty()
+        {
+            return Err("Search result line has no bot name");
+        }
+
+        let (file_name, markers) = split_markers(rest);
+        if file_name.is_empty()
+        {
+            return Err("Search result line has no file name");
+        }
+        let mut metadata: Vec<(String, String)> = Vec::new();
+        let mut size_bytes = None;
+        for (key, value) in markers
+        {
+            if key == "info"
+            {
+                size_bytes = parse_size(&value);
+            }
+            else
+            {
+                metadata.push((key, value));
+            }
+        }
+
+        let (stem, extension) = split_extension(&file_name);
+        let (author, title_with_series) = split_author(&stem);
+        let (title, series, tags) = split_series_and_tags(&title_with_series);
+        metadata.extend(tags.into_iter().map(|x| ("tag".to_string(), x)));
+
+        Ok(SearchResult {
+            bot,
+            author,
+            title,
+            series,
+            format: BookFormat::from_extension(extension.as_deref()),
+            extension,
+            size_bytes,
+            request_line: request_line.to_string(),
+            metadata,
+        })
+    }
+    /// The filename part of the request line, which is what the bot names the DCC offer.
+    pub fn requested_file(&self) -> String
+    {
+        let without_trigger = match self.request_line.split_once(' ')
+        {
+            Some((_, rest)) => rest,
+            None => "",
+        };
+        split_markers(without_trigger).0
+    }
+    pub fn get_metadata(&self, key: &str) -> Option<&str>
+    {
+        self.metadata
+            .iter()
+            .find(|(x, _)| x == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Splits `file ::INFO:: 1.2MB ::HASH:: abc` into the file and its lowercase keyed markers.
+fn split_markers(text: &str) -> (String, Vec<(String, String)>)
+{
+    let mut parts = text.split("::");
+    let file_name = parts.next().unwrap_or("").trim().to_string();
+    let mut markers = Vec::new();
+    while let Some(key) = parts.next()
+    {
+        let value = parts.next().unwrap_or("").trim().to_string();
+        markers.push((key.trim().to_lowercase(), value));
+    }
+    (file_name, markers)
+}
+
+fn split_extension(file_name: &str) -> (String, Option<String>)
+{
+    match file_name.rsplit_once('.')
+    {
+        Some((stem, extension))
+            if !stem.is_empty()
+                && (1..=5).contains(&extension.len())
+                && extension.chars().all(|x| x.is_ascii_alphanumeric())
+                && extension.chars().any(|x| x.is_ascii_alphabetic()) =>
+        {
+            (stem.to_string(), Some(extension.to_lowercase()))
+        }
+        _ => (file_name.to_string(), None),
+    }
+}
+
+/// `Author - Title`, or `Author_Name-Title_Words` from bots that replace spaces.
+fn split_author(stem: &str) -> (String, String)
+{
+    if let Some((author, title)) = stem.split_once(" - ")
+    {
+        return (clean_text(author), title.trim().to_string());
+    }
+    if !stem.contains(' ')
+    {
+        if let Some((author, title)) = stem.split_once('-')
+        {
+            if !author.is_empty() && !title.is_empty()
+            {
+                return (clean_text(author), title.replace('_', " "));
+            }
+        }
+    }
+    (String::new(), stem.to_string())
+}
+
+/// Pulls the series out of `Series 01 - Title` or `Title [Series 01]`, and `(retail)` style
+/// tags out of the title.
+fn split_series_and_tags(text: &str) -> (String, Option<String>, Vec<String>)
+{
+    let mut series = None;
+    let mut tags = Vec::new();
+    let mut title = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(x) = chars.next()
+    {
+        let closing = match x
+        {
+            '[' => ']',
+            '(' => ')',
+            '{' => '}',
+            _ =>
+            {
+                title.push(x);
+                continue;
+            }
+        };
+        let mut inner = String::new();
+        for y in chars.by_ref()
+        {
+            if y == closing
+            {
+                break;
+            }
+            inner.push(y);
+        }
+        let inner = clean_text(&inner);
+        if inner.is_empty()
+        {
+            continue;
+        }
+        if series.is_none() && looks_like_series(&inner)
+        {
+            series = Some(inner);
+        }
+        else
+        {
+            tags.push(inner);
+        }
+    }
+
+    if let Some((first, second)) = title.clone().split_once(" - ")
+    {
+        if series.is_none() && looks_like_series(first.trim())
+        {
+            series = Some(clean_text(first));
+            title = second.to_string();
+        }
+        else if series.is_some() && first.trim().is_empty()
+        {
+            title = second.to_string();
+        }
+    }
+    (clean_text(&title), series, tags)
+}
+
+/// A series marker ends in a number that is marked as a volume: `Middle-earth 01`,
+/// `Discworld #3`, `Dune Book 1`. A bare number is not enough, `Fahrenheit 451` is a title.
+fn looks_like_series(text: &str) -> bool
+{
+    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let (last, name) = match words.split_last()
+    {
+        Some((last, name)) if !name.is_empty() => (*last, name),
+        _ => return false,
+    };
+    let is_number = |x: &str| {
+        x.chars().next().is_some_and(|y| y.is_ascii_digit())
+            && x.chars().all(|y| y.is_ascii_digit() || y == '.')
+    };
+    if let Some(number) = last.strip_prefix('#')
+    {
+        return is_number(number);
+    }
+    if !is_number(last)
+    {
+        return false;
+    }
+    // `Book 1` on its own names no series
+    let after_volume_word = name.len() > 1 && is_volume_word(name[name.len() - 1]);
+    let zero_padded = last.len() > 1 && last.starts_with('0');
+    after_volume_word || zero_padded
+}
+
+/// Whether `word` is one of the [`VOLUME_WORDS`], ignoring case and an abbreviating dot.
+pub fn is_volume_word(word: &str) -> bool
+{
+    VOLUME_WORDS.contains(&word.trim_end_matches('.').to_lowercase().as_str())
+}
+
+fn clean_text(text: &str) -> String
+{
+    text.replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .trim_matches(|x: char| x == '-' || x == ',' || x.is_whitespace())
+        .to_string()
+}
+
+/// Parses sizes like `1.2MB`, `512.5 KB`, `1,024KB`, `2 MiB` or a plain byte count.
+pub fn parse_size(text: &str) -> Option<u64>
+{
+    let text = text.split_whitespace().take(2).collect::<String>();
+    let number_length = text
+        .find(|x: char| !(x.is_ascii_digit() || x == '.' || x == ','))
+        .unwrap_or(text.len());
+    let number = text[..number_length].replace(',', "").parse::<f64>().ok()?;
+    let multiplier: f64 = match text[number_length..].to_lowercase().as_str()
+    {
+        "" | "b" | "bytes" => 1.0,
+        "k" | "kb" | "kib" => 1024.0,
+        "m" | "mb" | "mib" => 1024.0 * 1024.0,
+        "g" | "gb" | "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((number * multiplier).round() as u64)
+}
+
+/// Formats a byte count the way search bots list sizes, e.g. `1.2MB`.
+pub fn format_size(bytes: u64) -> String
+{
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len()
+    {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0
+    {
+        format!("{}B", bytes)
+    }
+    else
+    {
+        format!("{:.1}{}", size, units[unit])
+    }
+}
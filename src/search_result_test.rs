@@ -0,0 +1,146 @@
+use crate::search_result::{parse_size, BookFormat, SearchResult};
+
+#[test]
+fn parse_basic_line_test()
+{
+    let result =
+        SearchResult::parse("!Bsk Tolkien, J.R.R - The Hobbit.epub  ::INFO:: 1.2MB").unwrap();
+    assert_eq!("Bsk", result.bot);
+    assert_eq!("Tolkien, J.R.R", result.author);
+    assert_eq!("The Hobbit", result.title);
+    assert_eq!(None, result.series);
+    assert_eq!(Some("epub".to_string()), result.extension);
+    assert_eq!(BookFormat::Epub, result.format);
+    assert_eq!(Some(1258291), result.size_bytes);
+    assert_eq!("Tolkien, J.R.R - The Hobbit.epub", result.requested_file());
+}
+#[test]
+fn parse_keeps_request_line_test()
+{
+    let line = "!DV8 Frank Herbert - Dune (retail).mobi ::INFO:: 812.4KB\r";
+    let result = SearchResult::parse(line).unwrap();
+    assert_eq!(line.trim(), result.request_line);
+    assert_eq!("Dune", result.title);
+    assert_eq!(BookFormat::Mobi, result.format);
+    assert_eq!(Some("retail"), result.get_metadata("tag"));
+}
+#[test]
+fn parse_series_test()
+{
+    let result = SearchResult::parse(
+        "!Oatmeal J.R.R. Tolkien - [Middle-earth 01] - The Hobbit.azw3 ::INFO:: 900KB",
+    )
+    .unwrap();
+    assert_eq!("J.R.R. Tolkien", result.author);
+    assert_eq!("The Hobbit", result.title);
+    assert_eq!(Some("Middle-earth 01".to_string()), result.series);
+    assert_eq!(BookFormat::Azw3, result.format);
+
+    let result = SearchResult::parse(
+        "!Oatmeal Terry Pratchett - Discworld 03 - Equal Rites.epub ::INFO:: 300KB",
+    )
+    .unwrap();
+    assert_eq!("Equal Rites", result.title);
+    assert_eq!(Some("Discworld 03".to_string()), result.series);
+
+    let result = SearchResult::parse(
+        "!LawdyServer Pratchett, Terry - Mort (Discworld #4) (v5.0).epub ::INFO:: 400KB",
+    )
+    .unwrap();
+    assert_eq!("Mort", result.title);
+    assert_eq!(Some("Discworld #4".to_string()), result.series);
+    assert_eq!(Some("v5.0"), result.get_metadata("tag"));
+
+    let result =
+        SearchResult::parse("!Bsk Isaac Asimov - Foundation Book 1 - Foundation.epub").unwrap();
+    assert_eq!("Foundation", result.title);
+    assert_eq!(Some("Foundation Book 1".to_string()), result.series);
+}
+#[test]
+// A number is only a volume when something marks it as one
+fn parse_title_with_number_test()
+{
+    let result = SearchResult::parse("!Bsk Ray Bradbury - Fahrenheit 451 - A Novel.epub").unwrap();
+    assert_eq!("Fahrenheit 451 - A Novel", result.title);
+    assert_eq!(None, result.series);
+
+    let result = SearchResult::parse("!Bsk Joseph Heller - Catch 22 - A Novel.epub").unwrap();
+    assert_eq!("Catch 22 - A Novel", result.title);
+    assert_eq!(None, result.series);
+
+    let result = SearchResult::parse("!Bsk Stephen King - Book 1 - The Gunslinger.epub").unwrap();
+    assert_eq!(None, result.series);
+}
+#[test]
+fn parse_line_without_author_separator_test()
+{
+    let result = SearchResult::parse("!shytot The Hobbit.epub ::INFO:: 300KB").unwrap();
+    assert_eq!("shytot", result.bot);
+    assert_eq!("", result.author);
+    assert_eq!("The Hobbit", result.title);
+    assert_eq!(BookFormat::Epub, result.format);
+
+    let result =
+        SearchResult::parse("!Dumbledore Tolkien_JRR-The_Hobbit.pdf ::INFO:: 2MB").unwrap();
+    assert_eq!("Tolkien JRR", result.author);
+    assert_eq!("The Hobbit", result.title);
+    assert_eq!(BookFormat::Pdf, result.format);
+
+    // Hyphenated titles with spaces are not split on the bare hyphen
+    let result = SearchResult::parse("!Bsk Spider-Man Tales.cbz ::INFO:: 20MB").unwrap();
+    assert_eq!("", result.author);
+    assert_eq!("Spider-Man Tales", result.title);
+}
+#[test]
+fn parse_archive_and_missing_extension_test()
+{
+    let result =
+        SearchResult::parse("!Bsk Asimov, Isaac - Foundation (epub).rar ::INFO:: 1,024 KB")
+            .unwrap();
+    assert_eq!(BookFormat::Rar, result.format);
+    assert_eq!("Foundation", result.title);
+    assert_eq!(Some(1048576), result.size_bytes);
+    assert_eq!(Some("epub"), result.get_metadata("tag"));
+
+    let result = SearchResult::parse("!Bsk Isaac Asimov - I, Robot ::INFO:: 1MB").unwrap();
+    assert_eq!(None, result.extension);
+    assert_eq!(BookFormat::Unknown, result.format);
+    assert_eq!("I, Robot", result.title);
+
+    let result = SearchResult::parse("!Bsk Author - Title.djvu.lzh").unwrap();
+    assert_eq!(BookFormat::Other("lzh".to_string()), result.format);
+    assert_eq!(None, result.size_bytes);
+}
+#[test]
+fn parse_extra_markers_test()
+{
+    let result = SearchResult::parse(
+        "!Pondering42 Le Guin, Ursula K - The Dispossessed.epub ::INFO:: 1.05MB ::HASH:: 0a1b2c3d",
+    )
+    .unwrap();
+    assert_eq!(Some(1101005), result.size_bytes);
+    assert_eq!(Some("0a1b2c3d"), result.get_metadata("hash"));
+    assert_eq!(
+        "Le Guin, Ursula K - The Dispossessed.epub",
+        result.requested_file()
+    );
+}
+#[test]
+fn parse_rejects_non_result_lines_test()
+{
+    assert!(SearchResult::parse("Search results for: hobbit").is_err());
+    assert!(SearchResult::parse("").is_err());
+    assert!(SearchResult::parse("!Bsk").is_err());
+    assert!(SearchResult::parse("! The Hobbit.epub").is_err());
+    assert!(SearchResult::parse("!Bsk ::INFO:: 1MB").is_err());
+}
+#[test]
+fn parse_size_test()
+{
+    assert_eq!(Some(512), parse_size("512"));
+    assert_eq!(Some(1536), parse_size("1.5KB"));
+    assert_eq!(Some(2097152), parse_size("2 MiB"));
+    assert_eq!(Some(1073741824), parse_size("1GB"));
+    assert_eq!(None, parse_size("big"));
+    assert_eq!(None, parse_size("12 parsecs"));
+}
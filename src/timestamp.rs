@@ -0,0 +1,96 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC calendar date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime
+{
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    /// Days since the epoch, used to find the weekday.
+    pub days: i64,
+}
+
+impl DateTime
+{
+    pub fn from_unix_seconds(seconds: u64) -> Self
+    {
+        let days = (seconds / 86400) as i64;
+        let seconds_of_day = (seconds % 86400) as u32;
+        // Howard Hinnant's civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10
+        {
+            month_index + 3
+        }
+        else
+        {
+            month_index - 9
+        } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds_of_day / 3600,
+            minute: seconds_of_day % 3600 / 60,
+            second: seconds_of_day % 60,
+            days,
+        }
+    }
+    pub fn weekday(&self) -> &'static str
+    {
+        WEEKDAYS[self.days.rem_euclid(7) as usize]
+    }
+    pub fn month_name(&self) -> &'static str
+    {
+        MONTHS[(self.month - 1) as usize]
+    }
+}
+
+/// `Mon Oct 19 14:03:09 2026 UTC`, the ctime layout most clients answer CTCP TIME with.
+pub fn format_ctcp_time(unix_seconds: u64) -> String
+{
+    let time = DateTime::from_unix_seconds(unix_seconds);
+    format!(
+        "{} {} {:02} {:02}:{:02}:{:02} {} UTC",
+        time.weekday(),
+        time.month_name(),
+        time.day,
+        time.hour,
+        time.minute,
+        time.second,
+        time.year
+    )
+}
+
+/// `2026-10-19 14:03:09 UTC`, for logs that are read and sorted by people.
+pub fn format_log_time(unix_seconds: u64) -> String
+{
+    let time = DateTime::from_unix_seconds(unix_seconds);
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    )
+}
+
+/// Seconds since the epoch, zero for times before it.
+pub fn unix_seconds(time: SystemTime) -> u64
+{
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
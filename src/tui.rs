@@ -0,0 +1,829 @@
+use crate::download_queue::DownloadState;
+use crate::result_browser::{ResultBrowser, SortKey};
+use crate::search_result::{format_size, BookFormat, SearchResult};
+use crate::works::Work;
+use std::collections::VecDeque;
+use std::io::{stdout, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+const LOG_LIMIT: usize = 500;
+const FORMAT_FILTERS: [Option<BookFormat>; 5] = [
+    None,
+    Some(BookFormat::Epub),
+    Some(BookFormat::Mobi),
+    Some(BookFormat::Azw3),
+    Some(BookFormat::Pdf),
+];
+
+/// A line for the log pane, either our own status output or a raw line from the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogLine
+{
+    Status(String),
+    Irc(String),
+}
+
+/// While the TUI owns the terminal, output is collected here instead of printed.
+static CAPTURED: Mutex<Option<Vec<LogLine>>> = Mutex::new(None);
+
+pub fn capture_output(enabled: bool)
+{
+    *CAPTURED.lock().unwrap() = if enabled { Some(Vec::new()) } else { None };
+}
+
+/// Set while stdout carries exported results, so status messages cannot corrupt them.
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn report_to_stderr(enabled: bool)
+{
+    STATUS_TO_STDERR.store(enabled, Ordering::SeqCst);
+}
+
+/// Prints a status message, or hands it to the log pane when the TUI is running.
+pub fn report(message: &str)
+{
+    match CAPTURED.lock().unwrap().as_mut()
+    {
+        Some(t) => t.push(LogLine::Status(message.to_string())),
+        None if STATUS_TO_STDERR.load(Ordering::SeqCst) => eprintln!("{}", message),
+        None => println!("{}", message),
+    }
+}
+
+/// Records a raw IRC line for the log pane, ignored outside the TUI.
+pub fn log_irc_line(line: &str)
+{
+    if let Some(t) = CAPTURED.lock().unwrap().as_mut()
+    {
+        t.push(LogLine::Irc(line.to_string()));
+    }
+}
+
+pub fn take_output() -> Vec<LogLine>
+{
+    match CAPTURED.lock().unwrap().as_mut()
+    {
+        Some(t) => std::mem::take(t),
+        None => Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key
+{
+    Char(char),
+    Enter,
+    Backspace,
+    Tab,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    CtrlC,
+}
+
+/// Decodes bytes read from a terminal in raw mode, including ANSI arrow and paging keys.
+pub fn decode_keys(bytes: &[u8]) -> Vec<Key>
+{
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len()
+    {
+        let byte = bytes[i];
+        i += 1;
+        let key = match byte
+        {
+            0x1b if i < bytes.len() && (bytes[i] == b'[' || bytes[i] == b'O') =>
+            {
+                // CSI or SS3: parameters, then one final byte in 0x40..=0x7e
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len() && !(0x40..=0x7e).contains(&bytes[end])
+                {
+                    end += 1;
+                }
+                i = (end + 1).min(bytes.len());
+                match (&bytes[start..end.min(bytes.len())], bytes.get(end))
+                {
+                    ([], Some(b'A')) => Key::Up,
+                    ([], Some(b'B')) => Key::Down,
+                    ([], Some(b'C')) => Key::Right,
+                    ([], Some(b'D')) => Key::Left,
+                    ([b'5'], Some(b'~')) => Key::PageUp,
+                    ([b'6'], Some(b'~')) => Key::PageDown,
+                    _ => continue,
+                }
+            }
+            0x1b => Key::Esc,
+            0x03 => Key::CtrlC,
+            b'\r' | b'\n' => Key::Enter,
+            0x7f | 0x08 => Key::Backspace,
+            b'\t' => Key::Tab,
+            0x00..=0x1f => continue,
+            0x20..=0x7e => Key::Char(byte as char),
+            _ =>
+            {
+                let length = match byte
+                {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => continue,
+                };
+                let end = (i - 1 + length).min(bytes.len());
+                let decoded = std::str::from_utf8(&bytes[i - 1..end])
+                    .ok()
+                    .and_then(|x| x.chars().next());
+                i = end;
+                match decoded
+                {
+                    Some(t) => Key::Char(t),
+                    None => continue,
+                }
+            }
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+/// A screen's worth of characters, drawn by a [`Backend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame
+{
+    pub width: usize,
+    pub height: usize,
+    rows: Vec<Vec<char>>,
+}
+
+impl Frame
+{
+    pub fn new(width: usize, height: usize) -> Self
+    {
+        Frame {
+            width,
+            height,
+            rows: vec![vec![' '; width]; height],
+        }
+    }
+    /// Writes `text` starting at `row`, `column`, clipped to the frame.
+    pub fn put(&mut self, row: usize, column: usize, text: &str)
+    {
+        let row = match self.rows.get_mut(row)
+        {
+            Some(t) => t,
+            None => return,
+        };
+        for (cell, x) in row.iter_mut().skip(column).zip(text.chars())
+        {
+            *cell = if x.is_control() { ' ' } else { x };
+        }
+    }
+    /// A horizontal rule with a title, like `── Transfers ────`.
+    pub fn rule(&mut self, row: usize, title: &str)
+    {
+        let line = format!("── {} {}", title, "─".repeat(self.width));
+        self.put(row, 0, &line);
+    }
+    pub fn lines(&self) -> Vec<String>
+    {
+        self.rows
+            .iter()
+            .map(|x| x.iter().collect::<String>().trim_end().to_string())
+            .collect()
+    }
+}
+
+pub trait Backend
+{
+    /// Width and height in characters.
+    fn size(&self) -> (usize, usize);
+    fn draw(&mut self, frame: &Frame) -> Result<(), &'static str>;
+}
+
+/// Keeps every drawn frame so tests can check what the user would have seen.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct HeadlessBackend
+{
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<Vec<String>>,
+}
+
+#[cfg(test)]
+impl HeadlessBackend
+{
+    pub fn new(width: usize, height: usize) -> Self
+    {
+        HeadlessBackend {
+            width,
+            height,
+            frames: Vec::new(),
+        }
+    }
+    pub fn last_frame(&self) -> Option<&Vec<String>>
+    {
+        self.frames.last()
+    }
+}
+
+#[cfg(test)]
+impl Backend for HeadlessBackend
+{
+    fn size(&self) -> (usize, usize)
+    {
+        (self.width, self.height)
+    }
+    fn draw(&mut self, frame: &Frame) -> Result<(), &'static str>
+    {
+        self.frames.push(frame.lines());
+        Ok(())
+    }
+}
+
+/// The `stty` settings from before raw mode, while a `TerminalBackend` has the terminal.
+static SAVED_MODE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Leaves the alternate screen and raw mode if a `TerminalBackend` entered them. Besides
+/// dropping the backend, this is how a cancelled session puts the terminal back before it
+/// exits from another thread.
+pub fn restore_terminal()
+{
+    // Taken, so the terminal is only restored once
+    let saved_mode = SAVED_MODE.lock().unwrap().take();
+    if let Some(saved_mode) = saved_mode
+    {
+        print!("\u{1b}[?25h\u{1b}[?1049l");
+        let _ = stdout().flush();
+        let _ = stty(&[saved_mode.as_str()]);
+    }
+}
+
+/// Draws on the real terminal using `stty` for raw mode and ANSI escapes for output.
+#[derive(Debug)]
+pub struct TerminalBackend
+{
+    width: usize,
+    height: usize,
+}
+
+impl TerminalBackend
+{
+    /// Switches the terminal to raw mode and the alternate screen until dropped.
+    pub fn enter() -> Result<TerminalBackend, &'static str>
+    {
+        let saved_mode = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        *SAVED_MODE.lock().unwrap() = Some(saved_mode.trim().to_string());
+        let mut backend = TerminalBackend {
+            width: 80,
+            height: 24,
+        };
+        backend.refresh_size();
+        print!("\u{1b}[?1049h\u{1b}[?25l");
+        let _ = stdout().flush();
+        Ok(backend)
+    }
+    pub fn refresh_size(&mut self)
+    {
+        let size = stty(&["size"]).unwrap_or_default();
+        let mut size = size.split_whitespace().map(|x| x.parse::<usize>());
+        if let (Some(Ok(height)), Some(Ok(width))) = (size.next(), size.next())
+        {
+            if width > 0 && height > 0
+            {
+                self.width = width;
+                self.height = height;
+            }
+        }
+    }
+}
+
+impl Backend for TerminalBackend
+{
+    fn size(&self) -> (usize, usize)
+    {
+        (self.width, self.height)
+    }
+    fn draw(&mut self, frame: &Frame) -> Result<(), &'static str>
+    {
+        let mut out = String::from("\u{1b}[H");
+        for (i, line) in frame.lines().iter().enumerate()
+        {
+            if i > 0
+            {
+                out.push_str("\r\n");
+            }
+            out.push_str(line);
+            out.push_str("\u{1b}[K");
+        }
+        let mut stdout = stdout();
+        match stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+        {
+            Ok(_) => Ok(()),
+            Err(_e) => Err("Unable to draw to the terminal"),
+        }
+    }
+}
+
+impl Drop for TerminalBackend
+{
+    fn drop(&mut self)
+    {
+        restore_terminal();
+    }
+}
+
+fn stty(args: &[&str]) -> Result<String, &'static str>
+{
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output();
+    match output
+    {
+        Ok(t) if t.status.success() => Ok(String::from_utf8_lossy(&t.stdout).to_string()),
+        _ => Err("Unable to change terminal mode, is stdin a terminal?"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Focus
+{
+    Search,
+    Results,
+    Filter,
+}
+
+/// What the session should do in response to a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppAction
+{
+    None,
+    Search(String),
+    Download(Vec<Work>),
+    /// The user answered the prompt shown in the status line.
+    Answer(bool),
+    Quit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferView
+{
+    pub file: String,
+    pub bot: String,
+    pub state: DownloadState,
+    pub received: u64,
+    pub total: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct App
+{
+    pub focus: Focus,
+    pub search_input: String,
+    pub filter_input: String,
+    pub browser: ResultBrowser,
+    /// Position of the highlighted row among the visible results.
+    pub cursor: usize,
+    pub transfers: Vec<TransferView>,
+    pub log: VecDeque<String>,
+    pub status: String,
+    /// A yes or no question, which takes every key until answered.
+    pub prompt: Option<String>,
+    /// Details of one result, shown over the results table.
+    pub detail: Option<String>,
+}
+
+impl Default for App
+{
+    fn default() -> Self
+    {
+        App::new()
+    }
+}
+
+impl App
+{
+    pub fn new() -> Self
+    {
+        App {
+            focus: Focus::Search,
+            search_input: String::new(),
+            filter_input: String::new(),
+            browser: ResultBrowser::new(Vec::new(), 10),
+            cursor: 0,
+            transfers: Vec::new(),
+            log: VecDeque::new(),
+            status: "Type a title and press Enter to search.".to_string(),
+            prompt: None,
+            detail: None,
+        }
+    }
+    pub fn set_results(&mut self, results: Vec<SearchResult>)
+    {
+        self.status = format!("{} results.", results.len());
+        self.browser = ResultBrowser::new(results, 10);
+        self.filter_input.clear();
+        self.cursor = 0;
+        self.detail = None;
+        self.focus = Focus::Results;
+    }
+    pub fn push_log(&mut self, line: LogLine)
+    {
+        let line = match line
+        {
+            LogLine::Status(t) =>
+            {
+                self.status = t.clone();
+                format!("* {}", t)
+            }
+            LogLine::Irc(t) => t,
+        };
+        self.log.push_back(line);
+        while self.log.len() > LOG_LIMIT
+        {
+            self.log.pop_front();
+        }
+    }
+    fn visible_count(&self) -> usize
+    {
+        self.browser.visible().len()
+    }
+    fn move_cursor(&mut self, offset: isize)
+    {
+        let last = self.visible_count().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(offset).min(last);
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> AppAction
+    {
+        if key == Key::CtrlC
+        {
+            return AppAction::Quit;
+        }
+        if self.prompt.is_some()
+        {
+            let answer = match key
+            {
+                Key::Char('y') | Key::Char('Y') => true,
+                Key::Char('n') | Key::Char('N') | Key::Esc => false,
+                _ => return AppAction::None,
+            };
+            self.prompt = None;
+            return AppAction::Answer(answer);
+        }
+        if self.detail.is_some()
+        {
+            if matches!(key, Key::Esc | Key::Enter | Key::Char('d') | Key::Char('q'))
+            {
+                self.detail = None;
+            }
+            return AppAction::None;
+        }
+        match self.focus
+        {
+            Focus::Search => self.handle_search_key(key),
+            Focus::Filter => self.handle_filter_key(key),
+            Focus::Results => self.handle_results_key(key),
+        }
+    }
+    fn handle_search_key(&mut self, key: Key) -> AppAction
+    {
+        match key
+        {
+            Key::Char(t) => self.search_input.push(t),
+            Key::Backspace =>
+            {
+                self.search_input.pop();
+            }
+            Key::Esc => self.search_input.clear(),
+            Key::Tab => self.focus = Focus::Results,
+            Key::Enter if !self.search_input.trim().is_empty() =>
+            {
+                let query = self.search_input.trim().to_string();
+                self.status = format!("Searching for {}. Please wait...", query);
+                return AppAction::Search(query);
+            }
+            _ => (),
+        }
+        AppAction::None
+    }
+    fn handle_filter_key(&mut self, key: Key) -> AppAction
+    {
+        match key
+        {
+            Key::Char(t) => self.filter_input.push(t),
+            Key::Backspace =>
+            {
+                self.filter_input.pop();
+            }
+            Key::Enter | Key::Tab | Key::Esc =>
+            {
+                self.focus = Focus::Results;
+                return AppAction::None;
+            }
+            _ => return AppAction::None,
+        }
+        self.browser.filter_text(&self.filter_input);
+        self.move_cursor(0);
+        AppAction::None
+    }
+    fn handle_results_key(&mut self, key: Key) -> AppAction
+    {
+        match key
+        {
+            Key::Up | Key::Char('k') => self.move_cursor(-1),
+            Key::Down | Key::Char('j') => self.move_cursor(1),
+            Key::PageUp => self.move_cursor(-10),
+            Key::PageDown => self.move_cursor(10),
+            Key::Tab => self.focus = Focus::Search,
+            Key::Char('/') => self.focus = Focus::Filter,
+            Key::Char('s') =>
+            {
+                let next = match self.browser.sort_key
+                {
+                    SortKey::Listed => SortKey::Title,
+                    SortKey::Title => SortKey::Author,
+                    SortKey::Author => SortKey::Size,
+                    SortKey::Size => SortKey::Bot,
+                    SortKey::Bot => SortKey::Listed,
+                };
+                self.browser.sort_by(next);
+            }
+            Key::Char('r') => self.browser.sort_by(self.browser.sort_key),
+            Key::Char('f') =>
+            {
+                let current = FORMAT_FILTERS
+                    .iter()
+                    .position(|x| *x == self.browser.format_filter)
+                    .unwrap_or(0);
+                let next = FORMAT_FILTERS[(current + 1) % FORMAT_FILTERS.len()].clone();
+                self.browser.filter_format(next);
+                self.move_cursor(0);
+            }
+            Key::Char('g') =>
+            {
+                self.browser.set_grouped(!self.browser.grouped);
+                self.move_cursor(0);
+            }
+            Key::Char('d') => self.detail = self.browser.render_detail(self.cursor),
+            Key::Enter =>
+            {
+                if let Some(t) = self.browser.work(self.cursor)
+                {
+                    self.status = format!("Requesting {} from {}.", t.title, t.best().bot);
+                    return AppAction::Download(vec![t]);
+                }
+            }
+            Key::Char('q') | Key::Esc => return AppAction::Quit,
+            _ => (),
+        }
+        AppAction::None
+    }
+
+    pub fn draw(&self, backend: &mut dyn Backend) -> Result<(), &'static str>
+    {
+        let (width, height) = backend.size();
+        backend.draw(&self.render(width, height))
+    }
+    /// Lays out, top to bottom: help, search box, results, transfers, IRC log and status line.
+    pub fn render(&self, width: usize, height: usize) -> Frame
+    {
+        let mut frame = Frame::new(width, height);
+        frame.put(
+            0,
+            0,
+            "Tab: switch  Enter: search/download  s/r: sort/reverse  f: format  g: group  /: filter  d: details  q: quit",
+        );
+        let searching = self.focus == Focus::Search;
+        frame.put(
+            1,
+            0,
+            &format!(
+                "{}Search: {}{}",
+                marker_text(searching),
+                self.search_input,
+                if searching { "_" } else { "" }
+            ),
+        );
+
+        // Header, search box, three rules and the status line
+        let available = height.saturating_sub(6);
+        let results_height = (available / 2).max(1);
+        let transfers_height = (available / 4).max(1);
+        let log_height = available.saturating_sub(results_height + transfers_height);
+
+        let mut row = 2;
+        frame.rule(row, &self.results_title());
+        row += 1;
+        match &self.detail
+        {
+            Some(detail) =>
+            {
+                for (i, line) in detail.lines().take(results_height).enumerate()
+                {
+                    frame.put(row + i, 2, line);
+                }
+            }
+            None => self.render_results(&mut frame, row, results_height),
+        }
+        row += results_height;
+
+        frame.rule(row, "Transfers");
+        row += 1;
+        for (i, transfer) in self.transfers.iter().take(transfers_height).enumerate()
+        {
+            frame.put(row + i, 2, &render_transfer(transfer));
+        }
+        row += transfers_height;
+
+        frame.rule(row, "IRC log");
+        row += 1;
+        let skip = self.log.len().saturating_sub(log_height);
+        for (i, line) in self.log.iter().skip(skip).enumerate()
+        {
+            frame.put(row + i, 0, line);
+        }
+
+        let status = match &self.prompt
+        {
+            Some(t) => format!("{} (y/n)", t),
+            None => self.status.clone(),
+        };
+        frame.put(height.saturating_sub(1), 0, &status);
+        frame
+    }
+    fn results_title(&self) -> String
+    {
+        let mut title = format!(
+            "Results ({} of {}",
+            self.visible_count(),
+            self.browser.results.len()
+        );
+        if let Some(format) = &self.browser.format_filter
+        {
+            title.push_str(&format!(", {} only", format.as_str()));
+        }
+        if self.focus == Focus::Filter || !self.filter_input.is_empty()
+        {
+            let caret = if self.focus == Focus::Filter { "_" } else { "" };
+            title.push_str(&format!(", filter: {}{}", self.filter_input, caret));
+        }
+        title.push(')');
+        title
+    }
+    fn render_results(&self, frame: &mut Frame, top: usize, height: usize)
+    {
+        let width = frame.width;
+        let title_width = width.saturating_sub(4 + 2 + 24 + 6 + 9 + 14 + 5).max(10);
+        frame.put(
+            top,
+            2,
+            &format!(
+                "{:>4} {:<title_width$} {:<24} {:<6} {:>9} {}",
+                "#", "Title", "Author", "Format", "Size", "Bot"
+            ),
+        );
+        let rows = height.saturating_sub(1);
+        if rows == 0
+        {
+            return;
+        }
+        // Scroll just far enough to keep the highlighted row on screen
+        let first = (self.cursor + 1).saturating_sub(rows);
+        let results = self.browser.visible();
+        for (i, result) in results.iter().enumerate().skip(first).take(rows)
+        {
+            let highlighted = i == self.cursor && self.focus != Focus::Search;
+            let copies = self.browser.copy_count(i);
+            let bot = if copies > 1
+            {
+                format!("{} +{}", result.bot, copies - 1)
+            }
+            else
+            {
+                result.bot.clone()
+            };
+            frame.put(
+                top + 1 + i - first,
+                0,
+                &format!(
+                    "{}{:>4} {} {} {} {:>9} {}",
+                    marker_text(highlighted),
+                    i,
+                    clip(&result.title, title_width),
+                    clip(&result.author, 24),
+                    clip(result.format.as_str(), 6),
+                    result.size_bytes.map(format_size).unwrap_or_default(),
+                    bot
+                ),
+            );
+        }
+    }
+}
+
+fn marker_text(active: bool) -> &'static str
+{
+    if active
+    {
+        "> "
+    }
+    else
+    {
+        "  "
+    }
+}
+
+fn render_transfer(transfer: &TransferView) -> String
+{
+    let progress = match transfer.total
+    {
+        Some(total) if total > 0 =>
+        {
+            let done = transfer.received.min(total);
+            let filled = (done * 20 / total) as usize;
+            format!(
+                "[{}{}] {:>3}% {}/{}",
+                "#".repeat(filled),
+                "-".repeat(20 - filled),
+                done * 100 / total,
+                format_size(done),
+                format_size(total)
+            )
+        }
+        _ if transfer.received > 0 => format_size(transfer.received),
+        _ => String::new(),
+    };
+    format!(
+        "{:<12} {} from {}  {}",
+        transfer.state.as_str(),
+        transfer.file,
+        transfer.bot,
+        progress
+    )
+}
+
+/// Clips `text` to `width` terminal columns, marking a cut with `…`, and pads it with spaces to
+/// fill them. `format!` pads by characters, which misaligns columns once a title has wide
+/// characters in it.
+pub fn clip(text: &str, width: usize) -> String
+{
+    let mut ret_val = String::new();
+    let mut used = 0;
+    if text.chars().map(char_width).sum::<usize>() <= width
+    {
+        ret_val.push_str(text);
+        used = text.chars().map(char_width).sum::<usize>();
+    }
+    else if width > 0
+    {
+        for x in text.chars()
+        {
+            if used + char_width(x) > width - 1
+            {
+                break;
+            }
+            ret_val.push(x);
+            used += char_width(x);
+        }
+        ret_val.push('…');
+        used += 1;
+    }
+    ret_val.push_str(&" ".repeat(width.saturating_sub(used)));
+    ret_val
+}
+
+/// Terminal columns a character takes up: two for wide CJK characters and emoji, none for
+/// combining marks and zero-width characters.
+fn char_width(x: char) -> usize
+{
+    match x as u32
+    {
+        0x0300..=0x036f | 0x200b..=0x200f | 0xfe00..=0xfe0f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
@@ -0,0 +1,202 @@
+use crate::download_queue::DownloadState;
+use crate::search_result::SearchResult;
+use crate::tui::{
+    clip, decode_keys, App, AppAction, Focus, HeadlessBackend, Key, LogLine, TransferView,
+};
+
+fn type_text(app: &mut App, text: &str) -> AppAction
+{
+    let mut action = AppAction::None;
+    for x in text.chars()
+    {
+        action = app.handle_key(Key::Char(x));
+    }
+    action
+}
+
+fn results() -> Vec<SearchResult>
+{
+    [
+        "!Bsk Tolkien, J.R.R - The Hobbit.epub ::INFO:: 1.2MB",
+        "!DV8 Herbert, Frank - Dune.mobi ::INFO:: 800KB",
+        "!Oatmeal Asimov, Isaac - Foundation.pdf ::INFO:: 3MB",
+    ]
+    .iter()
+    .map(|x| SearchResult::parse(x).unwrap())
+    .collect()
+}
+
+#[test]
+fn decode_keys_test()
+{
+    assert_eq!(
+        vec![
+            Key::Char('a'),
+            Key::Enter,
+            Key::Backspace,
+            Key::Tab,
+            Key::CtrlC
+        ],
+        decode_keys(b"a\r\x7f\t\x03")
+    );
+    assert_eq!(
+        vec![Key::Up, Key::Down, Key::Right, Key::Left],
+        decode_keys(b"\x1b[A\x1b[B\x1bOC\x1b[D")
+    );
+    assert_eq!(
+        vec![Key::PageUp, Key::PageDown, Key::Esc],
+        decode_keys(b"\x1b[5~\x1b[6~\x1b")
+    );
+    // Unknown sequences are skipped whole, multi-byte characters decoded
+    assert_eq!(
+        vec![Key::Char('é'), Key::Char('x')],
+        decode_keys("\u{1b}[1;5Hé\u{1b}[15~x".as_bytes())
+    );
+}
+#[test]
+fn search_box_test()
+{
+    let mut app = App::new();
+    assert_eq!(AppAction::None, app.handle_key(Key::Enter));
+    type_text(&mut app, "the hobbitt");
+    app.handle_key(Key::Backspace);
+    assert_eq!(
+        AppAction::Search("the hobbit".to_string()),
+        app.handle_key(Key::Enter)
+    );
+
+    let mut backend = HeadlessBackend::new(100, 24);
+    app.draw(&mut backend).unwrap();
+    let frame = backend.last_frame().unwrap();
+    assert_eq!(24, frame.len());
+    assert_eq!("> Search: the hobbit_", frame[1]);
+    assert_eq!("Searching for the hobbit. Please wait...", frame[23]);
+}
+#[test]
+fn results_table_test()
+{
+    let mut app = App::new();
+    app.set_results(results());
+    assert_eq!(Focus::Results, app.focus);
+    app.handle_key(Key::Down);
+    app.handle_key(Key::Down);
+    app.handle_key(Key::Down);
+    assert_eq!(2, app.cursor);
+    app.handle_key(Key::Up);
+
+    let mut backend = HeadlessBackend::new(100, 24);
+    app.draw(&mut backend).unwrap();
+    let frame = backend.last_frame().unwrap();
+    assert!(frame[2].starts_with("── Results (3 of 3)"));
+    assert!(frame[4].starts_with("     0 The Hobbit"));
+    assert!(frame[5].starts_with(">    1 Dune"));
+    assert!(frame[5].contains("800.0KB"));
+    assert!(frame[5].ends_with("DV8"));
+
+    match app.handle_key(Key::Enter)
+    {
+        AppAction::Download(selected) => assert_eq!("Dune", selected[0].title),
+        t => panic!("Unexpected action {:?}", t),
+    }
+}
+#[test]
+fn results_sort_and_filter_test()
+{
+    let mut app = App::new();
+    app.set_results(results());
+    app.handle_key(Key::Char('s'));
+    assert_eq!("Dune", app.browser.get(0).unwrap().title);
+    app.handle_key(Key::Char('f'));
+    assert_eq!(
+        Some("The Hobbit"),
+        app.browser.get(0).map(|x| x.title.as_str())
+    );
+    app.handle_key(Key::Char('f'));
+    app.handle_key(Key::Char('f'));
+    app.handle_key(Key::Char('f'));
+    app.handle_key(Key::Char('f'));
+    assert_eq!(None, app.browser.format_filter);
+
+    app.handle_key(Key::Char('/'));
+    assert_eq!(Focus::Filter, app.focus);
+    type_text(&mut app, "asimov");
+    assert_eq!("Foundation", app.browser.get(0).unwrap().title);
+    assert!(app.browser.get(1).is_none());
+    app.handle_key(Key::Enter);
+    assert_eq!(Focus::Results, app.focus);
+
+    let mut backend = HeadlessBackend::new(100, 24);
+    app.draw(&mut backend).unwrap();
+    assert!(backend.last_frame().unwrap()[2].contains("(1 of 3, filter: asimov)"));
+}
+#[test]
+fn detail_and_prompt_test()
+{
+    let mut app = App::new();
+    app.set_results(results());
+    app.handle_key(Key::Char('d'));
+    let mut backend = HeadlessBackend::new(100, 24);
+    app.draw(&mut backend).unwrap();
+    assert!(backend.last_frame().unwrap()[3]
+        .contains("Request: !Bsk Tolkien, J.R.R - The Hobbit.epub ::INFO:: 1.2MB"));
+    // Keys other than closing the detail view are ignored
+    assert_eq!(AppAction::None, app.handle_key(Key::Enter));
+    assert!(app.detail.is_none());
+
+    app.prompt = Some("Accept Dune.mobi from DV8?".to_string());
+    assert_eq!(AppAction::None, app.handle_key(Key::Char('q')));
+    app.draw(&mut backend).unwrap();
+    assert_eq!(
+        "Accept Dune.mobi from DV8? (y/n)",
+        backend.last_frame().unwrap()[23]
+    );
+    assert_eq!(AppAction::Answer(true), app.handle_key(Key::Char('y')));
+    assert!(app.prompt.is_none());
+    assert_eq!(AppAction::Quit, app.handle_key(Key::Char('q')));
+}
+#[test]
+fn transfers_and_log_panes_test()
+{
+    let mut app = App::new();
+    app.transfers.push(TransferView {
+        file: "Dune.mobi".to_string(),
+        bot: "DV8".to_string(),
+        state: DownloadState::Transferring,
+        received: 512 * 1024,
+        total: Some(1024 * 1024),
+    });
+    app.push_log(LogLine::Irc(
+        ":server NOTICE * :Looking up your hostname".to_string(),
+    ));
+    app.push_log(LogLine::Status("Accepting Dune.mobi from DV8.".to_string()));
+
+    let mut backend = HeadlessBackend::new(100, 24);
+    app.draw(&mut backend).unwrap();
+    let frame = backend.last_frame().unwrap();
+    let transfers = frame
+        .iter()
+        .position(|x| x.starts_with("── Transfers"))
+        .unwrap();
+    assert_eq!(
+        "  transferring Dune.mobi from DV8  [##########----------]  50% 512.0KB/1.0MB",
+        frame[transfers + 1]
+    );
+    let log = frame
+        .iter()
+        .position(|x| x.starts_with("── IRC log"))
+        .unwrap();
+    assert_eq!(":server NOTICE * :Looking up your hostname", frame[log + 1]);
+    assert_eq!("* Accepting Dune.mobi from DV8.", frame[log + 2]);
+    assert_eq!("Accepting Dune.mobi from DV8.", frame[23]);
+}
+#[test]
+fn clip_test()
+{
+    assert_eq!("Dune  ", clip("Dune", 6));
+    assert_eq!("The H…", clip("The Hobbit", 6));
+    // Wide characters take two columns each
+    assert_eq!("三体 ", clip("三体", 5));
+    assert_eq!("三体…", clip("三体三部曲", 5));
+    assert_eq!("三… ", clip("三体三部曲", 4));
+    assert_eq!("", clip("Dune", 0));
+}
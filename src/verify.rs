@@ -0,0 +1,360 @@
+use crate::crc32::Crc32;
+use crate::epub::read_epub_entries;
+use crate::library::{file_book, plan_filing, BookMetadata};
+use crate::mobi::{check_records, read_mobi};
+use crate::pkzip::{CompressionMethod, PkZip};
+use crate::search_result::BookFormat;
+use crate::works::{normalize_author, normalize_title};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where `--quarantine` moves suspect downloads, inside the download directory.
+pub const QUARANTINE_DIRECTORY_NAME: &str = "quarantine";
+
+/// Something wrong with a finished download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem
+{
+    /// The contents are not in the format the file name claims, such as a `.txt` renamed to
+    /// `.epub`.
+    WrongFormat
+    {
+        claimed: BookFormat,
+        actual: BookFormat,
+    },
+    /// The file is cut off or corrupt.
+    Damaged(&'static str),
+    /// The book's own metadata names another book.
+    TitleMismatch
+    {
+        /// From the request line.
+        requested: String,
+        /// From the file itself.
+        found: String,
+    },
+    /// The book's own metadata credits another author.
+    AuthorMismatch
+    {
+        /// From the request line.
+        requested: String,
+        /// From the file itself.
+        found: String,
+    },
+}
+
+impl Problem
+{
+    /// Reads on from the file name, as in `Dune.epub is damaged: ...`.
+    pub fn describe(&self) -> String
+    {
+        match self
+        {
+            Problem::WrongFormat { claimed, actual } => format!(
+                "is {}, not {} as its name says",
+                format_name(actual),
+                format_name(claimed)
+            ),
+            Problem::Damaged(reason) => format!("is damaged: {}", reason),
+            Problem::TitleMismatch { requested, found } =>
+            {
+                format!("is titled \"{}\", not \"{}\"", found, requested)
+            }
+            Problem::AuthorMismatch { requested, found } =>
+            {
+                format!("is by {}, not {}", found, requested)
+            }
+        }
+    }
+}
+
+fn format_name(format: &BookFormat) -> String
+{
+    match format
+    {
+        BookFormat::Epub => "an epub".to_string(),
+        BookFormat::Mobi | BookFormat::Azw | BookFormat::Azw3 => "a MOBI book".to_string(),
+        BookFormat::Pdf => "a PDF".to_string(),
+        BookFormat::Txt => "plain text".to_string(),
+        BookFormat::Zip | BookFormat::Cbz => "a zip archive".to_string(),
+        BookFormat::Rar | BookFormat::Cbr => "a RAR archive".to_string(),
+        BookFormat::Unknown => "unrecognised data".to_string(),
+        x => format!("a {} file", x.as_str()),
+    }
+}
+
+/// The real format of a file, from its magic bytes rather than its name. MOBI, AZW and AZW3
+/// books all come out as `Mobi`, zip archives holding an epub as `Epub`.
+pub fn identify_format(data: &[u8]) -> BookFormat
+{
+    let head = &data[..data.len().min(1024)];
+    let starts_with = |x: &[u8]| data.starts_with(x);
+    if PkZip::data_is_pkzip(data)
+    {
+        // The epub spec puts an uncompressed `mimetype` entry first, but not every tool does
+        let is_epub = data.get(30..58) == Some(b"mimetypeapplication/epub+zip".as_slice())
+            || data.windows(22).any(|x| x == b"META-INF/container.xml");
+        return if is_epub
+        {
+            BookFormat::Epub
+        }
+        else
+        {
+            BookFormat::Zip
+        };
+    }
+    if data.get(60..68) == Some(b"BOOKMOBI".as_slice())
+    {
+        return BookFormat::Mobi;
+    }
+    if head.windows(5).any(|x| x == b"%PDF-")
+    {
+        return BookFormat::Pdf;
+    }
+    if starts_with(b"Rar!\x1a\x07")
+    {
+        return BookFormat::Rar;
+    }
+    if starts_with(b"AT&TFORM")
+    {
+        return BookFormat::Djvu;
+    }
+    if starts_with(b"ITOLITLS")
+    {
+        return BookFormat::Lit;
+    }
+    if starts_with(b"{\\rtf")
+    {
+        return BookFormat::Rtf;
+    }
+    if starts_with(&[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1])
+    {
+        return BookFormat::Doc;
+    }
+    if starts_with(b"7z\xbc\xaf\x27\x1c")
+    {
+        return BookFormat::Other("7z".to_string());
+    }
+    if starts_with(&[0x1f, 0x8b])
+    {
+        return BookFormat::Other("gz".to_string());
+    }
+    if data.is_empty() || !is_text(head)
+    {
+        return BookFormat::Unknown;
+    }
+    let text = String::from_utf8_lossy(head).to_lowercase();
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.contains("<fictionbook")
+    {
+        BookFormat::Fb2
+    }
+    else if text.starts_with("<!doctype html") || text.starts_with("<html")
+    {
+        BookFormat::Other("html".to_string())
+    }
+    else
+    {
+        BookFormat::Txt
+    }
+}
+
+/// Text has no control characters besides whitespace, form feeds and escapes.
+fn is_text(data: &[u8]) -> bool
+{
+    data.iter()
+        .all(|x| *x >= 0x20 || [b'\t', b'\n', b'\r', 0x0c, 0x1b].contains(x))
+}
+
+/// Whether contents identified as `actual` are fine for a file named as `claimed`.
+fn format_matches(claimed: &BookFormat, actual: &BookFormat) -> bool
+{
+    match claimed
+    {
+        BookFormat::Mobi | BookFormat::Azw | BookFormat::Azw3 => *actual == BookFormat::Mobi,
+        BookFormat::Zip | BookFormat::Cbz =>
+        {
+            *actual == BookFormat::Zip || *actual == BookFormat::Epub
+        }
+        BookFormat::Rar | BookFormat::Cbr => *actual == BookFormat::Rar,
+        BookFormat::Epub
+        | BookFormat::Pdf
+        | BookFormat::Txt
+        | BookFormat::Rtf
+        | BookFormat::Fb2
+        | BookFormat::Djvu
+        | BookFormat::Lit => claimed == actual,
+        // A .doc may be a zipped .docx, and other extensions cannot be checked
+        _ => true,
+    }
+}
+
+pub fn verify_file(path: &Path, requested: &BookMetadata) -> Result<Vec<Problem>, &'static str>
+{
+    let data = match fs::read(path)
+    {
+        Ok(t) => t,
+        Err(_e) => return Err("Unable to read the download to verify it"),
+    };
+    let extension = path.extension().map(|x| x.to_string_lossy().to_string());
+    Ok(verify_data(&data, extension.as_deref(), requested))
+}
+
+/// Checks that a download is in the format its extension claims, that it is intact, and that
+/// the title and author it carries are the requested ones. Empty when nothing is wrong.
+pub fn verify_data(data: &[u8], extension: Option<&str>, requested: &BookMetadata) -> Vec<Problem>
+{
+    let claimed = BookFormat::from_extension(extension);
+    let actual = identify_format(data);
+    if !format_matches(&claimed, &actual)
+    {
+        return vec![Problem::WrongFormat { claimed, actual }];
+    }
+    // The title and author the file carries, for the formats they can be read from
+    let checked = match actual
+    {
+        BookFormat::Epub => check_zip(data)
+            .and_then(|x| read_epub_entries(&x))
+            .map(|x| Some((x.title, x.authors.join(" & ")))),
+        BookFormat::Zip => check_zip(data).map(|_| None),
+        BookFormat::Mobi => read_mobi(data)
+            .and_then(|x| check_records(data).map(|_| x))
+            .map(|x| Some((x.title, x.authors.join(" & ")))),
+        BookFormat::Pdf => check_pdf(data).map(|_| None),
+        _ => Ok(None),
+    };
+    let (title, author) = match checked
+    {
+        Ok(Some(t)) => t,
+        Ok(None) => return Vec::new(),
+        Err(e) => return vec![Problem::Damaged(e)],
+    };
+    let mut problems = Vec::new();
+    if !titles_match(&requested.title, &title)
+    {
+        problems.push(Problem::TitleMismatch {
+            requested: requested.title.clone(),
+            found: title,
+        });
+    }
+    if !authors_match(&requested.author, &author)
+    {
+        problems.push(Problem::AuthorMismatch {
+            requested: requested.author.clone(),
+            found: author,
+        });
+    }
+    problems
+}
+
+/// Decompresses every entry and checks its CRC, so a cut off or corrupt archive is caught
+/// before it is filed. Returns the contents of the entries by name.
+fn check_zip(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, &'static str>
+{
+    if !PkZip::is_complete(data)
+    {
+        return Err("the archive is cut off");
+    }
+    let mut entries = Vec::new();
+    for file in PkZip::new(data).get_files()
+    {
+        let supported = matches!(
+            file.compression_method,
+            CompressionMethod::NoCompression | CompressionMethod::Deflated
+        );
+        // Methods there is no decoder for
+        if !supported
+        {
+            continue;
+        }
+        // Directories and empty files, decompress gives a single zero byte for those
+        if file.uncompressed_size == 0
+        {
+            entries.push((file.file_name, Vec::new()));
+            continue;
+        }
+        let contents = match file.decompress()
+        {
+            Ok(t) => t,
+            Err(_e) => return Err("an entry in the archive cannot be decompressed"),
+        };
+        let mut crc = Crc32::default();
+        crc.update(&contents);
+        if contents.len() != file.uncompressed_size as usize || crc.finish() != file.crc_32
+        {
+            return Err("an entry in the archive fails its CRC check");
+        }
+        entries.push((file.file_name, contents));
+    }
+    Ok(entries)
+}
+
+/// A complete PDF ends with `startxref`, the offset of its cross-reference table, and `%%EOF`.
+fn check_pdf(data: &[u8]) -> Result<(), &'static str>
+{
+    let tail = String::from_utf8_lossy(&data[data.len().saturating_sub(1024)..]);
+    if !tail.contains("%%EOF")
+    {
+        return Err("the PDF has no end of file marker, it was cut off");
+    }
+    let cross_reference = tail
+        .rfind("startxref")
+        .and_then(|x| tail[x + 9..].split_whitespace().next())
+        .and_then(|x| x.parse::<usize>().ok());
+    match cross_reference
+    {
+        Some(t) if t < data.len() => Ok(()),
+        _ => Err("the PDF's cross-reference table is missing"),
+    }
+}
+
+/// Whether two titles name the same book. Most words of the shorter title must be in the
+/// longer one, so subtitles and edition notes on either side do not matter.
+pub fn titles_match(requested: &str, found: &str) -> bool
+{
+    let requested = normalize_title(requested);
+    let found = normalize_title(found);
+    if requested.is_empty() || found.is_empty()
+    {
+        return true;
+    }
+    let requested_words = requested.split(' ').collect::<Vec<&str>>();
+    let found_words = found.split(' ').collect::<Vec<&str>>();
+    let shared = requested_words
+        .iter()
+        .filter(|x| found_words.contains(x))
+        .count();
+    shared * 2 > requested_words.len().min(found_words.len())
+}
+
+/// Whether two author names could be the same person. One name in common is enough, as
+/// initials and name order vary between bots and publishers.
+pub fn authors_match(requested: &str, found: &str) -> bool
+{
+    let requested = normalize_author(requested);
+    let found = normalize_author(found);
+    // calibre writes `Unknown` for books without an author
+    if requested.is_empty() || found.is_empty() || found == "unknown"
+    {
+        return true;
+    }
+    let found_words = found.split(' ').collect::<Vec<&str>>();
+    requested
+        .split(' ')
+        .any(|x| x.len() > 1 && found_words.contains(&x))
+}
+
+/// Moves a suspect download into the quarantine directory next to it, returning where it is
+/// now.
+pub fn quarantine(path: &Path) -> Result<PathBuf, &'static str>
+{
+    let directory = path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(QUARANTINE_DIRECTORY_NAME);
+    let file_name = match path.file_name()
+    {
+        Some(t) => PathBuf::from(t),
+        None => return Err("The download has no file name"),
+    };
+    file_book(path, &plan_filing(&directory, &file_name, path))
+}
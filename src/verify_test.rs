@@ -0,0 +1,252 @@
+use crate::crc32::Crc32;
+use crate::library::BookMetadata;
+use crate::search_result::BookFormat;
+use crate::verify::{
+    authors_match, identify_format, quarantine, titles_match, verify_data, Problem,
+    QUARANTINE_DIRECTORY_NAME,
+};
+use std::env;
+use std::fs;
+use std::process;
+
+const CONTAINER: &str = r##"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"##;
+
+const PACKAGE: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Dune</dc:title>
+    <dc:creator>Frank Herbert</dc:creator>
+  </metadata>
+</package>"##;
+
+const PDF: &[u8] = b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog >>\nendobj\nxref\n0 1\n\
+0000000000 65535 f \ntrailer\n<< /Root 1 0 R >>\nstartxref\n45\n%%EOF\n";
+
+/// A zip archive with every entry stored uncompressed and its real CRC.
+fn stored_zip(entries: &[(&str, &[u8])]) -> Vec<u8>
+{
+    let mut data: Vec<u8> = Vec::new();
+    let mut central_directory: Vec<u8> = Vec::new();
+    for (name, contents) in entries.iter()
+    {
+        let offset = data.len() as u32;
+        let mut crc = Crc32::default();
+        crc.update(contents);
+        let size = (contents.len() as u32).to_le_bytes();
+        let name_length = (name.len() as u16).to_le_bytes();
+        // Version needed, flags, stored, time and date
+        let common = [20u8, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend([0x50, 0x4b, 0x03, 0x04]);
+        data.extend(common);
+        data.extend(crc.finish().to_le_bytes());
+        data.extend(size);
+        data.extend(size);
+        data.extend(name_length);
+        data.extend([0, 0]);
+        data.extend(name.as_bytes());
+        data.extend(*contents);
+
+        central_directory.extend([0x50, 0x4b, 0x01, 0x02, 20, 0]);
+        central_directory.extend(common);
+        central_directory.extend(crc.finish().to_le_bytes());
+        central_directory.extend(size);
+        central_directory.extend(size);
+        central_directory.extend(name_length);
+        // Extra field, comment, disk, internal and external attributes
+        central_directory.extend([0; 12]);
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(name.as_bytes());
+    }
+    let central_directory_offset = (data.len() as u32).to_le_bytes();
+    let count = (entries.len() as u16).to_le_bytes();
+    let central_directory_size = (central_directory.len() as u32).to_le_bytes();
+    data.extend(central_directory);
+    data.extend([0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0]);
+    data.extend(count);
+    data.extend(count);
+    data.extend(central_directory_size);
+    data.extend(central_directory_offset);
+    data.extend([0, 0]);
+    data
+}
+
+fn epub() -> Vec<u8>
+{
+    stored_zip(&[
+        ("mimetype", b"application/epub+zip"),
+        ("META-INF/container.xml", CONTAINER.as_bytes()),
+        ("content.opf", PACKAGE.as_bytes()),
+    ])
+}
+
+fn requested(author: &str, title: &str) -> BookMetadata
+{
+    BookMetadata {
+        author: author.to_string(),
+        title: title.to_string(),
+        ..BookMetadata::default()
+    }
+}
+
+#[test]
+fn identify_format_test()
+{
+    assert_eq!(BookFormat::Epub, identify_format(&epub()));
+    assert_eq!(
+        BookFormat::Zip,
+        identify_format(&stored_zip(&[("dune.txt", b"Dune")]))
+    );
+    assert_eq!(BookFormat::Pdf, identify_format(PDF));
+    assert_eq!(BookFormat::Rar, identify_format(b"Rar!\x1a\x07\x01\x00"));
+    assert_eq!(BookFormat::Rtf, identify_format(b"{\\rtf1\\ansi Dune}"));
+    assert_eq!(
+        BookFormat::Fb2,
+        identify_format(b"<?xml version=\"1.0\"?>\n<FictionBook xmlns=\"\">")
+    );
+    assert_eq!(
+        BookFormat::Other("html".to_string()),
+        identify_format(b"\n<!DOCTYPE html><html></html>")
+    );
+    assert_eq!(
+        BookFormat::Txt,
+        identify_format(b"Dune\r\nby Frank Herbert\n")
+    );
+    assert_eq!(BookFormat::Unknown, identify_format(&[0, 1, 2, 3]));
+    assert_eq!(BookFormat::Unknown, identify_format(&[]));
+}
+#[test]
+fn verify_intact_download_test()
+{
+    let dune = requested("Frank Herbert", "Dune");
+    assert!(verify_data(&epub(), Some("epub"), &dune).is_empty());
+    assert!(verify_data(PDF, Some("pdf"), &dune).is_empty());
+    assert!(verify_data(b"Dune", Some("txt"), &dune).is_empty());
+    // An epub inside a .zip is fine, as are subtitles and initials
+    assert!(verify_data(&epub(), Some("zip"), &dune).is_empty());
+    let subtitled = requested("F. Herbert", "Dune (Dune Chronicles, Book 1)");
+    assert!(verify_data(&epub(), Some("epub"), &subtitled).is_empty());
+}
+#[test]
+fn verify_wrong_format_test()
+{
+    let dune = requested("Frank Herbert", "Dune");
+    assert_eq!(
+        vec![Problem::WrongFormat {
+            claimed: BookFormat::Epub,
+            actual: BookFormat::Txt
+        }],
+        verify_data(b"Dune\nby Frank Herbert", Some("epub"), &dune)
+    );
+    assert_eq!(
+        vec![Problem::WrongFormat {
+            claimed: BookFormat::Mobi,
+            actual: BookFormat::Pdf
+        }],
+        verify_data(PDF, Some("mobi"), &dune)
+    );
+    assert_eq!(
+        "is plain text, not an epub as its name says",
+        verify_data(b"Dune", Some("epub"), &dune)[0].describe()
+    );
+}
+#[test]
+fn verify_damaged_download_test()
+{
+    let dune = requested("Frank Herbert", "Dune");
+    let data = epub();
+    assert_eq!(
+        vec![Problem::Damaged("the archive is cut off")],
+        verify_data(&data[..data.len() - 10], Some("epub"), &dune)
+    );
+    // Flip a byte of the package document without touching its CRC
+    let mut corrupt = data.clone();
+    let position = corrupt.windows(4).position(|x| x == b"Dune").unwrap();
+    corrupt[position] = b'd';
+    assert_eq!(
+        vec![Problem::Damaged(
+            "an entry in the archive fails its CRC check"
+        )],
+        verify_data(&corrupt, Some("epub"), &dune)
+    );
+    assert_eq!(
+        vec![Problem::Damaged(
+            "the PDF has no end of file marker, it was cut off"
+        )],
+        verify_data(&PDF[..PDF.len() - 20], Some("pdf"), &dune)
+    );
+    let no_cross_reference = String::from_utf8_lossy(PDF).replace("startxref\n45", "startxref\n");
+    assert_eq!(
+        vec![Problem::Damaged(
+            "the PDF's cross-reference table is missing"
+        )],
+        verify_data(no_cross_reference.as_bytes(), Some("pdf"), &dune)
+    );
+}
+#[test]
+fn verify_mismatched_download_test()
+{
+    let problems = verify_data(
+        &epub(),
+        Some("epub"),
+        &requested("Ursula K. Le Guin", "The Left Hand of Darkness"),
+    );
+    assert_eq!(
+        vec![
+            Problem::TitleMismatch {
+                requested: "The Left Hand of Darkness".to_string(),
+                found: "Dune".to_string()
+            },
+            Problem::AuthorMismatch {
+                requested: "Ursula K. Le Guin".to_string(),
+                found: "Frank Herbert".to_string()
+            },
+        ],
+        problems
+    );
+    assert_eq!(
+        "is titled \"Dune\", not \"The Left Hand of Darkness\"",
+        problems[0].describe()
+    );
+    assert_eq!(
+        "is by Frank Herbert, not Ursula K. Le Guin",
+        problems[1].describe()
+    );
+}
+#[test]
+fn titles_match_test()
+{
+    assert!(titles_match("Dune", "Dune"));
+    assert!(titles_match("Dune", "DUNE: 40th Anniversary Edition"));
+    assert!(titles_match("The Hobbit", "Hobbit, The"));
+    assert!(titles_match("Dune", ""));
+    assert!(!titles_match("Dune Messiah", "Children of Dune"));
+}
+#[test]
+fn authors_match_test()
+{
+    assert!(authors_match("Frank Herbert", "Herbert, Frank"));
+    assert!(authors_match("Herbert, Frank", "F. Herbert"));
+    assert!(authors_match("Frank Herbert", "Unknown"));
+    assert!(!authors_match("K. J. Parker", "J. K. Rowling"));
+}
+#[test]
+fn quarantine_test()
+{
+    let directory = env::temp_dir().join(format!("rs-book-downloader-verify-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("Dune.epub");
+    fs::write(&path, b"Dune").unwrap();
+    let moved = quarantine(&path).unwrap();
+    let exists = (path.exists(), moved.exists());
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(
+        directory.join(QUARANTINE_DIRECTORY_NAME).join("Dune.epub"),
+        moved
+    );
+    assert_eq!((false, true), exists);
+}
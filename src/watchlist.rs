@@ -0,0 +1,277 @@
+use crate::download_queue::{escape, unescape};
+use crate::reading_list::ReadingListEntry;
+use crate::timestamp::{format_log_time, unix_seconds};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const WATCHLIST_FILE_NAME: &str = ".rs-book-downloader-watchlist";
+pub const WATCH_LOG_FILE_NAME: &str = ".rs-book-downloader-watch.log";
+/// How often the daemon searches again for a book it has not found yet.
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Search bots kick or ignore users who search in quick succession.
+pub const DEFAULT_SEARCH_SPACING: Duration = Duration::from_secs(60);
+/// While nothing is due the daemon still rereads the watchlist this often, for new books.
+pub const WATCHLIST_POLL: Duration = Duration::from_secs(60);
+
+/// A book the daemon keeps searching for until a copy is downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEntry
+{
+    /// Empty when only the title is known.
+    pub author: String,
+    pub title: String,
+    pub last_checked: Option<SystemTime>,
+    /// The file that was downloaded, once one was.
+    pub found: Option<String>,
+}
+
+impl WatchEntry
+{
+    pub fn reading_list_entry(&self, line: usize) -> ReadingListEntry
+    {
+        ReadingListEntry {
+            author: self.author.clone(),
+            title: self.title.clone(),
+            line,
+        }
+    }
+    pub fn describe(&self) -> String
+    {
+        self.reading_list_entry(0).describe()
+    }
+}
+
+#[derive(Debug)]
+pub struct Watchlist
+{
+    pub entries: Vec<WatchEntry>,
+    watchlist_path: Option<PathBuf>,
+}
+
+impl Watchlist
+{
+    pub fn new(watchlist_path: Option<PathBuf>) -> Self
+    {
+        Watchlist {
+            entries: Vec::new(),
+            watchlist_path,
+        }
+    }
+    pub fn load(watchlist_path: PathBuf) -> Result<Self, &'static str>
+    {
+        let mut watchlist = Watchlist::new(Some(watchlist_path.clone()));
+        let contents = match fs::read_to_string(&watchlist_path)
+        {
+            Ok(t) => t,
+            Err(_e) => return Ok(watchlist),
+        };
+        for line in contents.lines().filter(|x| !x.trim().is_empty())
+        {
+            // last checked, found file, author and title
+            let fields = line.split('\t').map(unescape).collect::<Vec<String>>();
+            if fields.len() != 4
+            {
+                return Err("Malformed line in watchlist file");
+            }
+            let last_checked = match fields[0].as_str()
+            {
+                "" => None,
+                x => match x.parse::<u64>()
+                {
+                    Ok(t) => Some(UNIX_EPOCH + Duration::from_secs(t)),
+                    Err(_e) => return Err("Malformed time in watchlist file"),
+                },
+            };
+            watchlist.entries.push(WatchEntry {
+                last_checked,
+                found: Some(fields[1].clone()).filter(|x| !x.is_empty()),
+                author: fields[2].clone(),
+                title: fields[3].clone(),
+            });
+        }
+        Ok(watchlist)
+    }
+    pub fn save(&self) -> Result<(), &'static str>
+    {
+        let watchlist_path = match &self.watchlist_path
+        {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let contents = self
+            .entries
+            .iter()
+            .map(|x| {
+                format!(
+                    "{}\t{}\t{}\t{}\n",
+                    x.last_checked
+                        .map(unix_seconds)
+                        .map(|x| x.to_string())
+                        .unwrap_or_default(),
+                    escape(x.found.as_deref().unwrap_or("")),
+                    escape(&x.author),
+                    escape(&x.title)
+                )
+            })
+            .collect::<String>();
+        match fs::write(watchlist_path, contents)
+        {
+            Ok(_) => Ok(()),
+            Err(_e) => Err("Unable to write watchlist file"),
+        }
+    }
+    /// Adds an `author - title` or bare title, returning its index.
+    pub fn add(&mut self, book: &str) -> Result<usize, &'static str>
+    {
+        let (author, title) = match book.split_once(" - ")
+        {
+            Some((author, title)) if !title.trim().is_empty() => (author.trim(), title.trim()),
+            _ => ("", book.trim()),
+        };
+        if title.is_empty() || title.contains(['\t', '\n'])
+        {
+            return Err("A watched book needs a title on one line");
+        }
+        let duplicate = self
+            .entries
+            .iter()
+            .any(|x| x.author.eq_ignore_ascii_case(author) && x.title.eq_ignore_ascii_case(title));
+        if duplicate
+        {
+            return Err("That book is already on the watchlist");
+        }
+        self.entries.push(WatchEntry {
+            author: author.to_string(),
+            title: title.to_string(),
+            last_checked: None,
+            found: None,
+        });
+        Ok(self.entries.len() - 1)
+    }
+    /// Removes a book by its number in `--watch-list` or its `author - title`.
+    pub fn remove(&mut self, book: &str) -> Result<WatchEntry, &'static str>
+    {
+        let index = match book.trim().parse::<usize>()
+        {
+            Ok(t) if t >= 1 && t <= self.entries.len() => Some(t - 1),
+            _ => self
+                .entries
+                .iter()
+                .position(|x| x.describe().eq_ignore_ascii_case(book.trim())),
+        };
+        match index
+        {
+            Some(t) => Ok(self.entries.remove(t)),
+            None => Err("No such book on the watchlist"),
+        }
+    }
+    /// Stores what the daemon learned about a book, unless it was removed meanwhile. The
+    /// daemon records into a freshly loaded list so books added while it ran are kept.
+    pub fn record(&mut self, entry: &WatchEntry)
+    {
+        if let Some(t) = self
+            .entries
+            .iter_mut()
+            .find(|x| x.author == entry.author && x.title == entry.title)
+        {
+            *t = entry.clone();
+        }
+    }
+    /// Books not found yet that were never searched for, or not within `interval`, in order.
+    pub fn due(&self, interval: Duration, now: SystemTime) -> Vec<usize>
+    {
+        (0..self.entries.len())
+            .filter(|x| {
+                let entry = &self.entries[*x];
+                entry.found.is_none()
+                    && entry
+                        .last_checked
+                        .is_none_or(|t| now.duration_since(t).unwrap_or_default() >= interval)
+            })
+            .collect()
+    }
+    /// How long until the next book is due, `None` when every book was found.
+    pub fn next_due(&self, interval: Duration, now: SystemTime) -> Option<Duration>
+    {
+        self.entries
+            .iter()
+            .filter(|x| x.found.is_none())
+            .map(|x| match x.last_checked
+            {
+                Some(t) => interval.saturating_sub(now.duration_since(t).unwrap_or_default()),
+                None => Duration::ZERO,
+            })
+            .min()
+    }
+    pub fn render(&self) -> String
+    {
+        if self.entries.is_empty()
+        {
+            return "The watchlist is empty.\n".to_string();
+        }
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let status = match (&x.found, x.last_checked)
+                {
+                    (Some(file), _) => format!("downloaded {}", file),
+                    (None, Some(t)) =>
+                    {
+                        format!("last searched {}", format_log_time(unix_seconds(t)))
+                    }
+                    (None, None) => "not searched yet".to_string(),
+                };
+                format!("{:>3}. {}  ({})\n", i + 1, x.describe(), status)
+            })
+            .collect()
+    }
+}
+
+/// Appends a timestamped line to the watch log.
+pub fn append_watch_log(log_path: &Path, line: &str) -> Result<(), &'static str>
+{
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .and_then(|mut x| {
+            writeln!(
+                x,
+                "{}  {}",
+                format_log_time(unix_seconds(SystemTime::now())),
+                line
+            )
+        });
+    match written
+    {
+        Ok(_) => Ok(()),
+        Err(_e) => Err("Unable to write to the watch log"),
+    }
+}
+
+/// Runs the notify hook as `<command> <event> <book> <detail>` without waiting for it, so a
+/// slow hook cannot hold up the daemon.
+pub fn run_notify_hook(
+    command: &str,
+    event: &str,
+    book: &str,
+    detail: &str,
+) -> Result<(), &'static str>
+{
+    match Command::new(command).args([event, book, detail]).spawn()
+    {
+        Ok(mut t) =>
+        {
+            // Reap the hook once it exits
+            thread::spawn(move || t.wait());
+            Ok(())
+        }
+        Err(_e) => Err("Unable to run the notify command"),
+    }
+}
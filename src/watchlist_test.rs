@@ -0,0 +1,122 @@
+use crate::timestamp::format_log_time;
+use crate::watchlist::{append_watch_log, Watchlist};
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[test]
+fn watchlist_add_and_remove_test()
+{
+    let mut watchlist = Watchlist::new(None);
+    assert_eq!(0, watchlist.add("Frank Herbert - Dune").unwrap());
+    assert_eq!(1, watchlist.add("Hyperion").unwrap());
+    assert_eq!("Frank Herbert", watchlist.entries[0].author);
+    assert_eq!("", watchlist.entries[1].author);
+    assert!(watchlist.add("frank herbert - DUNE").is_err());
+    assert!(watchlist.add("  ").is_err());
+    assert_eq!("Hyperion", watchlist.remove("2").unwrap().title);
+    assert_eq!(
+        "Dune",
+        watchlist.remove("frank herbert - dune").unwrap().title
+    );
+    assert!(watchlist.remove("1").is_err());
+}
+#[test]
+fn watchlist_due_test()
+{
+    let interval = Duration::from_secs(3600);
+    let now = UNIX_EPOCH + Duration::from_secs(100_000);
+    let mut watchlist = Watchlist::new(None);
+    watchlist.add("Frank Herbert - Dune").unwrap();
+    watchlist.add("Dan Simmons - Hyperion").unwrap();
+    watchlist.add("Iain M. Banks - Excession").unwrap();
+    assert_eq!(Some(Duration::ZERO), watchlist.next_due(interval, now));
+    watchlist.entries[0].last_checked = Some(now - Duration::from_secs(600));
+    watchlist.entries[1].last_checked = Some(now - Duration::from_secs(7200));
+    watchlist.entries[2].found = Some("Excession.epub".to_string());
+    assert_eq!(vec![1], watchlist.due(interval, now));
+    watchlist.entries[1].last_checked = Some(now);
+    assert!(watchlist.due(interval, now).is_empty());
+    assert_eq!(
+        Some(Duration::from_secs(3000)),
+        watchlist.next_due(interval, now)
+    );
+    watchlist.entries[0].found = Some("Dune.epub".to_string());
+    watchlist.entries[1].found = Some("Hyperion.epub".to_string());
+    assert_eq!(None, watchlist.next_due(interval, now));
+}
+#[test]
+fn watchlist_persistence_test()
+{
+    let path = env::temp_dir().join(format!("watchlist-test-{}", process::id()));
+    let checked = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut watchlist = Watchlist::load(path.clone()).unwrap();
+    watchlist.add("Frank Herbert - Dune").unwrap();
+    watchlist.add("Hyperion").unwrap();
+    watchlist.entries[0].last_checked = Some(checked);
+    watchlist.entries[0].found = Some("Frank Herbert - Dune.epub".to_string());
+    watchlist.save().unwrap();
+
+    // A book added by another process while the daemon was searching is kept
+    let mut other = Watchlist::load(path.clone()).unwrap();
+    other.add("Iain M. Banks - Excession").unwrap();
+    other.save().unwrap();
+    let mut searched = watchlist.entries[1].clone();
+    searched.last_checked = Some(checked);
+    let mut current = Watchlist::load(path.clone()).unwrap();
+    current.record(&searched);
+    current.save().unwrap();
+
+    let loaded = Watchlist::load(path.clone()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(3, loaded.entries.len());
+    assert_eq!(watchlist.entries[0], loaded.entries[0]);
+    assert_eq!(searched, loaded.entries[1]);
+    assert_eq!("Excession", loaded.entries[2].title);
+    assert_eq!(None, loaded.entries[2].last_checked);
+    assert!(loaded
+        .render()
+        .contains("  1. Frank Herbert - Dune  (downloaded Frank Herbert - Dune.epub)\n"));
+    assert!(loaded
+        .render()
+        .contains("  2. Hyperion  (last searched 2023-11-14 22:13:20 UTC)\n"));
+    assert!(loaded.render().contains("(not searched yet)"));
+}
+#[test]
+// A tab or line break in any field must not break the file
+fn watchlist_persists_tabs_and_line_breaks_test()
+{
+    let path = env::temp_dir().join(format!("watchlist-escape-test-{}", process::id()));
+    let mut watchlist = Watchlist::load(path.clone()).unwrap();
+    watchlist.add("Frank\tHerbert - Dune").unwrap();
+    watchlist.entries[0].found = Some("Dune\n\\ 1965.epub".to_string());
+    watchlist.save().unwrap();
+    let loaded = Watchlist::load(path.clone());
+    fs::remove_file(&path).unwrap();
+    assert_eq!(watchlist.entries, loaded.unwrap().entries);
+}
+#[test]
+fn watch_log_test()
+{
+    let path = env::temp_dir().join(format!("watch-log-test-{}", process::id()));
+    append_watch_log(&path, "queued  Dune  Dune.epub from Bot1").unwrap();
+    append_watch_log(&path, "downloaded  Dune  Dune.epub from Bot1").unwrap();
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let lines = contents.lines().collect::<Vec<&str>>();
+    assert_eq!(2, lines.len());
+    assert!(lines[0].ends_with(" UTC  queued  Dune  Dune.epub from Bot1"));
+    assert!(lines[1].ends_with(" UTC  downloaded  Dune  Dune.epub from Bot1"));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert!(lines[0].starts_with(&format_log_time(now)[..4]));
+}
+#[test]
+fn format_log_time_test()
+{
+    assert_eq!("1970-01-01 00:00:00 UTC", format_log_time(0));
+    assert_eq!("2000-02-29 23:59:59 UTC", format_log_time(951868799));
+}
@@ -0,0 +1,254 @@
+use crate::search_result::{SearchResult, VOLUME_WORDS};
+
+/// Every copy of one book found in a search, best copy first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Work
+{
+    pub author: String,
+    pub title: String,
+    pub copies: Vec<SearchResult>,
+}
+
+impl Work
+{
+    pub fn single(result: SearchResult) -> Self
+    {
+        Work {
+            author: result.author.clone(),
+            title: result.title.clone(),
+            copies: vec![result],
+        }
+    }
+    pub fn best(&self) -> &SearchResult
+    {
+        &self.copies[0]
+    }
+    /// Copies to fall back to, in order, when the best one cannot be downloaded.
+    pub fn alternates(&self) -> &[SearchResult]
+    {
+        &self.copies[1..]
+    }
+}
+
+/// Groups results that are the same book, keeping the order of first appearance for both the
+/// works and the copies inside each.
+///
+/// Results without an author join the one work with the same title, if there is exactly one.
+pub fn group_works(results: &[SearchResult]) -> Vec<Work>
+{
+    let mut keys: Vec<(String, String)> = Vec::new();
+    let mut works: Vec<Work> = Vec::new();
+    for result in results.iter().filter(|x| !x.author.trim().is_empty())
+    {
+        let key = work_key(result);
+        match keys.iter().position(|x| *x == key)
+        {
+            Some(i) => works[i].copies.push(result.clone()),
+            None =>
+            {
+                keys.push(key);
+                works.push(Work::single(result.clone()));
+            }
+        }
+    }
+    for result in results.iter().filter(|x| x.author.trim().is_empty())
+    {
+        let title = normalize_title(&result.title);
+        let same_title = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.1 == title)
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        if same_title.len() == 1
+        {
+            works[same_title[0]].copies.push(result.clone());
+        }
+        else
+        {
+            keys.push((String::new(), title));
+            works.push(Work::single(result.clone()));
+        }
+    }
+    // Put authorless copies and works back where they were listed
+    for work in works.iter_mut()
+    {
+        work.copies
+            .sort_by_key(|x| results.iter().position(|result| result == x));
+    }
+    works.sort_by_key(|x| {
+        results
+            .iter()
+            .position(|result| x.copies.contains(result))
+            .unwrap_or(usize::MAX)
+    });
+    works
+}
+
+const ARTICLES: [&str; 3] = ["the", "a", "an"];
+
+/// Normalized author and title, equal for copies of the same book.
+pub fn work_key(result: &SearchResult) -> (String, String)
+{
+    (
+        normalize_author(&result.author),
+        normalize_title(&result.title),
+    )
+}
+
+/// Lowercase name parts in sorted order, so `Tolkien, J.R.R.` and `J R R Tolkien` agree.
+pub fn normalize_author(author: &str) -> String
+{
+    let mut words = words(author);
+    words.sort();
+    words.join(" ")
+}
+
+/// Lowercase title words without diacritics, punctuation, articles or numbering. Volume numbers
+/// stay part of the title, so `Foundation Book 1` and `Foundation Book 2` are different books,
+/// unless they are in brackets like `Dune Messiah (Book 2)`.
+pub fn normalize_title(title: &str) -> String
+{
+    let title = strip_bracketed_volumes(strip_leading_number(title));
+    let mut words = words(&title);
+    // Bots list both `The Hobbit` and `Hobbit, The`
+    if words.len() > 1 && ARTICLES.contains(&words[0].as_str())
+    {
+        words.remove(0);
+    }
+    if words.len() > 1 && words.last().is_some_and(|x| x == "the")
+    {
+        words.pop();
+    }
+    words.join(" ")
+}
+
+/// Drops `01 - Title` style numbering in front of the title, but not a title that starts with
+/// a number like `2001 A Space Odyssey`.
+fn strip_leading_number(title: &str) -> &str
+{
+    let trimmed = title.trim_start();
+    let digits = trimmed.len()
+        - trimmed
+            .trim_start_matches(|x: char| x.is_ascii_digit())
+            .len();
+    if digits == 0 || digits > 3
+    {
+        return title;
+    }
+    let rest = trimmed[digits..].trim_start();
+    match rest.strip_prefix('-').or_else(|| rest.strip_prefix('.'))
+    {
+        Some(t) if t.starts_with(' ') && !t.trim().is_empty() => t,
+        _ => title,
+    }
+}
+
+/// Drops bracketed volume numbering like `(Book 2)` or `[Dune Chronicles, Vol. 1]`.
+fn strip_bracketed_volumes(title: &str) -> String
+{
+    let mut ret_val = String::new();
+    let mut rest = title;
+    while let Some(open) = rest.find(['(', '['])
+    {
+        let close_char = if rest[open..].starts_with('(')
+        {
+            ')'
+        }
+        else
+        {
+            ']'
+        };
+        let close = match rest[open..].find(close_char)
+        {
+            Some(t) => open + t,
+            None => break,
+        };
+        let inner = words(&rest[open + 1..close]);
+        let is_volume = inner.len() >= 2
+            && VOLUME_WORDS.contains(&inner[inner.len() - 2].as_str())
+            && inner[inner.len() - 1].chars().all(|x| x.is_ascii_digit());
+        ret_val.push_str(&rest[..open]);
+        if !is_volume
+        {
+            ret_val.push_str(&rest[open..=close]);
+        }
+        rest = &rest[close + 1..];
+    }
+    ret_val.push_str(rest);
+    ret_val
+}
+
+/// Lowercase words of letters and digits without diacritics, with `&` read as `and`.
+fn words(text: &str) -> Vec<String>
+{
+    fold_diacritics(&text.replace('&', " and ").to_lowercase())
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/// The words of a query or title without any of the articles bots add or drop at will. Ranking
+/// and cache keys compare queries by these, grouping compares titles by [`normalize_title`].
+pub fn normalize_words(text: &str) -> Vec<String>
+{
+    words(text)
+        .into_iter()
+        .filter(|x| !ARTICLES.contains(&x.as_str()))
+        .collect()
+}
+
+/// Replaces accented Latin letters with their plain equivalents, `Gödel` becomes `Godel`.
+pub fn fold_diacritics(text: &str) -> String
+{
+    let mut ret_val = String::new();
+    for x in text.chars()
+    {
+        let folded = match x
+        {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'ç' | 'ć' | 'č' => "c",
+            'Ç' | 'Ć' | 'Č' => "C",
+            'ď' | 'đ' => "d",
+            'Ď' | 'Đ' => "D",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => "E",
+            'ğ' => "g",
+            'Ğ' => "G",
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+            'Ì' | 'Í' | 'Î' | 'Ï' | 'Ī' | 'Į' | 'İ' => "I",
+            'ł' => "l",
+            'Ł' => "L",
+            'ñ' | 'ń' | 'ň' => "n",
+            'Ñ' | 'Ń' | 'Ň' => "N",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+            'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+            'œ' => "oe",
+            'Œ' => "OE",
+            'ř' => "r",
+            'Ř' => "R",
+            'ś' | 'š' | 'ş' => "s",
+            'Ś' | 'Š' | 'Ş' => "S",
+            'ß' => "ss",
+            'ť' | 'ţ' => "t",
+            'Ť' | 'Ţ' => "T",
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+            'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ū' | 'Ů' | 'Ű' | 'Ų' => "U",
+            'ý' | 'ÿ' => "y",
+            'Ý' | 'Ÿ' => "Y",
+            'ź' | 'ż' | 'ž' => "z",
+            'Ź' | 'Ż' | 'Ž' => "Z",
+            _ =>
+            {
+                ret_val.push(x);
+                continue;
+            }
+        };
+        ret_val.push_str(folded);
+    }
+    ret_val
+}
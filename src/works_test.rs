@@ -0,0 +1,139 @@
+use crate::search_result::SearchResult;
+use crate::works::{
+    fold_diacritics, group_works, normalize_author, normalize_title, normalize_words,
+};
+
+fn parse(lines: &[&str]) -> Vec<SearchResult>
+{
+    lines
+        .iter()
+        .map(|x| SearchResult::parse(x).unwrap())
+        .collect()
+}
+
+#[test]
+fn normalize_title_test()
+{
+    assert_eq!("hobbit", normalize_title("The Hobbit"));
+    assert_eq!("hobbit", normalize_title("Hobbit, The"));
+    assert_eq!("dune", normalize_title("01 - Dune"));
+    assert_eq!("dune messiah", normalize_title("Dune Messiah (Book 2)"));
+    assert_eq!("pride and prejudice", normalize_title("Pride & Prejudice"));
+    assert_eq!("godel escher bach", normalize_title("Gödel, Escher, Bach"));
+    assert_eq!("dune", normalize_title("02. Dune"));
+    assert_eq!("dune", normalize_title("Dune [Dune Chronicles, Vol. 1]"));
+    assert_eq!("dune abridged", normalize_title("Dune (Abridged)"));
+    assert_eq!("book 1", normalize_title("Book 1"));
+    assert_eq!("1984", normalize_title("1984"));
+}
+#[test]
+fn normalize_title_keeps_numbers_test()
+{
+    // Unbracketed volume numbers are part of the title
+    assert_eq!("foundation book 1", normalize_title("Foundation Book 1"));
+    assert_ne!(
+        normalize_title("Foundation Book 1"),
+        normalize_title("Foundation Book 2")
+    );
+    // A number is only numbering when a dash or dot follows it
+    assert_eq!(
+        "2001 a space odyssey",
+        normalize_title("2001 A Space Odyssey")
+    );
+    assert_eq!(
+        "2001 a space odyssey",
+        normalize_title("2001: A Space Odyssey")
+    );
+    assert_eq!("1984 a novel", normalize_title("1984 - A Novel"));
+}
+#[test]
+fn normalize_words_test()
+{
+    assert_eq!(
+        vec!["hobbit", "or", "there", "and", "back", "again"],
+        normalize_words("The Hobbit, or There and Back Again")
+    );
+    assert!(normalize_words(" - ").is_empty());
+    // Queries and titles agree with grouping on `&`, case and diacritics
+    assert_eq!(
+        normalize_words("Pride and Prejudice"),
+        normalize_words("PRIDE & PREJUDICE")
+    );
+    assert_eq!(
+        normalize_title("Gödel, Escher, Bach"),
+        normalize_words("godel escher bach").join(" ")
+    );
+}
+#[test]
+fn normalize_author_test()
+{
+    assert_eq!(
+        normalize_author("Tolkien, J.R.R."),
+        normalize_author("J R R Tolkien")
+    );
+    assert_eq!(
+        normalize_author("García Márquez, Gabriel"),
+        normalize_author("Gabriel Garcia Marquez")
+    );
+    assert_ne!(
+        normalize_author("Herbert, Frank"),
+        normalize_author("Herbert, Brian")
+    );
+    assert_eq!("Cafe Creme", fold_diacritics("Café Crème"));
+}
+#[test]
+fn group_works_test()
+{
+    let results = parse(&[
+        "!Bsk Tolkien, J.R.R - The Hobbit.epub ::INFO:: 1.2MB",
+        "!DV8 Herbert, Frank - Dune.mobi ::INFO:: 800KB",
+        "!Oatmeal J R R Tolkien - Hobbit, The.epub ::INFO:: 1.1MB",
+        "!Pondering42 Herbert, Brian - Dune.epub",
+        "!DV8 Tolkien, J.R.R - The Hobbit.mobi",
+    ]);
+    let works = group_works(&results);
+    assert_eq!(3, works.len());
+    assert_eq!("The Hobbit", works[0].title);
+    assert_eq!(
+        vec!["Bsk", "Oatmeal", "DV8"],
+        works[0]
+            .copies
+            .iter()
+            .map(|x| x.bot.as_str())
+            .collect::<Vec<&str>>()
+    );
+    assert_eq!("Bsk", works[0].best().bot);
+    assert_eq!(2, works[0].alternates().len());
+    // Same title by a different author is a different book
+    assert_eq!("Herbert, Frank", works[1].author);
+    assert_eq!(1, works[1].copies.len());
+    assert_eq!("Herbert, Brian", works[2].author);
+}
+#[test]
+fn group_authorless_results_test()
+{
+    let results = parse(&[
+        "!Bsk Dune.epub",
+        "!DV8 Herbert, Frank - Dune.mobi",
+        "!Oatmeal Emma.epub",
+    ]);
+    let works = group_works(&results);
+    assert_eq!(2, works.len());
+    assert_eq!(2, works[0].copies.len());
+    assert_eq!("Bsk", works[0].best().bot);
+    assert_eq!("Emma", works[1].title);
+}
+#[test]
+fn group_works_keeps_volumes_apart_test()
+{
+    let results = parse(&[
+        "!Bsk Asimov, Isaac - Foundation Book 1.epub",
+        "!DV8 Asimov, Isaac - Foundation Book 2.epub",
+        "!Oatmeal Asimov, Isaac - Foundation Book 1.mobi",
+    ]);
+    let works = group_works(&results);
+    assert_eq!(2, works.len());
+    assert_eq!(2, works[0].copies.len());
+    assert_eq!("Foundation Book 2", works[1].title);
+    assert!(works[1].alternates().is_empty());
+}